@@ -0,0 +1,205 @@
+//! Consistent pseudonyms for instrument IDs, operator names, and experiment names in the outputs
+//! of `support-bundle`, `inspect`, `detect`, `history`, `list`, `show`, `report`, and
+//! `ingest --json-output`.
+//!
+//! Each sensitive value is replaced by its kind and a hash of the value, e.g., `K00123` by
+//! `instrument-1f3a5c7e`, wherever it occurs as a whole token, such that the structure of the
+//! outputs is kept and the same value gets the same pseudonym in all outputs and calls.  The
+//! hashes of short values can be reversed by trying all values, so set `anonymize_salt` to a
+//! secret for sharing outputs outside the institution.
+
+use serde_json::Value;
+use std::path::Path;
+
+use ingest::bcl_meta::read_meta_bytes;
+use ingest::sample_sheet::SampleSheet;
+use inspect;
+use settings::Settings;
+
+/// Meta data files scanned for sensitive values.
+const META_FILES: &[&str] = &[
+    "RunInfo.xml",
+    "RunParameters.xml",
+    "runParameters.xml",
+    "RunParameters.json",
+    "RunManifest.csv",
+];
+/// Names of XML elements and JSON fields with sensitive values, with the kind of the value.
+const SENSITIVE_FIELDS: &[(&str, &str)] = &[
+    ("Instrument", "instrument"),
+    ("InstrumentID", "instrument"),
+    ("InstrumentName", "instrument"),
+    ("InstrumentSerialNumber", "instrument"),
+    ("ScannerID", "instrument"),
+    ("Username", "operator"),
+    ("UserName", "operator"),
+    ("Operator", "operator"),
+    ("OperatorName", "operator"),
+    ("ExperimentName", "experiment"),
+    ("RunName", "experiment"),
+];
+/// Values shorter than this are not replaced, as they would match too much.
+const MIN_VALUE_LEN: usize = 3;
+
+/// Replaces sensitive values by pseudonyms, does nothing unless `--anonymize` is given.
+#[derive(Debug, Clone, Default)]
+pub struct Anonymizer {
+    /// Whether or not to replace anything.
+    enabled: bool,
+    /// Salt for hashing the values.
+    salt: String,
+    /// The sensitive values and their pseudonyms, longest value first.
+    pseudonyms: Vec<(String, String)>,
+}
+
+impl Anonymizer {
+    /// Construct from `settings`, knowing the configured operator name.
+    pub fn new(settings: &Settings) -> Self {
+        let mut result = Self {
+            enabled: settings.anonymize,
+            salt: settings.anonymize_salt.clone(),
+            pseudonyms: Vec::new(),
+        };
+        result.add("operator", &settings.ingest.operator);
+        result
+    }
+
+    /// Return whether or not values are replaced.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Add the sensitive `value` of the given `kind` (e.g., `instrument`).
+    pub fn add(&mut self, kind: &str, value: &str) {
+        let value = value.trim();
+        if !self.enabled
+            || value.chars().count() < MIN_VALUE_LEN
+            || self.pseudonyms.iter().any(|(known, _)| known == value)
+        {
+            return;
+        }
+        let pseudonym = format!("{}-{:08x}", kind, self.hash(value) as u32);
+        self.pseudonyms.push((value.to_string(), pseudonym));
+        self.pseudonyms
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    }
+
+    /// Add the sensitive values of the run folder at `path`, from its meta data files and its
+    /// sample sheet.  Files that cannot be read are ignored.
+    pub fn add_run_folder(&mut self, logger: &slog::Logger, path: &Path, settings: &Settings) {
+        if !self.enabled {
+            return;
+        }
+        if let Ok(result) = inspect::inspect_folder(logger, path, settings) {
+            let as_str = |value: &Value| value.as_str().unwrap_or("").to_string();
+            self.add("instrument", &as_str(&result["run_info"]["instrument"]));
+            self.add(
+                "experiment",
+                &as_str(&result["run_parameters"]["experiment_name"]),
+            );
+        }
+        for name in META_FILES {
+            if let Ok(contents) = read_meta_bytes(path, name) {
+                self.add_fields(&String::from_utf8_lossy(&contents));
+            }
+        }
+        if let Some(sample_sheet) = SampleSheet::from_folder(path) {
+            for (key, value) in &sample_sheet.header {
+                match key.as_str() {
+                    "investigator name" | "investigatorname" | "investigator" => {
+                        self.add("operator", value)
+                    }
+                    "experiment name" | "experimentname" => self.add("experiment", value),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Add the sensitive values of the flow cell `payload` sent to the API, if any.
+    pub fn add_payload(&mut self, payload: &Value) {
+        let as_str = |key: &str| payload[key].as_str().unwrap_or("").to_string();
+        self.add("instrument", &as_str("sequencing_machine"));
+        self.add("operator", &as_str("operator"));
+        self.add("experiment", &as_str("label"));
+    }
+
+    /// Add the values of the sensitive XML elements and JSON fields in `text`.
+    fn add_fields(&mut self, text: &str) {
+        for (name, kind) in SENSITIVE_FIELDS {
+            let open = format!("<{}>", name);
+            let close = format!("</{}>", name);
+            for (start, _) in text.match_indices(&open) {
+                let rest = &text[(start + open.len())..];
+                if let Some(end) = rest.find(&close) {
+                    self.add(kind, &rest[..end]);
+                }
+            }
+            let key = format!("\"{}\"", name);
+            for (start, _) in text.match_indices(&key) {
+                let rest = text[(start + key.len())..].trim_start();
+                if !rest.starts_with(':') {
+                    continue;
+                }
+                let rest = rest[1..].trim_start();
+                if !rest.starts_with('"') {
+                    continue;
+                }
+                if let Some(end) = rest[1..].find('"') {
+                    self.add(kind, &rest[1..=end]);
+                }
+            }
+        }
+    }
+
+    /// Return `text` with the whole-token occurrences of the sensitive values replaced by their
+    /// pseudonyms.  Tokens are delimited by characters other than letters and digits, so
+    /// `K00123` is also replaced in `180101_K00123_0001_AHXXXXXXX`.
+    pub fn apply(&self, text: &str) -> String {
+        if !self.enabled || self.pseudonyms.is_empty() {
+            return text.to_string();
+        }
+        let mut result = String::with_capacity(text.len());
+        let mut pos = 0;
+        'outer: while pos < text.len() {
+            let at_boundary = text[..pos]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !c.is_alphanumeric());
+            if at_boundary {
+                for (value, pseudonym) in &self.pseudonyms {
+                    let end = pos + value.len();
+                    if text[pos..].starts_with(value.as_str())
+                        && text[end..]
+                            .chars()
+                            .next()
+                            .is_none_or(|c| !c.is_alphanumeric())
+                    {
+                        result.push_str(pseudonym);
+                        pos = end;
+                        continue 'outer;
+                    }
+                }
+            }
+            let c = text[pos..].chars().next().unwrap();
+            result.push(c);
+            pos += c.len_utf8();
+        }
+        result
+    }
+
+    /// Return the FNV-1a hash of the salt and `value`, stable across calls and versions.
+    fn hash(&self, value: &str) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in self
+            .salt
+            .bytes()
+            .chain(b"\0".iter().cloned())
+            .chain(value.bytes())
+        {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+}
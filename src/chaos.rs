@@ -0,0 +1,126 @@
+//! Fault injection for exercising the error handling of the client, enabled with the hidden
+//! `--chaos RATE` developer flag.
+//!
+//! When enabled, opening the run folder files, writing the state files, and calling the API
+//! fail at random with the given rate.  Whether an operation fails only depends on the seed, the
+//! kind of the operation, its file or API path, and how often it was attempted before, such
+//! that the same faults are injected on each call regardless of the order in which threads run,
+//! while repeated attempts of an operation may succeed.
+
+use restson;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The configuration and the statistics of the fault injection.
+struct Chaos {
+    /// The probability of each operation to fail.
+    rate: f64,
+    /// The seed for deciding which operations fail.
+    seed: u64,
+    /// The number of attempts so far by kind and path of the operation.
+    attempts: HashMap<String, u64>,
+    /// The number of injected faults.
+    injected: usize,
+    /// Logger for reporting the injected faults.
+    logger: slog::Logger,
+}
+
+/// The fault injection, disabled if `None`.
+static CHAOS: Mutex<Option<Chaos>> = Mutex::new(None);
+
+/// Return the 64 bit FNV-1a hash of `bytes`, stable across platforms and compiler versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Enable the fault injection with the probability `rate` for each operation to fail and the
+/// `seed`, reporting the injected faults to `logger`.
+pub fn enable(logger: &slog::Logger, rate: f64, seed: u64) {
+    warn!(
+        logger,
+        "Injecting faults with rate {} and seed {} (--chaos), do not use in production", rate, seed
+    );
+    *CHAOS.lock().unwrap() = Some(Chaos {
+        rate,
+        seed,
+        attempts: HashMap::new(),
+        injected: 0,
+        logger: logger.clone(),
+    });
+}
+
+/// Return whether the fault injection is enabled.
+pub fn is_enabled() -> bool {
+    CHAOS.lock().unwrap().is_some()
+}
+
+/// Return the number of faults injected so far.
+pub fn injected() -> usize {
+    CHAOS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(0, |chaos| chaos.injected)
+}
+
+/// Decide whether the next attempt of the operation of `kind` on `path` fails, returning a
+/// number for choosing the kind of fault if so.
+fn decide(kind: &str, path: &str) -> Option<u64> {
+    let mut guard = CHAOS.lock().unwrap();
+    let chaos = guard.as_mut()?;
+    let key = format!("{} {}", kind, path);
+    let attempt = {
+        let attempts = chaos.attempts.entry(key.clone()).or_insert(0);
+        *attempts += 1;
+        *attempts
+    };
+    let hash = fnv1a(format!("{} {} {}", chaos.seed, key, attempt).as_bytes());
+    if (hash >> 11) as f64 / (1u64 << 53) as f64 >= chaos.rate {
+        return None;
+    }
+    chaos.injected += 1;
+    warn!(
+        chaos.logger,
+        "Injecting fault into {} (attempt {})", &key, attempt
+    );
+    Some(hash)
+}
+
+/// Return the error to fail the attempt of the operation of `kind` on `path` with, if any.
+fn io_error(kind: &str, path: &Path) -> io::Result<()> {
+    match decide(kind, &path.to_string_lossy()) {
+        Some(_) => Err(io::Error::other("fault injected by --chaos")),
+        None => Ok(()),
+    }
+}
+
+/// Open the file at `path` for reading as `File::open()` does, failing at random if enabled.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    io_error("open", path.as_ref())?;
+    File::open(path)
+}
+
+/// Create the file at `path` for writing as `File::create()` does, failing at random if enabled.
+pub fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    io_error("create", path.as_ref())?;
+    File::create(path)
+}
+
+/// Return the error to fail the API call with `method` to `path` with, if any: a server error,
+/// a connection error, or a timeout.
+pub fn api_error(method: &str, path: &str) -> Option<restson::Error> {
+    let hash = decide(method, &format!("/{}", path))?;
+    Some(match hash % 3 {
+        0 => restson::Error::HttpError(
+            503,
+            "{\"detail\": \"Fault injected by --chaos\"}".to_string(),
+        ),
+        1 => restson::Error::RequestError,
+        _ => restson::Error::TimeoutError,
+    })
+}
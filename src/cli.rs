@@ -0,0 +1,39 @@
+//! Helpers for navigating the subcommand tree defined in `cli.yaml`.
+
+use clap::ArgMatches;
+
+/// Top-level subcommands that are aliases for a subcommand in a namespace.
+///
+/// These keep existing invocations (e.g., `digestiflow-cli ingest ...`) working.
+pub const ALIASES: &[(&str, &str)] = &[
+    ("ingest", "flowcells ingest"),
+    ("reconcile", "flowcells reconcile"),
+    ("import", "flowcells import"),
+    ("detect", "folders detect"),
+    ("inspect", "folders inspect"),
+    ("support-bundle", "folders support-bundle"),
+    ("export", "folders export"),
+    ("healthcheck", "api healthcheck"),
+    ("replay", "api replay"),
+];
+
+/// Return the canonical path of the selected subcommand (e.g., `"flowcells ingest"`) and the
+/// `ArgMatches` of the innermost subcommand.
+///
+/// Aliases from `ALIASES` are resolved to the namespaced subcommand path.
+pub fn command_path<'a>(matches: &'a ArgMatches<'a>) -> (String, &'a ArgMatches<'a>) {
+    let mut names = Vec::new();
+    let mut current = matches;
+    while let (name, Some(sub_matches)) = current.subcommand() {
+        names.push(name);
+        current = sub_matches;
+    }
+    let path = names.join(" ");
+
+    let canonical = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == path)
+        .map(|(_, target)| target.to_string())
+        .unwrap_or(path);
+    (canonical, current)
+}
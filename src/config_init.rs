@@ -0,0 +1,299 @@
+//! Implementation of the `config init` command that asks for the server URL, the API token, the
+//! project, and the directories with the run folders, checks them against the server, and
+//! writes the configuration file.
+
+use clap::ArgMatches;
+use config::{File, FileFormat};
+use libc;
+use serde_json;
+use shellexpand;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::mem;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use uuid::Uuid;
+
+use super::errors::*;
+use cli::command_path;
+use ingest::check::check_project_access;
+use settings::{Settings, Web};
+
+/// The configuration file written if no other is given with `--output`.
+const DEFAULT_OUTPUT: &str = "~/.digestiflowrc.toml";
+
+/// The answers given in the wizard.
+#[derive(Debug, Default)]
+struct Answers {
+    /// The URL of Digestiflow Web.
+    url: String,
+    /// The API token.
+    token: String,
+    /// The UUID of the project.
+    project_uuid: String,
+    /// The directories whose sub directories are the run folders.
+    roots: Vec<String>,
+}
+
+impl Answers {
+    /// Construct with the values of `settings` as defaults.
+    fn from_settings(settings: &Settings) -> Self {
+        Self {
+            url: settings.web.url.clone(),
+            token: settings.web.token.clone(),
+            project_uuid: if settings.ingest.project_uuid.is_nil() {
+                String::new()
+            } else {
+                settings.ingest.project_uuid.to_string()
+            },
+            roots: settings.watch.roots.clone(),
+        }
+    }
+}
+
+/// Read one line from stdin, failing at the end of the input.
+fn read_line() -> Result<String> {
+    let mut line = String::new();
+    let num_read = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .chain_err(|| "Problem reading answer")?;
+    if num_read == 0 {
+        bail!("Unexpected end of input, nothing written");
+    }
+    Ok(line.trim().to_string())
+}
+
+/// Write `prompt` to the terminal.
+fn prompt(prompt: &str) -> Result<()> {
+    eprint!("{}", prompt);
+    io::stderr()
+        .flush()
+        .chain_err(|| "Problem writing to terminal")
+}
+
+/// Ask `question`, returning the answer or `default` if the answer is empty.
+fn ask(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        prompt(&format!("{}: ", question))?;
+    } else {
+        prompt(&format!("{} [{}]: ", question, default))?;
+    }
+    let answer = read_line()?;
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    })
+}
+
+/// Ask for the secret `question` without showing the answer on the terminal, returning the
+/// answer or `default` if the answer is empty.
+fn ask_secret(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        prompt(&format!("{}: ", question))?;
+    } else {
+        prompt(&format!("{} [keep current]: ", question))?;
+    }
+    let answer = if io::stdin().is_terminal() {
+        let fd = libc::STDIN_FILENO;
+        let mut termios = unsafe { mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            bail!(
+                "Problem configuring terminal: {}",
+                io::Error::last_os_error()
+            );
+        }
+        let mut silent = termios;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+        let answer = read_line();
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+        eprintln!();
+        answer?
+    } else {
+        read_line()?
+    };
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    })
+}
+
+/// Ask whether to go on with `question`, return whether the answer is yes.
+fn confirm(question: &str) -> Result<bool> {
+    prompt(&format!("{} [Y/n] ", question))?;
+    Ok(!matches!(read_line()?.to_lowercase().as_str(), "n" | "no"))
+}
+
+/// Ask for all settings, using the earlier `answers` as defaults.
+fn ask_all(answers: &mut Answers) -> Result<()> {
+    answers.url = ask(
+        "URL of Digestiflow Web (e.g., https://flowcells.example.org)",
+        &answers.url,
+    )?;
+    answers.token = ask_secret(
+        "API token (created in Digestiflow Web through the user menu, item \"API Tokens\")",
+        &answers.token,
+    )?;
+    loop {
+        let project_uuid = ask("UUID of the project", &answers.project_uuid)?;
+        match Uuid::parse_str(&project_uuid) {
+            Ok(_) => {
+                answers.project_uuid = project_uuid;
+                break;
+            }
+            Err(e) => eprintln!("Invalid project UUID {:?}: {}", project_uuid, e),
+        }
+    }
+    loop {
+        let roots = ask(
+            "Directories containing the run folders, separated by commas (empty for none)",
+            &answers.roots.join(","),
+        )?
+        .split(',')
+        .map(|root| root.trim().to_string())
+        .filter(|root| !root.is_empty())
+        .collect::<Vec<String>>();
+        let missing = roots
+            .iter()
+            .filter(|root| !Path::new(root).is_dir())
+            .collect::<Vec<&String>>();
+        if missing.is_empty() {
+            answers.roots = roots;
+            break;
+        }
+        for root in missing {
+            eprintln!("Directory {:?} does not exist", root);
+        }
+    }
+    Ok(())
+}
+
+/// Return `value` as TOML string.
+fn toml_string(value: &str) -> String {
+    // The escapes of JSON strings are valid in TOML basic strings.
+    serde_json::to_string(value).expect("Problem escaping string")
+}
+
+/// Return the configuration file with the `answers`.
+fn format_config(answers: &Answers) -> String {
+    let roots = answers
+        .roots
+        .iter()
+        .map(|root| toml_string(root))
+        .collect::<Vec<String>>()
+        .join(", ");
+    [
+        "# Configuration of digestiflow-cli, written by `digestiflow-cli config init`.",
+        "",
+        "[web]",
+        "# URL to your Digestiflow instance. \"$url/api\" must be the API entry URL.",
+        &format!("url = {}", toml_string(&answers.url)),
+        "# The secret token to use for the REST API, as created through the Web UI.",
+        &format!("token = {}", toml_string(&answers.token)),
+        "",
+        "[ingest]",
+        "# The UUID of the project to write to.",
+        &format!("project_uuid = {}", toml_string(&answers.project_uuid)),
+        "",
+        "[watch]",
+        "# Directories whose sub directories are the run folders to ingest.",
+        &format!("roots = [{}]", roots),
+        "",
+        "[reconcile]",
+        "# Directories whose sub directories are the run folders to reconcile with.",
+        &format!("roots = [{}]", roots),
+        "",
+    ]
+    .join("\n")
+}
+
+/// Write the configuration `content` to `path`, readable by the user only as it contains the
+/// API token.
+fn write_config(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).chain_err(|| format!("Problem creating directory {:?}", dir))?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .chain_err(|| format!("Problem creating {:?}", path))?;
+    file.write_all(content.as_bytes())
+        .chain_err(|| format!("Problem writing {:?}", path))?;
+    // An existing file keeps its permissions when opened.
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .chain_err(|| format!("Problem setting permissions of {:?}", path))
+}
+
+/// Main entry point for the `config init` command.
+///
+/// Asks for the settings from `matches` on the terminal, with the current configuration as
+/// defaults, checks them against the server unless `--no-verify` is given, and writes the
+/// configuration file given with `--output`, which is only overwritten with `--force`.
+pub fn run(logger: &slog::Logger, matches: &ArgMatches) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client config init");
+
+    let (_, m) = command_path(matches);
+    let output = shellexpand::tilde(m.value_of("output").unwrap_or(DEFAULT_OUTPUT))
+        .into_owned()
+        .to_string();
+    let path = Path::new(&output);
+    if path.exists() && !m.is_present("force") {
+        bail!(
+            "Configuration file {:?} exists, use --force to overwrite it",
+            path
+        );
+    }
+
+    let mut answers = Settings::new(matches)
+        .map(|settings| Answers::from_settings(&settings))
+        .unwrap_or_default();
+    loop {
+        ask_all(&mut answers)?;
+        if m.is_present("no_verify") {
+            break;
+        }
+        let web = Web {
+            url: answers.url.clone(),
+            token: answers.token.clone(),
+            ..Web::default()
+        };
+        let project_uuid =
+            Uuid::parse_str(&answers.project_uuid).chain_err(|| "Invalid project UUID")?;
+        eprintln!("Checking the settings against {}...", &answers.url);
+        match check_project_access(&web, &project_uuid) {
+            Ok(title) => {
+                eprintln!("Project {:?} is readable with the API token.", title);
+                break;
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                if !confirm("Enter the settings again?")? {
+                    bail!("Could not verify the settings against the server, nothing written");
+                }
+            }
+        }
+    }
+
+    let content = format_config(&answers);
+    let mut check = Settings::default_config().chain_err(|| "Problem with defaults")?;
+    check
+        .merge(File::from_str(&content, FileFormat::Toml))
+        .chain_err(|| "Problem with the configuration to write")?;
+    check
+        .try_into::<Settings>()
+        .chain_err(|| "Problem with the configuration to write")?;
+    write_config(path, &content)?;
+    println!("Wrote configuration file {}", &output);
+    info!(
+        logger,
+        "Check the configuration with `digestiflow-cli config validate`"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! Implementation of the `config validate` command that checks the layered configuration for
+//! unknown keys, missing required settings, and paths that cannot be reached, and prints the
+//! effective settings with secrets masked.
+
+use clap::ArgMatches;
+use config::{Config, Environment, File, Source, Value};
+use serde_json;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use super::errors::*;
+use settings::Settings;
+
+/// Names of the settings whose values are masked in the output.
+const SECRET_KEYS: &[&str] = &["token", "anonymize_salt"];
+/// Replacement for the values of secret settings.
+const MASK: &str = "********";
+
+/// Add the keys of the leaves of `table` to `keys`, joined with `.` to `prefix`.
+fn flatten_keys(prefix: &str, table: HashMap<String, Value>, keys: &mut BTreeSet<String>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value.clone().into_table() {
+            Ok(sub_table) => flatten_keys(&key, sub_table, keys),
+            Err(_) => {
+                keys.insert(key);
+            }
+        }
+    }
+}
+
+/// Return the keys of the leaves of the configuration `s`.
+fn config_keys(s: &Config) -> Result<BTreeSet<String>> {
+    let mut keys = BTreeSet::new();
+    let table = s
+        .collect()
+        .chain_err(|| "Problem reading configuration values")?;
+    flatten_keys("", table, &mut keys);
+    Ok(keys)
+}
+
+/// Return whether `key` is one of the `known` keys or below a section with free-form keys.
+fn is_known(key: &str, known: &BTreeSet<String>) -> bool {
+    if known.contains(key) || key.starts_with("layouts.") {
+        return true;
+    }
+    // Profiles contain the same keys as the top level, below their name.
+    let parts = key.splitn(3, '.').collect::<Vec<&str>>();
+    parts.len() == 3 && parts[0] == "profile" && is_known(parts[2], known)
+}
+
+/// Return the problems with the unknown keys of the configuration file `config_file` and of the
+/// environment.
+fn unknown_keys(config_file: &Option<String>) -> Result<Vec<String>> {
+    let known = config_keys(&Settings::default_config().chain_err(|| "Problem with defaults")?)?;
+    let mut problems = Vec::new();
+    if let Some(config_file) = config_file {
+        let mut s = Config::new();
+        s.merge(File::with_name(config_file))
+            .chain_err(|| format!("Problem loading configuration file {}", config_file))?;
+        for key in config_keys(&s)? {
+            if !is_known(&key, &known) {
+                problems.push(format!("unknown key {:?} in {}", key, config_file));
+            }
+        }
+    }
+    let mut s = Config::new();
+    s.merge(Environment::with_prefix("DIGESTIFLOW").separator("__"))
+        .chain_err(|| "Problem reading environment")?;
+    for key in config_keys(&s)? {
+        if !is_known(&key, &known) {
+            problems.push(format!(
+                "unknown key {:?} from environment variable DIGESTIFLOW_{}",
+                key,
+                key.to_uppercase().replace('.', "__")
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// Return the problems with the required settings that are missing in `settings`.
+fn missing_settings(settings: &Settings) -> Vec<String> {
+    let mut problems = Vec::new();
+    if settings.web.url.is_empty() {
+        problems.push("missing server URL, set `url` in the [web] section".to_string());
+    }
+    if settings.web.token.is_empty() && settings.web.token_command.is_empty() {
+        problems.push(
+            "missing API token, set `token` or `token_command` in the [web] section".to_string(),
+        );
+    }
+    if settings.ingest.project_uuid.is_nil() {
+        problems
+            .push("missing project UUID, set `project_uuid` in the [ingest] section".to_string());
+    }
+    problems
+}
+
+/// Return the problems with the paths of `settings` that cannot be reached: directories that
+/// must exist and files whose directory must exist.
+fn unreachable_paths(settings: &Settings) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (key, dirs) in &[
+        ("ingest.path", &settings.ingest.path),
+        ("watch.roots", &settings.watch.roots),
+        ("reconcile.roots", &settings.reconcile.roots),
+    ] {
+        for dir in dirs.iter() {
+            if !Path::new(dir).is_dir() {
+                problems.push(format!("{}: directory {:?} does not exist", key, dir));
+            } else if Path::new(dir).read_dir().is_err() {
+                problems.push(format!("{}: directory {:?} is not readable", key, dir));
+            }
+        }
+    }
+    for (key, file) in &[
+        ("ingest.state_file", &settings.ingest.state_file),
+        ("ingest.skip_list", &settings.ingest.skip_list),
+        ("ingest.json_output", &settings.ingest.json_output),
+        ("backfill.journal", &settings.backfill.journal),
+        ("reconcile.folder_cache", &settings.reconcile.folder_cache),
+        ("watch.pid_file", &settings.watch.pid_file),
+    ] {
+        if file.is_empty() {
+            continue;
+        }
+        let dir = match Path::new(file.as_str()).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if !dir.is_dir() {
+            problems.push(format!(
+                "{}: directory {:?} of {:?} does not exist",
+                key, dir, file
+            ));
+        }
+    }
+    problems
+}
+
+/// Replace the non-empty values of the settings in `SECRET_KEYS` in `value` by `MASK`.
+fn mask_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                let is_secret = SECRET_KEYS.contains(&key.as_str())
+                    && value.as_str().is_some_and(|value| !value.is_empty());
+                if is_secret {
+                    *value = serde_json::Value::String(MASK.to_string());
+                } else {
+                    mask_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(mask_secrets),
+        _ => (),
+    }
+}
+
+/// Main entry point for the `config validate` command.
+///
+/// Loads the configuration as the other commands do from `matches`, prints the problems found
+/// and the effective settings with secrets masked, and fails if there are problems.
+pub fn run(logger: &slog::Logger, matches: &ArgMatches) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client config validate");
+
+    let config_file =
+        Settings::config_file(matches).chain_err(|| "Problem finding configuration file")?;
+    let mut problems = unknown_keys(&config_file)?;
+    let layered =
+        Settings::layered_config(matches, None).chain_err(|| "Problem loading configuration")?;
+    match layered.clone().try_into::<Settings>() {
+        Ok(settings) => {
+            problems.extend(missing_settings(&settings));
+            problems.extend(unreachable_paths(&settings));
+        }
+        Err(e) => problems.push(format!("invalid configuration: {}", e)),
+    }
+
+    let mut effective = layered
+        .try_into::<serde_json::Value>()
+        .chain_err(|| "Problem converting configuration")?;
+    mask_secrets(&mut effective);
+
+    println!(
+        "Configuration file: {}",
+        config_file.as_ref().map_or("none", |path| path.as_str())
+    );
+    if problems.is_empty() {
+        println!("Problems: none");
+    } else {
+        println!("Problems:");
+        for problem in &problems {
+            println!("  - {}", problem);
+        }
+    }
+    println!("Effective settings:");
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&effective).chain_err(|| "Problem serializing to JSON")?
+    );
+
+    if !problems.is_empty() {
+        bail!("Found {} problem(s) in the configuration", problems.len())
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Implementation of the `detect` command that explains the folder layout detection.
+
+use std::fmt::Write as FmtWrite;
+use std::path::Path;
+use sxd_document::parser;
+
+use super::errors::*;
+use anonymize::Anonymizer;
+use ingest::bcl_meta::*;
+use settings::Settings;
+
+/// Write the result of checking each layout detection rule for the run folder at `path`, the
+/// detected layout, and the parser that would be used for the run parameters to `out`.
+///
+/// In case of errors, `out` contains the explanation up to the problem.
+pub fn explain(path: &Path, settings: &Settings, out: &mut String) -> Result<()> {
+    writeln!(out, "Run folder: {}", path.display()).unwrap();
+    writeln!(
+        out,
+        "RunInfo.xml: {}",
+        if meta_file_exists(path, "RunInfo.xml") {
+            "found"
+        } else {
+            "MISSING"
+        }
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(
+        out,
+        "Layout rules (checked in order, the first matching one is used):"
+    )
+    .unwrap();
+    let configured = settings.configured_layout_rules();
+    let checks = check_layout_rules(path, &layout_rules(&configured));
+    let selected = checks.iter().position(|check| check.matched());
+    for (i, check) in checks.iter().enumerate() {
+        let state = if Some(i) == selected {
+            "SELECTED"
+        } else if check.matched() {
+            "matched"
+        } else {
+            "failed"
+        };
+        let source = if i < configured.len() {
+            " (from configuration)"
+        } else {
+            ""
+        };
+        if check.rule.name.is_empty() {
+            writeln!(out, "  {:?} [{}]{}", check.rule.layout, state, source).unwrap();
+        } else {
+            writeln!(
+                out,
+                "  {} (parser {:?}) [{}]{}",
+                &check.rule.name, check.rule.layout, state, source
+            )
+            .unwrap();
+        }
+        for (marker, matched) in &check.markers {
+            writeln!(out, "    [{}] {}", if *matched { "x" } else { " " }, marker).unwrap();
+        }
+    }
+    writeln!(out).unwrap();
+
+    let folder_layout = match selected {
+        Some(i) => checks[i].rule.layout,
+        None => {
+            writeln!(out, "Detected layout: none").unwrap();
+            bail!("Could not guess folder layout from {:?}", path);
+        }
+    };
+    match selected.map(|i| &checks[i].rule.name) {
+        Some(name) if !name.is_empty() => writeln!(
+            out,
+            "Detected layout: {} (parser {:?})",
+            name, folder_layout
+        )
+        .unwrap(),
+        _ => writeln!(out, "Detected layout: {:?}", folder_layout).unwrap(),
+    }
+
+    let filename = run_parameters_file(folder_layout);
+    let non_xml_parser = match folder_layout {
+        FolderLayout::Aviti => Some("AVITI (JSON)"),
+        FolderLayout::Mgi => Some("MGI (BioInfo.csv)"),
+        FolderLayout::Nanopore => Some("Nanopore (final summary/JSON report)"),
+        _ => None,
+    };
+    if let Some(parser_name) = non_xml_parser {
+        writeln!(out, "Run parameters file: {}", filename).unwrap();
+        writeln!(out, "Run parameters parser: {}", parser_name).unwrap();
+        return Ok(());
+    }
+    let contents = read_meta_file(path, filename)?;
+    let package = parser::parse(contents.trim_start_matches('\u{feff}'))
+        .chain_err(|| format!("Problem parsing XML from {}", filename))?;
+    writeln!(out, "Run parameters file: {}", filename).unwrap();
+    writeln!(
+        out,
+        "Run parameters parser: {}",
+        param_parser_name(folder_layout, &package.as_document())
+    )
+    .unwrap();
+
+    Ok(())
+}
+
+/// Main entry point for the `detect` command.
+///
+/// Prints the result of checking each layout detection rule, the detected layout, and the
+/// parser that would be used for the run parameters.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client detect");
+    let path = Path::new(&settings.detect.path);
+    if !path.is_dir() {
+        bail!("Run folder {:?} does not exist", path);
+    }
+
+    let mut out = String::new();
+    let result = explain(path, settings, &mut out);
+    let mut anonymizer = Anonymizer::new(settings);
+    anonymizer.add_run_folder(logger, path, settings);
+    print!("{}", anonymizer.apply(&out));
+    result
+}
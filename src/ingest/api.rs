@@ -2,12 +2,648 @@
 
 use super::*;
 
+use chaos;
+use chrono::Local;
+use ingest::archive::{ArchivedPayload, PayloadArchive};
+use ingest::bcl_data::QValBinInfo;
+use ingest::histogram::{FilteredHistogram, Histogram};
+use ingest::spool::{self, PayloadSpool};
+use ingest::streaming::StreamingClient;
 use restson::{self, RestPath};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The time at which the next request may be sent, shared by all clients and threads.
+static NEXT_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Wait such that at most `requests_per_second` requests are sent, no limit if not positive.
+///
+/// Each call reserves the next free time slot, so the limit holds across all clients and
+/// threads.
+fn pace(requests_per_second: f64) {
+    if requests_per_second <= 0.0 {
+        return;
+    }
+    let interval = Duration::from_secs_f64(1.0 / requests_per_second);
+    let wait = {
+        let mut next = NEXT_REQUEST.lock().unwrap();
+        let now = Instant::now();
+        let slot = match *next {
+            Some(next) if next > now => next,
+            _ => now,
+        };
+        *next = Some(slot + interval);
+        slot - now
+    };
+    if wait > Duration::from_secs(0) {
+        thread::sleep(wait);
+    }
+}
+
+/// Run the `command` that prints the API token to stdout (e.g., fetching it from Vault) and
+/// return the token.
+pub fn run_token_command(command: &str) -> Result<String> {
+    let mut args = command.split_whitespace();
+    let program = args.next().chain_err(|| "Token command is empty")?;
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .chain_err(|| format!("Problem running token command {:?}", command))?;
+    if !output.status.success() {
+        bail!("Token command {:?} failed with {}", command, output.status);
+    }
+    let token = String::from_utf8(output.stdout)
+        .chain_err(|| format!("Token command {:?} printed invalid UTF-8", command))?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        bail!("Token command {:?} printed no token", command);
+    }
+    Ok(token)
+}
+
+/// Return the path and JSON payload size of a request for tracing.
+fn describe<U, T>(params: U, data: &T) -> (String, usize)
+where
+    T: Serialize + RestPath<U>,
+{
+    let mut counter = ByteCounter(0);
+    (
+        T::get_path(params).unwrap_or_default(),
+        serde_json::to_writer(&mut counter, data)
+            .map(|_| counter.0)
+            .unwrap_or(0),
+    )
+}
+
+/// Writer that only counts the bytes written, for the size of payloads without serializing
+/// them to memory.
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The supported versions of the API payload schema, see `Web::api_schema`.
+pub const API_SCHEMAS: &[u32] = &[1, 2, 3];
+
+/// The highest RTA version accepted by servers with payload schema 1 or 2.
+const MAX_RTA_VERSION_V2: i32 = 3;
+
+/// Number and total duration of the API calls made by a `Client`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApiStats {
+    /// The number of calls.
+    pub calls: usize,
+    /// The total duration of the calls, without pacing.
+    pub duration: Duration,
+    /// The number of payloads spooled as the server could not be reached.
+    pub spooled: usize,
+}
+
+/// The canned responses and the captured payloads of a `Client` without network access.
+#[derive(Debug, Default)]
+struct Offline {
+    /// The URL of the server, for the captured payloads.
+    server: String,
+    /// The responses to GET requests by path.
+    responses: HashMap<String, Value>,
+    /// The run folder currently processed.
+    folder: String,
+    /// The payloads that would have been sent since the last `take_payloads()`.
+    payloads: Vec<ArchivedPayload>,
+}
+
+/// The result of a request whose payload is spooled if the server cannot be reached, see
+/// `Client::set_spool()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery<K> {
+    /// The server received the payload and returned this body.
+    Received(K),
+    /// The server could not be reached and the payload was spooled for sending it later, there
+    /// is no response yet.
+    Spooled,
+}
+
+/// Wrapper around `RestClient` that paces the requests to the API.
+///
+/// Without network access (see `Client::offline()`), GET requests are answered from canned
+/// responses and the payloads of POST and PUT requests are captured instead of sent.
+pub struct Client {
+    /// The wrapped client.
+    inner: RestClient,
+    /// Maximal number of requests per second, no limit if not positive.
+    requests_per_second: f64,
+    /// Version of the payload schema expected by the server.
+    api_schema: u32,
+    /// Logger for tracing each call at debug level, no tracing if `None`.
+    trace: Option<slog::Logger>,
+    /// Statistics of the calls since the last `take_stats()`.
+    stats: ApiStats,
+    /// Archive for the payloads that were sent successfully, no archiving if `None`.
+    archive: Option<PayloadArchive>,
+    /// Spool for the payloads that could not be sent as the server could not be reached, no
+    /// spooling if `None`.
+    spool: Option<PayloadSpool>,
+    /// The canned responses and captured payloads, network access if `None`.
+    offline: Option<Offline>,
+    /// Command for fetching a new token when the server rejects the current one, and the
+    /// logger for reporting this, no refresh if `None`.
+    token_command: Option<(String, slog::Logger)>,
+    /// Client for POST requests with bodies serialized while they are sent, sent with the
+    /// wrapped client if `None`.
+    streaming: Option<StreamingClient>,
+}
+
+impl Client {
+    /// Construct from the `RestClient` to wrap, the maximal number of requests per second, and
+    /// the version of the payload schema expected by the server.
+    pub fn new(inner: RestClient, requests_per_second: f64, api_schema: u32) -> Self {
+        Self {
+            inner,
+            requests_per_second,
+            api_schema,
+            trace: None,
+            stats: ApiStats::default(),
+            archive: None,
+            spool: None,
+            offline: None,
+            token_command: None,
+            streaming: None,
+        }
+    }
+
+    /// Do not access the network of the server at `server` from now on.
+    ///
+    /// GET requests are answered with the responses given to `add_response()` and fail with
+    /// 404 otherwise.  The payloads of POST and PUT requests are kept for `take_payloads()` and
+    /// returned as the response, as the server would for flow cells.
+    pub fn set_offline(&mut self, server: &str) {
+        self.offline = Some(Offline {
+            server: server.to_string(),
+            ..Offline::default()
+        });
+    }
+
+    /// Answer GET requests with `params` with `response` when offline.
+    pub fn add_response<U, T>(&mut self, params: U, response: &T) -> Result<()>
+    where
+        T: Serialize + RestPath<U>,
+    {
+        let path = T::get_path(params).chain_err(|| "Problem building API path")?;
+        let response =
+            serde_json::to_value(response).chain_err(|| "Problem converting response to JSON")?;
+        if let Some(offline) = self.offline.as_mut() {
+            offline.responses.insert(path, response);
+        }
+        Ok(())
+    }
+
+    /// Return the payloads that would have been sent since the last call when offline.
+    pub fn take_payloads(&mut self) -> Vec<ArchivedPayload> {
+        match self.offline.as_mut() {
+            Some(offline) => offline.payloads.split_off(0),
+            None => Vec::new(),
+        }
+    }
+
+    /// Keep the payload `data` that would have been sent with `method` and return it as the
+    /// response, `None` if not offline.
+    fn capture<U, T, K>(
+        &mut self,
+        method: &str,
+        params: U,
+        data: &T,
+    ) -> Option<result::Result<K, restson::Error>>
+    where
+        T: Serialize + RestPath<U>,
+        K: DeserializeOwned,
+    {
+        let offline = self.offline.as_mut()?;
+        let path = match T::get_path(params) {
+            Ok(path) => path,
+            Err(e) => return Some(Err(e)),
+        };
+        let payload = match serde_json::to_value(data) {
+            Ok(payload) => payload,
+            Err(e) => return Some(Err(restson::Error::ParseError(e))),
+        };
+        offline.payloads.push(ArchivedPayload {
+            time: Local::now().to_rfc3339(),
+            server: offline.server.clone(),
+            method: method.to_string(),
+            path,
+            folder: offline.folder.clone(),
+            payload: payload.clone(),
+        });
+        Some(serde_json::from_value(payload).map_err(restson::Error::ParseError))
+    }
+
+    /// Log each call with method, path, payload size, response code, and duration to `logger`.
+    pub fn set_trace(&mut self, logger: slog::Logger) {
+        self.trace = Some(logger);
+    }
+
+    /// Fetch a new token with `command` and repeat the request once when the server rejects
+    /// the token with HTTP 401, logging the refresh to `logger`.
+    pub fn set_token_command(&mut self, logger: &slog::Logger, command: &str) {
+        self.token_command = Some((command.to_string(), logger.clone()));
+    }
+
+    /// Fetch a new token with the token command, if any, and use it for the following
+    /// requests.  Returns whether the token was replaced.
+    fn refresh_token(&mut self) -> bool {
+        let (command, logger) = match &self.token_command {
+            Some(token_command) => token_command,
+            None => return false,
+        };
+        warn!(
+            logger,
+            "API token was rejected, fetching a new one with {:?}", command
+        );
+        let token = match run_token_command(command) {
+            Ok(token) => token,
+            Err(e) => {
+                error!(logger, "Could not fetch new API token: {:?}", e);
+                return false;
+            }
+        };
+        if let Some(streaming) = self.streaming.as_mut() {
+            streaming.set_token(&token);
+        }
+        match self
+            .inner
+            .set_header("Authorization", &format!("Token {}", &token))
+        {
+            Ok(()) => true,
+            Err(e) => {
+                error!(logger, "Problem configuring REST client: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// Send the bodies of `post_streamed()` with `streaming` while they are serialized.
+    pub fn set_streaming(&mut self, streaming: StreamingClient) {
+        self.streaming = Some(streaming);
+    }
+
+    /// Write each payload that was sent successfully to `archive`.
+    pub fn set_archive(&mut self, archive: PayloadArchive) {
+        self.archive = Some(archive);
+    }
+
+    /// Write `data` sent with `method` to the archive, if any and if `result` is a success.
+    fn archive<U, T, R>(
+        &self,
+        method: &str,
+        params: U,
+        data: &T,
+        result: &result::Result<R, restson::Error>,
+    ) where
+        T: Serialize + RestPath<U>,
+    {
+        if let (Some(archive), Ok(_)) = (&self.archive, result) {
+            archive.write(method, &T::get_path(params).unwrap_or_default(), data);
+        }
+    }
+
+    /// Write each payload that could not be sent as the server could not be reached to
+    /// `spool` and treat it as sent, for sending it later with `replay`.
+    pub fn set_spool(&mut self, spool: PayloadSpool) {
+        self.spool = Some(spool);
+    }
+
+    /// Write `data` that could not be sent with `method` because of `error` to the spool, if
+    /// any and if the server could not be reached.  Returns `error` if not spooled.
+    fn spool<U, T>(
+        &mut self,
+        method: &str,
+        params: U,
+        data: &T,
+        error: restson::Error,
+    ) -> result::Result<(), restson::Error>
+    where
+        T: Serialize + RestPath<U>,
+    {
+        match &self.spool {
+            Some(spool) if spool::is_unreachable(&error) => {
+                spool
+                    .write(method, &T::get_path(params).unwrap_or_default(), data)
+                    .map_err(|_| error)?;
+                self.stats.spooled += 1;
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Record `folder` as the run folder for the payloads archived, spooled, or captured from
+    /// now on.
+    pub fn set_archive_folder(&mut self, folder: &str) {
+        if let Some(archive) = self.archive.as_mut() {
+            archive.set_folder(folder);
+        }
+        if let Some(spool) = self.spool.as_mut() {
+            spool.set_folder(folder);
+        }
+        if let Some(offline) = self.offline.as_mut() {
+            offline.folder = folder.to_string();
+        }
+    }
+
+    /// Return the statistics of the calls since the last call and reset them.
+    pub fn take_stats(&mut self) -> ApiStats {
+        let stats = self.stats;
+        self.stats = ApiStats::default();
+        stats
+    }
+
+    /// Pace and run `request` on the wrapped client, recording its duration.
+    ///
+    /// The path and payload size are only computed by `describe` if tracing or the fault
+    /// injection of `chaos` is enabled.  If the token is rejected and a token command is set,
+    /// `request` is repeated once with a new token.
+    fn call<R, D, F>(
+        &mut self,
+        method: &str,
+        describe: D,
+        mut request: F,
+    ) -> result::Result<R, restson::Error>
+    where
+        D: FnOnce() -> (String, usize),
+        F: FnMut(&mut RestClient) -> result::Result<R, restson::Error>,
+    {
+        pace(self.requests_per_second);
+        let start = Instant::now();
+        let described = if self.trace.is_some() || chaos::is_enabled() {
+            Some(describe())
+        } else {
+            None
+        };
+        let injected = described
+            .as_ref()
+            .and_then(|(path, _)| chaos::api_error(method, path));
+        let mut result = match injected {
+            Some(e) => Err(e),
+            None => request(&mut self.inner),
+        };
+        if let Err(restson::Error::HttpError(401, _)) = result {
+            if self.refresh_token() {
+                pace(self.requests_per_second);
+                result = request(&mut self.inner);
+            }
+        }
+        let duration = start.elapsed();
+        self.stats.calls += 1;
+        self.stats.duration += duration;
+
+        if let (Some(logger), Some((path, payload_size))) = (&self.trace, &described) {
+            let response = match &result {
+                Ok(_) => "OK".to_string(),
+                Err(restson::Error::HttpError(code, _)) => code.to_string(),
+                Err(e) => format!("{:?}", e),
+            };
+            debug!(
+                logger,
+                "API {} /{} ({} bytes) -> {} in {:.3}s",
+                method,
+                path,
+                payload_size,
+                response,
+                duration.as_secs_f64()
+            );
+        }
+        result
+    }
+
+    /// Make a GET request.
+    pub fn get<U, T>(&mut self, params: U) -> result::Result<T, restson::Error>
+    where
+        U: Copy,
+        T: DeserializeOwned + RestPath<U>,
+    {
+        if let Some(offline) = &self.offline {
+            let path = T::get_path(params)?;
+            return match offline.responses.get(&path) {
+                Some(response) => {
+                    serde_json::from_value(response.clone()).map_err(restson::Error::ParseError)
+                }
+                None => Err(restson::Error::HttpError(
+                    404,
+                    format!("No response for {} without network access", &path),
+                )),
+            };
+        }
+        self.call(
+            "GET",
+            || (T::get_path(params).unwrap_or_default(), 0),
+            |inner| inner.get(params),
+        )
+    }
+
+    /// Make a POST request.
+    pub fn post<U, T>(&mut self, params: U, data: &T) -> result::Result<(), restson::Error>
+    where
+        U: Copy,
+        T: Serialize + RestPath<U>,
+    {
+        if let Some(result) = self.capture::<U, T, Value>("POST", params, data) {
+            return result.map(|_| ());
+        }
+        let result = self.call(
+            "POST",
+            || describe(params, data),
+            |inner| inner.post(params, data),
+        );
+        self.archive("POST", params, data, &result);
+        result.or_else(|e| self.spool("POST", params, data, e))
+    }
+
+    /// Make a POST request whose body is serialized while it is sent, see `StreamingClient`.
+    ///
+    /// Without streaming client, the request is made as with `post()`.
+    pub fn post_streamed<U, T>(&mut self, params: U, data: &T) -> result::Result<(), restson::Error>
+    where
+        U: Copy,
+        T: Serialize + Sync + RestPath<U>,
+    {
+        if self.offline.is_some() || self.streaming.is_none() {
+            return self.post(params, data);
+        }
+        let (path, length) = describe(params, data);
+        let mut streaming = self.streaming.take().unwrap();
+        let result = self.call(
+            "POST",
+            || (path.clone(), length),
+            |_| streaming.post(&path, data, length),
+        );
+        self.streaming = Some(streaming);
+        self.archive("POST", params, data, &result);
+        result.or_else(|e| self.spool("POST", params, data, e))
+    }
+
+    /// Make a POST request and capture the returned body.
+    pub fn post_capture<U, T, K>(
+        &mut self,
+        params: U,
+        data: &T,
+    ) -> result::Result<K, restson::Error>
+    where
+        U: Copy,
+        T: Serialize + RestPath<U>,
+        K: DeserializeOwned,
+    {
+        if let Some(result) = self.capture("POST", params, data) {
+            return result;
+        }
+        let result = self.call(
+            "POST",
+            || describe(params, data),
+            |inner| inner.post_capture(params, data),
+        );
+        self.archive("POST", params, data, &result);
+        result
+    }
+
+    /// Make a PUT request and capture the returned body.
+    ///
+    /// Returns `Delivery::Spooled` instead of a body if the payload was spooled, see
+    /// `set_spool()`.
+    pub fn put_capture<U, T, K>(
+        &mut self,
+        params: U,
+        data: &T,
+    ) -> result::Result<Delivery<K>, restson::Error>
+    where
+        U: Copy,
+        T: Serialize + RestPath<U>,
+        K: DeserializeOwned,
+    {
+        if let Some(result) = self.capture("PUT", params, data) {
+            return result.map(Delivery::Received);
+        }
+        let result = self.call(
+            "PUT",
+            || describe(params, data),
+            |inner| inner.put_capture(params, data),
+        );
+        self.archive("PUT", params, data, &result);
+        match result {
+            Ok(body) => Ok(Delivery::Received(body)),
+            Err(e) => self
+                .spool("PUT", params, data, e)
+                .map(|()| Delivery::Spooled),
+        }
+    }
+
+    /// Make a PATCH request.
+    pub fn patch<U, T>(&mut self, params: U, data: &T) -> result::Result<(), restson::Error>
+    where
+        U: Copy,
+        T: Serialize + RestPath<U>,
+    {
+        if let Some(result) = self.capture::<U, T, Value>("PATCH", params, data) {
+            return result.map(|_| ());
+        }
+        let result = self.call(
+            "PATCH",
+            || describe(params, data),
+            |inner| inner.patch(params, data),
+        );
+        self.archive("PATCH", params, data, &result);
+        result.or_else(|e| self.spool("PATCH", params, data, e))
+    }
+
+    /// Make a DELETE request for the object of type `T` at `params`.
+    pub fn delete<U, T>(&mut self, params: U) -> result::Result<(), restson::Error>
+    where
+        U: Copy,
+        T: RestPath<U>,
+    {
+        if self.offline.is_some() {
+            return Err(restson::Error::HttpError(
+                405,
+                "DELETE is not possible without network access".to_string(),
+            ));
+        }
+        self.call(
+            "DELETE",
+            || (T::get_path(params).unwrap_or_default(), 0),
+            |inner| inner.delete::<U, T>(params),
+        )
+    }
+
+    /// Return `flowcell` in the payload schema expected by the server.
+    ///
+    /// Servers before schema 3 do not know RTA 4, so such flow cells are recorded as RTA 3.
+    fn flowcell_payload(&self, flowcell: &FlowCell) -> FlowCell {
+        let mut result = flowcell.clone();
+        if self.api_schema < 3 {
+            result.rta_version = cmp::min(result.rta_version, MAX_RTA_VERSION_V2);
+        }
+        result
+    }
+
+    /// POST a new flow cell in the payload schema expected by the server.
+    pub fn post_flowcell(
+        &mut self,
+        params: &ProjectArgs,
+        flowcell: &FlowCell,
+    ) -> result::Result<FlowCell, restson::Error> {
+        let payload = self.flowcell_payload(flowcell);
+        self.post_capture(params, &payload)
+    }
+
+    /// PUT an updated flow cell in the payload schema expected by the server.
+    pub fn put_flowcell(
+        &mut self,
+        params: &ProjectFlowcellArgs,
+        flowcell: &FlowCell,
+    ) -> result::Result<Delivery<FlowCell>, restson::Error> {
+        let payload = self.flowcell_payload(flowcell);
+        self.put_capture(params, &payload)
+    }
+
+    /// POST an index histogram in the payload schema expected by the server, with only the
+    /// `max_entries` most frequent entries (all if 0) that pass its `min_index_fraction`.
+    ///
+    /// The entries are filtered while the payload is serialized and sent.
+    pub fn post_histogram(
+        &mut self,
+        params: &ProjectFlowcellArgs,
+        hist: &LaneIndexHistogram,
+        max_entries: usize,
+    ) -> result::Result<(), restson::Error> {
+        if self.api_schema == 1 {
+            self.post_streamed(params, &LaneIndexHistogramV1::new(hist, max_entries))
+        } else {
+            self.post_streamed(params, &LaneIndexHistogramPayload::new(hist, max_entries))
+        }
+    }
+}
 
 /// Flow cell information from the DigestiFlow API.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FlowCell {
-    pub sodar_uuid: Option<String>,
+    pub sodar_uuid: Option<Uuid>,
     pub run_date: String,
     pub run_number: i32,
     pub slot: String,
@@ -25,11 +661,29 @@ pub struct FlowCell {
     pub delivery_type: String,
     pub planned_reads: Option<String>,
     pub current_reads: Option<String>,
+    /// The libraries on the flow cell, only read from the API.
+    #[serde(default, skip_serializing)]
+    pub libraries: Vec<Library>,
+}
+
+/// Library information from the DigestiFlow API, as far as needed for checking barcodes and
+/// writing sample sheets.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Library {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub reference: Option<String>,
+    pub barcode_seq: Option<String>,
+    pub barcode_seq2: Option<String>,
+    /// The lanes the library is sequenced on, all lanes if empty.
+    #[serde(default)]
+    pub lane_numbers: Vec<i32>,
 }
 
 /// Restson arguments `resolve FlowCell by (instrument, run_number, flowcell)``.
 pub struct ResolveFlowCellArgs {
-    pub project_uuid: String,
+    pub project_uuid: Uuid,
     pub instrument: String,
     pub run_number: i32,
     pub flowcell: String,
@@ -46,7 +700,7 @@ impl<'a> RestPath<&'a ResolveFlowCellArgs> for FlowCell {
 
 // Restson arguments: PUT FlowCell for creation
 pub struct ProjectArgs {
-    pub project_uuid: String,
+    pub project_uuid: Uuid,
 }
 
 impl<'a> RestPath<&'a ProjectArgs> for FlowCell {
@@ -55,10 +709,23 @@ impl<'a> RestPath<&'a ProjectArgs> for FlowCell {
     }
 }
 
-// Restson arguments: GET/PUT Flowcell by SODAR UUID.
+/// Querying the list of flow cells of a project.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum FlowCellArray {
+    Array(Vec<FlowCell>),
+}
+
+impl<'a> RestPath<&'a ProjectArgs> for FlowCellArray {
+    fn get_path(args: &'a ProjectArgs) -> result::Result<String, restson::Error> {
+        Ok(format!("api/flowcells/{}/", &args.project_uuid))
+    }
+}
+
+// Restson arguments: GET/PUT/DELETE Flowcell by SODAR UUID.
 pub struct ProjectFlowcellArgs {
-    pub project_uuid: String,
-    pub flowcell_uuid: String,
+    pub project_uuid: Uuid,
+    pub flowcell_uuid: Uuid,
 }
 
 impl<'a> RestPath<&'a ProjectFlowcellArgs> for FlowCell {
@@ -70,6 +737,84 @@ impl<'a> RestPath<&'a ProjectFlowcellArgs> for FlowCell {
     }
 }
 
+/// Partial update of the status fields of a flow cell, unchanged if `None`.
+///
+/// The unchanged vendor ID identifies the flow cell in the payload archive.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct FlowCellStatus {
+    pub vendor_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_sequencing: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_conversion: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_delivery: Option<String>,
+}
+
+impl<'a> RestPath<&'a ProjectFlowcellArgs> for FlowCellStatus {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/flowcells/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
+/// Project information from the DigestiFlow API.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Project {
+    pub sodar_uuid: Uuid,
+    pub title: String,
+}
+
+/// Querying the list of projects readable with the API token.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ProjectArray {
+    Array(Vec<Project>),
+}
+
+impl RestPath<()> for ProjectArray {
+    fn get_path(_: ()) -> result::Result<String, restson::Error> {
+        Ok("api/projects/".to_string())
+    }
+}
+
+/// Project-level ingest preferences from the DigestiFlow API, used as defaults beneath the local
+/// configuration.  Preferences that are not set leave the local defaults unchanged.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProjectSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analyze_adapters: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_adapters: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_tiles: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_reads_per_tile: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_index_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_histogram_entries: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_lanes: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_warnings: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lane_quality: Option<bool>,
+}
+
+impl<'a> RestPath<&'a ProjectArgs> for ProjectSettings {
+    fn get_path(args: &'a ProjectArgs) -> result::Result<String, restson::Error> {
+        Ok(format!("api/projects/{}/settings/", &args.project_uuid))
+    }
+}
+
 /// Index histogram information from the DigestiFlow API.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LaneIndexHistogram {
@@ -79,7 +824,64 @@ pub struct LaneIndexHistogram {
     pub index_read_no: i32,
     pub sample_size: usize,
     pub min_index_fraction: f64,
-    pub histogram: HashMap<String, usize>,
+    /// Fraction of N calls in the sampled index read bases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n_fraction: Option<f64>,
+    /// Total number of clusters in the lane.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_clusters: Option<u64>,
+    /// Fraction of the lane's clusters that was sampled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<f64>,
+    /// The quality binning table of the run, for interpreting quality scores.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality_bins: Option<Vec<QValBinInfo>>,
+    /// The sampling configuration the histogram was produced with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<HistogramSampling>,
+    pub histogram: Histogram,
+}
+
+impl LaneIndexHistogram {
+    /// Return the entries of the histogram that are posted: the `max_entries` most frequent
+    /// ones (all if 0) that pass `min_index_fraction`.
+    pub fn filtered(&self, max_entries: usize) -> FilteredHistogram<'_> {
+        FilteredHistogram::new(
+            &self.histogram,
+            max_entries,
+            self.sample_size,
+            self.min_index_fraction,
+        )
+    }
+}
+
+/// The sampling configuration an index histogram was produced with, for comparing histograms
+/// produced with different settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistogramSampling {
+    /// Number of reads requested from each sampled tile (from all files for FASTQ files), 0 for
+    /// all reads.
+    pub requested_reads: usize,
+    /// Fraction of the lane's clusters requested, if sampling by fraction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_fraction: Option<f64>,
+    /// Number of tiles sampled, 0 for FASTQ files.
+    pub tiles_sampled: usize,
+    /// Numbers of the tiles sampled, as far as known from the file names.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tiles: Vec<u32>,
+    /// Number of cycles of the index read sampled.
+    pub cycles_sampled: i32,
+    /// Number of trailing cycles of the index read skipped, see `--max-index-cycles`.
+    pub cycles_skipped: i32,
+    /// Minimum fraction of the reads for an index sequence to be included in the histogram.
+    pub min_index_fraction: f64,
+    /// Seed of the random number generator for choosing the tiles.
+    pub seed: u64,
+    /// Width of the count-min sketch if the counts are estimates as the memory budget did not
+    /// allow counting all distinct sequences, see `--max-memory`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sketch_width: Option<usize>,
 }
 
 impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneIndexHistogram {
@@ -91,8 +893,96 @@ impl<'a> RestPath<&'a ProjectFlowcellArgs> for LaneIndexHistogram {
     }
 }
 
+/// Index histogram as posted in version 2 and later of the payload schema, referring to the
+/// entries of `LaneIndexHistogram` and filtering the histogram while serializing.
+#[derive(Debug, Serialize)]
+pub struct LaneIndexHistogramPayload<'a> {
+    pub sodar_uuid: Option<&'a str>,
+    pub flowcell: &'a str,
+    pub lane: i32,
+    pub index_read_no: i32,
+    pub sample_size: usize,
+    pub min_index_fraction: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_clusters: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality_bins: Option<&'a Vec<QValBinInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<&'a HistogramSampling>,
+    pub histogram: FilteredHistogram<'a>,
+}
+
+impl<'a> LaneIndexHistogramPayload<'a> {
+    /// Construct for posting `hist` with the `max_entries` most frequent entries, all if 0.
+    pub fn new(hist: &'a LaneIndexHistogram, max_entries: usize) -> Self {
+        Self {
+            sodar_uuid: hist.sodar_uuid.as_deref(),
+            flowcell: &hist.flowcell,
+            lane: hist.lane,
+            index_read_no: hist.index_read_no,
+            sample_size: hist.sample_size,
+            min_index_fraction: hist.min_index_fraction,
+            n_fraction: hist.n_fraction,
+            total_clusters: hist.total_clusters,
+            coverage: hist.coverage,
+            quality_bins: hist.quality_bins.as_ref(),
+            sampling: hist.sampling.as_ref(),
+            histogram: hist.filtered(max_entries),
+        }
+    }
+}
+
+impl<'a, 'b> RestPath<&'a ProjectFlowcellArgs> for LaneIndexHistogramPayload<'b> {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/indexhistos/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
+/// Index histogram in version 1 of the payload schema, without the statistics added later.
+///
+/// Servers expecting this schema reject the additional fields of `LaneIndexHistogram`.
+#[derive(Debug, Serialize)]
+pub struct LaneIndexHistogramV1<'a> {
+    pub flowcell: &'a str,
+    pub lane: i32,
+    pub index_read_no: i32,
+    pub sample_size: usize,
+    pub min_index_fraction: f64,
+    pub histogram: FilteredHistogram<'a>,
+}
+
+impl<'a> LaneIndexHistogramV1<'a> {
+    /// Construct for posting `hist` with the `max_entries` most frequent entries, all if 0.
+    pub fn new(hist: &'a LaneIndexHistogram, max_entries: usize) -> Self {
+        Self {
+            flowcell: &hist.flowcell,
+            lane: hist.lane,
+            index_read_no: hist.index_read_no,
+            sample_size: hist.sample_size,
+            min_index_fraction: hist.min_index_fraction,
+            histogram: hist.filtered(max_entries),
+        }
+    }
+}
+
+impl<'a, 'b> RestPath<&'a ProjectFlowcellArgs> for LaneIndexHistogramV1<'b> {
+    fn get_path(args: &'a ProjectFlowcellArgs) -> result::Result<String, restson::Error> {
+        Ok(format!(
+            "api/indexhistos/{}/{}/",
+            &args.project_uuid, &args.flowcell_uuid
+        ))
+    }
+}
+
 /// Querying index histogram list from DigestiFlow API.
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum LaneIndexHistogramArray {
     Array(Vec<LaneIndexHistogram>),
@@ -123,3 +1013,28 @@ impl<'a> RestPath<&'a ProjectFlowcellArgs> for FlowCellMessage {
         ))
     }
 }
+
+/// Restson arguments: an explicit path, e.g., for probing endpoints.
+pub struct PathArgs {
+    pub path: String,
+}
+
+/// A payload given as JSON, e.g., for sending spooled payloads.
+#[derive(Debug, Serialize)]
+pub struct RawPayload(pub Value);
+
+impl<'a> RestPath<&'a PathArgs> for RawPayload {
+    fn get_path(args: &'a PathArgs) -> result::Result<String, restson::Error> {
+        Ok(args.path.clone())
+    }
+}
+
+/// Empty payload for probing the write endpoints, which the server rejects as invalid.
+#[derive(Debug, Serialize)]
+pub struct EmptyPayload {}
+
+impl<'a> RestPath<&'a PathArgs> for EmptyPayload {
+    fn get_path(args: &'a PathArgs) -> result::Result<String, restson::Error> {
+        Ok(args.path.clone())
+    }
+}
@@ -0,0 +1,156 @@
+//! Local archive of the payloads successfully sent to the API.
+//!
+//! Each payload is written as a JSON file named after the time it was sent, giving a local
+//! history of what went to the server that can be queried with `flowcells history`.
+
+use chrono::Local;
+use serde::Serialize;
+use serde_json::{self, Value};
+use slog;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::super::errors::*;
+
+/// Counter for telling apart payloads sent within the same microsecond.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// A payload as written to the archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedPayload {
+    /// The time the payload was sent, in RFC 3339 format.
+    pub time: String,
+    /// The URL of the server the payload was sent to.
+    pub server: String,
+    /// The HTTP method, `POST` or `PUT`.
+    pub method: String,
+    /// The path of the API endpoint, e.g., `api/flowcells/${project}/`.
+    pub path: String,
+    /// The run folder processed when the payload was sent, empty if unknown.
+    #[serde(default)]
+    pub folder: String,
+    /// The payload.
+    pub payload: Value,
+}
+
+/// A payload as written to the archive, serialized directly from the payload without building
+/// a JSON value of it first, which matters for large index histograms.
+#[derive(Serialize)]
+struct ArchivedPayloadRef<'a, T: 'a> {
+    time: String,
+    server: &'a str,
+    method: &'a str,
+    path: &'a str,
+    folder: &'a str,
+    payload: &'a T,
+}
+
+/// Writer of payloads to an archive directory.
+pub struct PayloadArchive {
+    logger: slog::Logger,
+    /// The directory to write to.
+    dir: PathBuf,
+    /// The URL of the server the payloads are sent to.
+    server: String,
+    /// The run folder currently processed.
+    folder: String,
+}
+
+impl PayloadArchive {
+    /// Construct for writing the payloads sent to `server` to the directory `dir`, which is
+    /// created if necessary.
+    pub fn new(logger: &slog::Logger, dir: &Path, server: &str) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .chain_err(|| format!("Problem creating archive directory {:?}", dir))?;
+        Ok(Self {
+            logger: logger.clone(),
+            dir: dir.to_path_buf(),
+            server: server.to_string(),
+            folder: String::new(),
+        })
+    }
+
+    /// Record `folder` as the run folder for the payloads written from now on.
+    pub fn set_folder(&mut self, folder: &str) {
+        self.folder = folder.to_string();
+    }
+
+    /// Write the `payload` sent with `method` to `path`.
+    ///
+    /// Problems writing the file are logged only as the payload has been sent already.
+    pub fn write<T: Serialize>(&self, method: &str, path: &str, payload: &T) {
+        match write_payload(&self.dir, &self.server, &self.folder, method, path, payload) {
+            Ok(target) => debug!(self.logger, "Archived payload to {:?}", &target),
+            Err(e) => warn!(self.logger, "Could not archive payload: {:?}", &e),
+        }
+    }
+}
+
+/// Write the `payload` for the endpoint `path` of `server`, sent with `method` while processing
+/// the run folder `folder`, as a new file to the directory `dir` and return the file's path.
+pub fn write_payload<T: Serialize>(
+    dir: &Path,
+    server: &str,
+    folder: &str,
+    method: &str,
+    path: &str,
+    payload: &T,
+) -> Result<PathBuf> {
+    let now = Local::now();
+    // Name the file after the time and the kind of payload, e.g., `flowcells`.
+    let kind = path.split('/').nth(1).unwrap_or("payload");
+    let file_name = format!(
+        "{}-{:06}-{}.json",
+        now.format("%Y%m%dT%H%M%S%.6f"),
+        SEQUENCE.fetch_add(1, Ordering::SeqCst) % 1_000_000,
+        kind
+    );
+    let archived = ArchivedPayloadRef {
+        time: now.to_rfc3339(),
+        server,
+        method,
+        path,
+        folder,
+        payload,
+    };
+    let target = dir.join(file_name);
+    let file = File::create(&target).chain_err(|| format!("Problem creating {:?}", &target))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, &archived)
+        .chain_err(|| format!("Problem writing JSON to {:?}", &target))?;
+    writer
+        .flush()
+        .chain_err(|| format!("Problem writing JSON to {:?}", &target))?;
+    Ok(target)
+}
+
+/// Return the paths of the payload files in the directory `dir`, in the order they were
+/// written.
+pub fn payload_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)
+        .chain_err(|| format!("Problem reading directory {:?}", dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<PathBuf>>();
+    // The file names start with the time and a sequence number.
+    paths.sort();
+    Ok(paths)
+}
+
+/// Read the payload from the file at `path`.
+pub fn read_payload(path: &Path) -> Result<ArchivedPayload> {
+    let file = File::open(path).chain_err(|| format!("Problem reading {:?}", path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .chain_err(|| format!("Problem parsing JSON from {:?}", path))
+}
+
+/// Read the payloads archived in the directory `dir`, in the order they were sent.
+pub fn read_archive(dir: &Path) -> Result<Vec<ArchivedPayload>> {
+    payload_files(dir)
+        .chain_err(|| format!("Problem reading archive directory {:?}", dir))?
+        .iter()
+        .map(|path| read_payload(path))
+        .collect()
+}
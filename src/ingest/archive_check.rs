@@ -0,0 +1,279 @@
+//! Implementation of the `archive-check` command that checks whether run folders are complete
+//! before they are moved to tape: the completion markers written by the instrument and the lane
+//! and cycle directories expected from `RunInfo.xml`.
+
+use serde_json;
+use sxd_xpath::evaluate_xpath;
+
+use super::*;
+
+/// Name of the file with the final status of the run, as written by the NextSeq, MiniSeq,
+/// NovaSeq, and NovaSeq X.
+const RUN_COMPLETION_STATUS_FILE: &str = "RunCompletionStatus.xml";
+/// Completion status of runs that ended as planned.
+const COMPLETED_AS_PLANNED: &str = "CompletedAsPlanned";
+
+/// The result of checking one run folder.
+#[derive(Debug, Serialize)]
+struct FolderCheck {
+    /// Path to the run folder.
+    path: String,
+    /// The detected folder layout, empty if unknown.
+    folder_layout: String,
+    /// The number of lanes from `RunInfo.xml`.
+    num_lanes: i32,
+    /// The number of cycles from `RunInfo.xml`.
+    num_cycles: i32,
+    /// Whether or not the lanes and cycles were checked, not for layouts without cycle
+    /// directories or files.
+    cycles_checked: bool,
+    /// The missing pieces, empty if the folder is complete.
+    problems: Vec<String>,
+}
+
+impl FolderCheck {
+    /// Return whether the run folder is complete.
+    fn is_complete(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Return the completion markers that the instrument writes into run folders with the given
+/// `folder_layout` at the end of the run.
+fn sentinels(folder_layout: FolderLayout) -> Vec<&'static str> {
+    match folder_layout {
+        FolderLayout::Aviti => vec![aviti::RUN_COMPLETE_FILE],
+        // Checked with `mgi::is_complete()` and `nanopore::is_complete()`.
+        FolderLayout::Mgi | FolderLayout::Nanopore => Vec::new(),
+        FolderLayout::MiSeq
+        | FolderLayout::MiSeqDep
+        | FolderLayout::HiSeqX
+        | FolderLayout::HiSeq3000 => vec!["RTAComplete.txt"],
+        FolderLayout::MiniSeq | FolderLayout::NextSeq500 => {
+            vec!["RTAComplete.txt", RUN_COMPLETION_STATUS_FILE]
+        }
+        FolderLayout::NovaSeq | FolderLayout::NovaSeqXplus | FolderLayout::NextSeq2000 => vec![
+            "RTAComplete.txt",
+            copy_complete_file(folder_layout),
+            RUN_COMPLETION_STATUS_FILE,
+        ],
+    }
+}
+
+/// Return the completion status from the `RunCompletionStatus.xml` of the run folder at `path`,
+/// e.g., `CompletedAsPlanned` or `UserEndedEarly`.
+fn completion_status(path: &Path) -> Result<String> {
+    let contents = read_meta_file(path, RUN_COMPLETION_STATUS_FILE)?;
+    let package = parser::parse(contents.trim_start_matches('\u{feff}'))
+        .chain_err(|| format!("Problem parsing XML from {}", RUN_COMPLETION_STATUS_FILE))?;
+    Ok(
+        evaluate_xpath(&package.as_document(), "string(//CompletionStatus)")
+            .chain_err(|| format!("Problem reading {}", RUN_COMPLETION_STATUS_FILE))?
+            .into_string()
+            .trim()
+            .to_string(),
+    )
+}
+
+/// Add the problems with the completion markers of the run folder at `path` to `problems`.
+fn check_sentinels(path: &Path, folder_layout: FolderLayout, problems: &mut Vec<String>) {
+    for name in sentinels(folder_layout) {
+        if !meta_file_exists(path, name) {
+            problems.push(format!("missing {}", name));
+        } else if name == RUN_COMPLETION_STATUS_FILE {
+            match completion_status(path) {
+                Ok(ref status) if status == COMPLETED_AS_PLANNED => (),
+                Ok(ref status) if status.is_empty() => {
+                    problems.push(format!("no completion status in {}", name))
+                }
+                Ok(status) => problems.push(format!("{} gives status {}", name, status)),
+                Err(e) => problems.push(format!("{}", e)),
+            }
+        }
+    }
+    match folder_layout {
+        FolderLayout::Mgi if !mgi::is_complete(path) => {
+            problems.push("missing *.fq.fqStat.txt files of the lanes".to_string())
+        }
+        FolderLayout::Nanopore if !nanopore::is_complete(path) => {
+            problems.push("missing final summary".to_string())
+        }
+        _ => (),
+    }
+}
+
+/// Add the lanes and cycles of `run_info` that are missing from the base calls of the run folder
+/// at `path` to `problems`, returning whether the `folder_layout` allows checking them.
+///
+/// The NextSeq 500/550 and MiniSeq write one BCL file per cycle and lane, the other Illumina
+/// instruments one directory per cycle and lane.
+fn check_cycles(
+    path: &Path,
+    folder_layout: FolderLayout,
+    run_info: &RunInfo,
+    problems: &mut Vec<String>,
+) -> bool {
+    let per_cycle_files = match folder_layout {
+        FolderLayout::Aviti | FolderLayout::Mgi | FolderLayout::Nanopore => return false,
+        FolderLayout::MiniSeq | FolderLayout::NextSeq500 => true,
+        _ => false,
+    };
+    let base_calls = path.join("Data").join("Intensities").join("BaseCalls");
+    let num_cycles: i32 = run_info.reads.iter().map(|desc| desc.num_cycles).sum();
+    for lane_no in 1..=run_info.lane_count {
+        let lane_dir = base_calls.join(format!("L{:03}", lane_no));
+        if !lane_dir.is_dir() {
+            problems.push(format!(
+                "lane {}: missing directory {:?}",
+                lane_no, lane_dir
+            ));
+            continue;
+        }
+        let missing = (1..=num_cycles)
+            .filter(|cycle| {
+                if per_cycle_files {
+                    !bcl_file_exists(&lane_dir, &format!("{:04}", cycle))
+                } else {
+                    !lane_dir.join(format!("C{}.1", cycle)).is_dir()
+                }
+            })
+            .collect::<Vec<i32>>();
+        if !missing.is_empty() {
+            problems.push(format!(
+                "lane {}: missing {} of {} cycles: {}",
+                lane_no,
+                missing.len(),
+                num_cycles,
+                format_cycle_ranges(&missing)
+            ));
+        }
+    }
+    true
+}
+
+/// Check the run folder at `path`, any problem reading it makes it incomplete.
+fn check_folder(logger: &slog::Logger, path: &Path, settings: &Settings) -> FolderCheck {
+    let mut result = FolderCheck {
+        path: path.to_string_lossy().to_string(),
+        folder_layout: String::new(),
+        num_lanes: 0,
+        num_cycles: 0,
+        cycles_checked: false,
+        problems: Vec::new(),
+    };
+    if !path.is_dir() {
+        result
+            .problems
+            .push("run folder does not exist".to_string());
+        return result;
+    }
+    let folder_layout = match configured_folder_layout(path, settings) {
+        Ok(Some(folder_layout)) => Ok(folder_layout),
+        Ok(None) => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules())),
+        Err(e) => Err(e),
+    };
+    let folder_layout = match folder_layout {
+        Ok(folder_layout) => folder_layout,
+        Err(e) => {
+            result
+                .problems
+                .push(format!("could not detect folder layout: {}", e));
+            return result;
+        }
+    };
+    result.folder_layout = format!("{:?}", folder_layout);
+
+    check_sentinels(path, folder_layout, &mut result.problems);
+    match parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut Vec::new(),
+    ) {
+        Ok((run_info, run_params)) => {
+            result.num_lanes = run_info.lane_count;
+            result.num_cycles = run_info.reads.iter().map(|desc| desc.num_cycles).sum();
+            // Only the cycles matter, `RunInfo.xml` may also mark index reads as reverse
+            // complement.
+            let reads = string_description(&run_info.reads);
+            let planned_reads = string_description(&run_params.planned_reads);
+            if !run_params.planned_reads.is_empty() && reads != planned_reads {
+                result
+                    .problems
+                    .push(format!("reads {} but planned {}", reads, planned_reads));
+            }
+            result.cycles_checked =
+                check_cycles(path, folder_layout, &run_info, &mut result.problems);
+        }
+        Err(e) => result
+            .problems
+            .push(format!("could not read run information: {}", e)),
+    }
+    result
+}
+
+/// Main entry point for the `archive-check` command.
+///
+/// Checks each run folder in `settings.archive_check.path` for the completion markers of its
+/// instrument and for the lane and cycle directories expected from `RunInfo.xml`, prints the
+/// missing pieces, and fails if any folder is incomplete.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client archive-check");
+
+    let checks = settings
+        .archive_check
+        .path
+        .iter()
+        .map(|path| check_folder(logger, Path::new(path), settings))
+        .collect::<Vec<FolderCheck>>();
+
+    if settings.archive_check.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&checks).chain_err(|| "Problem serializing to JSON")?
+        );
+    } else {
+        for check in &checks {
+            let summary = if check.folder_layout.is_empty() {
+                String::new()
+            } else if check.cycles_checked {
+                format!(
+                    " ({}, {} lane(s), {} cycles)",
+                    &check.folder_layout, check.num_lanes, check.num_cycles
+                )
+            } else {
+                format!(" ({}, lanes and cycles not checked)", &check.folder_layout)
+            };
+            println!(
+                "{}: {}{}",
+                &check.path,
+                if check.is_complete() {
+                    "complete"
+                } else {
+                    "INCOMPLETE"
+                },
+                summary
+            );
+            for problem in &check.problems {
+                println!("  - {}", problem);
+            }
+        }
+    }
+
+    let num_incomplete = checks.iter().filter(|check| !check.is_complete()).count();
+    info!(
+        logger,
+        "{} of {} folder(s) incomplete",
+        num_incomplete,
+        checks.len()
+    );
+    if num_incomplete > 0 {
+        bail!(
+            "{} of {} run folder(s) are incomplete",
+            num_incomplete,
+            checks.len()
+        )
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Meta data of Element Biosciences AVITI run folders.
+//!
+//! AVITI run folders have no `RunInfo.xml`.  Their `RunParameters.json` is mapped onto the
+//! `RunInfo` and `RunParameters` of Illumina run folders such that they are registered and
+//! updated in the same way.
+
+use chrono::DateTime;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use super::super::errors::*;
+use ingest::bcl_meta::{
+    date_from_run_id, meta_file_exists, ReadDescription, RunInfo, RunParameters,
+};
+use ingest::warnings::{Warning, WarningKind};
+
+/// Name of the run parameters file of AVITI run folders.
+pub const RUN_PARAMETERS_FILE: &str = "RunParameters.json";
+/// Name of the file written by the AVITI when the run folder has been uploaded completely.
+pub const RUN_COMPLETE_FILE: &str = "RunUploaded.json";
+/// Read order used when `ReadOrder` is missing, the AVITI sequences the index reads first.
+const DEFAULT_READ_ORDER: &str = "I1,I2,R1,R2";
+/// Number of lanes of AVITI flow cells, used when `AnalysisLanes` is missing.
+const DEFAULT_LANE_COUNT: i32 = 2;
+
+/// The parts of the AVITI `RunParameters.json` that are used.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct AvitiRunParameters {
+    /// The run name given by the operator.
+    pub run_name: String,
+    /// The run ID.
+    #[serde(rename = "RunID")]
+    pub run_id: String,
+    /// The name of the run folder, starting with the date.
+    pub run_folder_name: String,
+    /// The side of the instrument, `SideA` or `SideB`.
+    pub side: String,
+    /// The flow cell ID.
+    #[serde(rename = "FlowcellID")]
+    pub flowcell_id: String,
+    /// The start of the run, e.g., `2023-03-14T11:04:47.178953474Z`.
+    pub date: String,
+    /// The instrument serial number.
+    pub instrument_name: String,
+    /// The number of cycles by read name (`R1`, `R2`, `I1`, `I2`).
+    pub cycles: BTreeMap<String, i32>,
+    /// The comma-separated order in which the reads are sequenced, e.g., `I1,I2,R1,R2`.
+    pub read_order: String,
+    /// The lanes that are analyzed, e.g., `1+2`.
+    pub analysis_lanes: String,
+    /// The version of the instrument software, e.g., `2.4.0`.
+    pub platform_version: String,
+}
+
+/// Return whether the run folder at `path` is an AVITI run folder.
+pub fn is_aviti_folder(path: &Path) -> bool {
+    path.join(RUN_PARAMETERS_FILE).exists() && !meta_file_exists(path, "RunInfo.xml")
+}
+
+/// Read the `RunParameters.json` of the AVITI run folder at `path`.
+pub fn read_run_parameters(path: &Path) -> Result<AvitiRunParameters> {
+    let file = File::open(path.join(RUN_PARAMETERS_FILE))
+        .chain_err(|| format!("Problem reading {}", RUN_PARAMETERS_FILE))?;
+    serde_json::from_reader(file)
+        .chain_err(|| format!("Problem parsing JSON from {}", RUN_PARAMETERS_FILE))
+}
+
+/// Return the read descriptions of `params` in the order of sequencing.
+///
+/// Reads are numbered in this order; `I1` and `I2` are index reads.
+fn read_descriptions(
+    params: &AvitiRunParameters,
+    warnings: &mut Vec<Warning>,
+) -> Result<Vec<ReadDescription>> {
+    let read_order = if params.read_order.is_empty() {
+        warnings.push(Warning::new(
+            WarningKind::LayoutQuirk,
+            format!(
+                "No ReadOrder in {}, assuming {}",
+                RUN_PARAMETERS_FILE, DEFAULT_READ_ORDER
+            ),
+        ));
+        DEFAULT_READ_ORDER
+    } else {
+        &params.read_order
+    };
+
+    let mut reads = Vec::new();
+    for name in read_order.split(',').map(|name| name.trim()) {
+        let num_cycles = match params.cycles.get(name) {
+            Some(num_cycles) => *num_cycles,
+            None if params.read_order.is_empty() => 0,
+            None => bail!("No cycles given for read {} of ReadOrder", name),
+        };
+        if num_cycles > 0 {
+            reads.push(ReadDescription {
+                number: reads.len() as i32 + 1,
+                num_cycles,
+                is_index: name.starts_with('I'),
+                is_reverse_complement: false,
+            });
+        }
+    }
+    Ok(reads)
+}
+
+/// Convert the AVITI run parameters `params` into run info and run parameters.
+///
+/// The AVITI has no run counter, so the run number is always 0 and runs are told apart by
+/// their flow cell ID.  All planned reads are taken as current reads, a run is considered
+/// complete once `RunUploaded.json` exists.
+pub fn process_run_parameters(
+    params: &AvitiRunParameters,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
+    if params.flowcell_id.is_empty() || params.instrument_name.is_empty() {
+        bail!(
+            "FlowcellID and InstrumentName must be given in {}",
+            RUN_PARAMETERS_FILE
+        );
+    }
+
+    let run_id = if params.run_folder_name.is_empty() {
+        params.run_id.clone()
+    } else {
+        params.run_folder_name.clone()
+    };
+    let date = match DateTime::parse_from_rfc3339(&params.date) {
+        Ok(date) => date.format("%F").to_string(),
+        Err(_) => match date_from_run_id(&run_id) {
+            Some(date) => {
+                warnings.push(Warning::new(
+                    WarningKind::DateFallback,
+                    format!(
+                        "Could not parse date {:?}, using date {} from run ID {:?}",
+                        &params.date, &date, &run_id
+                    ),
+                ));
+                date
+            }
+            None => bail!("Could not parse date from string {}", &params.date),
+        },
+    };
+
+    let lane_count = if params.analysis_lanes.is_empty() {
+        DEFAULT_LANE_COUNT
+    } else {
+        params.analysis_lanes.split('+').count() as i32
+    };
+
+    let rta_version = if params.platform_version.is_empty() {
+        "0".to_string()
+    } else {
+        params.platform_version.clone()
+    };
+    let major = rta_version.split('.').next().unwrap();
+    if major.parse::<i32>().is_err() {
+        bail!("Could not parse PlatformVersion {:?}", &rta_version);
+    }
+
+    let slot = match params.side.as_str() {
+        "SideA" | "A" => "A",
+        "SideB" | "B" => "B",
+        "" => "",
+        side => {
+            warnings.push(Warning::new(
+                WarningKind::LayoutQuirk,
+                format!("Unknown Side {:?}, ignoring it", side),
+            ));
+            ""
+        }
+    };
+
+    let run_info = RunInfo {
+        run_id,
+        run_number: 0,
+        flowcell: params.flowcell_id.clone(),
+        instrument: params.instrument_name.clone(),
+        date,
+        lane_count,
+        reads: read_descriptions(params, warnings)?,
+        version: 0,
+        geometry: None,
+    };
+    let run_params = RunParameters {
+        planned_reads: read_descriptions(params, &mut Vec::new())?,
+        rta_version,
+        run_number: 0,
+        flowcell_slot: slot.to_string(),
+        experiment_name: params.run_name.clone(),
+        instrument_model: None,
+    };
+    Ok((run_info, run_params))
+}
+
+/// Read and convert the `RunParameters.json` of the AVITI run folder at `path`.
+///
+/// Quirks that had to be worked around are added to `warnings`.
+pub fn parse_run_folder(
+    path: &Path,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
+    process_run_parameters(&read_run_parameters(path)?, warnings)
+}
@@ -0,0 +1,332 @@
+//! Implementation of the `backfill` command that ingests many (e.g., archived) run folders with
+//! several folders in parallel, a global progress with ETA, and a journal for resuming.
+
+use chaos;
+use chrono::Local;
+use rayon::prelude::*;
+use serde_json;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::*;
+
+/// Width of the progress bar in characters.
+const PROGRESS_WIDTH: usize = 30;
+
+/// A folder recorded in the backfill journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Whether processing the folder succeeded with all servers.
+    pub success: bool,
+    /// The error of the failure, if any.
+    pub error: Option<String>,
+    /// Time processing the folder finished.
+    pub finished: String,
+    /// Size of the folder in bytes.
+    pub bytes: u64,
+    /// Time it took to process the folder, in seconds.
+    pub seconds: f64,
+}
+
+/// The folders processed by `backfill` so far, by path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    /// The processed folders by path.
+    pub folders: BTreeMap<String, JournalEntry>,
+}
+
+impl Journal {
+    /// Load the journal from the JSON file at `path`, empty if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+        serde_json::from_reader(file).chain_err(|| format!("Problem reading JSON from {:?}", path))
+    }
+
+    /// Write the journal as JSON to the file at `path`.
+    ///
+    /// The file is replaced atomically, such that an interrupted backfill leaves a usable one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let file =
+            chaos::create(&tmp_path).chain_err(|| format!("Problem creating {:?}", &tmp_path))?;
+        serde_json::to_writer_pretty(file, self)
+            .chain_err(|| format!("Problem writing JSON to {:?}", &tmp_path))?;
+        fs::rename(&tmp_path, path).chain_err(|| format!("Problem replacing {:?}", path))
+    }
+
+    /// Return whether the folder at `path` has been processed successfully.
+    pub fn is_done(&self, path: &str) -> bool {
+        self.folders.get(path).is_some_and(|entry| entry.success)
+    }
+}
+
+/// The global progress of a backfill, by folders and by bytes.
+#[derive(Debug)]
+struct Progress {
+    /// Time the processing started.
+    start: Instant,
+    /// The number of folders to process.
+    total_folders: usize,
+    /// The total size of the folders to process.
+    total_bytes: u64,
+    /// The number of processed folders.
+    done_folders: usize,
+    /// The size of the processed folders.
+    done_bytes: u64,
+    /// The time spent on the processed folders in seconds, summed over the workers.
+    busy_seconds: f64,
+    /// The number of folders that failed.
+    failed_folders: usize,
+}
+
+impl Progress {
+    /// Construct for processing `total_folders` folders of `total_bytes` bytes.
+    fn new(total_folders: usize, total_bytes: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            total_folders,
+            total_bytes,
+            done_folders: 0,
+            done_bytes: 0,
+            busy_seconds: 0.0,
+            failed_folders: 0,
+        }
+    }
+
+    /// Record a processed folder of `bytes` bytes that took `seconds` to process.
+    fn add(&mut self, bytes: u64, seconds: f64, success: bool) {
+        self.done_folders += 1;
+        self.done_bytes += bytes;
+        self.busy_seconds += seconds;
+        if !success {
+            self.failed_folders += 1;
+        }
+    }
+
+    /// Return the estimated time in seconds for processing a folder of `bytes` bytes, from the
+    /// time per byte of the folders processed so far.
+    fn folder_estimate(&self, bytes: u64) -> Option<f64> {
+        if self.done_bytes == 0 {
+            None
+        } else {
+            Some(self.busy_seconds * bytes as f64 / self.done_bytes as f64)
+        }
+    }
+
+    /// Return the estimated remaining time in seconds, from the overall throughput so far.
+    fn eta(&self) -> Option<f64> {
+        if self.done_bytes == 0 {
+            None
+        } else {
+            let elapsed = self.start.elapsed().as_secs_f64();
+            let remaining = self.total_bytes.saturating_sub(self.done_bytes);
+            Some(elapsed * remaining as f64 / self.done_bytes as f64)
+        }
+    }
+
+    /// Return the progress as a line with a bar, e.g., `[#####     ] 12/340 folders...`.
+    fn render(&self) -> String {
+        let fraction = if self.total_bytes > 0 {
+            self.done_bytes as f64 / self.total_bytes as f64
+        } else if self.total_folders > 0 {
+            self.done_folders as f64 / self.total_folders as f64
+        } else {
+            1.0
+        };
+        let filled = ((fraction * PROGRESS_WIDTH as f64).round() as usize).min(PROGRESS_WIDTH);
+        let eta = match self.eta() {
+            Some(seconds) => format_duration(seconds),
+            None => "unknown".to_string(),
+        };
+        format!(
+            "[{}{}] {}/{} folders ({} failed), {} of {} ({:.1}%), ETA {}",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_WIDTH - filled),
+            self.done_folders,
+            self.total_folders,
+            self.failed_folders,
+            format_bytes(self.done_bytes),
+            format_bytes(self.total_bytes),
+            100.0 * fraction,
+            eta
+        )
+    }
+}
+
+/// Format `seconds` as hours, minutes, and seconds, e.g., `5h12m`, `3m20s`, or `42s`.
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, (seconds % 3600) / 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// The state shared between the workers.
+struct SharedState {
+    /// The folders still to process, with their sizes.
+    queue: VecDeque<(String, u64)>,
+    /// The journal of processed folders.
+    journal: Journal,
+    /// The global progress.
+    progress: Progress,
+    /// The reports of the processed folders.
+    reports: Vec<FolderReport>,
+}
+
+/// Process the folders from the queue in `state` with each of the `servers` until the queue is
+/// empty.
+fn work(
+    logger: &slog::Logger,
+    servers: &[Settings],
+    state: &Mutex<SharedState>,
+    settings: &Settings,
+) -> Result<()> {
+    let mut clients = Vec::new();
+    for server_settings in servers {
+        clients.push(build_server_client(logger, server_settings, settings)?);
+    }
+
+    loop {
+        let (path_str, bytes) = {
+            let mut state = state.lock().unwrap();
+            match state.queue.pop_front() {
+                Some(entry) => {
+                    match state.progress.folder_estimate(entry.1) {
+                        Some(seconds) => info!(
+                            logger,
+                            "Starting folder {} ({}), expected to take {}",
+                            &entry.0,
+                            format_bytes(entry.1),
+                            format_duration(seconds)
+                        ),
+                        None => info!(
+                            logger,
+                            "Starting folder {} ({})",
+                            &entry.0,
+                            format_bytes(entry.1)
+                        ),
+                    }
+                    entry
+                }
+                None => return Ok(()),
+            }
+        };
+
+        let start = Instant::now();
+        let reports = servers
+            .iter()
+            .zip(clients.iter_mut())
+            .map(|(server_settings, client)| {
+                process_path(logger, &path_str, client, server_settings, settings)
+            })
+            .collect::<Vec<FolderReport>>();
+        let success = reports.iter().all(|report| report.success);
+        let seconds = start.elapsed().as_secs_f64();
+
+        let mut state = state.lock().unwrap();
+        state.journal.folders.insert(
+            path_str.clone(),
+            JournalEntry {
+                success,
+                error: reports
+                    .iter()
+                    .filter_map(|report| report.error.clone())
+                    .next(),
+                finished: Local::now().to_rfc3339(),
+                bytes,
+                seconds,
+            },
+        );
+        if !settings.backfill.journal.is_empty() {
+            state.journal.save(Path::new(&settings.backfill.journal))?;
+        }
+        state.progress.add(bytes, seconds, success);
+        state.reports.extend(reports);
+        info!(logger, "Progress: {}", state.progress.render());
+    }
+}
+
+/// Main entry point for the `backfill` command.
+///
+/// Processes the folders as with `ingest` but with `settings.backfill.jobs` folders in
+/// parallel, oldest first.  The folder sizes are used for estimating the remaining time, and
+/// folders recorded as successful in the journal `settings.backfill.journal` are skipped, such
+/// that an interrupted backfill can be resumed.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client backfill");
+    info!(logger, "Options: {:?}", settings);
+    env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.backfill.jobs < 1 {
+        bail!("The number of jobs must be positive");
+    }
+    configured_folder_layout(Path::new(""), settings)?;
+
+    let servers = select_servers(logger, settings)?;
+
+    // Skip the folders done in a previous call.
+    let journal_path = Path::new(&settings.backfill.journal);
+    let journal = if settings.backfill.journal.is_empty() {
+        Journal::default()
+    } else {
+        Journal::load(journal_path)?
+    };
+    let (done, paths): (Vec<String>, Vec<String>) =
+        order_by_run_date(logger, &settings.ingest.path, &settings.ingest.date_format)
+            .into_iter()
+            .partition(|path| journal.is_done(path));
+    info!(
+        logger,
+        "{} folder(s) to process, {} done before",
+        paths.len(),
+        done.len()
+    );
+
+    info!(logger, "Computing folder sizes...");
+    let queue = paths
+        .par_iter()
+        .map(|path| (path.clone(), DirSize::compute(Path::new(path)).bytes))
+        .collect::<Vec<(String, u64)>>();
+    let total_bytes = queue.iter().map(|(_, bytes)| bytes).sum();
+    info!(logger, "=> {} in total", format_bytes(total_bytes));
+
+    let state = Mutex::new(SharedState {
+        progress: Progress::new(queue.len(), total_bytes),
+        queue: queue.into_iter().collect(),
+        journal,
+        reports: Vec::new(),
+    });
+    let results = thread::scope(|scope| {
+        let workers = (0..settings.backfill.jobs)
+            .map(|_| scope.spawn(|| work(logger, &servers, &state, settings)))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Backfill worker panicked"))
+            .collect::<Vec<Result<()>>>()
+    });
+    for result in results {
+        result?;
+    }
+
+    let state = state.into_inner().unwrap();
+    info!(
+        logger,
+        "Done in {}: {}",
+        format_duration(state.progress.start.elapsed().as_secs_f64()),
+        state.progress.render()
+    );
+    summarize_reports(logger, &servers, &state.reports, settings)
+}
@@ -0,0 +1,124 @@
+//! Expected barcodes from the API or the sample sheet and matching observed indices to them.
+
+use std::path::Path;
+
+use ingest::api;
+use ingest::sample_sheet::SampleSheet;
+
+/// The barcodes expected for the first and second index read.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedBarcodes {
+    /// Expected barcodes of the first index read.
+    pub index1: Vec<String>,
+    /// Expected barcodes of the second index read.
+    pub index2: Vec<String>,
+}
+
+impl ExpectedBarcodes {
+    /// Take the expected barcodes from the libraries of `flowcell` or, if there are none, from
+    /// the `SampleSheet.csv` (or AVITI `RunManifest.csv`) in the run folder at `path`.
+    pub fn load(flowcell: &api::FlowCell, path: &Path) -> Self {
+        let mut result = Self::default();
+        if flowcell.libraries.is_empty() {
+            if let Some(sample_sheet) = SampleSheet::from_folder(path) {
+                for row in &sample_sheet.data {
+                    let index1 = row.get("index").or_else(|| row.get("index1"));
+                    result.push(index1.cloned(), row.get("index2").cloned());
+                }
+            }
+        } else {
+            for library in &flowcell.libraries {
+                result.push(library.barcode_seq.clone(), library.barcode_seq2.clone());
+            }
+        }
+        result
+    }
+
+    /// Whether or not no barcodes are expected at all.
+    pub fn is_empty(&self) -> bool {
+        self.index1.is_empty() && self.index2.is_empty()
+    }
+
+    /// Return the barcodes expected for the index read with number `index_read_no`.
+    pub fn for_index_read(&self, index_read_no: i32) -> &[String] {
+        match index_read_no {
+            1 => &self.index1,
+            2 => &self.index2,
+            _ => &[],
+        }
+    }
+
+    /// Return whether `seq` is within one mismatch of any of the barcodes for the index read
+    /// with number `index_read_no`, also considering the reverse complement.
+    pub fn matches(&self, index_read_no: i32, seq: &str) -> bool {
+        self.for_index_read(index_read_no).iter().any(|barcode| {
+            within_one_mismatch(seq, barcode) || within_one_mismatch(seq, &revcomp(barcode))
+        })
+    }
+
+    fn push(&mut self, index1: Option<String>, index2: Option<String>) {
+        for (seq, target) in [(index1, &mut self.index1), (index2, &mut self.index2)] {
+            if let Some(seq) = seq {
+                let seq = seq.trim().to_uppercase();
+                if !seq.is_empty() && !target.contains(&seq) {
+                    target.push(seq);
+                }
+            }
+        }
+    }
+}
+
+/// Return whether `seq` and `barcode` differ in at most one position, comparing the common
+/// prefix only as the barcode may be shorter than the index read.
+fn within_one_mismatch(seq: &str, barcode: &str) -> bool {
+    seq.chars()
+        .zip(barcode.chars())
+        .filter(|(a, b)| a != b)
+        .count()
+        <= 1
+}
+
+/// Return the reverse complement of `seq`.
+pub fn revcomp(seq: &str) -> String {
+    seq.chars()
+        .rev()
+        .map(|c| match c {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            'T' => 'A',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn within_one_mismatch_cases() {
+        assert!(within_one_mismatch("ACGTACGT", "ACGTACGT"));
+        assert!(within_one_mismatch("ACGTACGT", "ACGTACGA"));
+        assert!(!within_one_mismatch("ACGTACGT", "ACGTACCA"));
+        // Only the common prefix is compared with barcodes shorter than the index read.
+        assert!(within_one_mismatch("ACGTACGTNN", "ACGTAC"));
+        assert!(!within_one_mismatch("ACGTACGTNN", "TTGTAC"));
+    }
+
+    #[test]
+    fn matches_by_index_read_and_reverse_complement() {
+        let mut barcodes = ExpectedBarcodes::default();
+        barcodes.push(Some(" acgtacgt ".to_string()), Some("AACCGGTT".to_string()));
+        barcodes.push(Some("ACGTACGT".to_string()), None);
+        assert_eq!(barcodes.index1, vec!["ACGTACGT"]);
+        assert_eq!(barcodes.index2, vec!["AACCGGTT"]);
+
+        assert!(barcodes.matches(1, "ACGTACGT"));
+        assert!(barcodes.matches(1, "ACGTACGA"));
+        assert!(!barcodes.matches(1, "AACCGGTT"));
+        assert!(barcodes.matches(2, "AACCGGTT"));
+        assert!(barcodes.matches(2, &revcomp("AACCGGTT")));
+        assert!(!barcodes.matches(3, "ACGTACGT"));
+    }
+}
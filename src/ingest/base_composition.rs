@@ -0,0 +1,146 @@
+//! Sanity check of the base composition of the first template cycles.
+//!
+//! When clustering failed, the first cycles are dominated by a single base (e.g., `G` for the
+//! missing signal of two-channel chemistry) even though the index reads may look fine.  A few
+//! cycles are sampled lightly from the first tile of each lane for detecting this early.
+
+use rayon::prelude::*;
+use std::path::Path;
+
+use super::super::errors::*;
+use ingest::bcl_data::{find_file_stacks, load_base_calls};
+use ingest::bcl_meta::{truncated_cycles, FolderLayout, ReadDescription, RunInfo};
+
+/// The number of template cycles to sample.
+const SAMPLED_CYCLES: i32 = 3;
+/// The number of reads to sample per lane and cycle.
+const SAMPLED_READS: usize = 10_000;
+/// The bases in the order of `BaseComposition::fractions`.
+const BASES: [char; 4] = ['A', 'C', 'G', 'T'];
+
+/// The base composition of one cycle of one lane.
+#[derive(Debug, Clone, Serialize)]
+pub struct BaseComposition {
+    /// The number of the lane.
+    pub lane: i32,
+    /// The number of the cycle.
+    pub cycle: i32,
+    /// The number of sampled reads.
+    pub sample_size: usize,
+    /// The fractions of `A`, `C`, `G`, and `T` among the called bases.
+    pub fractions: [f64; 4],
+    /// The fraction of N calls.
+    pub n_fraction: f64,
+}
+
+impl BaseComposition {
+    /// Compute the composition of the base calls `calls` of `cycle` in `lane`.
+    fn from_calls(lane: i32, cycle: i32, calls: &[char]) -> Self {
+        let mut counts = [0usize; 4];
+        let mut n_count = 0;
+        for call in calls {
+            match BASES.iter().position(|base| base == call) {
+                Some(i) => counts[i] += 1,
+                None => n_count += 1,
+            }
+        }
+        let called = calls.len() - n_count;
+        let mut fractions = [0.0; 4];
+        if called > 0 {
+            for (fraction, count) in fractions.iter_mut().zip(counts.iter()) {
+                *fraction = *count as f64 / called as f64;
+            }
+        }
+        Self {
+            lane,
+            cycle,
+            sample_size: calls.len(),
+            fractions,
+            n_fraction: n_count as f64 / calls.len().max(1) as f64,
+        }
+    }
+
+    /// Return the most frequent base and its fraction among the called bases.
+    pub fn dominant_base(&self) -> (char, f64) {
+        BASES
+            .iter()
+            .cloned()
+            .zip(self.fractions.iter().cloned())
+            .fold(('N', 0.0), |best, (base, fraction)| {
+                if fraction > best.1 {
+                    (base, fraction)
+                } else {
+                    best
+                }
+            })
+    }
+}
+
+/// Return the first template read in `run_info` and the number of its first cycle.
+fn first_template_read(run_info: &RunInfo) -> Option<(&ReadDescription, i32)> {
+    let mut cycle = 1;
+    for desc in &run_info.reads {
+        if !desc.is_index {
+            return Some((desc, cycle));
+        }
+        cycle += desc.num_cycles;
+    }
+    None
+}
+
+/// Sample the base composition of the first template cycles of each lane of the run folder at
+/// `path`.
+///
+/// Returns an empty result for runs without base calls or template reads, and for run folders
+/// whose first template cycles have been removed.
+pub fn sample_base_composition(
+    logger: &slog::Logger,
+    path: &Path,
+    folder_layout: FolderLayout,
+    run_info: &RunInfo,
+) -> Result<Vec<BaseComposition>> {
+    let (desc, start_cycle) = match first_template_read(run_info) {
+        Some(read) => read,
+        None => return Ok(Vec::new()),
+    };
+    let num_cycles = SAMPLED_CYCLES.min(desc.num_cycles);
+    let missing = truncated_cycles(path, run_info).unwrap_or_default();
+    if !path
+        .join("Data")
+        .join("Intensities")
+        .join("BaseCalls")
+        .exists()
+        || (start_cycle..(start_cycle + num_cycles)).any(|cycle| missing.contains(&cycle))
+    {
+        return Ok(Vec::new());
+    }
+
+    let desc = ReadDescription {
+        number: desc.number,
+        num_cycles,
+        is_index: false,
+        is_reverse_complement: desc.is_reverse_complement,
+    };
+    let stacks = find_file_stacks(logger, folder_layout, &desc, path, start_cycle)
+        .chain_err(|| "Problem building paths to files")?;
+    let result = stacks
+        .par_iter()
+        .filter_map(|lane_stacks| lane_stacks.first())
+        .map(|stack| {
+            stack
+                .paths
+                .iter()
+                .enumerate()
+                .map(|(i, path)| {
+                    let (calls, _) = load_base_calls(logger, path, SAMPLED_READS)?;
+                    Ok(BaseComposition::from_calls(
+                        stack.lane_no,
+                        start_cycle + i as i32,
+                        &calls,
+                    ))
+                })
+                .collect::<Result<Vec<BaseComposition>>>()
+        })
+        .collect::<Result<Vec<Vec<BaseComposition>>>>()?;
+    Ok(result.into_iter().flatten().collect())
+}
@@ -1,21 +1,30 @@
 //! Code for analyzing raw BCL data.
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use chaos;
 use flate2::read::{GzDecoder, MultiGzDecoder};
 use glob::glob;
+use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rand_xorshift;
 use rayon::prelude::*;
 use regex::Regex;
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::SeekFrom;
-use std::path::Path;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use super::super::errors::*;
+use ingest::api::HistogramSampling;
 use ingest::bcl_meta::*;
+use ingest::fastq::*;
+use ingest::folder_size::format_bytes;
+use ingest::histogram::Histogram;
+use ingest::memory::{self, MemoryBudget, SeqCounter};
+use ingest::throttle;
 use settings::Settings;
 
 /// A list of BCL files defining a stack of base calls for a tile.
@@ -25,91 +34,336 @@ pub struct TileBclStack {
     pub lane_no: i32,
     /// The paths to the BCL files.
     pub paths: Vec<String>,
+    /// The number of the tile, if known, e.g., `1101` for surface 1, swath 1, tile 1.
+    pub tile_no: Option<u32>,
 }
 
 /// For a given index read, a histogram of counts (probably cut to top 1% or so).
 #[derive(Debug)]
 pub struct IndexCounts {
-    /// The index of the index.
+    /// The number of the index read, starting at 1.
     pub index_no: i32,
     /// The index of the lane.
     pub lane_no: i32,
     /// The number of reads read.
     pub sample_size: usize,
+    /// The fraction of N calls in the bases read.
+    pub n_fraction: f64,
     /// The filtered histogram of read frequencies.
-    pub hist: HashMap<String, usize>,
+    pub hist: Histogram,
+    /// I/O statistics from reading base call files, `None` for FASTQ files and merged lanes.
+    pub io: Option<IoStats>,
+    /// The sampling configuration the counts were produced with.
+    pub sampling: HistogramSampling,
 }
 
-/// Load compressed BCL file.
-fn load_bcl_gz(logger: &slog::Logger, path: &str, settings: &Settings) -> Result<Vec<u8>> {
-    // Open file
-    debug!(logger, "Processing compressed BCL file {}...", &path);
-    let file = File::open(&path).chain_err(|| "Problem opening gzip file")?;
-    let mut gz_decoder = MultiGzDecoder::new(file);
+/// I/O statistics from sampling one lane and index read from base call files.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct IoStats {
+    /// The number of bytes read from the base call files.
+    pub bytes_read: u64,
+    /// Time spent reading and decompressing base call files, summed over all threads.
+    pub decompress_seconds: f64,
+    /// Wall-clock time for sampling the lane.
+    pub seconds: f64,
+    /// Peak resident set size of the process after sampling the lane, in bytes, if known.
+    pub peak_rss_bytes: Option<u64>,
+}
+
+/// Reader wrapper counting the bytes read, for `IoStats`, and keeping the rate limit of
+/// `throttle`.
+struct CountingReader<R> {
+    /// The wrapped reader.
+    inner: R,
+    /// The number of bytes read so far.
+    count: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// Construct wrapping `inner`.
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        throttle::consume(n);
+        Ok(n)
+    }
+}
+
+impl IndexCounts {
+    /// Build from the index sequences counted in `counter` for the given lane and index read.
+    ///
+    /// Sequences seen in a fraction of at most `min_index_fraction` of the reads are not
+    /// included in the histogram.
+    pub fn from_counter(
+        index_no: i32,
+        lane_no: i32,
+        counter: SeqCounter,
+        min_index_fraction: f64,
+    ) -> Self {
+        let n_fraction = if counter.num_bases > 0 {
+            counter.num_n as f64 / counter.num_bases as f64
+        } else {
+            0.0
+        };
+        let num_seqs = counter.num_seqs;
+        let sketch_width = counter.sketch_width();
+
+        // Filter to top 1%.  The counts are one more than the occurrences, as they have always
+        // been posted this way.
+        let filtered_hist = Histogram::from_counts(
+            counter
+                .into_counts()
+                .into_iter()
+                .map(|(seq, count)| (seq, count + 1))
+                .filter(|(_, count)| *count as f64 > (num_seqs as f64) * min_index_fraction),
+        );
+
+        IndexCounts {
+            index_no,
+            lane_no,
+            sample_size: num_seqs,
+            n_fraction,
+            hist: filtered_hist,
+            io: None,
+            sampling: HistogramSampling {
+                sketch_width,
+                ..HistogramSampling::default()
+            },
+        }
+    }
+
+    /// Merge the counts of all lanes for the same index read into counts for lane 0.
+    ///
+    /// As the merge is based on the filtered per-lane histograms, sequences only pass the
+    /// `min_index_fraction` filter if they are seen often enough in all lanes combined and
+    /// counts of lanes where they were filtered out are missing.
+    pub fn merge_lanes(index_no: i32, counts: &[IndexCounts], min_index_fraction: f64) -> Self {
+        let sample_size: usize = counts.iter().map(|c| c.sample_size).sum();
+        let n_fraction = if sample_size > 0 {
+            counts
+                .iter()
+                .map(|c| c.n_fraction * c.sample_size as f64)
+                .sum::<f64>()
+                / sample_size as f64
+        } else {
+            0.0
+        };
+
+        let mut hist: HashMap<String, usize> = HashMap::new();
+        for (seq, count) in counts.iter().flat_map(|c| c.hist.0.iter()) {
+            *hist.entry(seq.clone()).or_default() += count;
+        }
+        let filtered_hist = Histogram::from_counts(
+            hist.into_iter()
+                .filter(|(_, count)| *count as f64 > (sample_size as f64) * min_index_fraction),
+        );
+        let sampling = HistogramSampling {
+            tiles_sampled: counts.iter().map(|c| c.sampling.tiles_sampled).sum(),
+            tiles: counts
+                .iter()
+                .flat_map(|c| c.sampling.tiles.iter().cloned())
+                .collect(),
+            sketch_width: counts.iter().filter_map(|c| c.sampling.sketch_width).max(),
+            ..counts
+                .first()
+                .map(|c| c.sampling.clone())
+                .unwrap_or_default()
+        };
 
+        IndexCounts {
+            index_no,
+            lane_no: 0,
+            sample_size,
+            n_fraction,
+            hist: filtered_hist,
+            io: None,
+            sampling,
+        }
+    }
+}
+
+/// Magic bytes at the start of gzip (and thus also BGZF) files.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Suffixes of BCL files, in the order that they are tried in by `bcl_path`.
+const BCL_SUFFIXES: &[&str] = &[".bcl", ".bcl.gz", ".bcl.bgzf"];
+
+/// Return path to the BCL file with the given `stem` (e.g., `s_1_1101`) in `dir`.
+///
+/// The first existing file with any suffix in `BCL_SUFFIXES` is used.  If no such file
+/// exists, the path with the first suffix is returned.
+fn bcl_path(dir: &Path, stem: &str) -> String {
+    let candidates = BCL_SUFFIXES
+        .iter()
+        .map(|suffix| dir.join(format!("{}{}", stem, suffix)))
+        .collect::<Vec<_>>();
+    candidates
+        .iter()
+        .find(|path| path.exists())
+        .unwrap_or(&candidates[0])
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Return whether a BCL file with the given `stem` exists in `dir`, with any suffix in
+/// `BCL_SUFFIXES`.
+pub fn bcl_file_exists(dir: &Path, stem: &str) -> bool {
+    BCL_SUFFIXES
+        .iter()
+        .any(|suffix| dir.join(format!("{}{}", stem, suffix)).exists())
+}
+
+/// Load BCL file, detecting gzip/BGZF compression from the magic bytes of the file.
+///
+/// At most `max_reads` reads are loaded, all if `max_reads` is 0.  Returns the reads and the
+/// number of bytes read from the file.
+fn load_bcl(logger: &slog::Logger, path: &str, max_reads: usize) -> Result<(Vec<u8>, u64)> {
+    // Open file and detect compression.
+    let mut file = chaos::open(path).chain_err(|| "Problem opening BCL file")?;
+    let mut magic = [0u8; 2];
+    let is_gzip = file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    file.seek(SeekFrom::Start(0))
+        .chain_err(|| "Problem seeking in BCL file")?;
+    let file = CountingReader::new(file);
+    if is_gzip {
+        debug!(logger, "Processing compressed BCL file {}...", &path);
+        let mut reader = MultiGzDecoder::new(file);
+        let buf = read_bcl_payload(&mut reader, max_reads)?;
+        Ok((buf, reader.get_ref().count))
+    } else {
+        debug!(logger, "Processing uncompressed BCL file {}...", &path);
+        let mut reader = file;
+        let buf = read_bcl_payload(&mut reader, max_reads)?;
+        Ok((buf, reader.count))
+    }
+}
+
+/// Read the byte count and at most `max_reads` bytes with bases and quality values from the
+/// (decompressed) BCL file `reader`, all if `max_reads` is 0.
+fn read_bcl_payload<R: Read>(reader: &mut R, max_reads: usize) -> Result<Vec<u8>> {
     // Read number of bytes in file.
-    let num_bytes = gz_decoder
+    let num_bytes = reader
         .read_u32::<LittleEndian>()
         .chain_err(|| "Problem reading byte count")? as usize;
 
     // Read array with bases and quality values.
-    let num_bytes = if settings.ingest.sample_reads_per_tile > 0 {
-        cmp::min(num_bytes, settings.ingest.sample_reads_per_tile as usize)
+    let num_bytes = if max_reads > 0 {
+        cmp::min(num_bytes, max_reads)
     } else {
         num_bytes
     };
     let mut buf = vec![0u8; num_bytes];
-    gz_decoder
+    reader
         .read_exact(&mut buf)
         .chain_err(|| "Problem reading payload")?;
 
     Ok(buf)
 }
 
-/// Load compressed BCL file.
-fn load_bcl(logger: &slog::Logger, path: &str, settings: &Settings) -> Result<Vec<u8>> {
-    // Open file
-    debug!(logger, "Processing uncompressed BCL file {}...", &path);
-    let mut file = File::open(&path).chain_err(|| "Problem opening BCL file")?;
+/// Return the path to the `*.bci` tile index in the NextSeq 500/550 lane directory `lane_dir`.
+fn bci_path(lane_dir: &Path) -> Option<PathBuf> {
+    let pattern = lane_dir.join("*.bci");
+    glob(pattern.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .next()
+}
 
-    // Read number of bytes in file.
-    let num_bytes = file
+/// Load the tile numbers and cluster counts from the `*.bci` tile index at `path`.
+///
+/// The tiles are given in the order of their reads in the lane's BCL files.
+fn load_bci(path: &Path) -> Result<Vec<(u32, u32)>> {
+    let mut buf = Vec::new();
+    chaos::open(path)
+        .and_then(|mut file| file.read_to_end(&mut buf))
+        .chain_err(|| format!("Problem reading tile index {:?}", path))?;
+    Ok(buf
+        .chunks_exact(8)
+        .map(|mut chunk| {
+            // Reading from a slice of 8 bytes cannot fail.
+            let tile_no = chunk.read_u32::<LittleEndian>().unwrap();
+            let num_clusters = chunk.read_u32::<LittleEndian>().unwrap();
+            (tile_no, num_clusters)
+        })
+        .collect())
+}
+
+/// Load the reads of tile `tile_no` from the lane-wise BGZF-compressed BCL file of a NextSeq
+/// 500/550 at `path`, with the tiles given by the tile index `bci`.
+///
+/// The reads of the tiles before `tile_no` are decompressed and skipped.  At most `max_reads`
+/// reads are loaded, all of the tile if `max_reads` is 0.  Returns the reads and the number of
+/// bytes read from the file.
+fn load_bgzf_tile(
+    logger: &slog::Logger,
+    path: &str,
+    bci: &[(u32, u32)],
+    tile_no: u32,
+    max_reads: usize,
+) -> Result<(Vec<u8>, u64)> {
+    let pos = bci
+        .iter()
+        .position(|(no, _)| *no == tile_no)
+        .chain_err(|| format!("Tile {} not found in tile index", tile_no))?;
+    let skip: u64 = bci[..pos].iter().map(|(_, n)| u64::from(*n)).sum();
+    let num_clusters = bci[pos].1 as usize;
+
+    debug!(
+        logger,
+        "Processing tile {} of BGZF-compressed BCL file {}...", tile_no, &path
+    );
+    let file = chaos::open(path).chain_err(|| "Problem opening BCL file")?;
+    let mut reader = MultiGzDecoder::new(CountingReader::new(file));
+    reader
         .read_u32::<LittleEndian>()
-        .chain_err(|| "Problem reading byte count")? as usize;
+        .chain_err(|| "Problem reading cluster count")?;
+    let skipped = io::copy(&mut (&mut reader).take(skip), &mut io::sink())
+        .chain_err(|| "Problem skipping reads of previous tiles")?;
+    if skipped < skip {
+        bail!("BCL file has fewer reads than given by the tile index");
+    }
 
-    // Read array with bases and quality values.
-    let num_bytes = if settings.ingest.sample_reads_per_tile > 0 {
-        cmp::min(num_bytes, settings.ingest.sample_reads_per_tile as usize)
+    let num_bytes = if max_reads > 0 {
+        cmp::min(num_clusters, max_reads)
     } else {
-        num_bytes
+        num_clusters
     };
     let mut buf = vec![0u8; num_bytes];
-    file.read_exact(&mut buf)
+    reader
+        .read_exact(&mut buf)
         .chain_err(|| "Problem reading payload")?;
 
-    Ok(buf)
+    Ok((buf, reader.get_ref().count))
 }
 
 /// Information of q-value mapping.
-struct QValBinInfo {
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QValBinInfo {
     /// quality score bin
-    _from: u32,
+    pub from: u32,
     /// quality score
-    _to: u32,
+    pub to: u32,
 }
 
 /// Information of offsets in `CBCL` file.
 struct OffsetInfo {
     /// ID of tile
-    _tile_no: u32,
+    tile_no: u32,
     /// Number of clusters on tile
     num_clusters: u32,
     /// Uncompressed size of tile
     _uncompressed_size: u32,
     /// Compressed size of tile
     compressed_size: u32,
-    /// 1: non-PF clusters are excluded, 0: non-PF clusters are not excluded.
-    _non_pf_flag: bool,
+    /// Whether non-PF clusters are excluded (flag 1), as with RTA 4, or not (flag 0).
+    non_pf_excluded: bool,
 }
 
 /// Header from a `CBCL` file.
@@ -120,10 +374,10 @@ struct CbclHeader {
     header_size: u32,
     /// Number of bits per base call, digestiflow-cli only supports 2 here
     _bits_per_basecall: u8,
-    /// Number of bits per q score, digestiflow-cli only supports 6 here
-    _bits_per_qscore: u8,
+    /// Number of bits per q score, digestiflow-cli supports 2 (binned) and 6 (unbinned) here
+    bits_per_qscore: u8,
     /// Information of q-value bins
-    _q_val_bins: Vec<QValBinInfo>,
+    q_val_bins: Vec<QValBinInfo>,
     /// Offset information of the gzip files inside the CBCL file.
     offset_infos: Vec<OffsetInfo>,
 }
@@ -131,7 +385,7 @@ struct CbclHeader {
 /// Read CBCL header
 fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
     let mut file =
-        File::open(&path).chain_err(|| format!("Problem opening CBCL file {}", &path))?;
+        chaos::open(path).chain_err(|| format!("Problem opening CBCL file {}", &path))?;
 
     let version = file
         .read_u16::<LittleEndian>()
@@ -151,9 +405,9 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
     let bits_per_qscore = file
         .read_u8()
         .chain_err(|| "Problem reading bits per qscore")?;
-    if bits_per_qscore != 2 {
+    if bits_per_qscore != 2 && bits_per_qscore != 6 {
         bail!(
-            "Can only work with bits_per_qscore = 2, but was {}",
+            "Can only work with bits_per_qscore = 2 or 6, but was {}",
             bits_per_qscore
         );
     }
@@ -169,10 +423,7 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
         let to = file
             .read_u32::<LittleEndian>()
             .chain_err(|| "Problem reading to.")?;
-        q_val_bins.push(QValBinInfo {
-            _from: from,
-            _to: to,
-        });
+        q_val_bins.push(QValBinInfo { from, to });
     }
 
     let num_offset_infos = file
@@ -193,13 +444,12 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
             .read_u32::<LittleEndian>()
             .chain_err(|| "Problem reading compressed block size")?;
         let non_pf_flag = file.read_u8().chain_err(|| "Problem reading non pf flag")?;
-        let non_pf_flag = non_pf_flag != 0;
         offset_infos.push(OffsetInfo {
-            _tile_no: tile_no,
+            tile_no,
             num_clusters,
             _uncompressed_size: uncompressed_size,
             compressed_size,
-            _non_pf_flag: non_pf_flag,
+            non_pf_excluded: non_pf_flag != 0,
         });
     }
 
@@ -207,115 +457,260 @@ fn load_cbcl_header(_logger: &slog::Logger, path: &str) -> Result<CbclHeader> {
         _version: version,
         header_size,
         _bits_per_basecall: bits_per_basecall,
-        _bits_per_qscore: bits_per_qscore,
-        _q_val_bins: q_val_bins,
+        bits_per_qscore,
+        q_val_bins,
         offset_infos,
     })
 }
 
-/// Read `settings.ingest.sample_reads_per_tile` number of reads from the given tile.
+/// Read at most `max_reads` reads from the given tile, all if `max_reads` is 0.
+///
+/// Returns the base calls and the number of bytes read from the file, including the header.
 fn load_from_cbcl(
     _logger: &slog::Logger,
     path: &str,
     header: &CbclHeader,
     tile_no: u32,
-    settings: &Settings,
-) -> Result<Vec<char>> {
+    max_reads: usize,
+) -> Result<(Vec<char>, u64)> {
     let table = vec!['A', 'C', 'G', 'T'];
     let tile_no = tile_no as usize;
     let mut result = Vec::new();
 
-    let mut file = File::open(&path).chain_err(|| format!("Problem opening CBCL file {}", path))?;
+    let mut file = chaos::open(path).chain_err(|| format!("Problem opening CBCL file {}", path))?;
     let mut offset = header.header_size as usize;
     for i in 0..tile_no {
         offset += header.offset_infos[i].compressed_size as usize;
     }
     file.seek(SeekFrom::Start(offset as u64))
         .chain_err(|| "Could not jump in CBCL file")?;
-    let mut gz_decoder = GzDecoder::new(file);
-    let num_bytes = cmp::min(
-        header.offset_infos[tile_no].num_clusters,
-        settings.ingest.sample_reads_per_tile as u32,
-    );
-    for j in 0..((num_bytes + 1) / 2) {
-        let b: u8 = gz_decoder
-            .read_u8()
-            .chain_err(|| "Problem reading data byte")?;
-        result.push(table[(b & 3) as usize]);
-        if num_bytes > j * 2 {
-            result.push(table[((b >> 4) & 3) as usize]);
+    let mut gz_decoder = GzDecoder::new(CountingReader::new(file));
+    let num_clusters = header.offset_infos[tile_no].num_clusters;
+    let num_calls = if max_reads > 0 {
+        cmp::min(num_clusters, max_reads as u32)
+    } else {
+        num_clusters
+    } as usize;
+    // Each base call has 2 bits for the base, followed by the bits of the quality score.  With
+    // binned (2 bit) quality scores, each byte holds two base calls, starting with the lower
+    // half; with unbinned (6 bit) quality scores, each byte holds one base call.  A quality
+    // score of 0 indicates a no-call.
+    let decode = |bits: u8| {
+        if bits >> 2 == 0 {
+            'N'
+        } else {
+            table[(bits & 3) as usize]
+        }
+    };
+    let calls_per_byte = if header.bits_per_qscore == 2 { 2 } else { 1 };
+    let mut buf = vec![0u8; num_calls.div_ceil(calls_per_byte)];
+    gz_decoder
+        .read_exact(&mut buf)
+        .chain_err(|| "Problem reading data bytes")?;
+    for b in buf {
+        if calls_per_byte == 2 {
+            result.push(decode(b & 15));
+            result.push(decode(b >> 4));
+        } else {
+            result.push(decode(b));
         }
     }
+    result.truncate(num_calls);
 
-    Ok(result)
+    let bytes_read = u64::from(header.header_size) + gz_decoder.get_ref().count;
+    Ok((result, bytes_read))
 }
 
-/// Analyze a single stack.
+/// Read at most `max_reads` base calls from the base call file `path`, all if `max_reads` is 0.
+///
+/// The `path` is a BCL file or, for CBCL and lane-wise `.bcl.bgzf` files, the file followed by
+/// `!` and the tile number as built by `find_file_stacks()`.  Returns the base calls and the
+/// number of bytes read from the file.
+pub fn load_base_calls(
+    logger: &slog::Logger,
+    path: &str,
+    max_reads: usize,
+) -> Result<(Vec<char>, u64)> {
+    // Regular expression for detecting CBL file
+    let cbcl_re =
+        Regex::new(r"^(.*\.cbcl)!(\d+)$").chain_err(|| "Problem constructing Regex object")?;
+    // Regular expression for detecting tile of lane-wise BGZF file (NextSeq 500/550)
+    let bgzf_re =
+        Regex::new(r"^(.*\.bcl\.bgzf)!(\d+)$").chain_err(|| "Problem constructing Regex object")?;
+
+    if let Some(captures) = cbcl_re.captures(path) {
+        let cbcl_header =
+            load_cbcl_header(logger, &captures[1]).chain_err(|| "Loading CBL header failed")?;
+        return load_from_cbcl(
+            logger,
+            &captures[1],
+            &cbcl_header,
+            captures[2].parse::<u32>().unwrap(),
+            max_reads,
+        )
+        .chain_err(|| "Problem loading CBCL tile");
+    }
+
+    let (buf, bytes_read) = match bgzf_re.captures(path) {
+        Some(captures) => {
+            let lane_dir = Path::new(&captures[1]).parent().unwrap();
+            let bci = bci_path(lane_dir)
+                .chain_err(|| "Tile index (.bci) file not found")
+                .and_then(|path| load_bci(&path))?;
+            load_bgzf_tile(
+                logger,
+                &captures[1],
+                &bci,
+                captures[2].parse::<u32>().unwrap(),
+                max_reads,
+            )
+            .chain_err(|| "Problem loading BCL tile")?
+        }
+        None => load_bcl(logger, path, max_reads).chain_err(|| "Problem loading BCL file.")?,
+    };
+
+    // Build bases for each spot, use no-call if all bits are unset.
+    let table = ['A', 'C', 'G', 'T'];
+    let mut chars = Vec::new();
+    for i in 0..buf.len() {
+        if buf[i] == 0 {
+            chars.push('N');
+        } else {
+            chars.push(table[(buf[i] & 3) as usize]);
+        }
+    }
+    debug!(logger, "Done processing {}.", &path);
+
+    Ok((chars, bytes_read))
+}
+
+/// Analyze the stacks with the numbers in `stack_nos` of each lane, given in the same order as
+/// `lane_stacks`.
+///
+/// The number of reads to sample from each stack of a lane is taken from `read_limits`, see
+/// `tile_read_limit()`.
 pub fn analyze_stacks(
     logger: &slog::Logger,
-    lane_stacks: &Vec<Vec<TileBclStack>>,
-    stack_no: usize,
+    lane_stacks: &[Vec<TileBclStack>],
+    stack_nos: &[Vec<usize>],
     index_no: i32,
+    read_limits: &HashMap<i32, usize>,
     settings: &Settings,
 ) -> Result<Vec<IndexCounts>> {
-    // Regular expression for detecting CBL file
-    let cbcl_re =
-        Regex::new(r"^(.*\.cbcl)!(\d+)$").chain_err(|| "Problem constructing Regex object")?;
+    // Limit the lanes sampled at the same time and the reads per tile to the memory budget.
+    let budget = MemoryBudget::from_settings(settings)?;
+    let num_cycles = lane_stacks
+        .iter()
+        .filter_map(|stacks| stacks.first())
+        .map(|stack| stack.paths.len())
+        .max()
+        .unwrap_or(0);
+    let max_reads = if read_limits.values().any(|limit| *limit == 0) {
+        0
+    } else {
+        read_limits.values().cloned().max().unwrap_or(0)
+    };
+    let parallel = budget.parallel_lanes(lane_stacks.len(), num_cycles, max_reads);
+    let tile_reads = budget.max_tile_reads(num_cycles, parallel);
+    let counter_bytes = budget.counter_bytes(parallel);
+    if let Some(limit) = budget.limit() {
+        info!(
+            logger,
+            "Sampling {} of {} lanes at a time with memory limit {}",
+            parallel,
+            lane_stacks.len(),
+            format_bytes(limit)
+        );
+    }
 
-    lane_stacks
-        .par_iter()
-        .map(|ref stacks_for_lane| {
-            let stack = &stacks_for_lane[stack_no];
-            // Read in the bases from the bcl files.
-            let bases = stack
-                .paths
+    let lanes = lane_stacks
+        .iter()
+        .zip(stack_nos.iter())
+        .collect::<Vec<(&Vec<TileBclStack>, &Vec<usize>)>>();
+    let mut result = Vec::new();
+    for chunk in lanes.chunks(parallel) {
+        result.extend(
+            chunk
                 .par_iter()
-                .map(|ref path| {
-                    let chars = if cbcl_re.is_match(&path) {
-                        // Because we know that the RE matches, the following two unwraps cannot
-                        // fail.
-                        let captures = cbcl_re.captures(&path).unwrap();
-                        let cbcl_header = load_cbcl_header(logger, &captures[1])
-                            .chain_err(|| "Loading CBL header failed")?;
-                        load_from_cbcl(
+                .map(|(stacks_for_lane, stack_nos)| {
+                    let lane_no = stacks_for_lane[stack_nos[0]].lane_no;
+                    let mut max_reads = read_limits.get(&lane_no).cloned().unwrap_or(0);
+                    if tile_reads > 0 && (max_reads == 0 || max_reads > tile_reads) {
+                        warn!(
                             logger,
-                            &captures[1],
-                            &cbcl_header,
-                            captures[2].parse::<u32>().unwrap(),
-                            settings,
-                        )
-                        .chain_err(|| "Problem loading CBCL tile")?
-                    } else {
-                        let buf = if path.ends_with(".gz") || path.ends_with(".bgzf") {
-                            load_bcl_gz(logger, &path, settings)
-                        } else {
-                            load_bcl(logger, &path, settings)
-                        }
-                        .chain_err(|| "Problem loading BCL file.")?;
-
-                        // Build bases for each spot, use no-call if all bits are unset.
-                        let table = vec!['A', 'C', 'G', 'T'];
-                        let mut chars = Vec::new();
-                        for i in 0..buf.len() {
-                            if buf[i] == 0 {
-                                chars.push('N');
-                            } else {
-                                chars.push(table[(buf[i] & 3) as usize]);
-                            }
-                        }
-                        debug!(logger, "Done processing {}.", &path);
-
-                        chars
-                    };
-
-                    Ok(chars)
+                            "Sampling at most {} reads per tile of lane {} to stay within the \
+                             memory limit",
+                            tile_reads,
+                            lane_no
+                        );
+                        max_reads = tile_reads;
+                    }
+                    analyze_lane(
+                        logger,
+                        stacks_for_lane,
+                        stack_nos,
+                        index_no,
+                        max_reads,
+                        counter_bytes,
+                        settings,
+                    )
                 })
-                .collect::<Result<Vec<_>>>()?;
+                .collect::<Result<Vec<IndexCounts>>>()?,
+        );
+    }
+    Ok(result)
+}
+
+/// Number of reads whose sequences are built at once before counting them.
+const CHUNK_READS: usize = 1 << 16;
+
+/// Analyze the stacks with the numbers in `stack_nos` of one lane for `analyze_stacks()`.
+///
+/// At most `max_reads` reads are sampled per tile (all if 0) and the sequences are counted in
+/// `counter_bytes` (unlimited if `None`).
+fn analyze_lane(
+    logger: &slog::Logger,
+    stacks_for_lane: &[TileBclStack],
+    stack_nos: &[usize],
+    index_no: i32,
+    max_reads: usize,
+    counter_bytes: Option<u64>,
+    settings: &Settings,
+) -> Result<IndexCounts> {
+    let start = Instant::now();
+    let lane_no = stacks_for_lane[stack_nos[0]].lane_no;
+    let mut counter = SeqCounter::new(counter_bytes, settings.ingest.min_index_fraction);
+    let mut bytes_read = 0;
+    let mut decompress_time = Duration::default();
+    for stack_no in stack_nos.iter() {
+        let stack = &stacks_for_lane[*stack_no];
+        // Read in the bases from the bcl files.
+        let bases = stack
+            .paths
+            .par_iter()
+            .map(|path| {
+                let file_start = Instant::now();
+                let (chars, bytes_read) = load_base_calls(logger, path, max_reads)?;
+                Ok((chars, bytes_read, file_start.elapsed()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        bytes_read += bases.iter().map(|(_, bytes, _)| bytes).sum::<u64>();
+        decompress_time += bases
+            .iter()
+            .map(|(_, _, duration)| *duration)
+            .sum::<Duration>();
+        let bases = bases
+            .into_iter()
+            .map(|(chars, _, _)| chars)
+            .collect::<Vec<Vec<char>>>();
 
-            // Build read sequences.
-            debug!(logger, "Building read sequences.");
-            let num_seqs = bases[0].len();
-            let seqs = (0..num_seqs)
+        // Build read sequences and count them in chunks, such that only the sequences of one
+        // chunk are held at a time.
+        debug!(logger, "Building read sequences.");
+        let num_seqs = bases[0].len();
+        for chunk_start in (0..num_seqs).step_by(CHUNK_READS) {
+            let chunk_end = cmp::min(num_seqs, chunk_start + CHUNK_READS);
+            let seqs = (chunk_start..chunk_end)
                 .into_par_iter()
                 .map(|i| {
                     let mut seq = String::new();
@@ -325,40 +720,45 @@ pub fn analyze_stacks(
                     seq
                 })
                 .collect::<Vec<String>>();
-            debug!(logger, "Done building read sequences.");
-
-            // TODO: parallelize counting?
-
-            // Build histogram.
-            let mut hist: HashMap<String, usize> = HashMap::new();
             for seq in &seqs {
-                *hist.entry(seq.clone()).or_insert(1) += 1;
+                counter.add(seq);
             }
+        }
+        debug!(logger, "Done building read sequences.");
+    }
 
-            // Filter to top 1%.
-            let mut filtered_hist = HashMap::new();
-            for (seq, count) in hist {
-                if count as f64 > (num_seqs as f64) * settings.ingest.min_index_fraction {
-                    filtered_hist.insert(seq.clone(), count);
-                }
-            }
-            debug!(logger, "=> filtered hist {:?}", &filtered_hist);
+    let mut counts = IndexCounts::from_counter(
+        index_no,
+        lane_no,
+        counter,
+        settings.ingest.min_index_fraction,
+    );
+    if let Some(width) = counts.sampling.sketch_width {
+        warn!(
+            logger,
+            "Estimated the index counts of lane {} with a count-min sketch of width {} as the \
+             distinct sequences exceed the memory limit",
+            lane_no,
+            width
+        );
+    }
+    counts.io = Some(IoStats {
+        bytes_read,
+        decompress_seconds: decompress_time.as_secs_f64(),
+        seconds: start.elapsed().as_secs_f64(),
+        peak_rss_bytes: memory::peak_rss(),
+    });
+    debug!(logger, "=> filtered hist {:?}", &counts.hist);
 
-            Ok(IndexCounts {
-                index_no: index_no,
-                lane_no: stack.lane_no,
-                sample_size: num_seqs,
-                hist: filtered_hist,
-            })
-        })
-        .collect()
+    Ok(counts)
 }
 
 /// Build tile-wise lists of files describing the BCL files for the given tile and each cycle.
 ///
-/// Note that for CBCL files, we generate file names such as `"path/to/file.cbcl!${tile_no}"`.
+/// Note that for CBCL files, we generate file names such as `"path/to/file.cbcl!${tile_no}"`,
+/// and likewise for the lane-wise `.bcl.bgzf` files of NextSeq 500/550.
 pub fn find_file_stacks(
-    _logger: &slog::Logger,
+    logger: &slog::Logger,
     folder_layout: FolderLayout,
     desc: &ReadDescription,
     path: &Path,
@@ -381,23 +781,67 @@ pub fn find_file_stacks(
             for (lane_no, ref lane_path) in lane_paths.iter().enumerate() {
                 let mut paths: Vec<String> = Vec::new();
                 for cycle in start_cycle..(start_cycle + desc.num_cycles) {
-                    paths.push(
-                        Path::new(lane_path)
-                            .join(format!("{:04}.bcl.bgzf", cycle))
-                            .to_str()
-                            .unwrap()
-                            .to_string(),
-                    );
+                    paths.push(bcl_path(Path::new(lane_path), &format!("{:04}", cycle)));
                 }
                 lane_stacks.push(vec![TileBclStack {
                     lane_no: lane_no as i32 + 1,
                     paths: paths,
+                    tile_no: None,
                 }]);
             }
 
             Ok(lane_stacks)
         }
-        FolderLayout::MiSeq => {
+        FolderLayout::NextSeq500 => {
+            let path = path
+                .join("Data")
+                .join("Intensities")
+                .join("BaseCalls")
+                .join("L???");
+            let lane_paths = glob(path.to_str().unwrap())
+                .expect("Failed to read glob pattern")
+                .map(|x| x.unwrap())
+                .collect::<Vec<PathBuf>>();
+
+            let mut tile_stacks = Vec::new();
+            for (lane_no, lane_path) in lane_paths.iter().enumerate() {
+                let paths = (start_cycle..(start_cycle + desc.num_cycles))
+                    .map(|cycle| bcl_path(lane_path, &format!("{:04}", cycle)))
+                    .collect::<Vec<String>>();
+                // One stack per tile from the tile index, the whole file without tile index.
+                let lane_stacks = match bci_path(lane_path) {
+                    Some(bci_path) => load_bci(&bci_path)?
+                        .iter()
+                        .map(|(tile_no, _)| TileBclStack {
+                            lane_no: lane_no as i32 + 1,
+                            paths: paths
+                                .iter()
+                                .map(|path| format!("{}!{}", path, tile_no))
+                                .collect(),
+                            tile_no: Some(*tile_no),
+                        })
+                        .collect(),
+                    None => {
+                        debug!(
+                            logger,
+                            "No tile index in {:?}, reading whole files", lane_path
+                        );
+                        vec![TileBclStack {
+                            lane_no: lane_no as i32 + 1,
+                            paths,
+                            tile_no: None,
+                        }]
+                    }
+                };
+                tile_stacks.push(lane_stacks);
+            }
+
+            Ok(tile_stacks)
+        }
+        FolderLayout::MiSeq
+        | FolderLayout::MiSeqDep
+        | FolderLayout::HiSeqX
+        | FolderLayout::HiSeq3000 => {
             let path = path
                 .join("Data")
                 .join("Intensities")
@@ -410,27 +854,35 @@ pub fn find_file_stacks(
 
             let mut tile_stacks = Vec::new();
             for (lane_no, ref lane_path) in lane_paths.iter().enumerate() {
-                let mut lane_stacks = Vec::new();
-                for suffix in &["", ".gz"] {
-                    let path = Path::new(lane_path)
-                        .join("C1.1")
-                        .join(format!("s_?_*.bcl{}", &suffix));
-                    for prototype in glob(path.to_str().unwrap()).unwrap() {
-                        let path = prototype.unwrap();
-                        let file_name = path.file_name().unwrap();
-                        let mut paths: Vec<String> = Vec::new();
-                        for cycle in start_cycle..(start_cycle + desc.num_cycles) {
-                            let path = Path::new(lane_path)
-                                .join(format!("C{}.1", cycle))
-                                .join(file_name);
-                            paths.push(path.to_str().unwrap().to_string());
-                        }
-                        lane_stacks.push(TileBclStack {
-                            lane_no: lane_no as i32 + 1,
-                            paths: paths,
-                        });
+                // Collect the tile file name stems (e.g., `s_1_1101`) from the first cycle of the
+                // read, the compression of each cycle's file is resolved separately.  Taking the
+                // read's own first cycle allows for sampling runs whose template cycles have been
+                // removed.
+                let mut stems = BTreeSet::new();
+                let path = Path::new(lane_path)
+                    .join(format!("C{}.1", start_cycle))
+                    .join("s_?_*.bcl*");
+                for prototype in glob(path.to_str().unwrap()).unwrap() {
+                    let path = prototype.unwrap();
+                    let file_name = path.file_name().unwrap().to_str().unwrap();
+                    if let Some(pos) = file_name.find(".bcl") {
+                        stems.insert(file_name[..pos].to_string());
                     }
                 }
+
+                let mut lane_stacks = Vec::new();
+                for stem in &stems {
+                    let paths = (start_cycle..(start_cycle + desc.num_cycles))
+                        .map(|cycle| {
+                            bcl_path(&Path::new(lane_path).join(format!("C{}.1", cycle)), stem)
+                        })
+                        .collect::<Vec<String>>();
+                    lane_stacks.push(TileBclStack {
+                        lane_no: lane_no as i32 + 1,
+                        paths,
+                        tile_no: stem.rsplit('_').next().and_then(|tile| tile.parse().ok()),
+                    });
+                }
                 tile_stacks.push(lane_stacks);
             }
 
@@ -451,7 +903,7 @@ pub fn find_file_stacks(
             for (lane_no, ref lane_path) in lane_paths.iter().enumerate() {
                 let mut lane_stacks = Vec::new();
                 let path = Path::new(lane_path)
-                    .join("C1.1")
+                    .join(format!("C{}.1", start_cycle))
                     .join(format!("L???_?.cbcl"));
                 for prototype in glob(path.to_str().unwrap()).unwrap() {
                     let path = prototype.unwrap();
@@ -472,6 +924,7 @@ pub fn find_file_stacks(
                     lane_stacks.push(TileBclStack {
                         lane_no: lane_no as i32 + 1,
                         paths: paths,
+                        tile_no: None,
                     });
                 }
                 tile_stacks.push(lane_stacks);
@@ -479,24 +932,385 @@ pub fn find_file_stacks(
 
             Ok(tile_stacks)
         }
-        _ => bail!(
-            "Don't know yet how to process folder layout {:?}",
+        FolderLayout::Aviti | FolderLayout::Mgi | FolderLayout::Nanopore => bail!(
+            "{:?} folders have no Illumina base call files",
             folder_layout
         ),
     }
 }
 
-/// Sample adapters for the given index read described in `desc` and return
-/// `IndexCounts` for each lane.
+/// Return whether the CBCL files of the first cycle in `lane_dir` only contain the clusters
+/// passing the filter, as written by RTA 4 without `*.filter` files.
+fn cbcl_excludes_non_pf(logger: &slog::Logger, lane_dir: &Path) -> bool {
+    let pattern = lane_dir.join(first_cycle_dir_name(lane_dir)).join("*.cbcl");
+    let headers = glob(pattern.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .map(|path| load_cbcl_header(logger, path.to_str().unwrap()))
+        .collect::<Result<Vec<CbclHeader>>>();
+    match headers {
+        Ok(headers) => {
+            !headers.is_empty()
+                && headers.iter().all(|header| {
+                    !header.offset_infos.is_empty()
+                        && header.offset_infos.iter().all(|info| info.non_pf_excluded)
+                })
+        }
+        Err(e) => {
+            debug!(logger, "Could not read CBCL headers: {:?}", &e);
+            false
+        }
+    }
+}
+
+/// Return the numbers of the lanes below the flow cell folder `path` without any `*.filter` file.
+///
+/// Lanes whose CBCL files exclude the clusters not passing the filter need no `*.filter` files
+/// and are not returned.
+pub fn lanes_without_filter_files(logger: &slog::Logger, path: &Path) -> Vec<i32> {
+    let path = path
+        .join("Data")
+        .join("Intensities")
+        .join("BaseCalls")
+        .join("L???");
+    glob(path.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|lane_path| match lane_path {
+            Ok(lane_path) => Some(lane_path).filter(|lane_path| {
+                let pattern = lane_path.join("*.filter");
+                glob(pattern.to_str().unwrap())
+                    .expect("Failed to read glob pattern")
+                    .next()
+                    .is_none()
+                    && !cbcl_excludes_non_pf(logger, lane_path)
+            }),
+            Err(e) => Some(e.path().to_path_buf()),
+        })
+        .filter_map(|lane_path| lane_no_from_path(&lane_path))
+        .collect()
+}
+
+/// Return the number of the lane from the name of its directory at `lane_path`, e.g., 1 for
+/// `L001`.
+fn lane_no_from_path(lane_path: &Path) -> Option<i32> {
+    lane_path
+        .file_name()?
+        .to_str()?
+        .strip_prefix('L')?
+        .parse::<i32>()
+        .ok()
+}
+
+/// Read the number of clusters from the header of a `*.filter` or `*.locs` file.
+///
+/// Both formats start with two 4 byte values (zero/version or version/float) followed by the
+/// number of clusters as 4 byte integer.
+fn header_cluster_count(path: &Path) -> Result<u64> {
+    let mut file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+    file.seek(SeekFrom::Start(8))
+        .chain_err(|| format!("Problem seeking in {:?}", path))?;
+    Ok(file
+        .read_u32::<LittleEndian>()
+        .chain_err(|| format!("Problem reading cluster count from {:?}", path))? as u64)
+}
+
+/// Return the sum of the results of `count` for all files matching `pattern`, `None` if there are
+/// no matching files.
+fn sum_cluster_counts<F>(pattern: &Path, count: F) -> Result<Option<u64>>
+where
+    F: Fn(&Path) -> Result<u64>,
+{
+    let mut result = None;
+    for path in glob(pattern.to_str().unwrap()).expect("Failed to read glob pattern") {
+        let path = path.chain_err(|| "Problem reading directory entry")?;
+        result = Some(result.unwrap_or(0) + count(&path)?);
+    }
+    Ok(result)
+}
+
+/// Return the total number of clusters for each lane of the flow cell folder at `path`.
+///
+/// The counts are taken from the `*.filter` files, the headers of the CBCL files of the first
+/// cycle, or the `*.locs` files, whichever are found first.  Lanes for which the number cannot
+/// be determined are missing from the result.
+pub fn lane_cluster_counts(logger: &slog::Logger, path: &Path) -> HashMap<i32, u64> {
+    let intensities = path.join("Data").join("Intensities");
+    let pattern = intensities.join("BaseCalls").join("L???");
+    let mut result = HashMap::new();
+    let lane_paths = glob(pattern.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|lane_path| lane_path.ok());
+    for lane_path in lane_paths {
+        let lane_no = match lane_no_from_path(&lane_path) {
+            Some(lane_no) => lane_no,
+            None => continue,
+        };
+        let lane_name = lane_path.file_name().unwrap().to_os_string();
+        let count = sum_cluster_counts(&lane_path.join("*.filter"), header_cluster_count)
+            .and_then(|count| match count {
+                None => sum_cluster_counts(
+                    &lane_path
+                        .join(first_cycle_dir_name(&lane_path))
+                        .join("*.cbcl"),
+                    |path| {
+                        let header = load_cbcl_header(logger, path.to_str().unwrap())?;
+                        Ok(header
+                            .offset_infos
+                            .iter()
+                            .map(|info| info.num_clusters as u64)
+                            .sum())
+                    },
+                ),
+                _ => Ok(count),
+            })
+            .and_then(|count| match count {
+                None => sum_cluster_counts(
+                    &intensities.join(&lane_name).join("*.locs"),
+                    header_cluster_count,
+                ),
+                _ => Ok(count),
+            });
+        match count {
+            Ok(Some(count)) => {
+                result.insert(lane_no, count);
+            }
+            Ok(None) => debug!(logger, "Could not find cluster count for lane {}", lane_no),
+            Err(e) => warn!(
+                logger,
+                "Problem determining cluster count for lane {}: {:?}", lane_no, e
+            ),
+        }
+    }
+    result
+}
+
+/// Replace the stacks of whole CBCL files (`path/to/file.cbcl!0`) in `stacks` by one stack per
+/// tile of the files, as given by the header of the first file of each stack.
+fn split_cbcl_tiles(
+    logger: &slog::Logger,
+    stacks: Vec<Vec<TileBclStack>>,
+) -> Result<Vec<Vec<TileBclStack>>> {
+    let mut result = Vec::new();
+    for lane_stacks in stacks {
+        let mut split = Vec::new();
+        for stack in lane_stacks {
+            let cbcl_path = match stack.paths.first() {
+                Some(path) if path.ends_with(".cbcl!0") => path.trim_end_matches("!0").to_string(),
+                _ => {
+                    split.push(stack);
+                    continue;
+                }
+            };
+            let header = load_cbcl_header(logger, &cbcl_path)?;
+            for (i, info) in header.offset_infos.iter().enumerate() {
+                split.push(TileBclStack {
+                    lane_no: stack.lane_no,
+                    paths: stack
+                        .paths
+                        .iter()
+                        .map(|path| format!("{}!{}", path.trim_end_matches("!0"), i))
+                        .collect(),
+                    tile_no: Some(info.tile_no),
+                });
+            }
+        }
+        result.push(split);
+    }
+    Ok(result)
+}
+
+/// Return the surface and swath of the tile `tile_no` from its leading digits, e.g., `(1, 2)`
+/// for tile `1201`.
+fn surface_swath(tile_no: u32) -> Option<(u32, u32)> {
+    let digits = tile_no.to_string();
+    if digits.len() < 4 {
+        return None;
+    }
+    let mut leading = digits.chars().filter_map(|c| c.to_digit(10));
+    Some((leading.next()?, leading.next()?))
+}
+
+/// Select the numbers of the stacks to sample from `stacks` of a lane: the fraction
+/// `tile_fraction` of them at random, but at least one of each surface and swath.
+fn select_tiles<R: Rng>(stacks: &[TileBclStack], tile_fraction: f64, rng: &mut R) -> Vec<usize> {
+    let mut order = (0..stacks.len()).collect::<Vec<usize>>();
+    order.shuffle(rng);
+    let count = cmp::max(1, (tile_fraction * stacks.len() as f64).ceil() as usize);
+
+    let mut seen = BTreeSet::new();
+    let mut result = order
+        .iter()
+        .filter(|stack_no| seen.insert(stacks[**stack_no].tile_no.and_then(surface_swath)))
+        .cloned()
+        .collect::<Vec<usize>>();
+    for stack_no in order {
+        if result.len() >= count {
+            break;
+        }
+        if !result.contains(&stack_no) {
+            result.push(stack_no);
+        }
+    }
+    result.sort_unstable();
+    result
+}
+
+/// The number of reads to sample from a lane, 0 for all reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadLimit {
+    /// The reads to sample from each sampled tile.
+    PerTile(usize),
+    /// The reads to sample from the lane, spread over the sampled tiles.
+    PerLane(usize),
+}
+
+/// Return the number of reads to sample from each lane in `stacks`.
+///
+/// This is `settings.ingest.sample_reads_per_tile` per tile unless
+/// `settings.ingest.sample_fraction` is set.  In this case, the given fraction of each lane's
+/// clusters in `cluster_counts` is sampled, falling back to `sample_reads_per_tile` per tile for
+/// lanes with unknown cluster count.
+fn lane_read_limits(
+    stacks: &[Vec<TileBclStack>],
+    cluster_counts: &HashMap<i32, u64>,
+    settings: &Settings,
+) -> HashMap<i32, ReadLimit> {
+    let per_tile = cmp::max(0, settings.ingest.sample_reads_per_tile) as usize;
+    stacks
+        .iter()
+        .filter_map(|lane_stacks| lane_stacks.first())
+        .map(|stack| {
+            let limit = match cluster_counts
+                .get(&stack.lane_no)
+                .filter(|_| settings.ingest.sample_fraction > 0.0)
+            {
+                Some(total) => ReadLimit::PerLane(cmp::max(
+                    1,
+                    (settings.ingest.sample_fraction * *total as f64).round() as usize,
+                )),
+                None => ReadLimit::PerTile(per_tile),
+            };
+            (stack.lane_no, limit)
+        })
+        .collect()
+}
+
+/// Return the maximal number of reads to sample from each of the `num_tiles` sampled tiles of
+/// a lane with the read limit `limit`, 0 for all reads.
+fn tile_read_limit(limit: ReadLimit, num_tiles: usize) -> usize {
+    match limit {
+        ReadLimit::PerTile(reads) => reads,
+        ReadLimit::PerLane(reads) => cmp::max(1, reads.div_ceil(cmp::max(1, num_tiles))),
+    }
+}
+
+/// Return the quality binning table from the first CBCL file of the run folder at `path`.
+///
+/// Returns `None` for layouts without CBCL files.  The table is the same for all CBCL files of
+/// a run as it is determined by the instrument configuration.
+pub fn quality_bins(logger: &slog::Logger, path: &Path) -> Option<Vec<QValBinInfo>> {
+    let pattern = path
+        .join("Data")
+        .join("Intensities")
+        .join("BaseCalls")
+        .join("L???")
+        .join("C*.1")
+        .join("*.cbcl");
+    let cbcl_path = glob(pattern.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .next()?;
+    match load_cbcl_header(logger, cbcl_path.to_str().unwrap()) {
+        Ok(header) => Some(header.q_val_bins),
+        Err(e) => {
+            debug!(
+                logger,
+                "Could not read quality bins from {:?}: {:?}", &cbcl_path, &e
+            );
+            None
+        }
+    }
+}
+
+/// Return the number of cycles to sample for the index read `index_no` described in `desc`.
+///
+/// This is the read's number of cycles, limited by the read's entry in
+/// `settings.ingest.max_index_cycles`, if any and positive.
+pub fn sampled_index_cycles(desc: &ReadDescription, index_no: i32, settings: &Settings) -> i32 {
+    match settings.ingest.max_index_cycles.get(index_no as usize - 1) {
+        Some(max_cycles) if *max_cycles > 0 => cmp::min(desc.num_cycles, *max_cycles),
+        _ => desc.num_cycles,
+    }
+}
+
+/// Sample adapters for the given index read, given as (index number, description, start cycle),
+/// and return `IndexCounts` for each lane.
+///
+/// Only the first `sampled_index_cycles()` cycles of the index read are sampled.  The numbers of
+/// clusters per lane in `cluster_counts` are read once by the caller with `lane_cluster_counts()`
+/// and shared between the index reads.
 pub fn sample_adapters(
     logger: &slog::Logger,
     path: &Path,
-    desc: &ReadDescription,
+    cluster_counts: &HashMap<i32, u64>,
+    index_read: (i32, &ReadDescription, i32),
     folder_layout: FolderLayout,
     settings: &Settings,
-    index_no: i32,
-    start_cycle: i32,
 ) -> Result<Vec<IndexCounts>> {
+    let (index_no, desc, start_cycle) = index_read;
+    let num_cycles = sampled_index_cycles(desc, index_no, settings);
+    if num_cycles < desc.num_cycles {
+        info!(
+            logger,
+            "Sampling only the first {} of {} cycles of index read {}",
+            num_cycles,
+            desc.num_cycles,
+            index_no
+        );
+    }
+    let sampling = HistogramSampling {
+        requested_reads: cmp::max(0, settings.ingest.sample_reads_per_tile) as usize,
+        requested_fraction: Some(settings.ingest.sample_fraction).filter(|f| *f > 0.0),
+        tiles_sampled: 0,
+        tiles: Vec::new(),
+        cycles_sampled: num_cycles,
+        cycles_skipped: desc.num_cycles - num_cycles,
+        min_index_fraction: settings.ingest.min_index_fraction,
+        seed: settings.seed,
+        sketch_width: None,
+    };
+    let desc = &ReadDescription {
+        number: desc.number,
+        num_cycles,
+        is_index: desc.is_index,
+        is_reverse_complement: desc.is_reverse_complement,
+    };
+
+    // Fall back to the FASTQ files for runs without base calls, if configured.
+    let base_calls = path.join("Data").join("Intensities").join("BaseCalls");
+    if !base_calls.exists() {
+        if !settings.ingest.sample_fastq {
+            bail!(
+                "No base calls found in {:?}, use --sample-fastq to sample FASTQ files",
+                path
+            );
+        }
+        let files = find_fastq_files(path);
+        if files.is_empty() {
+            bail!("Neither base calls nor FASTQ files found in {:?}", path);
+        }
+        info!(logger, "Sampling {} FASTQ files...", files.len());
+        let mut counts = sample_fastq(logger, &files, index_no, num_cycles as usize, settings)
+            .chain_err(|| "Problem sampling FASTQ files")?;
+        for count in &mut counts {
+            count.sampling = HistogramSampling {
+                sketch_width: count.sampling.sketch_width,
+                ..sampling.clone()
+            };
+        }
+        return Ok(counts);
+    }
+
     // Depending on the directory layout, build stacks of files to get adapters from.
     // Through this abstraction, we can treat the different layouts the same in
     // extracting the adapters.
@@ -504,12 +1318,180 @@ pub fn sample_adapters(
     let stacks = find_file_stacks(logger, folder_layout, desc, path, start_cycle)
         .chain_err(|| "Problem building paths to files")?;
 
+    let stacks = if settings.ingest.tile_fraction > 0.0 {
+        split_cbcl_tiles(logger, stacks).chain_err(|| "Problem reading CBCL tiles")?
+    } else {
+        stacks
+    };
+    let stacks = stacks
+        .into_iter()
+        .filter(|lane_stacks| {
+            if lane_stacks.is_empty() {
+                warn!(logger, "Found a lane without tiles, skipping it");
+            }
+            !lane_stacks.is_empty()
+        })
+        .collect::<Vec<Vec<TileBclStack>>>();
+    if stacks.is_empty() {
+        bail!("No tiles found to sample in {:?}", path);
+    }
+
     let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(settings.seed);
-    let stack_no = rng.gen_range(0, stacks[0].len());
+    let lane_limits = lane_read_limits(&stacks, cluster_counts, settings);
+    let stack_nos = if settings.ingest.tile_fraction > 0.0 {
+        let stack_nos = stacks
+            .iter()
+            .map(|lane_stacks| select_tiles(lane_stacks, settings.ingest.tile_fraction, &mut rng))
+            .collect::<Vec<Vec<usize>>>();
+        for (lane_stacks, stack_nos) in stacks.iter().zip(stack_nos.iter()) {
+            info!(
+                logger,
+                "Sampling {} of {} tiles of lane {}",
+                stack_nos.len(),
+                lane_stacks.len(),
+                lane_stacks[0].lane_no
+            );
+        }
+        stack_nos
+    } else {
+        // Lanes can have different numbers of tiles, so draw the tile for each lane.
+        stacks
+            .iter()
+            .map(|lane_stacks| vec![rng.gen_range(0, lane_stacks.len())])
+            .collect::<Vec<Vec<usize>>>()
+    };
+    // A fraction of the lane's clusters is spread over the sampled tiles.
+    let read_limits = stacks
+        .iter()
+        .zip(stack_nos.iter())
+        .filter_map(|(lane_stacks, stack_nos)| {
+            let lane_no = lane_stacks[0].lane_no;
+            let limit = lane_limits.get(&lane_no)?;
+            Some((lane_no, tile_read_limit(*limit, stack_nos.len())))
+        })
+        .collect::<HashMap<i32, usize>>();
 
     info!(logger, "Analyzing base call files...");
-    let counts = analyze_stacks(logger, &stacks, stack_no, index_no, settings)
-        .chain_err(|| "Problem with analyzing stacks")?;
+    debug!(logger, "Reads to sample per lane: {:?}", &read_limits);
+
+    let mut counts = analyze_stacks(
+        logger,
+        &stacks,
+        &stack_nos,
+        index_no,
+        &read_limits,
+        settings,
+    )
+    .chain_err(|| "Problem with analyzing stacks")?;
+    for (count, (lane_stacks, stack_nos)) in
+        counts.iter_mut().zip(stacks.iter().zip(stack_nos.iter()))
+    {
+        count.sampling = HistogramSampling {
+            requested_reads: read_limits.get(&count.lane_no).cloned().unwrap_or(0),
+            tiles_sampled: stack_nos.len(),
+            tiles: stack_nos
+                .iter()
+                .filter_map(|stack_no| lane_stacks[*stack_no].tile_no)
+                .collect(),
+            sketch_width: count.sampling.sketch_width,
+            ..sampling.clone()
+        };
+    }
 
     Ok(counts)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    /// Create the lane directories `lanes` below the base calls of a run folder in the temporary
+    /// directory named after `name`, with a `*.filter` file in the `filtered` lanes.
+    fn write_lanes(name: &str, lanes: &[i32], filtered: &[i32]) -> PathBuf {
+        let path = env::temp_dir().join(format!("digestiflow-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        for lane in lanes {
+            let lane_dir = path
+                .join("Data")
+                .join("Intensities")
+                .join("BaseCalls")
+                .join(format!("L{:03}", lane));
+            fs::create_dir_all(&lane_dir).unwrap();
+            if filtered.contains(lane) {
+                fs::write(lane_dir.join(format!("s_{}_1101.filter", lane)), b"").unwrap();
+            }
+        }
+        path
+    }
+
+    #[test]
+    fn lane_no_from_path_parses_lane_directory() {
+        assert_eq!(lane_no_from_path(Path::new("BaseCalls/L001")), Some(1));
+        assert_eq!(lane_no_from_path(Path::new("BaseCalls/L008")), Some(8));
+        assert_eq!(lane_no_from_path(Path::new("BaseCalls/Lxyz")), None);
+        assert_eq!(lane_no_from_path(Path::new("BaseCalls/C1.1")), None);
+    }
+
+    #[test]
+    fn lane_cluster_counts_skips_missing_lanes() {
+        let path = write_lanes("cluster-counts", &[2, 4], &[2, 4]);
+        for (lane, num_clusters) in &[(2, 100u32), (4, 300)] {
+            let mut filter = Vec::new();
+            for value in &[0u32, 3, *num_clusters] {
+                filter.extend_from_slice(&value.to_le_bytes());
+            }
+            let lane_dir = path
+                .join("Data")
+                .join("Intensities")
+                .join("BaseCalls")
+                .join(format!("L{:03}", lane));
+            fs::write(lane_dir.join(format!("s_{}_1101.filter", lane)), filter).unwrap();
+        }
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let counts = lane_cluster_counts(&logger, &path);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts.get(&2), Some(&100));
+        assert_eq!(counts.get(&4), Some(&300));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn lanes_without_filter_files_skips_missing_lanes() {
+        let path = write_lanes("no-filter", &[1, 3, 4], &[1, 4]);
+        let logger = slog::Logger::root(slog::Discard, o!());
+        assert_eq!(lanes_without_filter_files(&logger, &path), vec![3]);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn lane_read_limits_use_cluster_counts_with_sample_fraction() {
+        let stacks = [1, 2]
+            .iter()
+            .map(|lane_no| {
+                vec![TileBclStack {
+                    lane_no: *lane_no,
+                    paths: Vec::new(),
+                    tile_no: Some(1101),
+                }]
+            })
+            .collect::<Vec<Vec<TileBclStack>>>();
+        let cluster_counts = [(1, 1000u64)]
+            .iter()
+            .cloned()
+            .collect::<HashMap<i32, u64>>();
+        let mut settings = Settings::default();
+        settings.ingest.sample_reads_per_tile = 50;
+
+        settings.ingest.sample_fraction = 0.0;
+        let limits = lane_read_limits(&stacks, &cluster_counts, &settings);
+        assert_eq!(limits.get(&1), Some(&ReadLimit::PerTile(50)));
+        assert_eq!(limits.get(&2), Some(&ReadLimit::PerTile(50)));
+
+        settings.ingest.sample_fraction = 0.1;
+        let limits = lane_read_limits(&stacks, &cluster_counts, &settings);
+        assert_eq!(limits.get(&1), Some(&ReadLimit::PerLane(100)));
+        assert_eq!(limits.get(&2), Some(&ReadLimit::PerTile(50)));
+    }
+}
@@ -1,14 +1,24 @@
 //! Code for accessing data in the raw output directories.
 
-use chrono::{NaiveDate, NaiveDateTime};
-use std::path::Path;
+use chaos;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use flate2::read::GzDecoder;
+use glob::{glob, Pattern};
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
 use sxd_document::dom::Document;
 use sxd_xpath::nodeset::Node;
 use sxd_xpath::{evaluate_xpath, Value};
 
 use super::super::errors::*;
+use ingest::aviti;
+use ingest::mgi;
+use ingest::nanopore;
+use ingest::warnings::{Warning, WarningKind};
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum FolderLayout {
     /// MiSeq (Windows XP), HiSeq 2000, etc. `runParameters.xml`
     MiSeqDep,
@@ -16,6 +26,10 @@ pub enum FolderLayout {
     MiniSeq,
     /// HiSeq X
     HiSeqX,
+    /// HiSeq 3000/4000
+    HiSeq3000,
+    /// NextSeq 500/550
+    NextSeq500,
     /// NovaSeq
     NovaSeq,
     /// MiSeq (Windows 10)
@@ -24,85 +38,268 @@ pub enum FolderLayout {
     NovaSeqXplus,
     /// NextSeq 1000/2000
     NextSeq2000,
+    /// Element Biosciences AVITI, `RunParameters.json` and no `RunInfo.xml`
+    Aviti,
+    /// MGI DNBSEQ-G400/T7, lane directories with `BioInfo.csv` and no `RunInfo.xml`
+    Mgi,
+    /// Oxford Nanopore MinKNOW, `final_summary_*.txt` or `report_*.json` and no `RunInfo.xml`
+    Nanopore,
 }
 
-pub fn guess_folder_layout(path: &Path) -> Result<FolderLayout> {
-    let miniseq_marker = vec![
-        path.join("Data")
-            .join("Intensities")
-            .join("BaseCalls")
-            .join("L001"),
-        path.join("RunParameters.xml"),
-    ];
-    let miseqdep_marker = vec![
-        path.join("Data")
-            .join("Intensities")
-            .join("BaseCalls")
-            .join("L001")
-            .join("C1.1"),
-        path.join("runParameters.xml"),
-    ];
-    let miseq_marker = vec![
-        path.join("Data")
-            .join("Intensities")
-            .join("BaseCalls")
-            .join("L001")
-            .join("C1.1"),
-        path.join("RunParameters.xml"),
-    ];
-    let hiseqx_marker = vec![
-        path.join("Data").join("Intensities").join("s.locs"),
-        path.join("RunParameters.xml"),
-    ];
-    let novaseq_marker_any = vec![
-        path.join("Data")
-            .join("Intensities")
-            .join("BaseCalls")
-            .join("L001")
-            .join("C1.1")
-            .join("L001_1.cbcl"),
-        path.join("Data")
-            .join("Intensities")
-            .join("BaseCalls")
-            .join("L001")
-            .join("C1.1")
-            .join("L001_2.cbcl"),
-    ];
-    let novaseq_marker_all = vec![path.join("RunParameters.xml")];
-//    let novaseqxplus_marker = vec![path.join("Manifest.tsv")];
-    let linux_os_marker = vec![path.join("InstrumentAnalyticsLogs")];
-    let novaseqxplus_marker = vec![path.join("RTAExited.txt")];
+/// All folder layouts, for looking them up by name.
+const FOLDER_LAYOUTS: &[FolderLayout] = &[
+    FolderLayout::MiSeqDep,
+    FolderLayout::MiniSeq,
+    FolderLayout::HiSeqX,
+    FolderLayout::HiSeq3000,
+    FolderLayout::NextSeq500,
+    FolderLayout::NovaSeq,
+    FolderLayout::MiSeq,
+    FolderLayout::NovaSeqXplus,
+    FolderLayout::NextSeq2000,
+    FolderLayout::Aviti,
+    FolderLayout::Mgi,
+    FolderLayout::Nanopore,
+];
+
+impl FolderLayout {
+    /// Return the folder layout with the given `name` (e.g., `NovaSeq`), ignoring case.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match FOLDER_LAYOUTS
+            .iter()
+            .find(|layout| format!("{:?}", layout).eq_ignore_ascii_case(name.trim()))
+        {
+            Some(layout) => Ok(*layout),
+            None => bail!(
+                "Unknown folder layout {:?}, must be one of {:?}",
+                name,
+                FOLDER_LAYOUTS
+            ),
+        }
+    }
+}
+
+/// Return the name of the first cycle directory (e.g., `C1.1`) in `lane_dir`.
+///
+/// This is `C1.1` unless the template cycles have been removed from an archived run folder.
+pub fn first_cycle_dir_name(lane_dir: &Path) -> String {
+    let pattern = lane_dir.join("C*.1");
+    glob(pattern.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?.to_string();
+            name[1..name.len() - 2].parse::<i32>().ok()
+        })
+        .min()
+        .map(|cycle| format!("C{}.1", cycle))
+        .unwrap_or_else(|| "C1.1".to_string())
+}
+
+/// A rule for detecting a `FolderLayout` from marker files.
+///
+/// Besides the built-in rules, additional rules can be given in the configuration.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayoutRule {
+    /// The name of the rule given in the `[layouts]` section, empty otherwise.
+    #[serde(default)]
+    pub name: String,
+    /// The layout detected by the rule.
+    pub layout: FolderLayout,
+    /// Glob patterns relative to the run folder that must all match an existing path.
+    pub markers: Vec<String>,
+}
+
+impl LayoutRule {
+    fn new(layout: FolderLayout, markers: &[&str]) -> Self {
+        Self {
+            name: String::new(),
+            layout,
+            markers: markers.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+}
+
+/// Return the built-in layout detection rules, in the order they are checked.
+pub fn builtin_layout_rules() -> Vec<LayoutRule> {
+    let cbcl = "Data/Intensities/BaseCalls/L001/C*.1/L001_[12].cbcl";
+    let first_cycle = "Data/Intensities/BaseCalls/L001/C*.1";
+    vec![
+        LayoutRule::new(
+            FolderLayout::NovaSeqXplus,
+            &[
+                "RunParameters.xml",
+                cbcl,
+                "InstrumentAnalyticsLogs",
+                "RTAExited.txt",
+            ],
+        ),
+        LayoutRule::new(
+            FolderLayout::NextSeq2000,
+            &["RunParameters.xml", cbcl, "InstrumentAnalyticsLogs"],
+        ),
+        LayoutRule::new(FolderLayout::NovaSeq, &["RunParameters.xml", cbcl]),
+        // Checked before the MiSeq rules as the patterned flow cells also have per-tile BCL
+        // files in cycle directories, but a single `s.locs` file.
+        LayoutRule::new(
+            FolderLayout::HiSeq3000,
+            &[first_cycle, "Data/Intensities/s.locs", "runParameters.xml"],
+        ),
+        LayoutRule::new(
+            FolderLayout::HiSeqX,
+            &[first_cycle, "Data/Intensities/s.locs", "RunParameters.xml"],
+        ),
+        LayoutRule::new(FolderLayout::MiSeqDep, &[first_cycle, "runParameters.xml"]),
+        LayoutRule::new(FolderLayout::MiSeq, &[first_cycle, "RunParameters.xml"]),
+        // The MiniSeq has a single lane while the NextSeq 500/550 always has four.
+        LayoutRule::new(
+            FolderLayout::NextSeq500,
+            &["Data/Intensities/BaseCalls/L004", "RunParameters.xml"],
+        ),
+        LayoutRule::new(
+            FolderLayout::MiniSeq,
+            &["Data/Intensities/BaseCalls/L001", "RunParameters.xml"],
+        ),
+        // NovaSeq X run folder whose base calls were removed after conversion.
+        LayoutRule::new(
+            FolderLayout::NovaSeqXplus,
+            &[
+                "RunParameters.xml",
+                "InstrumentAnalyticsLogs",
+                "RTAExited.txt",
+            ],
+        ),
+        LayoutRule::new(FolderLayout::Aviti, &["RunParameters.json"]),
+        LayoutRule::new(FolderLayout::Mgi, &["L0?/BioInfo.csv"]),
+        LayoutRule::new(FolderLayout::Nanopore, &[nanopore::FINAL_SUMMARY_PATTERN]),
+        // MinKNOW run directory of a run that is still in progress.
+        LayoutRule::new(FolderLayout::Nanopore, &[nanopore::REPORT_PATTERN]),
+    ]
+}
 
-    if novaseq_marker_all.iter().all(|ref m| m.exists())
-        && novaseq_marker_any.iter().any(|ref m| m.exists())
+/// The result of checking a `LayoutRule` against a run folder.
+#[derive(Debug, Clone)]
+pub struct RuleCheck {
+    /// The rule that was checked.
+    pub rule: LayoutRule,
+    /// Each marker of the rule and whether it matched.
+    pub markers: Vec<(String, bool)>,
+}
+
+impl RuleCheck {
+    /// Whether or not all markers matched.
+    pub fn matched(&self) -> bool {
+        self.markers.iter().all(|(_, matched)| *matched)
+    }
+}
+
+/// Return whether the glob `pattern` relative to `path` matches an existing path.
+fn marker_exists(path: &Path, pattern: &str) -> bool {
+    let base = Pattern::escape(path.to_str().unwrap());
+    [pattern.to_string(), format!("{}.{}", pattern, GZ_EXTENSION)]
+        .iter()
+        .any(|pattern| {
+            glob(&format!("{}/{}", base, pattern))
+                .map(|mut paths| paths.any(|path| path.is_ok()))
+                .unwrap_or(false)
+        })
+}
+
+/// Check each of the `rules` against the run folder at `path`.
+pub fn check_layout_rules(path: &Path, rules: &[LayoutRule]) -> Vec<RuleCheck> {
+    rules
+        .iter()
+        .map(|rule| RuleCheck {
+            rule: rule.clone(),
+            markers: rule
+                .markers
+                .iter()
+                .map(|marker| (marker.clone(), marker_exists(path, marker)))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Return the rules from `configured` followed by the built-in rules.
+pub fn layout_rules(configured: &[LayoutRule]) -> Vec<LayoutRule> {
+    let mut result = configured.to_vec();
+    result.extend(builtin_layout_rules());
+    result
+}
+
+/// Guess the folder layout of the run folder at `path` from the first matching rule in `rules`.
+pub fn guess_folder_layout(path: &Path, rules: &[LayoutRule]) -> Result<FolderLayout> {
+    match check_layout_rules(path, rules)
+        .iter()
+        .find(|check| check.matched())
     {
-       if linux_os_marker.iter().any(|ref m| m.exists()) {
-           if novaseqxplus_marker.iter().any(|ref m| m.exists()) {
-               Ok(FolderLayout::NovaSeqXplus)
-           } else {
-               Ok(FolderLayout::NextSeq2000)
-           }
-        } else {
-            Ok(FolderLayout::NovaSeq)
-        }
-     } else if miseqdep_marker.iter().all(|ref m| m.exists()) {
-        Ok(FolderLayout::MiSeqDep)
-    } else if miseq_marker.iter().all(|ref m| m.exists()) {
-        Ok(FolderLayout::MiSeq)
-    } else if miniseq_marker.iter().all(|ref m| m.exists()) {
-        Ok(FolderLayout::MiniSeq)
-    } else if hiseqx_marker.iter().all(|ref m| m.exists()) {
-        Ok(FolderLayout::HiSeqX)
+        Some(check) => Ok(check.rule.layout),
+        None => bail!("Could not guess folder layout from {:?}", path),
+    }
+}
+
+/// Return the name of the run parameters file for the given `folder_layout`.
+pub fn run_parameters_file(folder_layout: FolderLayout) -> &'static str {
+    match folder_layout {
+        FolderLayout::MiSeqDep | FolderLayout::HiSeq3000 => "runParameters.xml",
+        FolderLayout::Aviti => aviti::RUN_PARAMETERS_FILE,
+        FolderLayout::Mgi => "L01/BioInfo.csv",
+        FolderLayout::Nanopore => nanopore::FINAL_SUMMARY_PATTERN,
+        _ => "RunParameters.xml",
+    }
+}
+
+/// Extension of the compressed meta data files in archived run folders.
+const GZ_EXTENSION: &str = "gz";
+
+/// Return the path to the meta data file `name` (e.g., `RunInfo.xml`) in the run folder at
+/// `path`, falling back to the gzip-compressed `name.gz` of archived run folders if only that
+/// exists.
+pub fn meta_file(path: &Path, name: &str) -> PathBuf {
+    let plain = path.join(name);
+    let compressed = path.join(format!("{}.{}", name, GZ_EXTENSION));
+    if !plain.exists() && compressed.exists() {
+        compressed
     } else {
-        bail!("Could not guess folder layout from {:?}", path)
+        plain
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Return whether the meta data file `name` or its compressed variant exists at `path`.
+pub fn meta_file_exists(path: &Path, name: &str) -> bool {
+    meta_file(path, name).exists()
+}
+
+/// Read the meta data file `name` in the run folder at `path`, decompressing `name.gz` if
+/// only that exists.
+pub fn read_meta_bytes(path: &Path, name: &str) -> Result<Vec<u8>> {
+    let file_path = meta_file(path, name);
+    let mut contents = Vec::new();
+    chaos::open(&file_path)
+        .and_then(|file| {
+            if file_path.extension() == Some(OsStr::new(GZ_EXTENSION)) {
+                GzDecoder::new(file).read_to_end(&mut contents)
+            } else {
+                BufReader::new(file).read_to_end(&mut contents)
+            }
+        })
+        .chain_err(|| format!("Problem reading {:?}", &file_path))?;
+    Ok(contents)
+}
+
+/// Read the meta data file `name` in the run folder at `path` as text, see `read_meta_bytes()`.
+pub fn read_meta_file(path: &Path, name: &str) -> Result<String> {
+    String::from_utf8(read_meta_bytes(path, name)?)
+        .chain_err(|| format!("Problem decoding {:?}", meta_file(path, name)))
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct ReadDescription {
     pub number: i32,
     pub num_cycles: i32,
     pub is_index: bool,
+    /// Whether the read is sequenced as the reverse complement, from `RunInfo.xml` v6.
+    pub is_reverse_complement: bool,
 }
 
 pub fn string_description(read_descs: &Vec<ReadDescription>) -> String {
@@ -113,7 +310,111 @@ pub fn string_description(read_descs: &Vec<ReadDescription>) -> String {
         .join("")
 }
 
-#[derive(Debug)]
+/// Get date in `%F` format from the leading `YYMMDD` or `YYYYMMDD` token of the `run_id`.
+pub fn date_from_run_id(run_id: &str) -> Option<String> {
+    let token = run_id.split('_').next()?;
+    let fmt = match token.len() {
+        6 => "%y%m%d",
+        8 => "%Y%m%d",
+        _ => return None,
+    };
+    NaiveDate::parse_from_str(token, fmt)
+        .ok()
+        .map(|date| date.format("%F").to_string())
+}
+
+/// Formats of the `<Date>` in `RunInfo.xml` written by the instruments and their software
+/// versions, tried in order.
+///
+/// Timestamps with a time zone are taken as written, i.e., the date is the local date of the
+/// instrument.
+pub const RUN_DATE_FORMATS: &[&str] = &[
+    "%y%m%d",
+    "%Y%m%d",
+    "%-m/%-d/%Y %-I:%M:%S %p",
+    "%-m/%-d/%Y %H:%M:%S",
+    "%-m/%-d/%Y",
+    "%Y-%m-%dT%H:%M:%SZ",
+    "%Y-%m-%dT%H:%M:%S%.fZ",
+    "%Y-%m-%dT%H:%M:%S%:z",
+    "%Y-%m-%dT%H:%M:%S%.f%:z",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d",
+    "%d-%b-%y",
+    "%d-%b-%Y",
+];
+/// Days from 0001-01-01 to the Unix epoch 1970-01-01.
+const UNIX_EPOCH_DAYS_FROM_CE: i64 = 719_163;
+
+/// Parse `value` with the `strftime`-like `format`, which may or may not include a time and a
+/// time zone.
+fn parse_date_with(value: &str, format: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, format)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, format).map(|dt| dt.date()))
+        .or_else(|_| DateTime::parse_from_str(value, format).map(|dt| dt.naive_local().date()))
+        .ok()
+}
+
+/// Parse `value` as seconds or milliseconds since the Unix epoch (e.g., `1717402530` or
+/// `1717402530123.0`), taking the date in UTC.
+fn parse_epoch_date(value: &str) -> Option<NaiveDate> {
+    let int_part = value.split('.').next().unwrap_or("");
+    if value.split('.').count() > 2
+        || !value.chars().all(|c| c.is_ascii_digit() || c == '.')
+        || int_part.is_empty()
+    {
+        return None;
+    }
+    let seconds = match int_part.len() {
+        9 | 10 => int_part.parse::<i64>().ok()?,
+        12 | 13 => int_part.parse::<i64>().ok()? / 1000,
+        _ => return None,
+    };
+    NaiveDate::from_num_days_from_ce_opt(
+        (UNIX_EPOCH_DAYS_FROM_CE + seconds.div_euclid(86_400)) as i32,
+    )
+}
+
+/// Parse the `<Date>` of `RunInfo.xml` in `value` with the custom `date_format` (if not empty),
+/// then with the `RUN_DATE_FORMATS`, then as time since the Unix epoch.
+pub fn parse_run_date(value: &str, date_format: &str) -> Option<NaiveDate> {
+    let value = value.trim();
+    Some(date_format)
+        .filter(|format| !format.is_empty())
+        .into_iter()
+        .chain(RUN_DATE_FORMATS.iter().cloned())
+        .filter_map(|format| parse_date_with(value, format))
+        .next()
+        .or_else(|| parse_epoch_date(value))
+}
+
+/// The latest version of `RunInfo.xml` known to be parsed correctly.
+pub const RUN_INFO_VERSION: i32 = 6;
+
+/// The geometry of the flow cell from the `<FlowcellLayout>` of `RunInfo.xml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FlowcellGeometry {
+    /// The number of imaged surfaces, 2 for top and bottom.
+    pub surface_count: i32,
+    /// The number of swaths per surface.
+    pub swath_count: i32,
+    /// The number of tiles per swath.
+    pub tile_count: i32,
+    /// The tile naming convention from the `<TileSet>` of v6, e.g., `FourDigit`, else empty.
+    pub tile_naming: String,
+    /// The tiles from the `<TileSet>` of v6, e.g., `1_1101` for tile 1101 of lane 1, else empty.
+    pub tiles: Vec<String>,
+}
+
+impl FlowcellGeometry {
+    /// Return the number of tiles per lane.
+    pub fn tiles_per_lane(&self) -> i32 {
+        self.surface_count * self.swath_count * self.tile_count
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct RunInfo {
     /// The long, full run ID.
     pub run_id: String,
@@ -123,9 +424,69 @@ pub struct RunInfo {
     pub date: String,
     pub lane_count: i32,
     pub reads: Vec<ReadDescription>,
+    /// The version of the `RunInfo.xml` schema, 0 if not given.
+    pub version: i32,
+    /// The geometry of the flow cell, if given in `RunInfo.xml`.
+    pub geometry: Option<FlowcellGeometry>,
 }
 
-pub fn process_xml_run_info(info_doc: &Document) -> Result<RunInfo> {
+/// Parse the flow cell geometry from the `<FlowcellLayout>` of `info_doc`, `None` if the
+/// surface, swath, and tile counts are not given.
+///
+/// The `<TileSet>` listing the tiles has been added with version 6.
+fn process_xml_flowcell_geometry(info_doc: &Document) -> Result<Option<FlowcellGeometry>> {
+    let count = |name: &str| -> Result<Option<i32>> {
+        let xpath = format!("//FlowcellLayout/@{}", name);
+        let value = evaluate_xpath(info_doc, &xpath)
+            .chain_err(|| format!("Problem reading {}", &xpath))?
+            .into_string();
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(value.parse::<i32>().chain_err(|| {
+                format!("Problem parsing {} {:?}", &xpath, &value)
+            })?))
+        }
+    };
+    let (surface_count, swath_count, tile_count) = match (
+        count("SurfaceCount")?,
+        count("SwathCount")?,
+        count("TileCount")?,
+    ) {
+        (Some(surfaces), Some(swaths), Some(tiles)) => (surfaces, swaths, tiles),
+        _ => return Ok(None),
+    };
+
+    let tile_naming = evaluate_xpath(info_doc, "//FlowcellLayout/TileSet/@TileNamingConvention")
+        .chain_err(|| "Problem reading //FlowcellLayout/TileSet/@TileNamingConvention")?
+        .into_string();
+    let tiles = if let Value::Nodeset(nodeset) =
+        evaluate_xpath(info_doc, "//FlowcellLayout/TileSet/Tiles/Tile/text()")
+            .chain_err(|| "Problem finding //FlowcellLayout/TileSet/Tiles/Tile tags")?
+    {
+        nodeset
+            .document_order()
+            .iter()
+            .map(|node| node.string_value().trim().to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Ok(Some(FlowcellGeometry {
+        surface_count,
+        swath_count,
+        tile_count,
+        tile_naming,
+        tiles,
+    }))
+}
+
+pub fn process_xml_run_info(
+    info_doc: &Document,
+    date_format: &str,
+    warnings: &mut Vec<Warning>,
+) -> Result<RunInfo> {
     let reads = if let Value::Nodeset(nodeset) =
         evaluate_xpath(&info_doc, "//RunInfoRead|//Read")
             .chain_err(|| "Problem finding Read or RunInfoRead tags")?
@@ -155,6 +516,11 @@ pub fn process_xml_run_info(info_doc: &Document) -> Result<RunInfo> {
                             .expect("Problem accessing IsIndexedRead attribute")
                             .value()
                             == "Y",
+                        // Only given since v6.
+                        is_reverse_complement: elem
+                            .attribute("IsReverseComplement")
+                            .map(|attr| attr.value() == "Y")
+                            .unwrap_or(false),
                     })
                 }
             } else {
@@ -166,25 +532,44 @@ pub fn process_xml_run_info(info_doc: &Document) -> Result<RunInfo> {
         bail!("Problem getting Read or RunInfoRead elements")
     };
 
+    let version = evaluate_xpath(info_doc, "//RunInfo/@Version")
+        .chain_err(|| "Problem reading //RunInfo/@Version")?
+        .into_string()
+        .parse::<i32>()
+        .unwrap_or(0);
+    if version > RUN_INFO_VERSION {
+        warnings.push(Warning::new(
+            WarningKind::LayoutQuirk,
+            format!(
+                "RunInfo.xml has version {}, newer than the latest known version {}",
+                version, RUN_INFO_VERSION
+            ),
+        ));
+    }
+
+    let run_id = evaluate_xpath(info_doc, "//Run/@Id")
+        .chain_err(|| "Problem reading //Run/@Id")?
+        .into_string();
     let xml_date = evaluate_xpath(&info_doc, "//Date/text()")
         .chain_err(|| "Problem reading //Date/text()")?
         .into_string();
-    let date_string = if let Ok(good) = NaiveDate::parse_from_str(&xml_date, "%y%m%d") {
+    let date_string = if let Some(good) = parse_run_date(&xml_date, date_format) {
         good.format("%F").to_string()
+    } else if let Some(good) = date_from_run_id(&run_id) {
+        warnings.push(Warning::new(
+            WarningKind::DateFallback,
+            format!(
+                "Could not parse date {:?}, using date {} from run ID {:?}",
+                &xml_date, &good, &run_id
+            ),
+        ));
+        good
     } else {
-        if let Ok(good) = NaiveDateTime::parse_from_str(&xml_date, "%-m/%-d/%Y %-I:%M:%S %p") {
-            good.format("%F").to_string()
-        } else if let Ok(good) = NaiveDateTime::parse_from_str(&xml_date, "%Y-%m-%dT%H:%M:%SZ") {
-            good.format("%F").to_string()
-        } else {
-            bail!("Could not parse date from string {}", &xml_date);
-        }
+        bail!("Could not parse date from string {}", &xml_date);
     };
 
     Ok(RunInfo {
-        run_id: evaluate_xpath(&info_doc, "//Run/@Id")
-            .chain_err(|| "Problem reading //Run/@Id")?
-            .into_string(),
+        run_id,
         run_number: evaluate_xpath(&info_doc, "//Run/@Number")
             .chain_err(|| "Problem reading //Run/@Number")?
             .into_number() as i32,
@@ -199,16 +584,54 @@ pub fn process_xml_run_info(info_doc: &Document) -> Result<RunInfo> {
             .chain_err(|| "Problem reading //FlowcellLayout/@LaneCount")?
             .into_number() as i32,
         reads: reads,
+        version,
+        geometry: process_xml_flowcell_geometry(info_doc)?,
     })
 }
 
-#[derive(Debug)]
+/// The RTA version writing the base calls of XLEAP-SBS chemistry, e.g., on the NextSeq 1000/2000.
+pub const RTA4: i32 = 4;
+
+/// Return the major version of the RTA version string `rta_version`, e.g., `4` for `v4.6.7`.
+pub fn rta_major_version(rta_version: &str) -> Option<i32> {
+    rta_version
+        .trim_start_matches('v')
+        .split('.')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[derive(Debug, Serialize)]
 pub struct RunParameters {
     pub planned_reads: Vec<ReadDescription>,
     pub rta_version: String,
     pub run_number: i32,
+    /// The flow cell slot (side), empty if not given in the run parameters; see
+    /// `resolve_flowcell_slot()`.
     pub flowcell_slot: String,
     pub experiment_name: String,
+    /// The instrument model, for layouts shared by several models.
+    pub instrument_model: Option<InstrumentModel>,
+}
+
+/// Instrument models that share a folder layout but may differ in details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum InstrumentModel {
+    /// NextSeq 1000, serial numbers starting with `VL`
+    NextSeq1000,
+    /// NextSeq 2000, serial numbers starting with `VH`
+    NextSeq2000,
+}
+
+impl fmt::Display for InstrumentModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstrumentModel::NextSeq1000 => write!(f, "NextSeq 1000"),
+            InstrumentModel::NextSeq2000 => write!(f, "NextSeq 2000"),
+        }
+    }
 }
 
 pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters> {
@@ -241,6 +664,7 @@ pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters>
                             .expect("Problem accessing IsIndexedRead attribute")
                             .value()
                             == "Y",
+                        is_reverse_complement: false,
                     })
                 }
             } else {
@@ -269,24 +693,280 @@ pub fn process_xml_param_doc_miseq(info_doc: &Document) -> Result<RunParameters>
         run_number: evaluate_xpath(&info_doc, "//ScanNumber/text()")
             .chain_err(|| "Problem getting ScanNumber element")?
             .into_number() as i32,
-        flowcell_slot: if let Ok(elem) = evaluate_xpath(&info_doc, "//FCPosition/text()") {
-            let elem = elem.into_string();
-            if elem.is_empty() {
-                "A".to_string()
-            } else {
-                elem
-            }
-        } else {
-            "A".to_string()
-        },
+        flowcell_slot: evaluate_xpath(info_doc, "//FCPosition/text()")
+            .map(|elem| elem.into_string())
+            .unwrap_or_default(),
         experiment_name: if let Ok(elem) = evaluate_xpath(&info_doc, "//ExperimentName/text()") {
             elem.into_string()
         } else {
             "".to_string()
         },
+        instrument_model: None,
+    })
+}
+
+/// Return whether the `runParameters.xml` document was written by the HiSeq Control Software.
+fn is_hiseq_param_doc(info_doc: &Document) -> bool {
+    match evaluate_xpath(info_doc, "//Setup/ApplicationName/text()") {
+        Ok(value) => value.into_string().contains("HiSeq"),
+        Err(_) => false,
+    }
+}
+
+/// Parse `runParameters.xml` of HiSeq 2000/2500 (GERALD-era, rapid, and high-output runs) and
+/// HiSeq 3000/4000, and `RunParameters.xml` of HiSeq X.
+///
+/// Depending on the HCS version, the planned reads are given as `<Read>` tags below
+/// `<Setup><Reads>`, as `<RunInfoRead>` tags below `<Reads>` (HiSeq X), or as `<Read1>`,
+/// `<IndexRead1>`, `<IndexRead2>`, and `<Read2>` text nodes.  The run number is given as `<ScanNumber>` or `<RunNumber>` and otherwise taken
+/// from the run ID.
+pub fn process_xml_param_doc_hiseq(
+    info_doc: &Document,
+    warnings: &mut Vec<Warning>,
+) -> Result<RunParameters> {
+    let mut reads = Vec::new();
+    if let Value::Nodeset(nodeset) =
+        evaluate_xpath(info_doc, "//Setup/Reads/Read | //Reads/RunInfoRead")
+            .chain_err(|| "Problem finding Setup/Reads/Read or Reads/RunInfoRead tags")?
+    {
+        for node in nodeset.document_order() {
+            if let Node::Element(elem) = node {
+                let attr = |name: &str| -> Result<String> {
+                    Ok(elem
+                        .attribute(name)
+                        .chain_err(|| format!("Problem accessing {} attribute", name))?
+                        .value()
+                        .to_string())
+                };
+                let num_cycles = attr("NumCycles")?
+                    .parse::<i32>()
+                    .chain_err(|| "Problem parsing NumCycles attribute")?;
+                if num_cycles > 0 {
+                    reads.push(ReadDescription {
+                        number: attr("Number")?
+                            .parse::<i32>()
+                            .chain_err(|| "Problem parsing Number attribute")?,
+                        num_cycles,
+                        is_index: attr("IsIndexedRead")? == "Y",
+                        is_reverse_complement: false,
+                    });
+                }
+            } else {
+                bail!("Read was not a tag!")
+            }
+        }
+    }
+    if reads.is_empty() {
+        let tags = [
+            ("Read1", false),
+            ("IndexRead1", true),
+            ("IndexRead2", true),
+            ("Read2", false),
+        ];
+        for (tag, is_index) in tags.iter() {
+            let num_cycles = evaluate_xpath(info_doc, &format!("//Setup/{}/text()", tag))
+                .map(|value| value.into_number())
+                .unwrap_or(0.0);
+            if num_cycles.is_finite() && num_cycles > 0.0 {
+                reads.push(ReadDescription {
+                    number: reads.len() as i32 + 1,
+                    num_cycles: num_cycles as i32,
+                    is_index: *is_index,
+                    is_reverse_complement: false,
+                });
+            }
+        }
+        if !reads.is_empty() {
+            warnings.push(Warning::new(
+                WarningKind::LayoutQuirk,
+                "Planned reads taken from Read1/IndexRead1/IndexRead2/Read2 tags".to_string(),
+            ));
+        }
+    }
+
+    let text = |xpath: &str| -> String {
+        evaluate_xpath(info_doc, xpath)
+            .map(|value| value.into_string())
+            .unwrap_or_default()
+    };
+
+    let run_number = ["//Setup/ScanNumber/text()", "//Setup/RunNumber/text()"]
+        .iter()
+        .map(|xpath| text(xpath))
+        .find(|value| !value.is_empty())
+        .or_else(|| {
+            text("//Setup/RunID/text()")
+                .split('_')
+                .nth(2)
+                .map(String::from)
+        })
+        .chain_err(|| "Problem finding run number in runParameters.xml")?
+        .parse::<i32>()
+        .chain_err(|| "Problem parsing run number")?;
+
+    let run_mode = text("//Setup/RunMode/text()");
+    if !run_mode.is_empty() && run_mode != "HighOutput" {
+        warnings.push(Warning::new(
+            WarningKind::LayoutQuirk,
+            format!("HiSeq run mode is {:?}", &run_mode),
+        ));
+    }
+
+    Ok(RunParameters {
+        planned_reads: reads,
+        rta_version: text("//Setup/RTAVersion/text()"),
+        run_number,
+        flowcell_slot: text("//Setup/FCPosition/text()"),
+        experiment_name: text("//Setup/ExperimentName/text()"),
+        instrument_model: None,
+    })
+}
+
+/// Return whether the `RunParameters.xml` document was written by an iSeq 100.
+fn is_iseq_param_doc(info_doc: &Document) -> bool {
+    ["//InstrumentType/text()", "//ApplicationName/text()"]
+        .iter()
+        .any(|xpath| match evaluate_xpath(info_doc, xpath) {
+            Ok(value) => value.into_string().contains("iSeq"),
+            Err(_) => false,
+        })
+}
+
+/// Parse `RunParameters.xml` of iSeq 100.
+///
+/// The planned reads are given as `<PlannedRead1Cycles>` etc. text nodes or, with newer control
+/// software, as `<Read>` tags with `ReadName` and `Cycles` attributes.  The run number is given
+/// as `<RunCounter>` or `<RunNumber>` and otherwise taken from the run ID.
+pub fn process_xml_param_doc_iseq(
+    info_doc: &Document,
+    warnings: &mut Vec<Warning>,
+) -> Result<RunParameters> {
+    let text = |xpath: &str| -> String {
+        evaluate_xpath(info_doc, xpath)
+            .map(|value| value.into_string())
+            .unwrap_or_default()
+    };
+
+    let mut reads = Vec::new();
+    let tags = [
+        ("PlannedRead1Cycles", false),
+        ("PlannedIndex1ReadCycles", true),
+        ("PlannedIndex2ReadCycles", true),
+        ("PlannedRead2Cycles", false),
+    ];
+    for (tag, is_index) in tags.iter() {
+        let num_cycles = evaluate_xpath(info_doc, &format!("//{}/text()", tag))
+            .map(|value| value.into_number())
+            .unwrap_or(0.0);
+        if num_cycles.is_finite() && num_cycles > 0.0 {
+            reads.push(ReadDescription {
+                number: reads.len() as i32 + 1,
+                num_cycles: num_cycles as i32,
+                is_index: *is_index,
+                is_reverse_complement: false,
+            });
+        }
+    }
+    if reads.is_empty() {
+        if let Value::Nodeset(nodeset) = evaluate_xpath(info_doc, "//PlannedReads/Read")
+            .chain_err(|| "Problem finding PlannedReads/Read tags")?
+        {
+            for node in nodeset.document_order() {
+                if let Node::Element(elem) = node {
+                    let num_cycles = elem
+                        .attribute("Cycles")
+                        .chain_err(|| "Problem accessing Cycles attribute")?
+                        .value()
+                        .parse::<i32>()
+                        .chain_err(|| "Problem parsing Cycles attribute")?;
+                    if num_cycles > 0 {
+                        reads.push(ReadDescription {
+                            number: reads.len() as i32 + 1,
+                            num_cycles,
+                            is_index: elem
+                                .attribute("ReadName")
+                                .map(|attr| attr.value().starts_with("Index"))
+                                .unwrap_or(false),
+                            is_reverse_complement: false,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    if reads.is_empty() {
+        warnings.push(Warning::new(
+            WarningKind::LayoutQuirk,
+            "No planned reads found in iSeq run parameters".to_string(),
+        ));
+    }
+
+    let run_number = ["//RunCounter/text()", "//RunNumber/text()"]
+        .iter()
+        .map(|xpath| text(xpath))
+        .find(|value| !value.is_empty())
+        .or_else(|| text("//RunId/text()").split('_').nth(2).map(String::from))
+        .chain_err(|| "Problem finding run number in RunParameters.xml")?
+        .parse::<i32>()
+        .chain_err(|| "Problem parsing run number")?;
+
+    let rta_version = ["//RtaVersion/text()", "//RTAVersion/text()"]
+        .iter()
+        .map(|xpath| text(xpath))
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+
+    Ok(RunParameters {
+        planned_reads: reads,
+        rta_version: rta_version.trim_start_matches('v').to_string(),
+        run_number,
+        flowcell_slot: String::new(),
+        experiment_name: text("//ExperimentName/text()"),
+        instrument_model: None,
     })
 }
 
+/// Parse `RunParameters.xml` of NextSeq 500/550.
+///
+/// The planned reads are given as `<Read1>`, `<Index1Read>`, `<Index2Read>`, and `<Read2>` text
+/// nodes below `<Setup>`.  If these are missing, the MiniSeq tags are tried.
+pub fn process_xml_param_doc_nextseq500(
+    info_doc: &Document,
+    warnings: &mut Vec<Warning>,
+) -> Result<RunParameters> {
+    let mut reads = Vec::new();
+    let tags = [
+        ("Read1", false),
+        ("Index1Read", true),
+        ("Index2Read", true),
+        ("Read2", false),
+    ];
+    for (tag, is_index) in tags.iter() {
+        let num_cycles = evaluate_xpath(info_doc, &format!("//Setup/{}/text()", tag))
+            .map(|value| value.into_number())
+            .unwrap_or(0.0);
+        if num_cycles.is_finite() && num_cycles > 0.0 {
+            reads.push(ReadDescription {
+                number: reads.len() as i32 + 1,
+                num_cycles: num_cycles as i32,
+                is_index: *is_index,
+                is_reverse_complement: false,
+            });
+        }
+    }
+
+    let mut run_params = process_xml_param_doc_miniseq(info_doc)?;
+    if reads.is_empty() {
+        warnings.push(Warning::new(
+            WarningKind::LayoutQuirk,
+            "Planned reads of NextSeq 500/550 taken from MiniSeq tags".to_string(),
+        ));
+    } else {
+        run_params.planned_reads = reads;
+    }
+    Ok(run_params)
+}
+
 pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameters> {
     let mut reads = Vec::new();
     let mut number = 1;
@@ -298,6 +978,7 @@ pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameter
                 number: number,
                 num_cycles: num_cycles,
                 is_index: false,
+                is_reverse_complement: false,
             });
             number += 1;
         }
@@ -310,6 +991,7 @@ pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameter
                 number: number,
                 num_cycles: num_cycles,
                 is_index: true,
+                is_reverse_complement: false,
             });
             number += 1;
         }
@@ -322,6 +1004,7 @@ pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameter
                 number: number,
                 num_cycles: num_cycles,
                 is_index: true,
+                is_reverse_complement: false,
             });
             number += 1;
         }
@@ -334,6 +1017,7 @@ pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameter
                 number: number,
                 num_cycles: num_cycles,
                 is_index: false,
+                is_reverse_complement: false,
             });
             // number += 1;
         }
@@ -356,22 +1040,16 @@ pub fn process_xml_param_doc_miniseq(info_doc: &Document) -> Result<RunParameter
         run_number: evaluate_xpath(&info_doc, "//RunNumber/text()")
             .chain_err(|| "Problem getting RunNumber element")?
             .into_number() as i32,
-        flowcell_slot: if let Ok(elem) = evaluate_xpath(&info_doc, "//Side/text()") {
-            let elem = elem.into_string();
-            if elem.is_empty() {
-                "A".to_string()
-            } else {
-                elem
-            }
-        } else {
-            "A".to_string()
-        },
+        flowcell_slot: evaluate_xpath(info_doc, "//Side/text()")
+            .map(|elem| elem.into_string())
+            .unwrap_or_default(),
 
         experiment_name: if let Ok(elem) = evaluate_xpath(&info_doc, "//ExperimentName/text()") {
             elem.into_string()
         } else {
             "".to_string()
         },
+        instrument_model: None,
     })
 }
 
@@ -402,7 +1080,8 @@ pub fn process_xml_param_doc_novaseqxplus(info_doc: &Document) -> Result<RunPara
                             .expect("Problem accessing ReadName attribute")
                             .value()
                             .to_string()
-                            .starts_with("Index")
+                            .starts_with("Index"),
+                        is_reverse_complement: false,
                     });
                     number += 1;
                 }
@@ -433,90 +1112,75 @@ pub fn process_xml_param_doc_novaseqxplus(info_doc: &Document) -> Result<RunPara
         run_number: evaluate_xpath(&info_doc, "//RunNumber/text()")
             .chain_err(|| "Problem getting RunNumber element")?
             .into_number() as i32,
-        flowcell_slot: if let Ok(elem) = evaluate_xpath(&info_doc, "//Side/text()") {
-            let elem = elem.into_string();
-            if elem.is_empty() {
-                "A".to_string()
-            } else {
-                elem
-            }
-        } else {
-            "A".to_string()
-        },
+        flowcell_slot: evaluate_xpath(info_doc, "//Side/text()")
+            .map(|elem| elem.into_string())
+            .unwrap_or_default(),
 
         experiment_name: if let Ok(elem) = evaluate_xpath(&info_doc, "//ExperimentName/text()") {
             elem.into_string()
         } else {
             "".to_string()
         },
+        instrument_model: None,
     })
 }
 
-pub fn process_xml_param_doc_nextseq2000(info_doc: &Document) -> Result<RunParameters> {
-    let mut reads = Vec::new();
-    let mut number = 1;
+/// Names of the reads in NextSeq 1000/2000 run parameters, in the order of sequencing.
+const NEXTSEQ2000_READ_NAMES: &[&str] = &["Read1", "Index1", "Index2", "Read2"];
 
-    println!("parsing NextSeq 2000 RunParameters");
-    if let Ok(value) = evaluate_xpath(&info_doc, "//Read1/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: false,
-            });
-            number += 1;
-        }
+/// Return the number of cycles of the read `name` (e.g., `"Index1"`) from NextSeq 1000/2000
+/// run parameters, 0 if the read is not present.
+///
+/// Depending on the recipe and control software version, the cycles are given as the text of
+/// elements such as `<Index1>` or as the `Cycles` attribute of `<Read ReadName="Index1">` in
+/// the `<PlannedReads>` section (e.g., for custom and 3' index kits).  The latter takes
+/// precedence if present, reads missing from it are not sequenced even if the elements give
+/// cycles for them.
+fn nextseq2000_read_cycles(info_doc: &Document, name: &str) -> Result<i32> {
+    let has_planned = evaluate_xpath(info_doc, "count(//PlannedReads/Read)")
+        .chain_err(|| "Problem counting PlannedReads/Read tags")?
+        .into_number()
+        > 0.0;
+    let planned = evaluate_xpath(
+        info_doc,
+        &format!("//PlannedReads/Read[@ReadName='{}']/@Cycles", name),
+    )
+    .chain_err(|| format!("Problem getting planned cycles of {}", name))?
+    .into_string();
+    let cycles = if !has_planned {
+        evaluate_xpath(info_doc, &format!("//{}/text()", name))
+            .chain_err(|| format!("Problem getting {} element", name))?
+            .into_string()
     } else {
-        bail!("Read1 was not a tag!")
-    }
-
-
-    if let Ok(value) = evaluate_xpath(&info_doc, "//Index1/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: true,
-            });
-            number += 1;
-        }
+        planned
+    };
+    if cycles.trim().is_empty() {
+        Ok(0)
     } else {
-        bail!("Index1 was not a tag!")
+        cycles
+            .trim()
+            .parse::<i32>()
+            .chain_err(|| format!("Invalid number of cycles {:?} for {}", cycles, name))
     }
+}
 
-
-    if let Ok(value) = evaluate_xpath(&info_doc, "//Index2/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
+pub fn process_xml_param_doc_nextseq2000(info_doc: &Document) -> Result<RunParameters> {
+    let mut reads = Vec::new();
+    for name in NEXTSEQ2000_READ_NAMES {
+        let num_cycles = nextseq2000_read_cycles(info_doc, name)?;
+        if num_cycles > 0 {
             reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: true,
+                number: reads.len() as i32 + 1,
+                num_cycles,
+                is_index: name.starts_with("Index"),
+                is_reverse_complement: false,
             });
-            number += 1;
         }
-    } else {
-        bail!("Index2 was not a tag!")
     }
-
-
-    if let Ok(value) = evaluate_xpath(&info_doc, "//Read2/text()") {
-        let num_cycles = value.into_number() as i32;
-        if num_cycles != 0 {
-            reads.push(ReadDescription {
-                number: number,
-                num_cycles: num_cycles,
-                is_index: false,
-            });
-            // number += 1;
-        }
-    } else {
-        bail!("Read2 was not a tag!")
+    if reads.is_empty() {
+        bail!("Found no planned reads in NextSeq 1000/2000 run parameters");
     }
 
-
     let rta_version = evaluate_xpath(&info_doc, "//RTAVersion/text()")
         .chain_err(|| "Problem getting RTAVersion element")?
         .into_string();
@@ -526,78 +1190,512 @@ pub fn process_xml_param_doc_nextseq2000(info_doc: &Document) -> Result<RunParam
 
     Ok(RunParameters {
         planned_reads: reads,
+        // Runs with XLEAP-SBS chemistry give RTA version 4.
         rta_version: if !rta_version3.is_empty() {
-//      fix for new NextSeq2000 running RTA version 4.xxx
-            if rta_version3.starts_with("4") {
-                "3".to_string()
-            } else {
-                rta_version3.to_string()
-            }
+            rta_version3.trim_start_matches('v').to_string()
         } else {
             rta_version
         },
         run_number: evaluate_xpath(&info_doc, "//RunCounter/text()")
             .chain_err(|| "Problem getting RunNumber element")?
             .into_number() as i32,
-        flowcell_slot: if let Ok(elem) = evaluate_xpath(&info_doc, "//Side/text()") {
-            let elem = elem.into_string();
-            if elem.is_empty() {
-                "A".to_string()
-            } else {
-                elem
-            }
-        } else {
-            "A".to_string()
-        },
+        flowcell_slot: evaluate_xpath(info_doc, "//Side/text()")
+            .map(|elem| elem.into_string())
+            .unwrap_or_default(),
 
         experiment_name: if let Ok(elem) = evaluate_xpath(&info_doc, "//ExperimentName/text()") {
             elem.into_string()
         } else {
             "".to_string()
         },
+        instrument_model: None,
     })
 }
 
+/// Return the model of a NextSeq 1000/2000 from the `InstrumentType` element of the run
+/// parameters or, if missing, from the prefix of the `instrument` serial number.
+/// Return whether the run parameters in `param_doc` are from a NextSeq 1000/2000.
+///
+/// With RTA 4, the NextSeq 1000/2000 writes `RTAExited.txt` like the NovaSeq X and thus has
+/// its folder layout.
+fn is_nextseq2000_param_doc(param_doc: &Document) -> bool {
+    match evaluate_xpath(param_doc, "//InstrumentType/text()") {
+        Ok(value) => value.into_string().contains("NextSeq"),
+        Err(_) => false,
+    }
+}
+
+pub fn nextseq2000_model(param_doc: &Document, instrument: &str) -> Option<InstrumentModel> {
+    let instrument_type = evaluate_xpath(param_doc, "//InstrumentType/text()")
+        .map(|value| value.into_string())
+        .unwrap_or_default();
+    if instrument_type.contains("1000") {
+        Some(InstrumentModel::NextSeq1000)
+    } else if instrument_type.contains("2000") {
+        Some(InstrumentModel::NextSeq2000)
+    } else if instrument.starts_with("VL") {
+        Some(InstrumentModel::NextSeq1000)
+    } else if instrument.starts_with("VH") {
+        Some(InstrumentModel::NextSeq2000)
+    } else {
+        None
+    }
+}
+
+/// Return a description of the parser used by `process_xml()` for the run parameters of the
+/// given `folder_layout`.
+pub fn param_parser_name(folder_layout: FolderLayout, param_doc: &Document) -> &'static str {
+    match folder_layout {
+        FolderLayout::MiSeqDep if is_hiseq_param_doc(param_doc) => "HiSeq",
+        FolderLayout::HiSeqX | FolderLayout::HiSeq3000 => "HiSeq",
+        FolderLayout::NovaSeq if is_iseq_param_doc(param_doc) => "iSeq 100",
+        FolderLayout::MiSeqDep | FolderLayout::MiSeq => "MiSeq",
+        FolderLayout::MiniSeq | FolderLayout::NovaSeq => "MiniSeq/NovaSeq",
+        FolderLayout::NovaSeqXplus if is_nextseq2000_param_doc(param_doc) => "NextSeq 1000/2000",
+        FolderLayout::NovaSeqXplus => "NovaSeq X",
+        FolderLayout::NextSeq500 => "NextSeq 500/550",
+        FolderLayout::NextSeq2000 => "NextSeq 1000/2000",
+        FolderLayout::Aviti => "AVITI",
+        FolderLayout::Mgi => "MGI",
+        FolderLayout::Nanopore => "Nanopore",
+    }
+}
 
 pub fn process_xml(
     logger: &slog::Logger,
     folder_layout: FolderLayout,
     info_doc: &Document,
     param_doc: &Document,
+    date_format: &str,
+    warnings: &mut Vec<Warning>,
 ) -> Result<(RunInfo, RunParameters)> {
-    let run_info = process_xml_run_info(info_doc)?;
+    let run_info = process_xml_run_info(info_doc, date_format, warnings)?;
     debug!(logger, "RunInfo => {:?}", &run_info);
 
-    let run_params = match folder_layout {
+    let mut run_params = match folder_layout {
+        FolderLayout::MiSeqDep if is_hiseq_param_doc(param_doc) => {
+            process_xml_param_doc_hiseq(param_doc, warnings)?
+        }
         FolderLayout::MiSeqDep | FolderLayout:: MiSeq => process_xml_param_doc_miseq(param_doc)?,
+        FolderLayout::NovaSeq if is_iseq_param_doc(param_doc) => {
+            process_xml_param_doc_iseq(param_doc, warnings)?
+        }
         FolderLayout::MiniSeq | FolderLayout::NovaSeq => process_xml_param_doc_miniseq(param_doc)?,
+        FolderLayout::NovaSeqXplus | FolderLayout::NextSeq2000
+            if is_nextseq2000_param_doc(param_doc) =>
+        {
+            process_xml_param_doc_nextseq2000(param_doc)?
+        }
         FolderLayout::NovaSeqXplus => process_xml_param_doc_novaseqxplus(param_doc)?,
+        FolderLayout::NextSeq500 => process_xml_param_doc_nextseq500(param_doc, warnings)?,
         FolderLayout::NextSeq2000 => process_xml_param_doc_nextseq2000(param_doc)?,
-        _ => bail!(
-            "Don't yet know how to parse folder layout {:?}",
-            folder_layout
-        ),
+        FolderLayout::HiSeqX | FolderLayout::HiSeq3000 => {
+            process_xml_param_doc_hiseq(param_doc, warnings)?
+        }
+        FolderLayout::Aviti => bail!("AVITI run folders have no XML run parameters"),
+        FolderLayout::Mgi => bail!("MGI output directories have no XML run parameters"),
+        FolderLayout::Nanopore => bail!("MinKNOW run directories have no XML run parameters"),
     };
+    if folder_layout == FolderLayout::NextSeq2000 || is_nextseq2000_param_doc(param_doc) {
+        run_params.instrument_model = nextseq2000_model(param_doc, &run_info.instrument);
+        match run_params.instrument_model {
+            Some(model) => debug!(logger, "Instrument model is {}", model),
+            None => warnings.push(Warning::new(
+                WarningKind::LayoutQuirk,
+                format!(
+                    "Could not tell NextSeq 1000 from 2000 for instrument {}",
+                    &run_info.instrument
+                ),
+            )),
+        }
+    }
     debug!(logger, "RunParameters => {:?}", &run_params);
 
     Ok((run_info, run_params))
 }
 
+/// Return the flow cell slot from the name of the run folder, `folder_name`, if its last token
+/// is the flow cell ID `flowcell` prefixed by `A` or `B` (e.g., `180101_A00123_0042_BHXXXXXXXX`).
+pub fn slot_from_folder_name(folder_name: &str, flowcell: &str) -> Option<String> {
+    let token = folder_name.rsplit('_').next()?;
+    if flowcell.is_empty() || token.len() != flowcell.len() + 1 {
+        return None;
+    }
+    let (slot, rest) = token.split_at(1);
+    if (slot == "A" || slot == "B") && rest.eq_ignore_ascii_case(flowcell) {
+        Some(slot.to_string())
+    } else {
+        None
+    }
+}
+
+/// Cross-check the flow cell slot of `run_params` with the A/B indicator in the name of the run
+/// folder at `path` and fill it in.
+///
+/// The slot from the run parameters is kept, a disagreeing folder name is reported in
+/// `warnings`.  If the run parameters give no slot, it is taken from the folder name and
+/// defaults to `A`.
+pub fn resolve_flowcell_slot(
+    path: &Path,
+    run_info: &RunInfo,
+    run_params: &mut RunParameters,
+    warnings: &mut Vec<Warning>,
+) {
+    let folder_slot = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| slot_from_folder_name(name, &run_info.flowcell));
+    match folder_slot {
+        Some(folder_slot) if run_params.flowcell_slot.is_empty() => {
+            run_params.flowcell_slot = folder_slot;
+        }
+        Some(folder_slot) if run_params.flowcell_slot != folder_slot => {
+            warnings.push(Warning::new(
+                WarningKind::SlotMismatch,
+                format!(
+                    "Run parameters give slot {} but the folder name indicates slot {}",
+                    &run_params.flowcell_slot, &folder_slot
+                ),
+            ));
+        }
+        _ => {
+            if run_params.flowcell_slot.is_empty() {
+                run_params.flowcell_slot = "A".to_string();
+            }
+        }
+    }
+}
+
+/// Return the cycles of `run_info` that have a cycle directory (`C*.1`) in the first lane of the
+/// run folder at `path`, empty for layouts without cycle directories.
+pub fn present_cycles(path: &Path, run_info: &RunInfo) -> Vec<i32> {
+    let lane_dir = path
+        .join("Data")
+        .join("Intensities")
+        .join("BaseCalls")
+        .join("L001");
+    let num_cycles: i32 = run_info.reads.iter().map(|desc| desc.num_cycles).sum();
+    (1..=num_cycles)
+        .filter(|cycle| lane_dir.join(format!("C{}.1", cycle)).exists())
+        .collect()
+}
+
+/// Return the cycles that are missing from the cycle directories (`C*.1`) of the first lane of
+/// a run folder whose template cycles have been removed, e.g., when archiving.
+///
+/// A folder is considered truncated if a cycle directory is missing while a later one exists,
+/// which cannot happen while the sequencer is still writing.  Returns `None` for complete or
+/// in-progress folders and for layouts without cycle directories.
+pub fn truncated_cycles(path: &Path, run_info: &RunInfo) -> Option<Vec<i32>> {
+    let num_cycles: i32 = run_info.reads.iter().map(|desc| desc.num_cycles).sum();
+    let present = present_cycles(path, run_info);
+    let last_present = *present.last()?;
+    let missing = (1..last_present)
+        .filter(|cycle| !present.contains(cycle))
+        .chain((last_present + 1)..=num_cycles)
+        .collect::<Vec<i32>>();
+    if missing.iter().any(|cycle| *cycle < last_present) {
+        Some(missing)
+    } else {
+        None
+    }
+}
+
+/// Return the cycles of the index reads from `run_info`.
+pub fn index_cycles(run_info: &RunInfo) -> Vec<i32> {
+    let mut result = Vec::new();
+    let mut cycle = 1;
+    for desc in &run_info.reads {
+        if desc.is_index {
+            result.extend(cycle..(cycle + desc.num_cycles));
+        }
+        cycle += desc.num_cycles;
+    }
+    result
+}
+
+/// Return whether `run_info` describes any index read with cycles.
+pub fn has_index_reads(run_info: &RunInfo) -> bool {
+    run_info
+        .reads
+        .iter()
+        .any(|desc| desc.is_index && desc.num_cycles > 0)
+}
+
+/// Format `cycles` as a compact list of ranges, e.g., `1-151, 160-310`.
+pub fn format_cycle_ranges(cycles: &[i32]) -> String {
+    let mut ranges: Vec<(i32, i32)> = Vec::new();
+    for &cycle in cycles {
+        match ranges.last_mut() {
+            Some(ref mut range) if range.1 + 1 == cycle => range.1 = cycle,
+            _ => ranges.push((cycle, cycle)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                format!("{}", first)
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Return the name of the file that marks the run folder of the given `folder_layout` as
+/// completely written.
+///
+/// The NovaSeq, NovaSeq X, and NextSeq 1000/2000 write `CopyComplete.txt` after copying all
+/// files to the output folder, which can be well after `RTAComplete.txt` appeared.  The AVITI
+/// writes `RunUploaded.json`.
+pub fn copy_complete_file(folder_layout: FolderLayout) -> &'static str {
+    match folder_layout {
+        FolderLayout::Aviti => aviti::RUN_COMPLETE_FILE,
+        FolderLayout::NovaSeq | FolderLayout::NovaSeqXplus | FolderLayout::NextSeq2000 => {
+            "CopyComplete.txt"
+        }
+        _ => "RTAComplete.txt",
+    }
+}
+
 pub fn get_status_sequencing(
     run_info: &RunInfo,
     run_params: &RunParameters,
     path: &Path,
     current_status: &str,
 ) -> String {
+    // Archived runs may lack their template cycles (and `RTAComplete.txt`) but such a run
+    // is finished if its index cycles are intact.
+    let truncated_complete = truncated_cycles(path, run_info)
+        .map(|missing| {
+            index_cycles(run_info)
+                .iter()
+                .all(|cycle| !missing.contains(cycle))
+        })
+        .unwrap_or(false);
+
     if current_status == "closed" || current_status == "complete" {
         // has final status
-        return current_status.to_string();
+        current_status.to_string()
     } else if (!run_params.planned_reads.is_empty()) && (run_info.reads != run_params.planned_reads)
     {
-        return "failed".to_string();
-    } else if path.join("RTAComplete.txt").exists() {
-        return "complete".to_string();
+        "failed".to_string()
+    } else if meta_file_exists(path, "RTAComplete.txt")
+        || meta_file_exists(path, aviti::RUN_COMPLETE_FILE)
+        || mgi::is_complete(path)
+        || nanopore::is_complete(path)
+        || truncated_complete
+    {
+        "complete".to_string()
+    } else if rta_major_version(&run_params.rta_version).is_some_and(|major| major >= RTA4)
+        && meta_file_exists(path, "RTAExited.txt")
+    {
+        // RTA 4 writes `RTAExited.txt` when it stops, so the run was aborted.
+        "failed".to_string()
     } else {
-        return "in_progress".to_string();
+        "in_progress".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sxd_document::parser;
+
+    /// Return the run parameters parsed from the NextSeq 1000/2000 `contents`, and the model.
+    fn parse_nextseq2000(contents: &str) -> (RunParameters, Option<InstrumentModel>) {
+        let package = parser::parse(contents).unwrap();
+        let param_doc = package.as_document();
+        assert!(is_nextseq2000_param_doc(&param_doc));
+        (
+            process_xml_param_doc_nextseq2000(&param_doc).unwrap(),
+            nextseq2000_model(&param_doc, ""),
+        )
+    }
+
+    /// Return the read descriptions for the `(num_cycles, is_index)` of each read.
+    fn reads(reads: &[(i32, bool)]) -> Vec<ReadDescription> {
+        reads
+            .iter()
+            .enumerate()
+            .map(|(i, (num_cycles, is_index))| ReadDescription {
+                number: i as i32 + 1,
+                num_cycles: *num_cycles,
+                is_index: *is_index,
+                is_reverse_complement: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nextseq2000_read_elements() {
+        let (params, model) = parse_nextseq2000(include_str!(
+            "../../tests/data/nextseq2000/RunParameters_read_elements.xml"
+        ));
+        assert_eq!(
+            params.planned_reads,
+            reads(&[(151, false), (10, true), (10, true), (151, false)])
+        );
+        assert_eq!(params.rta_version, "3.7.17");
+        assert_eq!(params.run_number, 42);
+        assert_eq!(params.flowcell_slot, "A");
+        assert_eq!(params.experiment_name, "Read elements");
+        assert_eq!(model, Some(InstrumentModel::NextSeq2000));
+    }
+
+    #[test]
+    fn nextseq2000_planned_reads() {
+        // The planned reads take precedence over the kit defaults in `<Read1>` etc.
+        let (params, model) = parse_nextseq2000(include_str!(
+            "../../tests/data/nextseq2000/RunParameters_planned_reads.xml"
+        ));
+        assert_eq!(
+            params.planned_reads,
+            reads(&[(28, false), (8, true), (91, false)])
+        );
+        assert_eq!(params.rta_version, "3.10.30");
+        assert_eq!(params.run_number, 7);
+        assert_eq!(params.flowcell_slot, "B");
+        assert_eq!(params.experiment_name, "Planned reads");
+        assert_eq!(model, Some(InstrumentModel::NextSeq1000));
+    }
+
+    /// Return the date `2024-06-03`, as written by the instruments in the examples below.
+    fn june_3() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 6, 3).unwrap()
+    }
+
+    #[test]
+    fn run_date_formats() {
+        // One example per entry of `RUN_DATE_FORMATS`, in the same order.
+        let examples = [
+            "240603",
+            "20240603",
+            "6/3/2024 10:15:30 AM",
+            "6/3/2024 22:15:30",
+            "6/3/2024",
+            "2024-06-03T10:15:30Z",
+            "2024-06-03T10:15:30.1234567Z",
+            "2024-06-03T10:15:30+02:00",
+            "2024-06-03T10:15:30.123+02:00",
+            "2024-06-03T10:15:30",
+            "2024-06-03 10:15:30",
+            "2024-06-03",
+            "03-Jun-24",
+            "03-Jun-2024",
+        ];
+        assert_eq!(examples.len(), RUN_DATE_FORMATS.len());
+        for (example, format) in examples.iter().zip(RUN_DATE_FORMATS.iter()) {
+            assert_eq!(
+                parse_date_with(example, format),
+                Some(june_3()),
+                "{}",
+                format
+            );
+            assert_eq!(parse_run_date(example, ""), Some(june_3()), "{}", example);
+        }
+        // Surrounding whitespace of the XML text is ignored.
+        assert_eq!(parse_run_date("\n  240603\n", ""), Some(june_3()));
+    }
+
+    #[test]
+    fn run_date_keeps_local_date_of_time_zone() {
+        // 2024-06-04 in UTC, but the instrument's date is taken.
+        assert_eq!(
+            parse_run_date("2024-06-03T22:15:30-05:00", ""),
+            Some(june_3())
+        );
+        assert_eq!(
+            parse_run_date("2024-06-03T00:15:30+02:00", ""),
+            Some(june_3())
+        );
+    }
+
+    #[test]
+    fn run_date_from_epoch() {
+        // 2024-06-03T08:15:30Z in seconds and milliseconds.
+        for value in &[
+            "1717402530",
+            "1717402530.5",
+            "1717402530123",
+            "1717402530123.0",
+        ] {
+            assert_eq!(parse_run_date(value, ""), Some(june_3()), "{}", value);
+        }
+        // Seconds since the epoch are taken in UTC.
+        assert_eq!(
+            parse_run_date("1717372800", ""),
+            Some(june_3()),
+            "2024-06-03T00:00:00Z"
+        );
+        assert_eq!(
+            parse_run_date("1717372799", ""),
+            NaiveDate::from_ymd_opt(2024, 6, 2)
+        );
+    }
+
+    #[test]
+    fn run_date_custom_format() {
+        assert_eq!(parse_run_date("03.06.2024", "%d.%m.%Y"), Some(june_3()));
+        assert_eq!(parse_run_date("03.06.2024", ""), None);
+        // The custom format is tried before the built-in ones.
+        assert_eq!(parse_run_date("030624", "%d%m%y"), Some(june_3()));
+        assert_eq!(
+            parse_run_date("030624", ""),
+            NaiveDate::from_ymd_opt(2003, 6, 24)
+        );
+        // The built-in formats are still tried if the custom one does not match.
+        assert_eq!(parse_run_date("2024-06-03", "%d.%m.%Y"), Some(june_3()));
+    }
+
+    #[test]
+    fn run_date_invalid() {
+        for value in &[
+            "",
+            "yesterday",
+            "2024-13-03",
+            "31-Jun-24",
+            "1717402",
+            "17174025301234",
+            "1717402530.1.2",
+        ] {
+            assert_eq!(parse_run_date(value, ""), None, "{:?}", value);
+        }
+    }
+
+    /// Return the `RunInfo.xml` of a run with ID `run_id` and the `<Date>` `date`.
+    fn run_info_xml(run_id: &str, date: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+<RunInfo Version="6">
+  <Run Id="{}" Number="42">
+    <Flowcell>HXXXXCCXX</Flowcell>
+    <Instrument>E00123</Instrument>
+    <Date>{}</Date>
+    <Reads>
+      <Read Number="1" NumCycles="151" IsIndexedRead="N"/>
+    </Reads>
+    <FlowcellLayout LaneCount="1"/>
+  </Run>
+</RunInfo>"#,
+            run_id, date
+        )
+    }
+
+    #[test]
+    fn run_info_date_falls_back_to_run_id() {
+        let contents = run_info_xml("240603_E00123_0042_AHXXXXCCXX", "sometime");
+        let package = parser::parse(&contents).unwrap();
+        let mut warnings = Vec::new();
+        let run_info = process_xml_run_info(&package.as_document(), "", &mut warnings).unwrap();
+        assert_eq!(run_info.date, "2024-06-03");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::DateFallback);
+
+        let contents = run_info_xml("RUN_E00123_0042_AHXXXXCCXX", "sometime");
+        let package = parser::parse(&contents).unwrap();
+        assert!(process_xml_run_info(&package.as_document(), "", &mut Vec::new()).is_err());
     }
 }
@@ -0,0 +1,396 @@
+//! Implementation of the `api check` command that checks the connection to Digestiflow Web: the
+//! server URL, the API token, the project, and the endpoints used by `ingest`.
+//!
+//! The write endpoints are probed with empty payloads or for a flow cell that does not exist,
+//! which the server rejects, such that nothing is created or changed.
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::*;
+
+/// Timeout for each request.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Instrument of the flow cell that does not exist, for probing the resolve endpoint.
+const PROBE_INSTRUMENT: &str = "digestiflow-cli-check";
+/// Vendor ID of the flow cell that does not exist, for probing the resolve endpoint.
+const PROBE_VENDOR_ID: &str = "NOSUCHFLOWCELL";
+/// The names of the checks, in the order they are run.
+const CHECKS: &[&str] = &[
+    "configuration",
+    "server reachable",
+    "API token",
+    "project",
+    "resolve flow cells",
+    "register flow cells",
+    "update flow cells",
+    "list index histograms",
+    "post index histograms",
+    "post messages",
+];
+
+/// The outcome of one check.
+#[derive(Debug)]
+enum Outcome {
+    /// The check passed, with details.
+    Passed(String),
+    /// The check failed, with the reason and what to do about it.
+    Failed(String),
+    /// The check was not run as an earlier one failed.
+    Skipped,
+}
+
+/// Return whether the `body` of an error response comes from the Digestiflow API (i.e., is JSON)
+/// rather than from the web server or a proxy in front of it.
+fn is_api_response(body: &str) -> bool {
+    serde_json::from_str::<Value>(body).is_ok()
+}
+
+/// Return the message of the API error response `body`, empty if it is none.
+fn api_detail(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(Value::Object(object)) => match object.get("detail") {
+            Some(Value::String(detail)) => detail.clone(),
+            _ => Value::Object(object).to_string(),
+        },
+        Ok(value) => value.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Describe the failure `err` of the request to `path` on the server at `url`, with what to do
+/// about it.
+pub fn describe_error(url: &str, path: &str, err: &restson::Error) -> String {
+    match err {
+        restson::Error::UrlError => format!("{:?} is no valid URL", url),
+        restson::Error::RequestError => format!(
+            "Cannot connect to {}; check the URL, the network connection, and the proxy and \
+             firewall settings",
+            url
+        ),
+        restson::Error::TimeoutError => format!(
+            "No response from {} within {}s; check the proxy and firewall settings",
+            url,
+            CHECK_TIMEOUT.as_secs()
+        ),
+        restson::Error::ParseError(e) => format!(
+            "The response for /{} is not the JSON expected from Digestiflow Web ({}); the URL \
+             may point to another application or to the login page of a proxy",
+            path, e
+        ),
+        restson::Error::HttpError(401, _) => format!(
+            "The API token was rejected for /{} (HTTP 401), it is invalid or was deleted; create \
+             a new one in Digestiflow Web through the user menu, item \"API Tokens\"",
+            path
+        ),
+        restson::Error::HttpError(403, body) => format!(
+            "Permission denied for /{} (HTTP 403: {}); the user of the API token needs the \
+             contributor role in the project",
+            path,
+            api_detail(body)
+        ),
+        restson::Error::HttpError(code, body)
+            if (*code == 404 || *code == 405) && !is_api_response(body) =>
+        {
+            format!(
+                "/{} not found (HTTP {}) and the response does not come from the Digestiflow \
+                 API; check the URL for a missing or extra path prefix, or update Digestiflow \
+                 Web",
+                path, code
+            )
+        }
+        restson::Error::HttpError(code, _) if *code >= 500 => format!(
+            "Server error for /{} (HTTP {}); the server is reachable but failing, check its logs",
+            path, code
+        ),
+        restson::Error::HttpError(code, body) => format!(
+            "Unexpected response for /{} (HTTP {}: {})",
+            path,
+            code,
+            api_detail(body)
+        ),
+        e => format!("Request to /{} failed: {:?}", path, e),
+    }
+}
+
+/// Return the outcome of probing the endpoint `path` with a request for an invalid payload or a
+/// missing object, given its `result`.
+///
+/// The endpoint is available if the request succeeded or the API rejected it as invalid (HTTP
+/// 400) or for the missing object (HTTP 404).
+fn probe_outcome<T>(url: &str, path: &str, result: result::Result<T, restson::Error>) -> Outcome {
+    match result {
+        Ok(_) => Outcome::Passed(format!("/{} is available", path)),
+        Err(restson::Error::HttpError(code, ref body))
+            if (code == 400 || code == 404) && is_api_response(body) =>
+        {
+            Outcome::Passed(format!("/{} is available", path))
+        }
+        Err(e) => Outcome::Failed(describe_error(url, path, &e)),
+    }
+}
+
+/// Check the configuration of the server in `web` and construct the client for it, with a
+/// timeout of `CHECK_TIMEOUT` for each request.
+pub fn build_check_client(web: &Web) -> result::Result<api::Client, String> {
+    if web.url.is_empty() {
+        return Err(
+            "No server URL configured; use --web-url or set `url` in the [web] section of \
+             ~/.digestiflowrc.toml"
+                .to_string(),
+        );
+    }
+    if web.token.is_empty() && web.token_command.is_empty() {
+        return Err(
+            "No API token configured; set `token` or `token_command` in the [web] section of \
+             ~/.digestiflowrc.toml"
+                .to_string(),
+        );
+    }
+    if !api::API_SCHEMAS.contains(&web.api_schema) {
+        return Err(format!(
+            "Invalid API schema {}, supported are {:?}",
+            web.api_schema,
+            api::API_SCHEMAS
+        ));
+    }
+    let token = if web.token_command.is_empty() {
+        web.token.clone()
+    } else {
+        api::run_token_command(&web.token_command).map_err(|e| {
+            format!(
+                "Could not fetch API token: {}; check `token_command` in the [web] section of \
+                 ~/.digestiflowrc.toml",
+                e
+            )
+        })?
+    };
+    let mut client = RestClient::new(&web.url)
+        .map_err(|e| format!("Invalid server URL {:?}: {:?}", &web.url, e))?;
+    client
+        .set_header("Authorization", &format!("Token {}", &token))
+        .map_err(|e| format!("Problem configuring REST client: {:?}", e))?;
+    client.set_timeout(CHECK_TIMEOUT);
+    Ok(api::Client::new(
+        client,
+        web.requests_per_second,
+        web.api_schema,
+    ))
+}
+
+/// Check that the server in `web` accepts the API token and gives access to the project
+/// `project_uuid`, returning the title of the project or the reason for the failure with what
+/// to do about it.
+pub fn check_project_access(web: &Web, project_uuid: &Uuid) -> result::Result<String, String> {
+    let mut client = build_check_client(web)?;
+    let projects = match client.get(()) {
+        Ok(api::ProjectArray::Array(projects)) => projects,
+        Err(e) => return Err(describe_error(&web.url, "api/projects/", &e)),
+    };
+    match projects
+        .iter()
+        .find(|project| project.sodar_uuid == *project_uuid)
+    {
+        Some(project) => Ok(project.title.clone()),
+        None => Err(format!(
+            "Project {} is not readable with the API token; readable are: {}",
+            project_uuid,
+            projects
+                .iter()
+                .map(|project| format!("{} ({})", &project.title, &project.sodar_uuid))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Run the checks of `CHECKS` against the server in `web` with the project `project_uuid`,
+/// appending their outcomes to `outcomes` until one fails that the others depend on.
+fn run_checks(web: &Web, project_uuid: &Uuid, outcomes: &mut Vec<Outcome>) {
+    let url = &web.url;
+    let client = build_check_client(web).and_then(|client| {
+        if project_uuid.is_nil() {
+            Err(
+                "No project UUID given; use --project-uuid or set `project_uuid` in the [ingest] \
+                 section of ~/.digestiflowrc.toml"
+                    .to_string(),
+            )
+        } else {
+            Ok(client)
+        }
+    });
+    let mut client = match client {
+        Ok(client) => {
+            outcomes.push(Outcome::Passed(format!(
+                "server {}, API schema {}",
+                url, web.api_schema
+            )));
+            client
+        }
+        Err(message) => {
+            outcomes.push(Outcome::Failed(message));
+            return;
+        }
+    };
+
+    // Listing the projects needs a valid token.
+    let path = "api/projects/";
+    let projects = match client.get(()) {
+        Ok(api::ProjectArray::Array(projects)) => {
+            outcomes.push(Outcome::Passed(format!("{} responded", url)));
+            outcomes.push(Outcome::Passed(format!(
+                "accepted, {} project(s) readable",
+                projects.len()
+            )));
+            projects
+        }
+        Err(restson::Error::HttpError(code, body)) if code == 401 || code == 403 => {
+            outcomes.push(Outcome::Passed(format!(
+                "{} responded with HTTP {}",
+                url, code
+            )));
+            outcomes.push(Outcome::Failed(describe_error(
+                url,
+                path,
+                &restson::Error::HttpError(code, body),
+            )));
+            return;
+        }
+        Err(e) => {
+            outcomes.push(Outcome::Failed(describe_error(url, path, &e)));
+            return;
+        }
+    };
+
+    let path = format!("api/flowcells/{}/", project_uuid);
+    let flowcells = match client.get(&api::ProjectArgs {
+        project_uuid: *project_uuid,
+    }) {
+        Ok(api::FlowCellArray::Array(flowcells)) => flowcells,
+        Err(restson::Error::HttpError(404, ref body)) if is_api_response(body) => {
+            outcomes.push(Outcome::Failed(format!(
+                "Project {} not found or not readable with the API token; readable are: {}",
+                project_uuid,
+                projects
+                    .iter()
+                    .map(|project| format!("{} ({})", &project.title, &project.sodar_uuid))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )));
+            return;
+        }
+        Err(e) => {
+            outcomes.push(Outcome::Failed(describe_error(url, &path, &e)));
+            return;
+        }
+    };
+    outcomes.push(Outcome::Passed(format!(
+        "{:?} with {} flow cell(s)",
+        projects
+            .iter()
+            .find(|project| project.sodar_uuid == *project_uuid)
+            .map_or("(title unknown)", |project| project.title.as_str()),
+        flowcells.len()
+    )));
+
+    let args = api::ResolveFlowCellArgs {
+        project_uuid: *project_uuid,
+        instrument: PROBE_INSTRUMENT.to_string(),
+        run_number: 0,
+        flowcell: PROBE_VENDOR_ID.to_string(),
+    };
+    let path = format!(
+        "api/flowcells/resolve/{}/{}/{}/{}/",
+        project_uuid, &args.instrument, args.run_number, &args.flowcell
+    );
+    let result: result::Result<api::FlowCell, restson::Error> = client.get(&args);
+    outcomes.push(probe_outcome(url, &path, result));
+
+    let args = api::PathArgs {
+        path: format!("api/flowcells/{}/", project_uuid),
+    };
+    let result: result::Result<Value, restson::Error> =
+        client.post_capture(&args, &api::EmptyPayload {});
+    outcomes.push(probe_outcome(url, &args.path, result));
+
+    // The flow cell that does not exist is used for the write endpoints, an existing one (if
+    // any) for listing the index histograms.
+    let missing_uuid = Uuid::nil();
+    let args = api::PathArgs {
+        path: format!("api/flowcells/{}/{}/", project_uuid, &missing_uuid),
+    };
+    let result: result::Result<api::Delivery<Value>, restson::Error> =
+        client.put_capture(&args, &api::EmptyPayload {});
+    outcomes.push(probe_outcome(url, &args.path, result));
+
+    let flowcell_uuid = flowcells
+        .iter()
+        .filter_map(|flowcell| flowcell.sodar_uuid)
+        .next()
+        .unwrap_or(missing_uuid);
+    let path = format!("api/indexhistos/{}/{}/", project_uuid, &flowcell_uuid);
+    let result: result::Result<api::LaneIndexHistogramArray, restson::Error> =
+        client.get(&api::ProjectFlowcellArgs {
+            project_uuid: *project_uuid,
+            flowcell_uuid,
+        });
+    outcomes.push(probe_outcome(url, &path, result));
+
+    for endpoint in &["indexhistos", "messages"] {
+        let args = api::PathArgs {
+            path: format!("api/{}/{}/{}/", endpoint, project_uuid, &missing_uuid),
+        };
+        let result: result::Result<Value, restson::Error> =
+            client.post_capture(&args, &api::EmptyPayload {});
+        outcomes.push(probe_outcome(url, &args.path, result));
+    }
+}
+
+/// Run the checks against the server in `web` and print their outcomes, returning the number of
+/// failed checks.
+fn check_server(logger: &slog::Logger, web: &Web, project_uuid: &Uuid) -> usize {
+    info!(logger, "Checking server {:?}...", &web.url);
+    let mut outcomes = Vec::new();
+    run_checks(web, project_uuid, &mut outcomes);
+    while outcomes.len() < CHECKS.len() {
+        outcomes.push(Outcome::Skipped);
+    }
+
+    println!("Server {}:", &web.url);
+    let mut num_failed = 0;
+    for (name, outcome) in CHECKS.iter().zip(outcomes.iter()) {
+        match outcome {
+            Outcome::Passed(detail) => println!("  [ OK ] {}: {}", name, detail),
+            Outcome::Failed(message) => {
+                num_failed += 1;
+                println!("  [FAIL] {}: {}", name, message)
+            }
+            Outcome::Skipped => println!("  [SKIP] {}", name),
+        }
+    }
+    num_failed
+}
+
+/// Main entry point for the `api check` command.
+///
+/// Checks the server `settings.web` (and `settings.secondary_web`, if configured) with the
+/// project `settings.ingest.project_uuid` and prints the outcome of each check.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client api check");
+
+    let mut num_failed = check_server(logger, &settings.web, &settings.ingest.project_uuid);
+    if !settings.secondary_web.url.is_empty() {
+        println!();
+        num_failed += check_server(
+            logger,
+            &settings.secondary_web,
+            &settings.ingest.project_uuid,
+        );
+    }
+
+    if num_failed > 0 {
+        bail!("{} check(s) failed", num_failed)
+    } else {
+        Ok(())
+    }
+}
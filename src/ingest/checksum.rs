@@ -0,0 +1,181 @@
+//! Checksums of files for the manifests of run folders, see the `manifest` command: XXH64 for
+//! speed and MD5 for compatibility with `md5sum`.
+//!
+//! The files are streamed through the hash functions, the digests are formatted as by `xxhsum`
+//! and `md5sum`.
+
+use md5::{Digest, Md5};
+use std::hash::Hasher as _;
+use std::io::{self, Read};
+use std::path::Path;
+use twox_hash::XxHash64;
+
+use super::super::errors::*;
+use chaos;
+
+/// Size of the buffer for reading files.
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// A checksum algorithm for manifests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// 64 bit xxHash, as written by `xxhsum`.
+    Xxh64,
+    /// MD5, as written by `md5sum`.
+    Md5,
+}
+
+impl Algorithm {
+    /// Return the algorithm with the given `name`, `xxh64` or `md5`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "xxh64" => Ok(Algorithm::Xxh64),
+            "md5" => Ok(Algorithm::Md5),
+            _ => bail!(
+                "Unknown checksum algorithm {:?}, must be xxh64 or md5",
+                name
+            ),
+        }
+    }
+
+    /// Return the algorithm whose digests have the length of `digest`, if any.
+    pub fn from_digest(digest: &str) -> Option<Self> {
+        match digest.len() {
+            16 => Some(Algorithm::Xxh64),
+            32 => Some(Algorithm::Md5),
+            _ => None,
+        }
+    }
+
+    /// Return the name of the algorithm, also used as extension of manifests.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Xxh64 => "xxh64",
+            Algorithm::Md5 => "md5",
+        }
+    }
+
+    /// Return the hex digest of the file at `path`.
+    pub fn file_digest(self, path: &Path) -> io::Result<String> {
+        self.digest(chaos::open(path)?)
+    }
+
+    /// Return the hex digest of everything read from `reader`.
+    fn digest<R: Read>(self, reader: R) -> io::Result<String> {
+        match self {
+            Algorithm::Xxh64 => {
+                let mut hasher = XxHash64::with_seed(0);
+                feed(reader, |data| hasher.write(data))?;
+                Ok(format!("{:016x}", hasher.finish()))
+            }
+            Algorithm::Md5 => {
+                let mut hasher = Md5::new();
+                feed(reader, |data| hasher.update(data))?;
+                Ok(hasher
+                    .finalize()
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Pass everything read from `reader` to `update` in pieces.
+fn feed<R: Read, F: FnMut(&[u8])>(mut reader: R, mut update: F) -> io::Result<()> {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(num_read) => update(&buffer[..num_read]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader returning the data in pieces of at most `piece` bytes, for streaming the data
+    /// across the block boundaries of the hash functions.
+    struct PieceReader<'a> {
+        data: &'a [u8],
+        piece: usize,
+    }
+
+    impl<'a> Read for PieceReader<'a> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let num_read = self.piece.min(buffer.len()).min(self.data.len());
+            buffer[..num_read].copy_from_slice(&self.data[..num_read]);
+            self.data = &self.data[num_read..];
+            Ok(num_read)
+        }
+    }
+
+    /// Return the hex digest of `data` with `algorithm`, read at once.
+    fn digest(algorithm: Algorithm, data: &[u8]) -> String {
+        algorithm.digest(data).unwrap()
+    }
+
+    #[test]
+    fn md5_rfc1321_test_suite() {
+        let vectors: [(&str, &str); 7] = [
+            ("", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("a", "0cc175b9c0f1b6a831c399e269772661"),
+            ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                "abcdefghijklmnopqrstuvwxyz",
+                "c3fcd3d76192e4007dfb496cca67e13b",
+            ),
+            (
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "d174ab98d277d9f5a5611c2c9f419d9f",
+            ),
+            (
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+        for (input, expected) in &vectors {
+            assert_eq!(digest(Algorithm::Md5, input.as_bytes()), *expected);
+        }
+    }
+
+    #[test]
+    fn xxh64_reference_vectors() {
+        let vectors: [(&str, &str); 4] = [
+            ("", "ef46db3751d8e999"),
+            ("a", "d24ec4f1a98c6e5b"),
+            ("abc", "44bc2cf5ad770999"),
+            (
+                "Nobody inspects the spammish repetition",
+                "fbcea83c8a378bf1",
+            ),
+        ];
+        for (input, expected) in &vectors {
+            assert_eq!(digest(Algorithm::Xxh64, input.as_bytes()), *expected);
+        }
+    }
+
+    #[test]
+    fn digest_does_not_depend_on_pieces() {
+        // Lengths around the 32 byte stripes of XXH64 and the 64 byte blocks of MD5, including
+        // the length of MD5 padding that needs an extra block.
+        let data = (0..300u32).map(|i| (i * 7 + 3) as u8).collect::<Vec<u8>>();
+        for algorithm in &[Algorithm::Xxh64, Algorithm::Md5] {
+            for len in &[31, 32, 33, 55, 56, 63, 64, 65, 127, 128, 129, 300] {
+                let expected = digest(*algorithm, &data[..*len]);
+                for piece in &[1, 7, 31, 32, 33, 63, 64, 65] {
+                    let reader = PieceReader {
+                        data: &data[..*len],
+                        piece: *piece,
+                    };
+                    assert_eq!(algorithm.digest(reader).unwrap(), expected);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Implementation of the `report --compare` command that tabulates the read structures, yields,
+//! top barcodes, and quality metrics of several runs side by side, e.g., for a weekly overview.
+
+use std::collections::{BTreeSet, HashMap};
+
+use super::*;
+use anonymize::Anonymizer;
+
+/// Number of most frequent index sequences shown per lane and index read.
+const TOP_BARCODES: usize = 3;
+
+/// The columns of one run in the comparison.
+#[derive(Debug, Default)]
+struct RunSummary {
+    /// The values of the rows, by row label.
+    values: HashMap<String, String>,
+}
+
+impl RunSummary {
+    /// Set the value of the row `label`.
+    fn set(&mut self, label: &str, value: String) {
+        self.values.insert(label.to_string(), value);
+    }
+}
+
+/// Return `value` formatted with `precision` digits after scaling by `scale`, or `-`.
+fn format_opt(value: Option<f64>, scale: f64, precision: usize) -> String {
+    value.map_or("-".to_string(), |value| {
+        format!("{:.*}", precision, value * scale)
+    })
+}
+
+/// Return the mean of the known `values`, if any.
+fn mean(values: impl Iterator<Item = Option<f64>>) -> Option<f64> {
+    let values = values.flatten().collect::<Vec<f64>>();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Return the label of the row with the top barcodes of `lane` and `index_read_no`.
+fn barcodes_label(lane: i32, index_read_no: i32) -> String {
+    format!("top barcodes L{} I{}", lane, index_read_no)
+}
+
+/// Summarize the run folder at `path`, with the top barcodes from the index histograms of its
+/// flow cell if `client` is given.
+///
+/// The labels of the rows with top barcodes are added to `barcode_rows`.
+fn summarize(
+    logger: &slog::Logger,
+    client: Option<&mut api::Client>,
+    path: &Path,
+    settings: &Settings,
+    barcode_rows: &mut BTreeSet<(i32, i32)>,
+) -> Result<RunSummary> {
+    let folder_layout = match configured_folder_layout(path, settings)? {
+        Some(folder_layout) => folder_layout,
+        None => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules()))
+            .chain_err(|| format!("Could not guess folder layout from {:?}", path))?,
+    };
+    let (run_info, _) = parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut Vec::new(),
+    )?;
+
+    let mut summary = RunSummary::default();
+    summary.set("run", run_info.run_id.clone());
+    summary.set("date", run_info.date.clone());
+    summary.set("instrument", run_info.instrument.clone());
+    summary.set("flow cell", run_info.flowcell.clone());
+    summary.set("layout", format!("{:?}", folder_layout));
+    summary.set("lanes", run_info.lane_count.to_string());
+    summary.set("read structure", string_description(&run_info.reads));
+
+    let thresholds = interop::LaneThresholds::from_settings(settings);
+    let lanes = interop::lane_quality(path, &thresholds)
+        .chain_err(|| format!("Problem reading InterOp metrics of {:?}", path))?;
+    let clusters = lanes.iter().map(|lane| lane.clusters).sum::<Option<f64>>();
+    let clusters_pf = lanes
+        .iter()
+        .map(|lane| lane.clusters_pf)
+        .sum::<Option<f64>>()
+        .filter(|_| !lanes.is_empty());
+    let num_cycles: i32 = run_info.reads.iter().map(|desc| desc.num_cycles).sum();
+    summary.set("clusters PF (M)", format_opt(clusters_pf, 1e-6, 1));
+    summary.set(
+        "yield (Gb)",
+        format_opt(clusters_pf.map(|pf| pf * f64::from(num_cycles)), 1e-9, 2),
+    );
+    summary.set(
+        "% PF",
+        format_opt(
+            clusters
+                .filter(|clusters| *clusters > 0.0)
+                .and_then(|clusters| clusters_pf.map(|pf| pf / clusters)),
+            100.0,
+            1,
+        ),
+    );
+    summary.set(
+        "density (K/mm²)",
+        format_opt(mean(lanes.iter().map(|lane| lane.density)), 0.001, 0),
+    );
+    summary.set(
+        "error rate (%)",
+        format_opt(mean(lanes.iter().map(|lane| lane.error_rate)), 1.0, 2),
+    );
+    let failed = lanes
+        .iter()
+        .filter(|lane| lane.is_failed())
+        .map(|lane| lane.lane.to_string())
+        .collect::<Vec<String>>();
+    summary.set(
+        "failed lanes",
+        if lanes.is_empty() {
+            "-".to_string()
+        } else if failed.is_empty() {
+            "none".to_string()
+        } else {
+            failed.join(", ")
+        },
+    );
+
+    let client = match client {
+        Some(client) => client,
+        None => return Ok(summary),
+    };
+    let result: result::Result<api::FlowCell, restson::Error> =
+        client.get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: run_info.instrument.clone(),
+            run_number: run_info.run_number,
+            flowcell: run_info.flowcell.clone(),
+        });
+    let flowcell = match result {
+        Ok(flowcell) => flowcell,
+        Err(restson::Error::HttpError(404, _)) => {
+            info!(
+                logger,
+                "Flow cell {} is not registered, no barcodes", &run_info.flowcell
+            );
+            return Ok(summary);
+        }
+        Err(e) => return Err(e).chain_err(|| "Could not query flow cell from server"),
+    };
+    let api::LaneIndexHistogramArray::Array(histograms) = client
+        .get(&api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            flowcell_uuid: flowcell
+                .sodar_uuid
+                .chain_err(|| "Flow cell from server has no UUID")?,
+        })
+        .chain_err(|| "Could not query index histograms from server")?;
+    for hist in &histograms {
+        let total: usize = hist.histogram.0.iter().map(|(_, count)| count).sum();
+        let top = hist
+            .histogram
+            .0
+            .iter()
+            .take(TOP_BARCODES)
+            .map(|(seq, count)| {
+                format!(
+                    "{} ({:.1}%)",
+                    seq,
+                    100.0 * *count as f64 / total.max(1) as f64
+                )
+            })
+            .collect::<Vec<String>>();
+        barcode_rows.insert((hist.lane, hist.index_read_no));
+        summary.set(
+            &barcodes_label(hist.lane, hist.index_read_no),
+            top.join(" "),
+        );
+    }
+    Ok(summary)
+}
+
+/// Return the rows of the comparison of `summaries`, with the row labels in the first column
+/// and the values of the runs in the following columns.
+fn rows(summaries: &[RunSummary], barcode_rows: &BTreeSet<(i32, i32)>) -> Vec<Vec<String>> {
+    let mut labels = [
+        "run",
+        "date",
+        "instrument",
+        "flow cell",
+        "layout",
+        "lanes",
+        "read structure",
+        "clusters PF (M)",
+        "yield (Gb)",
+        "% PF",
+        "density (K/mm²)",
+        "error rate (%)",
+        "failed lanes",
+    ]
+    .iter()
+    .map(|label| label.to_string())
+    .collect::<Vec<String>>();
+    labels.extend(
+        barcode_rows
+            .iter()
+            .map(|(lane, index_read_no)| barcodes_label(*lane, *index_read_no)),
+    );
+    labels
+        .into_iter()
+        .map(|label| {
+            let mut row = vec![label.clone()];
+            row.extend(summaries.iter().map(|summary| {
+                summary
+                    .values
+                    .get(&label)
+                    .cloned()
+                    .unwrap_or_else(|| "-".to_string())
+            }));
+            row
+        })
+        .collect()
+}
+
+/// Return `rows` as a table with aligned columns.
+fn format_text(rows: &[Vec<String>]) -> String {
+    let mut widths = vec![0; rows.first().map_or(0, |row| row.len())];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut result = String::new();
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| {
+                let padding = width - cell.chars().count();
+                format!("{}{}", cell, " ".repeat(padding))
+            })
+            .collect::<Vec<String>>()
+            .join("  ");
+        result.push_str(line.trim_end());
+        result.push('\n');
+    }
+    result
+}
+
+/// Return `text` with the characters that are special in HTML escaped.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Return `rows` as an HTML document with a table, with the first row as header.
+fn format_html(rows: &[Vec<String>]) -> String {
+    let mut lines = vec![
+        "<!DOCTYPE html>".to_string(),
+        "<html>".to_string(),
+        "<head>".to_string(),
+        "<meta charset=\"utf-8\">".to_string(),
+        "<title>Run comparison</title>".to_string(),
+        "<style>table { border-collapse: collapse; } th, td { border: 1px solid #ccc; \
+         padding: 2px 6px; text-align: left; }</style>"
+            .to_string(),
+        "</head>".to_string(),
+        "<body>".to_string(),
+        "<table>".to_string(),
+    ];
+    for (i, row) in rows.iter().enumerate() {
+        let cells = row
+            .iter()
+            .enumerate()
+            .map(|(j, cell)| {
+                let tag = if i == 0 || j == 0 { "th" } else { "td" };
+                format!("<{}>{}</{}>", tag, escape_html(cell), tag)
+            })
+            .collect::<Vec<String>>()
+            .join("");
+        lines.push(format!("<tr>{}</tr>", cells));
+    }
+    lines.extend(vec![
+        "</table>".to_string(),
+        "</body>".to_string(),
+        "</html>".to_string(),
+        String::new(),
+    ]);
+    lines.join("\n")
+}
+
+/// Main entry point for the `report` command.
+///
+/// Prints the read structures, yields, and quality metrics from the InterOp files of the run
+/// folders in `settings.report.compare` side by side, together with the top barcodes from the
+/// index histograms of their flow cells in the project `settings.ingest.project_uuid`, if
+/// given.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client report");
+
+    let format_rows = match settings.report.format.as_str() {
+        "text" => format_text,
+        "html" => format_html,
+        format => bail!("Invalid output format {:?}", format),
+    };
+    if settings.report.compare.is_empty() {
+        bail!("You have to specify the run folders to compare");
+    }
+    let mut client = if settings.ingest.project_uuid.is_nil() {
+        info!(logger, "No project UUID given, not showing barcodes");
+        None
+    } else {
+        Some(build_client(
+            logger,
+            &settings.web,
+            settings.trace_api,
+            &settings.archive_dir,
+        )?)
+    };
+
+    let mut barcode_rows = BTreeSet::new();
+    let summaries = settings
+        .report
+        .compare
+        .iter()
+        .map(|path| {
+            summarize(
+                logger,
+                client.as_mut(),
+                Path::new(path),
+                settings,
+                &mut barcode_rows,
+            )
+            .chain_err(|| format!("Problem summarizing run folder {:?}", path))
+        })
+        .collect::<Result<Vec<RunSummary>>>()?;
+
+    let mut anonymizer = Anonymizer::new(settings);
+    for path in &settings.report.compare {
+        anonymizer.add_run_folder(logger, Path::new(path), settings);
+    }
+    let output = format_rows(&rows(&summaries, &barcode_rows));
+    print!("{}", anonymizer.apply(&output));
+
+    Ok(())
+}
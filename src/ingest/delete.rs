@@ -0,0 +1,97 @@
+//! Implementation of the `delete` command that removes an erroneously registered flow cell from
+//! Digestiflow Web, e.g., one registered with a wrong project or from a test run.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use super::*;
+
+/// Ask on the terminal to confirm `question` by typing `vendor_id`, return whether the answer
+/// matches it.
+///
+/// Typing the vendor ID rather than `y` makes sure that the shown flow cell is the intended one.
+fn confirm(question: &str, vendor_id: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("Cannot ask for confirmation without a terminal, use --yes to delete anyway");
+    }
+    eprint!(
+        "{}\nType the vendor ID {} to confirm: ",
+        question, vendor_id
+    );
+    io::stderr()
+        .flush()
+        .chain_err(|| "Problem writing to terminal")?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .chain_err(|| "Problem reading answer")?;
+    Ok(answer.trim() == vendor_id)
+}
+
+/// Main entry point for the `delete` command.
+///
+/// Resolves the flow cell `settings.delete.flowcell` (vendor ID or UUID) of the project
+/// `settings.ingest.project_uuid` and deletes it after its vendor ID was typed for confirmation,
+/// unless `settings.delete.yes` is set.  The server deletes its index histograms and messages
+/// with it.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client delete");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.delete.flowcell.is_empty() {
+        bail!("You have to specify the flow cell");
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let flowcell = find_flowcell(
+        &mut client,
+        settings.ingest.project_uuid,
+        &settings.delete.flowcell,
+        &settings.delete.instrument,
+        settings.delete.run_number,
+    )?;
+    let args = api::ProjectFlowcellArgs {
+        project_uuid: settings.ingest.project_uuid,
+        flowcell_uuid: flowcell
+            .sodar_uuid
+            .chain_err(|| "Flow cell from server has no UUID")?,
+    };
+    let api::LaneIndexHistogramArray::Array(histograms) = client
+        .get(&args)
+        .chain_err(|| "Could not query index histograms from server")?;
+    let description = format!(
+        "flow cell {} (run date {}, instrument {}, run {}, {} index histograms) from {}",
+        &flowcell.vendor_id,
+        &flowcell.run_date,
+        &flowcell.sequencing_machine,
+        flowcell.run_number,
+        histograms.len(),
+        &settings.web.url
+    );
+    if settings.dry_run {
+        info!(logger, "Not deleting {} in dry-run mode", &description);
+        return Ok(());
+    }
+    let question = format!("Delete {}?", &description);
+    if !settings.delete.yes && !confirm(&question, &flowcell.vendor_id)? {
+        info!(
+            logger,
+            "Not deleting flow cell {}, the vendor ID was not typed", &flowcell.vendor_id
+        );
+        return Ok(());
+    }
+
+    client
+        .delete::<_, api::FlowCell>(&args)
+        .chain_err(|| "Problem deleting flow cell")?;
+    info!(logger, "Deleted {}", &description);
+
+    Ok(())
+}
@@ -0,0 +1,272 @@
+//! Implementation of the `demux` command that demultiplexes a completed run folder with
+//! bcl-convert or bcl2fastq, using a sample sheet written from the libraries in Digestiflow Web,
+//! and tracks the progress in the conversion status of the flow cell.
+
+use hostname;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+use super::samplesheet_cmd::build_sample_sheet;
+use super::*;
+
+/// Values of `status_sequencing` of flow cells that can be demultiplexed.
+const COMPLETE_STATUSES: &[&str] = &["complete", "complete_warnings"];
+/// Number of trailing output lines of the demultiplexer included in the message on failure.
+const TAIL_LINES: usize = 20;
+
+/// Return the sample sheet format for `tool` and its arguments for demultiplexing the run folder
+/// at `path` into `output_dir` with the sample sheet at `sample_sheet`.
+///
+/// bcl-convert refuses to write into existing output directories without `--force`, and the
+/// output directory always exists as the sample sheet is written into it.
+fn tool_args(
+    tool: &str,
+    path: &Path,
+    output_dir: &Path,
+    sample_sheet: &Path,
+) -> Result<(&'static str, Vec<String>)> {
+    let path = path.to_string_lossy().to_string();
+    let output_dir = output_dir.to_string_lossy().to_string();
+    let sample_sheet = sample_sheet.to_string_lossy().to_string();
+    match tool {
+        "bcl-convert" => Ok((
+            "v2",
+            vec![
+                "--bcl-input-directory".to_string(),
+                path,
+                "--output-directory".to_string(),
+                output_dir,
+                "--sample-sheet".to_string(),
+                sample_sheet,
+                "--force".to_string(),
+            ],
+        )),
+        "bcl2fastq" => Ok((
+            "v1",
+            vec![
+                "--runfolder-dir".to_string(),
+                path,
+                "--output-dir".to_string(),
+                output_dir,
+                "--sample-sheet".to_string(),
+                sample_sheet,
+            ],
+        )),
+        tool => bail!(
+            "Invalid demultiplexer {:?}, expected bcl-convert or bcl2fastq",
+            tool
+        ),
+    }
+}
+
+/// Log the lines of the output `stream` of the demultiplexer `tool` with `logger` and return the
+/// last `TAIL_LINES` of them.
+fn log_lines<R: Read>(logger: &slog::Logger, tool: &str, stream: R) -> VecDeque<String> {
+    let mut tail = VecDeque::new();
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        info!(logger, "{}: {}", tool, &line);
+        if tail.len() == TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+    tail
+}
+
+/// Run the demultiplexer `tool` with `program` and `args`, streaming its output into `logger`.
+///
+/// Returns the exit status and the last lines of its output, from stderr if it wrote any.
+fn run_tool(
+    logger: &slog::Logger,
+    tool: &str,
+    program: &str,
+    args: &[String],
+) -> Result<(ExitStatus, VecDeque<String>)> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .chain_err(|| format!("Problem running {:?}", program))?;
+    let stdout = child.stdout.take().chain_err(|| "Problem reading output")?;
+    let stderr = child.stderr.take().chain_err(|| "Problem reading output")?;
+    let (stdout_logger, stdout_tool) = (logger.clone(), tool.to_string());
+    let stdout_thread = thread::spawn(move || log_lines(&stdout_logger, &stdout_tool, stdout));
+    let stderr_tail = log_lines(logger, tool, stderr);
+    let stdout_tail = stdout_thread.join().unwrap_or_default();
+    let status = child
+        .wait()
+        .chain_err(|| format!("Problem waiting for {:?}", program))?;
+    if stderr_tail.is_empty() {
+        Ok((status, stdout_tail))
+    } else {
+        Ok((status, stderr_tail))
+    }
+}
+
+/// Set the conversion status of the flow cell `flowcell` to `status` and post a message with
+/// `subject` and `body` to it if given.
+fn update_status(
+    client: &mut api::Client,
+    settings: &Settings,
+    flowcell: &api::FlowCell,
+    status: &str,
+    message: Option<(&str, String)>,
+) -> Result<()> {
+    let args = api::ProjectFlowcellArgs {
+        project_uuid: settings.ingest.project_uuid,
+        flowcell_uuid: flowcell
+            .sodar_uuid
+            .chain_err(|| "Flow cell from server has no UUID")?,
+    };
+    client
+        .patch(
+            &args,
+            &api::FlowCellStatus {
+                vendor_id: flowcell.vendor_id.clone(),
+                status_conversion: Some(status.to_string()),
+                ..api::FlowCellStatus::default()
+            },
+        )
+        .chain_err(|| "Problem updating conversion status of flow cell")?;
+    if let Some((subject, body)) = message {
+        client
+            .post(
+                &args,
+                &api::FlowCellMessage {
+                    subject: Some(subject.to_string()),
+                    body,
+                    state: "sent".to_string(),
+                },
+            )
+            .chain_err(|| "Problem posting message")?;
+    }
+    Ok(())
+}
+
+/// Main entry point for the `demux` command.
+///
+/// Resolves the flow cell of the run folder `settings.demux.path` in the project
+/// `settings.ingest.project_uuid`, writes its sample sheet into the output directory, and runs
+/// `settings.demux.tool` on the run folder.  The conversion status of the flow cell is set to
+/// `in_progress` while the tool runs and to `complete` or `failed` afterwards; on failure, the
+/// last lines of the output of the tool are posted as a message.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client demux");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    let args = &settings.demux;
+    let path = Path::new(&args.path);
+    let output_dir = match args.output_dir.as_str() {
+        "" => path.join("Unaligned"),
+        output_dir => PathBuf::from(output_dir),
+    };
+    let sample_sheet_path = output_dir.join("SampleSheet.csv");
+    let (format, tool_args) = tool_args(&args.tool, path, &output_dir, &sample_sheet_path)?;
+    let command = match args.command.as_str() {
+        "" => args.tool.as_str(),
+        command => command,
+    };
+    let mut command_args = command
+        .split_whitespace()
+        .map(|arg| arg.to_string())
+        .collect::<Vec<String>>();
+    if command_args.is_empty() {
+        bail!("Demultiplexer command is empty");
+    }
+    let program = command_args.remove(0);
+    command_args.extend(tool_args);
+    let is_non_empty = fs::read_dir(&output_dir)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if is_non_empty && !args.force {
+        bail!(
+            "Output directory {:?} is not empty, use --force to write into it",
+            &output_dir
+        );
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let (flowcell, sample_sheet) = build_sample_sheet(logger, &mut client, path, format, settings)?;
+    if !COMPLETE_STATUSES.contains(&flowcell.status_sequencing.as_str()) {
+        bail!(
+            "Flow cell {} is not complete (status_sequencing {:?}), run ingest first",
+            &flowcell.vendor_id,
+            &flowcell.status_sequencing
+        );
+    }
+    info!(
+        logger,
+        "Demultiplexing {} with: {} {}",
+        &flowcell.vendor_id,
+        &program,
+        command_args.join(" ")
+    );
+    if settings.dry_run {
+        info!(logger, "Not demultiplexing in dry-run mode");
+        return Ok(());
+    }
+
+    fs::create_dir_all(&output_dir)
+        .chain_err(|| format!("Problem creating output directory {:?}", &output_dir))?;
+    fs::write(&sample_sheet_path, sample_sheet)
+        .chain_err(|| format!("Problem writing sample sheet {:?}", &sample_sheet_path))?;
+    update_status(&mut client, settings, &flowcell, "in_progress", None)?;
+
+    let host = hostname::get()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    match run_tool(logger, &args.tool, &program, &command_args) {
+        Ok((status, _)) if status.success() => {
+            update_status(&mut client, settings, &flowcell, "complete", None)?;
+            info!(
+                logger,
+                "Demultiplexed {} into {:?}", &flowcell.vendor_id, &output_dir
+            );
+            Ok(())
+        }
+        Ok((status, tail)) => {
+            let body = format!(
+                "Running {} on {} failed with {}, the last lines of its output were:\n\n```\n{}\n```",
+                &args.tool,
+                host,
+                status,
+                tail.into_iter().collect::<Vec<String>>().join("\n")
+            );
+            update_status(
+                &mut client,
+                settings,
+                &flowcell,
+                "failed",
+                Some(("Demultiplexing failed", body)),
+            )?;
+            bail!("{} failed with {}", &args.tool, status)
+        }
+        Err(e) => {
+            let body = format!("Running {} on {} failed: {}", &args.tool, host, &e);
+            update_status(
+                &mut client,
+                settings,
+                &flowcell,
+                "failed",
+                Some(("Demultiplexing failed", body)),
+            )?;
+            Err(e)
+        }
+    }
+}
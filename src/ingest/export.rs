@@ -0,0 +1,144 @@
+//! Implementation of the `export` command that analyzes a run folder as `ingest` does but writes
+//! the flow cell and its index histograms to a local file instead of sending them to the API,
+//! for sequencer hosts without network access to Digestiflow Web.
+
+use serde_json::{self, Value};
+use std::fs;
+
+use super::*;
+
+/// The flow cell record of a run folder as written by `export` and read by `import`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FlowCellExport {
+    /// Version of digestiflow-cli that wrote the export.
+    pub version: String,
+    /// Path to the run folder.
+    pub path: String,
+    /// The detected folder layout.
+    pub folder_layout: String,
+    /// The flow cell as it would be registered.
+    pub flowcell: api::FlowCell,
+    /// The index histograms as they would be posted, without the UUID of the flow cell that
+    /// is only known after registering it.
+    pub index_histograms: Vec<Value>,
+    /// The warnings from parsing and analyzing the run folder.
+    pub warnings: Vec<Warning>,
+}
+
+/// Return the index histograms of the run folder at `path`, computed with an offline client
+/// that captures them instead of posting them.
+fn index_histograms(
+    logger: &slog::Logger,
+    flowcell: &api::FlowCell,
+    run_info: &RunInfo,
+    path: &Path,
+    folder_layout: FolderLayout,
+    settings: &Settings,
+) -> Result<Vec<Value>> {
+    // The histograms are posted to a placeholder flow cell, which has none yet.
+    let mut flowcell = flowcell.clone();
+    flowcell.sodar_uuid = Some(Uuid::nil());
+    let mut settings = settings.clone();
+    settings.dry_run = false;
+    settings.ingest.post_adapters = true;
+    let mut web = settings.web.clone();
+    web.url = OFFLINE_URL.to_string();
+    let mut client = build_client(logger, &web, false, "")?;
+    client.set_offline(&web.url);
+    client.add_response(
+        &api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            flowcell_uuid: Uuid::nil(),
+        },
+        &api::LaneIndexHistogramArray::Array(Vec::new()),
+    )?;
+
+    analyze_adapters(
+        logger,
+        &flowcell,
+        &mut client,
+        run_info,
+        path,
+        folder_layout,
+        &settings,
+    )?;
+    Ok(client
+        .take_payloads()
+        .into_iter()
+        .map(|captured| {
+            let mut histogram = captured.payload;
+            if let Some(fields) = histogram.as_object_mut() {
+                fields.remove("flowcell");
+            }
+            histogram
+        })
+        .collect())
+}
+
+/// Main entry point for the `export` command.
+///
+/// Parses the run folder `settings.export.path` and analyzes its index reads with the settings
+/// in `settings.ingest`, and writes the flow cell and index histograms as JSON to
+/// `settings.export.output`, or to stdout if not given or in dry-run mode.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client export");
+    env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
+
+    let path = Path::new(&settings.export.path);
+    if !run_info_file(path).exists() {
+        bail!("Path {:?} does not exist", run_info_file(path));
+    }
+    let folder_layout = match configured_folder_layout(path, settings)? {
+        Some(folder_layout) => folder_layout,
+        None => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules()))
+            .chain_err(|| format!("Could not guess folder layout from {:?}", path))?,
+    };
+    info!(logger, "Folder layout is {:?}", folder_layout);
+
+    let mut warnings = Vec::new();
+    let (run_info, run_params) = parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut warnings,
+    )?;
+    let flowcell = build_flow_cell(&run_info, &run_params, path, None, settings);
+
+    let index_histograms = if !has_index_reads(&run_info) {
+        info!(logger, "Run has no index reads, not analyzing adapters");
+        Vec::new()
+    } else if !settings.ingest.analyze_adapters {
+        info!(logger, "You asked me to not analyze adapters.");
+        Vec::new()
+    } else if folder_layout == FolderLayout::Mgi {
+        info!(logger, "Cannot analyze adapters of MGI output directories");
+        Vec::new()
+    } else {
+        index_histograms(logger, &flowcell, &run_info, path, folder_layout, settings)?
+    };
+
+    let export = FlowCellExport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        path: settings.export.path.clone(),
+        folder_layout: format!("{:?}", folder_layout),
+        flowcell,
+        index_histograms,
+        warnings,
+    };
+    let json = serde_json::to_string_pretty(&export).chain_err(|| "Problem serializing to JSON")?;
+    if settings.dry_run || settings.export.output.is_empty() || settings.export.output == "-" {
+        println!("{}", json);
+    } else {
+        fs::write(&settings.export.output, json + "\n")
+            .chain_err(|| format!("Problem writing {:?}", &settings.export.output))?;
+        info!(
+            logger,
+            "Wrote flow cell {} with {} index histogram(s) to {:?}",
+            &export.flowcell.vendor_id,
+            export.index_histograms.len(),
+            &settings.export.output
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+//! Sampling of index sequences from the headers of FASTQ files.
+//!
+//! This is used for runs whose base calls have been removed after on-instrument conversion,
+//! e.g., NovaSeq X runs that only keep DRAGEN ORA-compressed FASTQ files, and for AVITI runs
+//! converted with `bases2fastq`.
+
+use chaos;
+use flate2::read::MultiGzDecoder;
+use glob::glob;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use super::super::errors::*;
+use ingest::bcl_data::IndexCounts;
+use ingest::memory::{MemoryBudget, SeqCounter, MAX_LANES};
+use settings::Settings;
+
+/// Patterns of the FASTQ files to sample from, relative to the run folder.  Only the first read
+/// is used as its headers contain all index sequences.  The last pattern matches the files
+/// written by `bases2fastq` for AVITI runs.
+const FASTQ_PATTERNS: &[&str] = &[
+    "**/*_R1_001.fastq.gz",
+    "**/*_R1_001.fastq.ora",
+    "**/*_R1.fastq.gz",
+];
+
+/// Return paths to the FASTQ files of the run folder at `path`.
+pub fn find_fastq_files(path: &Path) -> Vec<PathBuf> {
+    let mut result = FASTQ_PATTERNS
+        .iter()
+        .flat_map(|pattern| {
+            glob(path.join(pattern).to_str().unwrap())
+                .expect("Failed to read glob pattern")
+                .filter_map(|path| path.ok())
+        })
+        .collect::<Vec<PathBuf>>();
+    result.sort();
+    result
+}
+
+/// Open the FASTQ file at `path`, decompressing ORA files with `settings.ingest.ora_command`.
+///
+/// The child process is returned for ORA files such that it can be stopped once enough reads
+/// have been read.
+fn open_fastq(
+    path: &Path,
+    settings: &Settings,
+) -> Result<(Box<dyn BufRead>, Option<std::process::Child>)> {
+    if path.extension().map(|ext| ext == "ora").unwrap_or(false) {
+        let mut args = settings.ingest.ora_command.split_whitespace();
+        let program = args.next().chain_err(|| "ORA command is empty")?;
+        let mut child = Command::new(program)
+            .args(args)
+            .arg(path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .chain_err(|| format!("Problem running {:?}", &settings.ingest.ora_command))?;
+        let stdout = child
+            .stdout
+            .take()
+            .chain_err(|| "Problem reading ORA output")?;
+        Ok((Box::new(BufReader::new(stdout)), Some(child)))
+    } else {
+        let file = chaos::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+        Ok((Box::new(BufReader::new(MultiGzDecoder::new(file))), None))
+    }
+}
+
+/// Parse lane number and index sequences from a FASTQ header line such as
+/// `@A00123:8:HXXXXXXXX:1:1101:1000:1000 1:N:0:ACGTACGT+TTGGCCAA`.
+fn parse_header(line: &str) -> Option<(i32, Vec<String>)> {
+    let mut tokens = line.split_whitespace();
+    let lane = tokens.next()?.split(':').nth(3)?.parse::<i32>().ok()?;
+    let indices = tokens.next()?.rsplit(':').next()?;
+    Some((
+        lane,
+        indices.split('+').map(|seq| seq.to_uppercase()).collect(),
+    ))
+}
+
+/// Sample the first `num_cycles` bases of the index read with number `index_no` from the FASTQ
+/// `files` and return `IndexCounts` for each lane.
+///
+/// From each file, a share of `settings.ingest.sample_reads_per_tile` reads proportional to
+/// the file's size is read, such that all samples (including undetermined reads) are
+/// represented.
+pub fn sample_fastq(
+    logger: &slog::Logger,
+    files: &[PathBuf],
+    index_no: i32,
+    num_cycles: usize,
+    settings: &Settings,
+) -> Result<Vec<IndexCounts>> {
+    let sizes = files
+        .iter()
+        .map(|path| fs::metadata(path).map(|m| m.len()).unwrap_or(0))
+        .collect::<Vec<u64>>();
+    let total_size = sizes.iter().sum::<u64>().max(1);
+
+    // The lanes are not known in advance, so the budget is shared by the most lanes possible.
+    let counter_bytes = MemoryBudget::from_settings(settings)?.counter_bytes(MAX_LANES);
+    let mut counters: BTreeMap<i32, SeqCounter> = BTreeMap::new();
+    for (path, size) in files.iter().zip(sizes.iter()) {
+        let quota = if settings.ingest.sample_reads_per_tile > 0 {
+            let share = settings.ingest.sample_reads_per_tile as f64 * *size as f64;
+            Some(((share / total_size as f64).ceil() as usize).max(1))
+        } else {
+            None
+        };
+        debug!(logger, "Sampling {:?} reads from {:?}", quota, path);
+
+        let (reader, child) = open_fastq(path, settings)?;
+        for (num_reads, line) in reader.lines().step_by(4).enumerate() {
+            if quota.map(|quota| num_reads >= quota).unwrap_or(false) {
+                break;
+            }
+            let line = line.chain_err(|| format!("Problem reading {:?}", path))?;
+            if let Some((lane, indices)) = parse_header(&line) {
+                if let Some(seq) = indices.get(index_no as usize - 1) {
+                    let len = cmp::min(seq.len(), num_cycles);
+                    counters
+                        .entry(lane)
+                        .or_insert_with(|| {
+                            SeqCounter::new(counter_bytes, settings.ingest.min_index_fraction)
+                        })
+                        .add(&seq[..len]);
+                }
+            }
+        }
+        if let Some(mut child) = child {
+            // The decompressor may still be running if not all reads were needed.
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    let counts = counters
+        .into_iter()
+        .map(|(lane, counter)| {
+            IndexCounts::from_counter(index_no, lane, counter, settings.ingest.min_index_fraction)
+        })
+        .collect::<Vec<IndexCounts>>();
+    debug!(
+        logger,
+        "Sampled reads per lane: {:?}",
+        counts
+            .iter()
+            .map(|c| (c.lane_no, c.sample_size))
+            .collect::<HashMap<i32, usize>>()
+    );
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+
+    /// Write a gzip-compressed FASTQ file to `path` with a read of each `(lane, index)`.
+    fn write_fastq(path: &Path, reads: &[(i32, &str)]) {
+        let file = fs::File::create(path).unwrap();
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        for (i, (lane, index)) in reads.iter().enumerate() {
+            write!(
+                encoder,
+                "@A00123:8:HXXXXXXXX:{}:1101:1000:{} 1:N:0:{}\nACGT\n+\nFFFF\n",
+                lane, i, index
+            )
+            .unwrap();
+        }
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn parse_header_dual_index() {
+        assert_eq!(
+            parse_header("@A00123:8:HXXXXXXXX:2:1101:1000:1000 1:N:0:acgtacgt+TTGGCCAA"),
+            Some((2, vec!["ACGTACGT".to_string(), "TTGGCCAA".to_string()]))
+        );
+        assert_eq!(parse_header("@A00123:8:HXXXXXXXX"), None);
+    }
+
+    #[test]
+    fn sample_fastq_labels_counts_by_lane() {
+        // Lanes 1 and 3 are missing, the counts must not be labelled by their position.
+        let dir = env::temp_dir().join(format!("digestiflow-cli-fastq-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Undetermined_S0_R1_001.fastq.gz");
+        write_fastq(
+            &path,
+            &[
+                (4, "TTTTTTTT"),
+                (2, "ACGTACGT"),
+                (4, "TTTTTTTT"),
+                (2, "ACGTACGT"),
+            ],
+        );
+        let logger = slog::Logger::root(slog::Discard, o!());
+
+        let counts = sample_fastq(&logger, &[path], 1, 8, &Settings::default()).unwrap();
+        let lanes = counts
+            .iter()
+            .map(|c| (c.lane_no, c.sample_size, c.hist.0[0].0.clone()))
+            .collect::<Vec<(i32, usize, String)>>();
+        assert_eq!(
+            lanes,
+            vec![
+                (2, 2, "ACGTACGT".to_string()),
+                (4, 2, "TTTTTTTT".to_string())
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+//! Discovery of the run folders below root directories, with a cached snapshot of the folders
+//! such that only changed folders have to be examined again.
+
+use rayon::prelude::*;
+use serde_json;
+use slog;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::super::errors::*;
+use super::{read_run_info, run_info_file};
+use ingest::bcl_meta::{guess_folder_layout, FolderLayout, LayoutRule};
+
+/// Identification of a run from its `RunInfo.xml` (or `RunParameters.json` for AVITI).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunIdentity {
+    /// The instrument serial number.
+    pub instrument: String,
+    /// The run number.
+    pub run_number: i32,
+    /// The flow cell vendor ID.
+    pub flowcell: String,
+}
+
+/// A directory below a root, as seen in the last scan.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedFolder {
+    /// Modification time of the directory in nanoseconds since the epoch.
+    pub mtime: u64,
+    /// Modification time of its `RunInfo.xml` (or `RunParameters.json` for AVITI) in
+    /// nanoseconds since the epoch, 0 if missing.
+    pub run_info_mtime: u64,
+    /// The run, `None` if the directory is no (readable) run folder.
+    pub run: Option<RunIdentity>,
+    /// The detected folder layout, if any.
+    pub layout: Option<FolderLayout>,
+}
+
+/// Snapshot of the directories below the roots, by path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FolderCache {
+    /// The directories by path.
+    pub folders: BTreeMap<String, CachedFolder>,
+}
+
+impl FolderCache {
+    /// Load the snapshot from the JSON file at `path`, empty if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+        serde_json::from_reader(file).chain_err(|| format!("Problem reading JSON from {:?}", path))
+    }
+
+    /// Write the snapshot as JSON to the file at `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).chain_err(|| format!("Problem creating {:?}", path))?;
+        serde_json::to_writer_pretty(file, self)
+            .chain_err(|| format!("Problem writing JSON to {:?}", path))
+    }
+
+    /// Scan the directories directly below `roots` in parallel and return the new snapshot.
+    ///
+    /// Directories whose modification time and that of their `RunInfo.xml` are unchanged since
+    /// the snapshot `self` are taken from it, only the others are read (with the custom
+    /// `date_format` for the run date) and their layout detected with `rules`.
+    pub fn scan(
+        &self,
+        logger: &slog::Logger,
+        roots: &[String],
+        rules: &[LayoutRule],
+        date_format: &str,
+    ) -> Self {
+        let mut paths = Vec::new();
+        for root in roots {
+            match fs::read_dir(root) {
+                Ok(entries) => paths.extend(
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                        .map(|entry| entry.path()),
+                ),
+                Err(e) => warn!(logger, "Could not list run folder root {}: {}", root, e),
+            }
+        }
+
+        let folders = paths
+            .par_iter()
+            .map(|path| {
+                let key = path.to_string_lossy().to_string();
+                let mtime = mtime_nanos(path);
+                let run_info_mtime = mtime_nanos(&run_info_file(path));
+                let folder = match self.folders.get(&key) {
+                    Some(cached)
+                        if cached.mtime == mtime && cached.run_info_mtime == run_info_mtime =>
+                    {
+                        cached.clone()
+                    }
+                    _ => examine_folder(logger, path, mtime, run_info_mtime, rules, date_format),
+                };
+                (key, folder)
+            })
+            .collect::<BTreeMap<_, _>>();
+        Self { folders }
+    }
+
+    /// Return the run folders with their runs and layouts.
+    pub fn run_folders(&self) -> Vec<(PathBuf, &RunIdentity, Option<FolderLayout>)> {
+        self.folders
+            .iter()
+            .filter_map(|(path, folder)| {
+                folder
+                    .run
+                    .as_ref()
+                    .map(|run| (PathBuf::from(path), run, folder.layout))
+            })
+            .collect()
+    }
+}
+
+/// Return the modification time of `path` in nanoseconds since the epoch, 0 if not available.
+pub fn mtime_nanos(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Read the run from the `RunInfo.xml` of the directory at `path` and detect its layout.
+fn examine_folder(
+    logger: &slog::Logger,
+    path: &Path,
+    mtime: u64,
+    run_info_mtime: u64,
+    rules: &[LayoutRule],
+    date_format: &str,
+) -> CachedFolder {
+    debug!(logger, "Examining {:?}", path);
+    let run = if run_info_mtime == 0 {
+        None
+    } else {
+        match read_run_info(path, date_format) {
+            Ok(run_info) => Some(RunIdentity {
+                instrument: run_info.instrument,
+                run_number: run_info.run_number,
+                flowcell: run_info.flowcell,
+            }),
+            Err(e) => {
+                warn!(logger, "Could not read run info from {:?}: {:?}", path, &e);
+                None
+            }
+        }
+    };
+    let layout = if run.is_some() {
+        guess_folder_layout(path, rules).ok()
+    } else {
+        None
+    };
+    CachedFolder {
+        mtime,
+        run_info_mtime,
+        run,
+        layout,
+    }
+}
@@ -0,0 +1,115 @@
+//! Computation of the size and file counts of run folders, e.g., for storage accounting.
+
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::Add;
+use std::path::Path;
+
+/// Total size and number of files below a directory.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DirSize {
+    /// The total size of the files in bytes.
+    pub bytes: u64,
+    /// The number of files.
+    pub files: u64,
+}
+
+impl Add for DirSize {
+    type Output = DirSize;
+
+    fn add(self, other: DirSize) -> DirSize {
+        DirSize {
+            bytes: self.bytes + other.bytes,
+            files: self.files + other.files,
+        }
+    }
+}
+
+impl DirSize {
+    /// Compute for the directory at `path`, with sub directories traversed in parallel.
+    ///
+    /// Symbolic links are not followed and entries that cannot be read are ignored.
+    pub fn compute(path: &Path) -> Self {
+        read_entries(path)
+            .par_iter()
+            .map(entry_size)
+            .reduce(Self::default, |a, b| a + b)
+    }
+}
+
+/// Return the readable entries of the directory at `path`.
+fn read_entries(path: &Path) -> Vec<fs::DirEntry> {
+    fs::read_dir(path)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Return the size of the file or directory `entry`.
+fn entry_size(entry: &fs::DirEntry) -> DirSize {
+    match entry.file_type() {
+        Ok(ref file_type) if file_type.is_dir() => DirSize::compute(&entry.path()),
+        Ok(ref file_type) if file_type.is_file() => DirSize {
+            bytes: entry.metadata().map(|meta| meta.len()).unwrap_or(0),
+            files: 1,
+        },
+        _ => DirSize::default(),
+    }
+}
+
+/// Format `bytes` in human-readable units, e.g., "1.2 GiB".
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+impl fmt::Display for DirSize {
+    /// Format size in human-readable units with the file count, e.g., "1.2 GiB in 340 files".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {} files", format_bytes(self.bytes), self.files)
+    }
+}
+
+/// Size of a run folder, in total and for each of its top-level directories.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FolderSize {
+    /// The size of the whole folder.
+    pub total: DirSize,
+    /// The size of each top-level directory (e.g., `Data`, `InterOp`, `Thumbnail_Images`), by
+    /// name.
+    pub subdirs: BTreeMap<String, DirSize>,
+}
+
+impl FolderSize {
+    /// Compute for the run folder at `path`.
+    pub fn compute(path: &Path) -> Self {
+        let sizes = read_entries(path)
+            .par_iter()
+            .map(|entry| {
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                let name = entry.file_name().to_string_lossy().to_string();
+                (if is_dir { Some(name) } else { None }, entry_size(entry))
+            })
+            .collect::<Vec<_>>();
+
+        let mut result = Self::default();
+        for (name, size) in sizes {
+            result.total = result.total + size;
+            if let Some(name) = name {
+                result.subdirs.insert(name, size);
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,181 @@
+//! Implementation of the `api healthcheck` command that quickly checks the configured servers and
+//! ingest paths and reports the result as JSON, for monitoring systems.
+//!
+//! Unlike `api check`, only a single lightweight request is sent to each server.
+
+use serde_json;
+use std::fs;
+use std::time::Instant;
+
+use super::check::{build_check_client, describe_error};
+use super::*;
+
+/// Exit code if all checks passed.
+pub const EXIT_HEALTHY: i32 = 0;
+/// Exit code if the servers are healthy but a configured path is missing or not readable.
+pub const EXIT_WARNING: i32 = 1;
+/// Exit code if a server cannot be reached, rejects the API token, or does not give access to
+/// the project.
+pub const EXIT_CRITICAL: i32 = 2;
+
+/// The health of one configured server.
+#[derive(Debug, Serialize)]
+struct ServerHealth {
+    /// Whether this is the `primary` or the `secondary` server.
+    role: String,
+    /// The configured URL.
+    url: String,
+    /// Whether or not all checks of the server passed.
+    healthy: bool,
+    /// The time for listing the projects, in milliseconds.
+    latency_ms: Option<u64>,
+    /// The number of projects readable with the API token.
+    projects: Option<usize>,
+    /// Whether or not the configured project is readable, `None` if no project is configured.
+    project_readable: Option<bool>,
+    /// The reason the server is not healthy, with what to do about it.
+    error: Option<String>,
+}
+
+/// The health of one configured path.
+#[derive(Debug, Serialize)]
+struct PathHealth {
+    /// The configured path.
+    path: String,
+    /// The setting the path was configured with, e.g., `watch.roots`.
+    source: String,
+    /// Whether or not the path exists.
+    exists: bool,
+    /// Whether or not the path is a directory whose entries can be listed.
+    readable: bool,
+    /// The reason the path is not readable.
+    error: Option<String>,
+}
+
+/// Check the server in `web` with the `role` by listing the projects, including whether the
+/// project `project_uuid` is readable unless it is nil.
+fn check_server(role: &str, web: &Web, project_uuid: &Uuid) -> ServerHealth {
+    let mut health = ServerHealth {
+        role: role.to_string(),
+        url: web.url.clone(),
+        healthy: false,
+        latency_ms: None,
+        projects: None,
+        project_readable: None,
+        error: None,
+    };
+    let mut client = match build_check_client(web) {
+        Ok(client) => client,
+        Err(message) => {
+            health.error = Some(message);
+            return health;
+        }
+    };
+
+    let start = Instant::now();
+    let projects = match client.get(()) {
+        Ok(api::ProjectArray::Array(projects)) => projects,
+        Err(e) => {
+            health.error = Some(describe_error(&web.url, "api/projects/", &e));
+            return health;
+        }
+    };
+    health.latency_ms = Some(start.elapsed().as_millis() as u64);
+    health.projects = Some(projects.len());
+    if !project_uuid.is_nil() {
+        let readable = projects
+            .iter()
+            .any(|project| project.sodar_uuid == *project_uuid);
+        health.project_readable = Some(readable);
+        if !readable {
+            health.error = Some(format!(
+                "Project {} is not readable with the API token",
+                project_uuid
+            ));
+            return health;
+        }
+    }
+    health.healthy = true;
+    health
+}
+
+/// Check that the directory `path` configured with the setting `source` exists and can be
+/// listed.
+fn check_path(path: &str, source: &str) -> PathHealth {
+    let mut health = PathHealth {
+        path: path.to_string(),
+        source: source.to_string(),
+        exists: false,
+        readable: false,
+        error: None,
+    };
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            health.exists = true;
+            if !metadata.is_dir() {
+                health.error = Some("Not a directory".to_string());
+            } else if let Err(e) = fs::read_dir(path) {
+                health.error = Some(format!("Cannot list directory: {}", e));
+            } else {
+                health.readable = true;
+            }
+        }
+        Err(e) => health.error = Some(e.to_string()),
+    }
+    health
+}
+
+/// Main entry point for the `api healthcheck` command.
+///
+/// Checks the server `settings.web` (and `settings.secondary_web`, if configured) and the paths
+/// from `settings.ingest.path`, `settings.watch.roots`, `settings.reconcile.roots`, and
+/// `settings.healthcheck.path`, prints the result as JSON, and returns the exit code: one of
+/// `EXIT_HEALTHY`, `EXIT_WARNING`, and `EXIT_CRITICAL`.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<i32> {
+    info!(logger, "Running: digestiflow-cli-client api healthcheck");
+
+    let mut servers = vec![check_server(
+        "primary",
+        &settings.web,
+        &settings.ingest.project_uuid,
+    )];
+    if !settings.secondary_web.url.is_empty() {
+        servers.push(check_server(
+            "secondary",
+            &settings.secondary_web,
+            &settings.ingest.project_uuid,
+        ));
+    }
+
+    let mut paths = Vec::new();
+    for (source, values) in &[
+        ("ingest.path", &settings.ingest.path),
+        ("watch.roots", &settings.watch.roots),
+        ("reconcile.roots", &settings.reconcile.roots),
+        ("healthcheck.path", &settings.healthcheck.path),
+    ] {
+        paths.extend(values.iter().map(|path| check_path(path, source)));
+    }
+
+    let code = if servers.iter().any(|server| !server.healthy) {
+        EXIT_CRITICAL
+    } else if paths.iter().any(|path| !path.readable) {
+        EXIT_WARNING
+    } else {
+        EXIT_HEALTHY
+    };
+    let value = json!({
+        "healthy": code == EXIT_HEALTHY,
+        "exit_code": code,
+        "version": env!("CARGO_PKG_VERSION"),
+        "servers": servers,
+        "paths": paths,
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&value).chain_err(|| "Problem serializing to JSON")?
+    );
+    info!(logger, "Health check finished with exit code {}", code);
+
+    Ok(code)
+}
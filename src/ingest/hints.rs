@@ -0,0 +1,182 @@
+//! Annotation hints from `ExperimentName` and the sample sheet header.
+//!
+//! The hints are mapped into the label, description, and operator of newly registered flow
+//! cells through the templates configured in `IngestArgs`.
+
+use std::path::Path;
+
+use ingest::bcl_meta::RunParameters;
+use ingest::sample_sheet::SampleSheet;
+
+/// Line starting the block of a flow cell description that is managed by the client.
+const BLOCK_BEGIN: &str = "<!-- begin digestiflow-cli -->";
+/// Line ending the block of a flow cell description that is managed by the client.
+const BLOCK_END: &str = "<!-- end digestiflow-cli -->";
+
+/// Hints on the purpose of a run.
+#[derive(Debug, Clone, Default)]
+pub struct RunHints {
+    /// The experiment name from the run parameters.
+    pub experiment_name: String,
+    /// The investigator name from the sample sheet header.
+    pub investigator: Option<String>,
+    /// The project name from the sample sheet header.
+    pub project: Option<String>,
+    /// The description from the sample sheet header.
+    pub description: Option<String>,
+    /// The size and file count of the run folder, only computed if used in a template.
+    pub folder_size: Option<String>,
+}
+
+impl RunHints {
+    /// Collect the hints for the run folder at `path` from `run_params` and the `SampleSheet.csv`
+    /// file, if any.
+    pub fn from_folder(path: &Path, run_params: &RunParameters) -> Self {
+        let mut result = Self {
+            experiment_name: run_params.experiment_name.clone(),
+            ..Self::default()
+        };
+        if let Some(sample_sheet) = SampleSheet::from_folder(path) {
+            for (key, value) in sample_sheet.header {
+                match key.as_str() {
+                    "investigator name" | "investigatorname" | "investigator" => {
+                        result.investigator = Some(value)
+                    }
+                    "project name" | "projectname" | "project" => result.project = Some(value),
+                    "description" => result.description = Some(value),
+                    _ => (),
+                }
+            }
+        }
+        result
+    }
+
+    /// Render the `template`, replacing the placeholders `{experiment_name}`, `{investigator}`,
+    /// `{project}`, `{description}`, and `{folder_size}` by the hint values (empty if missing).
+    ///
+    /// Returns `None` if the result is empty.
+    pub fn render(&self, template: &str) -> Option<String> {
+        let empty = String::new();
+        let result = template
+            .replace("{experiment_name}", &self.experiment_name)
+            .replace(
+                "{investigator}",
+                self.investigator.as_ref().unwrap_or(&empty),
+            )
+            .replace("{project}", self.project.as_ref().unwrap_or(&empty))
+            .replace("{description}", self.description.as_ref().unwrap_or(&empty))
+            .replace("{folder_size}", self.folder_size.as_ref().unwrap_or(&empty))
+            .trim()
+            .to_string();
+        if result.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
+}
+
+/// Return the content of the managed block in `description`, if any.
+pub fn managed_block_content(description: &str) -> Option<&str> {
+    let begin = description.find(BLOCK_BEGIN)? + BLOCK_BEGIN.len();
+    let end = begin + description[begin..].find(BLOCK_END)?;
+    Some(description[begin..end].trim())
+}
+
+/// Return `description` with the managed block set to `content`, keeping the text around it.
+///
+/// The block is replaced in place if present and appended otherwise; it is removed if `content`
+/// is `None`.  Returns `None` if the result is empty.
+pub fn with_managed_block(description: Option<&str>, content: Option<&str>) -> Option<String> {
+    let description = description.unwrap_or("");
+    let (before, after) = match description.find(BLOCK_BEGIN) {
+        Some(begin) => match description[begin..].find(BLOCK_END) {
+            Some(end) => (
+                &description[..begin],
+                &description[(begin + end + BLOCK_END.len())..],
+            ),
+            None => (&description[..begin], ""),
+        },
+        None => (description, ""),
+    };
+
+    let parts = vec![
+        before.trim().to_string(),
+        content
+            .map(|content| format!("{}\n{}\n{}", BLOCK_BEGIN, content, BLOCK_END))
+            .unwrap_or_default(),
+        after.trim().to_string(),
+    ];
+    let result = parts
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    if result.is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the managed block with `content`.
+    fn block(content: &str) -> String {
+        format!("{}\n{}\n{}", BLOCK_BEGIN, content, BLOCK_END)
+    }
+
+    #[test]
+    fn managed_block_content_cases() {
+        let description = format!("Notes\n\n{}\n\nMore notes", block("Run of lab A"));
+        assert_eq!(managed_block_content(&description), Some("Run of lab A"));
+        assert_eq!(managed_block_content("Notes only"), None);
+        // Without end marker, there is no block.
+        let unterminated = format!("Notes\n\n{}\nRun of lab A", BLOCK_BEGIN);
+        assert_eq!(managed_block_content(&unterminated), None);
+    }
+
+    #[test]
+    fn with_managed_block_replaces_block_in_place() {
+        let description = format!("Notes\n\n{}\n\nMore notes", block("Old"));
+        assert_eq!(
+            with_managed_block(Some(&description), Some("New")),
+            Some(format!("Notes\n\n{}\n\nMore notes", block("New")))
+        );
+    }
+
+    #[test]
+    fn with_managed_block_appends_block() {
+        assert_eq!(
+            with_managed_block(Some("Notes\n"), Some("New")),
+            Some(format!("Notes\n\n{}", block("New")))
+        );
+        assert_eq!(with_managed_block(None, Some("New")), Some(block("New")));
+    }
+
+    #[test]
+    fn with_managed_block_removes_block() {
+        let description = format!("Notes\n\n{}\n\nMore notes", block("Old"));
+        assert_eq!(
+            with_managed_block(Some(&description), None),
+            Some("Notes\n\nMore notes".to_string())
+        );
+        assert_eq!(with_managed_block(Some(&block("Old")), None), None);
+        assert_eq!(with_managed_block(None, None), None);
+    }
+
+    #[test]
+    fn with_managed_block_replaces_unterminated_block_to_end() {
+        let description = format!("Notes\n\n{}\nOld", BLOCK_BEGIN);
+        assert_eq!(
+            with_managed_block(Some(&description), Some("New")),
+            Some(format!("Notes\n\n{}", block("New")))
+        );
+        assert_eq!(
+            with_managed_block(Some(&description), None),
+            Some("Notes".to_string())
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Index histograms with deterministic ordering.
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::collections::HashMap;
+
+/// Histogram of index sequence counts.
+///
+/// The entries are sorted by count (descending) and then by sequence (ascending).  This order
+/// is kept when serializing to a JSON object such that repeated postings and exports of the
+/// same data are identical.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram(pub Vec<(String, usize)>);
+
+impl Histogram {
+    /// Construct from `(sequence, count)` pairs in arbitrary order.
+    pub fn from_counts<I>(counts: I) -> Self
+    where
+        I: IntoIterator<Item = (String, usize)>,
+    {
+        let mut entries = counts.into_iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Histogram(entries)
+    }
+}
+
+impl Serialize for Histogram {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (seq, count) in &self.0 {
+            map.serialize_entry(seq, count)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Histogram {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let counts: HashMap<String, usize> = HashMap::deserialize(deserializer)?;
+        Ok(Histogram::from_counts(counts))
+    }
+}
+
+/// View of the entries of a histogram that are posted: the `max_entries` most frequent ones (all
+/// if 0) that were seen in more than a fraction of `min_fraction` of the `sample_size` reads.
+///
+/// The entries are filtered while serializing, without copying the histogram.
+#[derive(Debug, Clone, Copy)]
+pub struct FilteredHistogram<'a> {
+    histogram: &'a Histogram,
+    max_entries: usize,
+    min_count: f64,
+}
+
+impl<'a> FilteredHistogram<'a> {
+    /// Construct for the entries of `histogram` that pass the filters.
+    pub fn new(
+        histogram: &'a Histogram,
+        max_entries: usize,
+        sample_size: usize,
+        min_fraction: f64,
+    ) -> Self {
+        Self {
+            histogram,
+            max_entries: if max_entries > 0 {
+                max_entries
+            } else {
+                usize::MAX
+            },
+            min_count: sample_size as f64 * min_fraction,
+        }
+    }
+
+    /// Return the entries that pass the filters, most frequent first.
+    pub fn entries(&self) -> impl Iterator<Item = &'a (String, usize)> {
+        let min_count = self.min_count;
+        self.histogram
+            .0
+            .iter()
+            .take_while(move |(_, count)| *count as f64 > min_count)
+            .take(self.max_entries)
+    }
+
+    /// Return the number of entries that pass the filters.
+    pub fn num_entries(&self) -> usize {
+        self.entries().count()
+    }
+}
+
+impl<'a> Serialize for FilteredHistogram<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.num_entries()))?;
+        for (seq, count) in self.entries() {
+            map.serialize_entry(seq, count)?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,274 @@
+//! Implementation of the `history` command that shows what was sent to the API for each flow
+//! cell and when, from the payload archive written with `--archive-dir`.
+
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as FmtWrite;
+
+use super::archive::{read_archive, ArchivedPayload};
+use super::*;
+
+/// Fields of flow cell payloads that are not compared between successive postings.
+const IGNORED_FIELDS: &[&str] = &["sodar_uuid"];
+/// Fields of flow cell payloads shown for the first posting of a flow cell.
+const SUMMARY_FIELDS: &[&str] = &[
+    "status_sequencing",
+    "status_conversion",
+    "status_delivery",
+    "current_reads",
+];
+
+/// The archived payloads of one flow cell sent to one server.
+#[derive(Debug, Clone, Default)]
+struct FlowCellHistory {
+    /// The vendor ID of the flow cell, empty if it could not be determined.
+    vendor_id: String,
+    /// The URL of the server.
+    server: String,
+    /// The run folders the payloads were sent for.
+    folders: Vec<String>,
+    /// The payloads, in the order they were sent.
+    entries: Vec<ArchivedPayload>,
+}
+
+/// Return the kind of the payload sent to `path`, e.g., `flowcells` for `api/flowcells/...`.
+fn payload_kind(path: &str) -> &str {
+    path.split('/').nth(1).unwrap_or("")
+}
+
+/// Return the flow cell UUID from the `path` of a flow cell update, message, or histogram.
+fn flowcell_uuid(path: &str) -> Option<&str> {
+    path.split('/').nth(3).filter(|uuid| !uuid.is_empty())
+}
+
+/// Return the string field `key` of `payload`, empty if missing.
+fn payload_str<'a>(payload: &'a Value, key: &str) -> &'a str {
+    payload
+        .get(key)
+        .and_then(|value| value.as_str())
+        .unwrap_or("")
+}
+
+/// Group `payloads` by flow cell and server, in the order of their first payload.
+///
+/// Flow cell payloads carry the vendor ID.  Messages and index histograms are assigned through
+/// the flow cell UUID in their path, which is known from updates of the flow cell or from the
+/// flow cell payload sent before for the same folder.
+fn group_by_flowcell(payloads: Vec<ArchivedPayload>) -> Vec<FlowCellHistory> {
+    let mut result: Vec<FlowCellHistory> = Vec::new();
+    let mut vendor_by_uuid: HashMap<(String, String), String> = HashMap::new();
+    let mut vendor_by_folder: HashMap<(String, String), String> = HashMap::new();
+
+    for payload in payloads {
+        let server = payload.server.clone();
+        let uuid = flowcell_uuid(&payload.path)
+            .or_else(|| {
+                payload
+                    .payload
+                    .get("sodar_uuid")
+                    .and_then(|uuid| uuid.as_str())
+            })
+            .map(|uuid| uuid.to_string());
+        let vendor_id = if payload_kind(&payload.path) == "flowcells" {
+            payload_str(&payload.payload, "vendor_id").to_string()
+        } else {
+            uuid.as_ref()
+                .and_then(|uuid| vendor_by_uuid.get(&(server.clone(), uuid.clone())))
+                .or_else(|| vendor_by_folder.get(&(server.clone(), payload.folder.clone())))
+                .cloned()
+                .unwrap_or_default()
+        };
+        if !vendor_id.is_empty() {
+            if let Some(uuid) = uuid {
+                vendor_by_uuid.insert((server.clone(), uuid), vendor_id.clone());
+            }
+            if !payload.folder.is_empty() {
+                vendor_by_folder
+                    .insert((server.clone(), payload.folder.clone()), vendor_id.clone());
+            }
+        }
+
+        let pos = match result
+            .iter()
+            .position(|history| history.vendor_id == vendor_id && history.server == server)
+        {
+            Some(pos) => pos,
+            None => {
+                result.push(FlowCellHistory {
+                    vendor_id,
+                    server,
+                    ..FlowCellHistory::default()
+                });
+                result.len() - 1
+            }
+        };
+        let history = &mut result[pos];
+        if !payload.folder.is_empty() && !history.folders.contains(&payload.folder) {
+            history.folders.push(payload.folder.clone());
+        }
+        history.entries.push(payload);
+    }
+    result
+}
+
+/// Return the fields that differ between the flow cell payloads `previous` and `current`.
+fn changed_fields(previous: &Value, current: &Value) -> Vec<String> {
+    let keys = previous
+        .as_object()
+        .into_iter()
+        .chain(current.as_object())
+        .flat_map(|object| object.keys())
+        .filter(|key| !IGNORED_FIELDS.contains(&key.as_str()))
+        .collect::<BTreeSet<&String>>();
+    keys.into_iter()
+        .filter_map(|key| {
+            let before = previous.get(key).unwrap_or(&Value::Null);
+            let after = current.get(key).unwrap_or(&Value::Null);
+            if before == after {
+                None
+            } else {
+                Some(format!("{}: {} -> {}", key, before, after))
+            }
+        })
+        .collect()
+}
+
+/// Return the lines describing `entry` with the (merged) `payload`, given the flow cell payload
+/// `previous` sent before.
+fn describe(entry: &ArchivedPayload, payload: &Value, previous: Option<&Value>) -> Vec<String> {
+    match (payload_kind(&entry.path), previous) {
+        ("flowcells", Some(previous)) => {
+            let changes = changed_fields(previous, payload);
+            if changes.is_empty() {
+                vec!["flow cell updated, nothing changed".to_string()]
+            } else {
+                let mut lines = vec!["flow cell updated:".to_string()];
+                lines.extend(changes.into_iter().map(|change| format!("  {}", change)));
+                lines
+            }
+        }
+        ("flowcells", None) => {
+            let fields = SUMMARY_FIELDS
+                .iter()
+                .filter(|key| entry.method != "PATCH" || payload.get(**key).is_some())
+                .map(|key| format!("{}={}", key, payload.get(*key).unwrap_or(&Value::Null)))
+                .collect::<Vec<String>>();
+            vec![format!(
+                "flow cell {} with {}",
+                match entry.method.as_str() {
+                    "POST" => "registered",
+                    "PATCH" => "patched (first archived payload)",
+                    _ => "updated (first archived payload)",
+                },
+                fields.join(", ")
+            )]
+        }
+        ("messages", _) => {
+            let mut lines = vec![format!("message {:?}", payload_str(payload, "subject"))];
+            lines.extend(
+                payload_str(payload, "body")
+                    .lines()
+                    .map(|line| format!("  {}", line)),
+            );
+            lines
+        }
+        ("indexhistos", _) => vec![format!(
+            "index histogram of lane {}, index read {}",
+            payload.get("lane").unwrap_or(&Value::Null),
+            payload.get("index_read_no").unwrap_or(&Value::Null)
+        )],
+        (kind, _) => vec![format!("{} {}", &entry.method, kind)],
+    }
+}
+
+/// Write the `history` of a flow cell to `out`, with the changes between successive flow cell
+/// payloads.
+fn write_history(history: &FlowCellHistory, out: &mut String) {
+    let vendor_id = if history.vendor_id.is_empty() {
+        "(unknown flow cell)"
+    } else {
+        &history.vendor_id
+    };
+    writeln!(out, "{} on {}:", vendor_id, &history.server).unwrap();
+    for folder in &history.folders {
+        writeln!(out, "  folder: {}", folder).unwrap();
+    }
+
+    let mut previous: Option<Value> = None;
+    for entry in &history.entries {
+        // PATCH payloads (from `set-status`) only have the changed fields.
+        let payload = match (&previous, &entry.payload) {
+            (Some(Value::Object(previous)), Value::Object(fields)) if entry.method == "PATCH" => {
+                let mut merged = previous.clone();
+                merged.extend(fields.clone());
+                Value::Object(merged)
+            }
+            _ => entry.payload.clone(),
+        };
+        let lines = describe(entry, &payload, previous.as_ref());
+        writeln!(out, "  {} {}", &entry.time, &lines[0]).unwrap();
+        for line in &lines[1..] {
+            writeln!(out, "{}", format!("      {}", line).trim_end()).unwrap();
+        }
+        if payload_kind(&entry.path) == "flowcells" {
+            previous = Some(payload);
+        }
+    }
+    writeln!(out).unwrap();
+}
+
+/// Main entry point for the `history` command.
+///
+/// Reads the payloads archived in `settings.archive_dir` and prints them by flow cell, limited
+/// to `settings.history.flowcell` and `settings.history.folder` if given, and anonymized with
+/// `--anonymize`.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client history");
+    if settings.archive_dir.is_empty() {
+        bail!("You have to specify the payload archive with --archive-dir");
+    }
+
+    let payloads = read_archive(Path::new(&settings.archive_dir))?;
+    info!(logger, "Read {} archived payload(s)", payloads.len());
+    let folder = Path::new(&settings.history.folder);
+    let histories = group_by_flowcell(payloads)
+        .into_iter()
+        .filter(|history| {
+            settings.history.flowcell.is_empty() || history.vendor_id == settings.history.flowcell
+        })
+        .filter(|history| {
+            settings.history.folder.is_empty()
+                || history
+                    .folders
+                    .iter()
+                    .any(|history_folder| Path::new(history_folder) == folder)
+        })
+        .collect::<Vec<FlowCellHistory>>();
+
+    if histories.is_empty() {
+        info!(
+            logger,
+            "No archived payloads found for the given flow cell or folder"
+        );
+    }
+    let mut anonymizer = Anonymizer::new(settings);
+    let mut out = String::new();
+    for history in &histories {
+        if anonymizer.is_enabled() {
+            for folder in history.folders.iter().map(Path::new) {
+                if folder.is_dir() {
+                    anonymizer.add_run_folder(logger, folder, settings);
+                }
+            }
+            for entry in &history.entries {
+                if payload_kind(&entry.path) == "flowcells" {
+                    anonymizer.add_payload(&entry.payload);
+                }
+            }
+        }
+        write_history(history, &mut out);
+    }
+    print!("{}", anonymizer.apply(&out));
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! Implementation of the `import` command that reads flow cells written by `export` on hosts
+//! without network access and registers or updates them with the API, together with their
+//! index histograms.
+
+use serde_json::{self, Value};
+use std::fs;
+
+use super::export::FlowCellExport;
+use super::*;
+
+/// Read the exported flow cell from the file at `path`.
+fn read_export(path: &str) -> Result<FlowCellExport> {
+    let contents = fs::read_to_string(path).chain_err(|| format!("Problem reading {:?}", path))?;
+    serde_json::from_str(&contents).chain_err(|| format!("Problem parsing export {:?}", path))
+}
+
+/// Return the exported index `histograms` for the flow cell with the UUID `flowcell_uuid`.
+fn parse_histograms(
+    histograms: &[Value],
+    flowcell_uuid: &Uuid,
+) -> Result<Vec<api::LaneIndexHistogram>> {
+    histograms
+        .iter()
+        .map(|histogram| {
+            let mut histogram = histogram.clone();
+            match histogram.as_object_mut() {
+                Some(fields) => {
+                    fields.insert(
+                        "flowcell".to_string(),
+                        Value::from(flowcell_uuid.to_string()),
+                    );
+                }
+                None => bail!("Invalid index histogram in export: {}", histogram),
+            }
+            serde_json::from_value(histogram).chain_err(|| "Invalid index histogram in export")
+        })
+        .collect()
+}
+
+/// Post the exported index `histograms` to the `flowcell` on the server, unless it already has
+/// at least as many and `settings.ingest.force_analyze_adapters` is not set.
+fn post_histograms(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    histograms: &[Value],
+    settings: &Settings,
+) -> Result<()> {
+    if histograms.is_empty() {
+        return Ok(());
+    }
+    let flowcell_uuid = match flowcell.sodar_uuid {
+        Some(flowcell_uuid) => flowcell_uuid,
+        None => bail!("Flow cell has no UUID, cannot post index histograms"),
+    };
+    let histograms = parse_histograms(histograms, &flowcell_uuid)?;
+    let args = api::ProjectFlowcellArgs {
+        project_uuid: settings.ingest.project_uuid,
+        flowcell_uuid,
+    };
+    let api::LaneIndexHistogramArray::Array(existing) = client
+        .get(&args)
+        .chain_err(|| "Problem querying index histograms of flow cell")?;
+    if existing.len() >= histograms.len() && !settings.ingest.force_analyze_adapters {
+        info!(
+            logger,
+            "Flow cell already has {} index histogram(s), not posting the exported ones",
+            existing.len()
+        );
+        return Ok(());
+    }
+    for histogram in &histograms {
+        client
+            .post_histogram(&args, histogram, settings.ingest.max_histogram_entries)
+            .chain_err(|| "Problem posting index histogram")?;
+    }
+    info!(logger, "Posted {} index histogram(s)", histograms.len());
+    Ok(())
+}
+
+/// Register or update the flow cell exported to the file at `path`, following the settings in
+/// `settings.ingest` as `ingest` does for run folders.
+fn import_file(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    path: &str,
+    settings: &Settings,
+) -> Result<()> {
+    let export = read_export(path)?;
+    let exported = &export.flowcell;
+    info!(
+        logger,
+        "Importing flow cell {} (run {} on {}) from {:?}",
+        &exported.vendor_id,
+        exported.run_number,
+        &exported.sequencing_machine,
+        path
+    );
+    client.set_archive_folder(&export.path);
+
+    let mut warnings = export.warnings.clone();
+    let result: result::Result<api::FlowCell, restson::Error> =
+        client.get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: exported.sequencing_machine.clone(),
+            run_number: exported.run_number,
+            flowcell: exported.vendor_id.clone(),
+        });
+    let flowcell = match result {
+        Ok(flowcell) => {
+            let is_final = flowcell.status_sequencing != "initial"
+                && flowcell.status_sequencing != "in_progress"
+                && flowcell.status_sequencing != "failed";
+            if !settings.ingest.update {
+                info!(logger, "Flow cell exists, you asked me to not update it.");
+                flowcell
+            } else if is_final && settings.ingest.skip_if_status_final {
+                info!(
+                    logger,
+                    "Flowcell has a final sequencing status ({:?}), skipping",
+                    &flowcell.status_sequencing
+                );
+                flowcell
+            } else {
+                check_lane_count(
+                    logger,
+                    &flowcell,
+                    exported.num_lanes,
+                    settings,
+                    &mut warnings,
+                )?;
+                if settings.dry_run {
+                    info!(logger, "Dry running activated, not updating flow cell.");
+                    return Ok(());
+                }
+                put_updated_flowcell(
+                    logger,
+                    client,
+                    &flowcell,
+                    exported,
+                    export.folder_layout == format!("{:?}", FolderLayout::Nanopore),
+                    settings,
+                )?
+            }
+        }
+        Err(restson::Error::HttpError(404, _)) => {
+            if !settings.ingest.register {
+                info!(
+                    logger,
+                    "Flow cell not found, you asked me to not register it."
+                );
+                return Ok(());
+            }
+            if settings.dry_run {
+                info!(logger, "Dry running activated, not registering flow cell.");
+                return Ok(());
+            }
+            info!(logger, "Registering flow cell...");
+            post_new_flowcell(logger, client, exported, settings)?
+        }
+        Err(e) => bail!("Problem resolving flow cell: {:?}", e),
+    };
+
+    if settings.dry_run {
+        info!(
+            logger,
+            "Dry running activated, not posting index histograms."
+        );
+        return Ok(());
+    }
+    post_histograms(
+        logger,
+        client,
+        &flowcell,
+        &export.index_histograms,
+        settings,
+    )?;
+    if settings.ingest.post_warnings && !warnings.is_empty() {
+        post_warnings(logger, client, &flowcell, &warnings, settings)?;
+    }
+    Ok(())
+}
+
+/// Main entry point for the `import` command.
+///
+/// Registers or updates the flow cells exported to the files in `settings.import.path` with the
+/// settings in `settings.ingest`, and posts their index histograms.  Continues with the other
+/// files if one cannot be imported and fails at the end.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client import");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.import.path.is_empty() {
+        bail!("You have to specify at least one exported file");
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let mut num_failed = 0;
+    for path in &settings.import.path {
+        if let Err(e) = import_file(logger, &mut client, path, settings) {
+            error!(logger, "Could not import {:?}: {}", path, e);
+            num_failed += 1;
+        }
+    }
+    if num_failed > 0 {
+        bail!(
+            "Could not import {} of {} file(s)",
+            num_failed,
+            settings.import.path.len()
+        );
+    }
+    Ok(())
+}
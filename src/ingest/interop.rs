@@ -0,0 +1,315 @@
+//! Per-lane quality indicators from the Illumina InterOp metrics files.
+//!
+//! Some runs fail only in specific lanes (e.g., a clogged lane on the HiSeq), which the status of
+//! the whole flow cell cannot express.  The cluster density and fraction of clusters passing
+//! filter are read from `InterOp/TileMetricsOut.bin` (versions 2 and 3), the error rate from the
+//! PhiX alignment from `InterOp/ErrorMetricsOut.bin` (versions 3 and 4), if present.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use super::super::errors::*;
+use settings::Settings;
+
+/// Tile metric codes of version 2 files.
+const CODE_DENSITY: u16 = 100;
+const CODE_CLUSTER_COUNT: u16 = 102;
+const CODE_CLUSTER_COUNT_PF: u16 = 103;
+/// Tile metric code of version 3 files for cluster counts.
+const CODE_TILE: u8 = b't';
+
+/// Thresholds for flagging a lane as failed.
+#[derive(Debug, Clone)]
+pub struct LaneThresholds {
+    /// Minimal fraction of clusters passing filter.
+    pub min_pf_fraction: f64,
+    /// Maximal error rate in percent.
+    pub max_error_rate: f64,
+    /// Minimal cluster density relative to the median density of the lanes.
+    pub min_relative_density: f64,
+}
+
+impl LaneThresholds {
+    /// Construct from the ingest settings in `settings`.
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            min_pf_fraction: settings.ingest.lane_min_pf_fraction,
+            max_error_rate: settings.ingest.lane_max_error_rate,
+            min_relative_density: settings.ingest.lane_min_relative_density,
+        }
+    }
+}
+
+/// The quality indicators of one lane.
+#[derive(Debug, Clone, Serialize)]
+pub struct LaneQuality {
+    /// The number of the lane.
+    pub lane: i32,
+    /// The number of tiles with metrics.
+    pub tiles: usize,
+    /// The mean cluster density in clusters per mm², if known.
+    pub density: Option<f64>,
+    /// The total number of clusters, if known.
+    pub clusters: Option<f64>,
+    /// The total number of clusters passing filter, if known.
+    pub clusters_pf: Option<f64>,
+    /// The fraction of clusters passing filter, if known.
+    pub pf_fraction: Option<f64>,
+    /// The mean error rate in percent over tiles and cycles, if the run had PhiX spiked in.
+    pub error_rate: Option<f64>,
+    /// The reasons for flagging the lane as failed, empty if it looks fine.
+    pub failures: Vec<String>,
+}
+
+impl LaneQuality {
+    /// Return whether the lane is flagged as failed.
+    pub fn is_failed(&self) -> bool {
+        !self.failures.is_empty()
+    }
+}
+
+/// Sums over the tiles of a lane.
+#[derive(Debug, Default)]
+struct LaneSums {
+    /// The tiles seen.
+    tiles: BTreeSet<u32>,
+    /// Sum and count of the tile densities.
+    density: (f64, usize),
+    /// The total cluster count.
+    clusters: f64,
+    /// The total cluster count passing filter.
+    clusters_pf: f64,
+    /// Sum and count of the error rates of tiles and cycles.
+    error_rate: (f64, usize),
+}
+
+/// Minimal reader of little-endian values from InterOp records.
+struct Record<'a>(&'a [u8]);
+
+impl<'a> Record<'a> {
+    fn u8(&self, offset: usize) -> u8 {
+        self.0[offset]
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.0[offset..(offset + 4)]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn f32(&self, offset: usize) -> f64 {
+        f64::from(f32::from_bits(self.u32(offset)))
+    }
+}
+
+/// Read the InterOp file at `path` and return its version, the bytes after the version and
+/// record size, and the record size.
+fn read_records(path: &Path) -> Result<(u8, Vec<u8>, usize)> {
+    let contents = fs::read(path).chain_err(|| format!("Problem reading {:?}", path))?;
+    if contents.len() < 2 || contents[1] == 0 {
+        bail!("InterOp file {:?} is truncated", path);
+    }
+    Ok((contents[0], contents[2..].to_vec(), contents[1] as usize))
+}
+
+/// Add the tile metrics from the file at `path` to `sums`.
+fn read_tile_metrics(path: &Path, sums: &mut BTreeMap<i32, LaneSums>) -> Result<()> {
+    let (version, body, record_size) = read_records(path)?;
+    match (version, record_size) {
+        (2, 10) => {
+            for record in body.chunks_exact(record_size).map(Record) {
+                let lane = sums.entry(i32::from(record.u16(0))).or_default();
+                lane.tiles.insert(u32::from(record.u16(2)));
+                let value = record.f32(6);
+                if !value.is_finite() {
+                    continue;
+                }
+                match record.u16(4) {
+                    CODE_DENSITY => {
+                        lane.density.0 += value;
+                        lane.density.1 += 1;
+                    }
+                    CODE_CLUSTER_COUNT => lane.clusters += value,
+                    CODE_CLUSTER_COUNT_PF => lane.clusters_pf += value,
+                    _ => (),
+                }
+            }
+        }
+        (3, 15) if body.len() >= 4 => {
+            let area = Record(&body).f32(0);
+            for record in body[4..].chunks_exact(record_size).map(Record) {
+                if record.u8(6) != CODE_TILE {
+                    continue;
+                }
+                let lane = sums.entry(i32::from(record.u16(0))).or_default();
+                lane.tiles.insert(record.u32(2));
+                let (clusters, clusters_pf) = (record.f32(7), record.f32(11));
+                if clusters.is_finite() && clusters_pf.is_finite() {
+                    lane.clusters += clusters;
+                    lane.clusters_pf += clusters_pf;
+                    if area > 0.0 {
+                        lane.density.0 += clusters / area;
+                        lane.density.1 += 1;
+                    }
+                }
+            }
+        }
+        _ => bail!(
+            "Unsupported version {} (record size {}) of {:?}",
+            version,
+            record_size,
+            path
+        ),
+    }
+    Ok(())
+}
+
+/// Add the error rates from the file at `path` to `sums`.
+fn read_error_metrics(path: &Path, sums: &mut BTreeMap<i32, LaneSums>) -> Result<()> {
+    let (version, body, record_size) = read_records(path)?;
+    let rate_offset = match (version, record_size) {
+        (3, 30) => 6,
+        (4, 12) => 8,
+        _ => bail!(
+            "Unsupported version {} (record size {}) of {:?}",
+            version,
+            record_size,
+            path
+        ),
+    };
+    for record in body.chunks_exact(record_size).map(Record) {
+        let rate = record.f32(rate_offset);
+        if rate.is_finite() {
+            let lane = sums.entry(i32::from(record.u16(0))).or_default();
+            lane.error_rate.0 += rate;
+            lane.error_rate.1 += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Return the median of `values`, which must not be empty.
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Read the quality indicators of the lanes of the run folder at `path` from its InterOp files
+/// and flag the lanes failing the `thresholds`.
+///
+/// Returns an empty result for run folders without tile metrics, e.g., for other vendors.
+pub fn lane_quality(path: &Path, thresholds: &LaneThresholds) -> Result<Vec<LaneQuality>> {
+    let interop = path.join("InterOp");
+    let tile_metrics = interop.join("TileMetricsOut.bin");
+    if !tile_metrics.exists() {
+        return Ok(Vec::new());
+    }
+    let mut sums = BTreeMap::new();
+    read_tile_metrics(&tile_metrics, &mut sums)?;
+    let error_metrics = interop.join("ErrorMetricsOut.bin");
+    if error_metrics.exists() {
+        read_error_metrics(&error_metrics, &mut sums)?;
+    }
+
+    let mut result = sums
+        .iter()
+        .map(|(lane, sums)| LaneQuality {
+            lane: *lane,
+            tiles: sums.tiles.len(),
+            density: Some(sums.density)
+                .filter(|(_, count)| *count > 0)
+                .map(|(sum, count)| sum / count as f64),
+            clusters: Some(sums.clusters).filter(|clusters| *clusters > 0.0),
+            clusters_pf: Some(sums.clusters_pf).filter(|_| sums.clusters > 0.0),
+            pf_fraction: Some(sums.clusters)
+                .filter(|clusters| *clusters > 0.0)
+                .map(|clusters| sums.clusters_pf / clusters),
+            error_rate: Some(sums.error_rate)
+                .filter(|(_, count)| *count > 0)
+                .map(|(sum, count)| sum / count as f64),
+            failures: Vec::new(),
+        })
+        .collect::<Vec<LaneQuality>>();
+
+    let mut densities = result
+        .iter()
+        .filter_map(|lane| lane.density)
+        .collect::<Vec<f64>>();
+    let median_density = if densities.len() > 1 {
+        Some(median(&mut densities))
+    } else {
+        None
+    };
+    for lane in &mut result {
+        if let (Some(density), Some(median_density)) = (lane.density, median_density) {
+            if density < thresholds.min_relative_density * median_density {
+                lane.failures.push(format!(
+                    "cluster density {:.0} K/mm² is below {:.0}% of the median {:.0} K/mm²",
+                    density / 1000.0,
+                    100.0 * thresholds.min_relative_density,
+                    median_density / 1000.0
+                ));
+            }
+        }
+        if let Some(pf_fraction) = lane.pf_fraction {
+            if pf_fraction < thresholds.min_pf_fraction {
+                lane.failures.push(format!(
+                    "{:.1}% of clusters passing filter is below {:.1}%",
+                    100.0 * pf_fraction,
+                    100.0 * thresholds.min_pf_fraction
+                ));
+            }
+        } else if lane.tiles > 0 {
+            lane.failures.push("no clusters".to_string());
+        }
+        if let Some(error_rate) = lane.error_rate {
+            if error_rate > thresholds.max_error_rate {
+                lane.failures.push(format!(
+                    "error rate {:.2}% is above {:.2}%",
+                    error_rate, thresholds.max_error_rate
+                ));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Render `lanes` as the body of a flow cell message.
+pub fn message_body(lanes: &[LaneQuality]) -> String {
+    let format_opt = |value: Option<f64>, scale: f64, precision: usize| {
+        value.map_or("-".to_string(), |value| {
+            format!("{:.*}", precision, value * scale)
+        })
+    };
+    let mut lines = vec![
+        "The InterOp metrics show the following quality per lane:".to_string(),
+        String::new(),
+        "| lane | density (K/mm²) | % PF | error rate (%) | status |".to_string(),
+        "|-----:|----------------:|-----:|---------------:|--------|".to_string(),
+    ];
+    for lane in lanes {
+        lines.push(format!(
+            "| {} | {} | {} | {} | {} |",
+            lane.lane,
+            format_opt(lane.density, 0.001, 0),
+            format_opt(lane.pf_fraction, 100.0, 1),
+            format_opt(lane.error_rate, 1.0, 2),
+            if lane.is_failed() {
+                format!("FAILED: {}", lane.failures.join("; "))
+            } else {
+                "ok".to_string()
+            }
+        ));
+    }
+    lines.join("\n")
+}
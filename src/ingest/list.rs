@@ -0,0 +1,141 @@
+//! Implementation of the `list` command that prints the flow cells of a project in Digestiflow
+//! Web, for checking the server state from the same client the folders are ingested with.
+
+use chrono::NaiveDate;
+use serde_json;
+
+use super::*;
+use anonymize::Anonymizer;
+
+/// The column headers of the table output.
+const COLUMNS: &[&str] = &[
+    "vendor_id",
+    "run_date",
+    "instrument",
+    "run",
+    "sequencing",
+    "conversion",
+    "delivery",
+    "label",
+];
+
+/// Parse the date `value` in `YYYY-MM-DD` format.
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+/// Return whether `flowcell` passes the filters in `settings.list`.
+fn is_selected(flowcell: &api::FlowCell, since: Option<NaiveDate>, settings: &Settings) -> bool {
+    (settings.list.status_sequencing.is_empty()
+        || flowcell.status_sequencing == settings.list.status_sequencing)
+        && (settings.list.instrument.is_empty()
+            || flowcell.sequencing_machine == settings.list.instrument)
+        && since.is_none_or(|since| {
+            parse_date(&flowcell.run_date).is_some_and(|run_date| run_date >= since)
+        })
+}
+
+/// Return `flowcells` as a table with aligned columns, anonymized with `anonymizer`.
+fn format_table(flowcells: &[api::FlowCell], anonymizer: &Anonymizer) -> String {
+    let mut rows = vec![COLUMNS
+        .iter()
+        .map(|column| column.to_string())
+        .collect::<Vec<String>>()];
+    for flowcell in flowcells {
+        let cells = [
+            flowcell.vendor_id.clone(),
+            flowcell.run_date.clone(),
+            flowcell.sequencing_machine.clone(),
+            flowcell.run_number.to_string(),
+            flowcell.status_sequencing.clone(),
+            flowcell.status_conversion.clone(),
+            flowcell.status_delivery.clone(),
+            flowcell.label.clone().unwrap_or_default(),
+        ];
+        rows.push(cells.iter().map(|cell| anonymizer.apply(cell)).collect());
+    }
+    let mut widths = vec![0; COLUMNS.len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut result = String::new();
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = *width))
+            .collect::<Vec<String>>()
+            .join("  ");
+        result.push_str(line.trim_end());
+        result.push('\n');
+    }
+    result
+}
+
+/// Main entry point for the `list` command.
+///
+/// Prints the flow cells of the project `settings.ingest.project_uuid` that pass the filters in
+/// `settings.list`, ordered by run date, instrument, and run number.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client list");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    let since = match (
+        settings.list.since.as_str(),
+        parse_date(&settings.list.since),
+    ) {
+        ("", _) => None,
+        (_, Some(since)) => Some(since),
+        (since, None) => bail!("Invalid date {:?}, expected YYYY-MM-DD", since),
+    };
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let api::FlowCellArray::Array(flowcells) = client
+        .get(&api::ProjectArgs {
+            project_uuid: settings.ingest.project_uuid,
+        })
+        .chain_err(|| "Could not list flow cells of project")?;
+    info!(logger, "Found {} flow cells in project", flowcells.len());
+
+    let mut flowcells = flowcells
+        .into_iter()
+        .filter(|flowcell| is_selected(flowcell, since, settings))
+        .collect::<Vec<api::FlowCell>>();
+    flowcells.sort_by(|a, b| {
+        a.run_date
+            .cmp(&b.run_date)
+            .then_with(|| a.sequencing_machine.cmp(&b.sequencing_machine))
+            .then_with(|| a.run_number.cmp(&b.run_number))
+    });
+
+    let mut anonymizer = Anonymizer::new(settings);
+    if anonymizer.is_enabled() {
+        for flowcell in &flowcells {
+            anonymizer.add_payload(
+                &serde_json::to_value(flowcell).chain_err(|| "Problem serializing to JSON")?,
+            );
+        }
+    }
+    match settings.list.format.as_str() {
+        "table" => print!("{}", format_table(&flowcells, &anonymizer)),
+        "json" => println!(
+            "{}",
+            anonymizer.apply(
+                &serde_json::to_string_pretty(&flowcells)
+                    .chain_err(|| "Problem serializing to JSON")?
+            )
+        ),
+        format => bail!("Invalid output format {:?}", format),
+    }
+
+    Ok(())
+}
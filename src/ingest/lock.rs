@@ -0,0 +1,163 @@
+//! Advisory lock files in run folders, such that overlapping calls (e.g., from cron) do not
+//! process the same folder concurrently and post its index histograms twice.
+//!
+//! The lock file records the process ID, host name, and time of the call holding the lock.  It
+//! is removed when processing the folder ends; a lock file left behind by a crashed call is
+//! stale and only removed with `--break-stale-locks`.
+
+use chrono::{DateTime, Duration, Local};
+use hostname;
+use libc;
+use serde_json;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time;
+
+use super::super::errors::*;
+
+/// Name of the lock file in the run folder.
+pub const LOCK_FILE: &str = ".digestiflow.lock";
+/// Age in hours after which a lock is stale regardless of its process, e.g., for locks of other
+/// hosts whose processes cannot be checked.
+const STALE_LOCK_HOURS: i64 = 24;
+/// Time after which a lock file that cannot be read is stale.
+const UNREADABLE_GRACE: time::Duration = time::Duration::from_secs(60);
+
+/// The holder of a lock, as recorded in the lock file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockHolder {
+    /// The process ID.
+    pub pid: u32,
+    /// The name of the host.
+    pub hostname: String,
+    /// Time the lock was acquired.
+    pub since: String,
+}
+
+impl LockHolder {
+    /// Construct for the current process.
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            hostname: current_hostname(),
+            since: Local::now().to_rfc3339(),
+        }
+    }
+
+    /// Return whether the lock is stale, i.e., its process no longer runs on this host or it is
+    /// older than `STALE_LOCK_HOURS`.
+    fn is_stale(&self) -> bool {
+        let too_old = DateTime::parse_from_rfc3339(&self.since)
+            .map(|since| Local::now().signed_duration_since(since))
+            .map_or(true, |age| age > Duration::hours(STALE_LOCK_HOURS));
+        too_old || (self.hostname == current_hostname() && !is_running(self.pid))
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "process {} on {} since {}",
+            self.pid, &self.hostname, &self.since
+        )
+    }
+}
+
+/// Return the name of this host.
+fn current_hostname() -> String {
+    hostname::get()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Return whether the process `pid` runs on this host.
+pub fn is_running(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// A lock on a run folder, released when dropped.
+#[derive(Debug)]
+pub struct FolderLock {
+    /// Path to the lock file.
+    path: PathBuf,
+}
+
+impl Drop for FolderLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The outcome of trying to lock a run folder.
+#[derive(Debug)]
+pub enum Locking {
+    /// The lock was acquired.
+    Acquired(FolderLock),
+    /// The folder is locked by the described holder.
+    Held(String),
+    /// The lock file cannot be written, e.g., for read-only run folders.
+    Unsupported(String),
+}
+
+/// Read the holder of the lock file at `path`.
+fn read_holder(path: &Path) -> Result<LockHolder> {
+    let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+    serde_json::from_reader(file).chain_err(|| format!("Problem reading JSON from {:?}", path))
+}
+
+/// Lock the run folder at `folder`.
+///
+/// Stale locks are removed if `break_stale` is set, otherwise an error is returned.
+pub fn lock_folder(logger: &slog::Logger, folder: &Path, break_stale: bool) -> Result<Locking> {
+    let path = folder.join(LOCK_FILE);
+    let holder = LockHolder::current();
+    // The second attempt follows removing a stale lock.
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => {
+                let lock = FolderLock { path: path.clone() };
+                serde_json::to_writer(file, &holder)
+                    .chain_err(|| format!("Problem writing JSON to {:?}", &path))?;
+                return Ok(Locking::Acquired(lock));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                // The holder may not have written the lock file yet, so unreadable lock files
+                // are only stale after a grace period.
+                let (other, is_stale) = match read_holder(&path) {
+                    Ok(other) => (other.to_string(), other.is_stale()),
+                    Err(_) => (
+                        "unknown process (unreadable lock file)".to_string(),
+                        file_age(&path) > UNREADABLE_GRACE,
+                    ),
+                };
+                if !is_stale {
+                    return Ok(Locking::Held(other));
+                } else if !break_stale {
+                    bail!(
+                        "Stale lock file {:?} of {}; remove it or use --break-stale-locks",
+                        &path,
+                        &other
+                    );
+                }
+                warn!(logger, "Breaking stale lock of {} on {:?}", &other, folder);
+                fs::remove_file(&path).chain_err(|| format!("Problem removing {:?}", &path))?;
+            }
+            Err(e) => return Ok(Locking::Unsupported(e.to_string())),
+        }
+    }
+    bail!("Could not lock {:?}, other calls keep locking it", folder)
+}
+
+/// Return the time since the file at `path` was last modified, zero if unknown.
+fn file_age(path: &Path) -> time::Duration {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.elapsed().ok())
+        .unwrap_or_default()
+}
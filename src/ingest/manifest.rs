@@ -0,0 +1,587 @@
+//! Implementation of the `manifest` command that writes the checksums of all files of a run
+//! folder into a manifest, or verifies the files against an existing manifest, e.g., after
+//! transferring an archived run.
+//!
+//! Manifests have the format of `xxhsum` and `md5sum`, with one line for each file: the hex
+//! digest, two spaces, and the path relative to the run folder.  They can thus also be checked
+//! with `xxhsum -c` or `md5sum -c` in the run folder.
+//!
+//! The files are walked in the order of their paths and their checksums computed in parallel in
+//! batches, such that the memory used does not grow with the size of the run folder.  Progress
+//! is logged periodically, and files that cannot be read are collected and reported at the end
+//! instead of stopping at the first one.
+
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::result;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::vec;
+
+use super::super::errors::*;
+use ingest::backfill::format_duration;
+use ingest::checksum::Algorithm;
+use ingest::folder_size::format_bytes;
+use ingest::lock::LOCK_FILE;
+use settings::Settings;
+
+/// Name of the manifest in the run folder if no other is given, followed by the algorithm.
+const DEFAULT_NAME: &str = "manifest";
+
+/// Number of files whose checksums are computed in parallel before the results are written,
+/// bounding the memory used for large run folders.
+const BATCH_SIZE: usize = 1024;
+
+/// Interval between the progress messages.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A file (or directory) of the run folder that could not be read, with the reason.
+#[derive(Debug)]
+struct FileError {
+    /// The path relative to the run folder.
+    path: PathBuf,
+    /// Description of the problem.
+    message: String,
+}
+
+/// Walker over the files below a run folder in the order of their paths.
+///
+/// Only the entries of the directories on the way to the current file are held in memory.
+/// Symbolic links are not followed but counted.  The lock file of `ingest` (see `lock`) is left
+/// out, as it only exists while the folder is processed.
+struct FileWalker<'a> {
+    /// The run folder.
+    root: &'a Path,
+    /// The paths relative to `root` to leave out, e.g., the manifest itself.
+    exclude: &'a [PathBuf],
+    /// The remaining entries of the directories on the way to the current file, sorted.
+    stack: Vec<vec::IntoIter<PathBuf>>,
+    /// The number of symbolic links skipped.
+    num_links: usize,
+}
+
+impl<'a> FileWalker<'a> {
+    /// Construct for walking the files below `root`, except for `exclude`.
+    fn new(root: &'a Path, exclude: &'a [PathBuf]) -> result::Result<Self, FileError> {
+        let mut walker = Self {
+            root,
+            exclude,
+            stack: Vec::new(),
+            num_links: 0,
+        };
+        let entries = walker.read_dir(Path::new(""))?;
+        walker.stack.push(entries);
+        Ok(walker)
+    }
+
+    /// Return the sorted paths relative to the run folder of the entries of the directory
+    /// `dir`, also relative to it.
+    fn read_dir(&self, dir: &Path) -> result::Result<vec::IntoIter<PathBuf>, FileError> {
+        let error = |e: io::Error| FileError {
+            path: dir.to_path_buf(),
+            message: format!("Problem reading directory: {}", e),
+        };
+        let mut names = fs::read_dir(self.root.join(dir))
+            .map_err(error)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<OsString>>>()
+            .map_err(error)?;
+        names.sort();
+        Ok(names
+            .into_iter()
+            .map(|name| dir.join(name))
+            .collect::<Vec<PathBuf>>()
+            .into_iter())
+    }
+}
+
+impl<'a> Iterator for FileWalker<'a> {
+    type Item = result::Result<PathBuf, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let relative = match self.stack.last_mut()?.next() {
+                Some(relative) => relative,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let file_type = match fs::symlink_metadata(self.root.join(&relative)) {
+                Ok(meta) => meta.file_type(),
+                Err(e) => {
+                    return Some(Err(FileError {
+                        path: relative,
+                        message: format!("Problem reading type: {}", e),
+                    }))
+                }
+            };
+            if file_type.is_dir() {
+                match self.read_dir(&relative) {
+                    Ok(entries) => self.stack.push(entries),
+                    Err(e) => return Some(Err(e)),
+                }
+            } else if file_type.is_symlink() {
+                self.num_links += 1;
+            } else if file_type.is_file()
+                && !self.exclude.contains(&relative)
+                && relative != Path::new(LOCK_FILE)
+            {
+                return Some(Ok(relative));
+            }
+        }
+    }
+}
+
+/// Return the next at most `BATCH_SIZE` files from `walker`, adding the problems to `errors`.
+fn next_batch(walker: &mut FileWalker, errors: &mut Vec<FileError>) -> Vec<PathBuf> {
+    let mut batch = Vec::new();
+    while batch.len() < BATCH_SIZE {
+        match walker.next() {
+            Some(Ok(file)) => batch.push(file),
+            Some(Err(e)) => errors.push(e),
+            None => break,
+        }
+    }
+    batch
+}
+
+/// The files and bytes processed so far, and the time of the last progress message.
+#[derive(Debug)]
+struct ProgressCounts {
+    files: usize,
+    bytes: u64,
+    last_report: Instant,
+}
+
+/// Progress of computing the checksums of a run folder, updated from the worker threads and
+/// logged every `PROGRESS_INTERVAL`.
+#[derive(Debug)]
+struct Progress {
+    /// Time the processing started.
+    start: Instant,
+    /// The number of files to process.
+    total_files: usize,
+    /// The total size of the files to process.
+    total_bytes: u64,
+    /// The files and bytes processed so far.
+    counts: Mutex<ProgressCounts>,
+}
+
+impl Progress {
+    /// Construct for processing `total_files` files of `total_bytes` bytes.
+    fn new(total_files: usize, total_bytes: u64) -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            total_files,
+            total_bytes,
+            counts: Mutex::new(ProgressCounts {
+                files: 0,
+                bytes: 0,
+                last_report: start,
+            }),
+        }
+    }
+
+    /// Record a processed file of `bytes` bytes, and log the progress if due.
+    fn add(&self, logger: &slog::Logger, bytes: u64) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.files += 1;
+        counts.bytes += bytes;
+        if counts.last_report.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        counts.last_report = Instant::now();
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let remaining = self.total_bytes.saturating_sub(counts.bytes);
+        info!(
+            logger,
+            "Checksummed {}/{} file(s), {} of {} ({}/s), ETA {}",
+            counts.files,
+            self.total_files,
+            format_bytes(counts.bytes),
+            format_bytes(self.total_bytes),
+            format_bytes((counts.bytes as f64 / elapsed) as u64),
+            format_duration(elapsed * remaining as f64 / counts.bytes.max(1) as f64)
+        );
+    }
+}
+
+/// Return the digest of the `file` relative to `root` with `algorithm`, `None` if it does not
+/// exist, and record it in `progress`.
+fn file_digest(
+    logger: &slog::Logger,
+    root: &Path,
+    file: &Path,
+    algorithm: Algorithm,
+    progress: &Progress,
+) -> result::Result<Option<String>, String> {
+    let path = root.join(file);
+    let size = match fs::metadata(&path) {
+        Ok(ref meta) if meta.is_file() => meta.len(),
+        Ok(_) => return Ok(None),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Problem reading metadata: {}", e)),
+    };
+    let digest = algorithm
+        .file_digest(&path)
+        .map_err(|e| format!("Problem reading file: {}", e))?;
+    progress.add(logger, size);
+    Ok(Some(digest))
+}
+
+/// Return the digests of the `files` relative to `root` with `algorithm`, computed in
+/// parallel, `None` for files that do not exist and the problem for files that cannot be read.
+fn digests(
+    logger: &slog::Logger,
+    root: &Path,
+    files: &[PathBuf],
+    algorithm: Algorithm,
+    progress: &Progress,
+) -> Vec<result::Result<Option<String>, String>> {
+    files
+        .par_iter()
+        .map(|file| file_digest(logger, root, file, algorithm, progress))
+        .collect()
+}
+
+/// Return the number and total size in bytes of the files below `root`, except for `exclude`.
+fn count_files(root: &Path, exclude: &[PathBuf]) -> (usize, u64) {
+    match FileWalker::new(root, exclude) {
+        Ok(walker) => walker
+            .filter_map(|file| file.ok())
+            .filter_map(|file| fs::metadata(root.join(file)).ok())
+            .fold((0, 0), |(files, bytes), meta| {
+                (files + 1, bytes + meta.len())
+            }),
+        Err(_) => (0, 0),
+    }
+}
+
+/// Log the problems in `errors`, and fail with a summary if there are any.
+fn report_errors(logger: &slog::Logger, errors: &[FileError], path: &Path) -> Result<()> {
+    for error in errors {
+        error!(logger, "{}: {}", error.path.display(), &error.message);
+    }
+    if !errors.is_empty() {
+        bail!(
+            "Could not read {} of the files and directories of {:?}",
+            errors.len(),
+            path
+        );
+    }
+    Ok(())
+}
+
+/// Warn about the symbolic links skipped by `walker` below `root`.
+fn warn_links(logger: &slog::Logger, walker: &FileWalker, root: &Path) {
+    if walker.num_links > 0 {
+        warn!(
+            logger,
+            "Skipped {} symbolic link(s) in {:?}, they are not followed", walker.num_links, root
+        );
+    }
+}
+
+/// Return the entry of the manifest line `line` with the number `line_no`: the digest and the
+/// path of the file, `None` for empty lines and comments.
+fn parse_line(line_no: usize, line: &str) -> Result<Option<(String, PathBuf)>> {
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    // The path is preceded by a space and a second space, or `*` for binary mode.
+    let entry = line.find(' ').and_then(|pos| {
+        let path = line.get((pos + 2)..)?;
+        Some((line[..pos].to_lowercase(), PathBuf::from(path)))
+    });
+    match entry {
+        Some((ref digest, ref path))
+            if Algorithm::from_digest(digest).is_some()
+                && digest.chars().all(|c| c.is_ascii_hexdigit())
+                && !path.as_os_str().is_empty() =>
+        {
+            Ok(Some((digest.clone(), path.clone())))
+        }
+        _ => bail!("Invalid line {} of manifest: {:?}", line_no + 1, line),
+    }
+}
+
+/// Return an iterator over the entries of the manifest at `output`.
+fn read_manifest(output: &Path) -> Result<impl Iterator<Item = Result<(String, PathBuf)>>> {
+    let file = File::open(output).chain_err(|| format!("Problem reading manifest {:?}", output))?;
+    let output = output.to_path_buf();
+    Ok(BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter_map(move |(line_no, line)| {
+            match line.chain_err(|| format!("Problem reading manifest {:?}", &output)) {
+                Ok(line) => parse_line(line_no, &line).transpose(),
+                Err(e) => Some(Err(e)),
+            }
+        }))
+}
+
+/// Write the manifest of the run folder at `path` to `output`, or to stdout.
+///
+/// The manifest is written to a temporary file first that replaces `output` only if all files
+/// could be read.
+fn write_manifest(
+    logger: &slog::Logger,
+    path: &Path,
+    output: &Path,
+    to_stdout: bool,
+    algorithm: Algorithm,
+) -> Result<()> {
+    let mut tmp_name = output.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_output = PathBuf::from(tmp_name);
+    let exclude = if to_stdout {
+        Vec::new()
+    } else {
+        [output, &tmp_output]
+            .iter()
+            .filter_map(|file| file.strip_prefix(path).ok())
+            .map(Path::to_path_buf)
+            .collect()
+    };
+
+    let (total_files, total_bytes) = count_files(path, &exclude);
+    info!(
+        logger,
+        "Computing {} checksums of {} file(s) ({})...",
+        algorithm.name(),
+        total_files,
+        format_bytes(total_bytes)
+    );
+    let progress = Progress::new(total_files, total_bytes);
+    let mut writer: BufWriter<Box<dyn Write>> = if to_stdout {
+        BufWriter::new(Box::new(io::stdout()))
+    } else {
+        let file = File::create(&tmp_output)
+            .chain_err(|| format!("Problem creating manifest {:?}", &tmp_output))?;
+        BufWriter::new(Box::new(file))
+    };
+
+    let mut walker = FileWalker::new(path, &exclude)
+        .map_err(|e| format!("Problem reading run folder {:?}: {}", path, &e.message))?;
+    let mut errors = Vec::new();
+    let mut num_files = 0;
+    loop {
+        let batch = next_batch(&mut walker, &mut errors);
+        if batch.is_empty() {
+            break;
+        }
+        for (file, digest) in batch
+            .iter()
+            .zip(digests(logger, path, &batch, algorithm, &progress))
+        {
+            let name = file.to_string_lossy();
+            let message = match digest {
+                Ok(Some(_)) if name.contains('\n') => "Cannot write file name to manifest",
+                Ok(Some(digest)) => {
+                    writeln!(writer, "{}  {}", digest, name)
+                        .chain_err(|| format!("Problem writing manifest {:?}", &tmp_output))?;
+                    num_files += 1;
+                    continue;
+                }
+                Ok(None) => "File disappeared while writing manifest",
+                Err(ref message) => message,
+            };
+            errors.push(FileError {
+                path: file.clone(),
+                message: message.to_string(),
+            });
+        }
+    }
+    writer
+        .flush()
+        .chain_err(|| format!("Problem writing manifest {:?}", &tmp_output))?;
+    drop(writer);
+    warn_links(logger, &walker, path);
+
+    if !to_stdout {
+        if !errors.is_empty() {
+            fs::remove_file(&tmp_output)
+                .chain_err(|| format!("Problem removing {:?}", &tmp_output))?;
+        } else {
+            fs::rename(&tmp_output, output)
+                .chain_err(|| format!("Problem writing manifest {:?}", output))?;
+            info!(
+                logger,
+                "Wrote manifest {:?} with {} file(s) in {:.1}s",
+                output,
+                num_files,
+                progress.start.elapsed().as_secs_f64()
+            );
+        }
+    }
+    report_errors(logger, &errors, path)
+}
+
+/// Verify the files of the run folder at `path` against the manifest `output`, failing if any
+/// file is missing, has a different checksum, or cannot be read.  Files not in the manifest are
+/// only reported.
+///
+/// The manifest is read twice, for checking it and the totals first, and only the paths of the
+/// files are kept for finding the files not in it.
+fn verify_manifest(logger: &slog::Logger, path: &Path, output: &Path) -> Result<()> {
+    let mut algorithm = None;
+    let mut listed = HashSet::new();
+    let mut total_bytes = 0;
+    for entry in read_manifest(output)? {
+        let (digest, file) = entry?;
+        match algorithm {
+            None => algorithm = Algorithm::from_digest(&digest),
+            Some(algorithm) if Algorithm::from_digest(&digest) != Some(algorithm) => {
+                bail!("Manifest {:?} mixes checksum algorithms", output)
+            }
+            Some(_) => (),
+        }
+        total_bytes += fs::metadata(path.join(&file))
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        listed.insert(file);
+    }
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => bail!("Manifest {:?} is empty", output),
+    };
+
+    info!(
+        logger,
+        "Verifying {} checksums of {} file(s) ({})...",
+        algorithm.name(),
+        listed.len(),
+        format_bytes(total_bytes)
+    );
+    let progress = Progress::new(listed.len(), total_bytes);
+    let mut num_missing = 0;
+    let mut num_failed = 0;
+    let mut errors = Vec::new();
+    let mut entries = read_manifest(output)?;
+    loop {
+        let batch = entries
+            .by_ref()
+            .take(BATCH_SIZE)
+            .collect::<Result<Vec<(String, PathBuf)>>>()?;
+        if batch.is_empty() {
+            break;
+        }
+        let files = batch
+            .iter()
+            .map(|(_, file)| file.clone())
+            .collect::<Vec<PathBuf>>();
+        for ((expected, file), digest) in batch
+            .iter()
+            .zip(digests(logger, path, &files, algorithm, &progress))
+        {
+            match digest {
+                Ok(None) => {
+                    println!("MISSING  {}", file.display());
+                    num_missing += 1;
+                }
+                Ok(Some(ref digest)) if digest != expected => {
+                    println!("FAILED   {}", file.display());
+                    num_failed += 1;
+                }
+                Ok(Some(_)) => (),
+                Err(message) => {
+                    println!("ERROR    {}", file.display());
+                    errors.push(FileError {
+                        path: file.clone(),
+                        message,
+                    });
+                }
+            }
+        }
+    }
+
+    let exclude = output
+        .strip_prefix(path)
+        .ok()
+        .map(Path::to_path_buf)
+        .into_iter()
+        .collect::<Vec<PathBuf>>();
+    let mut num_unlisted = 0;
+    match FileWalker::new(path, &exclude) {
+        Ok(mut walker) => {
+            for file in walker.by_ref() {
+                match file {
+                    Ok(ref file) if listed.contains(file) => (),
+                    Ok(file) => {
+                        println!("EXTRA    {}", file.display());
+                        num_unlisted += 1;
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+            warn_links(logger, &walker, path);
+        }
+        Err(e) => errors.push(e),
+    }
+    if num_unlisted > 0 {
+        warn!(
+            logger,
+            "{} file(s) of the run folder are not in the manifest", num_unlisted
+        );
+    }
+
+    info!(
+        logger,
+        "Verified {} file(s) in {:.1}s: {} missing, {} with different checksums, {} unreadable",
+        listed.len(),
+        progress.start.elapsed().as_secs_f64(),
+        num_missing,
+        num_failed,
+        errors.len()
+    );
+    report_errors(logger, &errors, path)?;
+    if num_missing + num_failed > 0 {
+        bail!(
+            "{} of {} file(s) in manifest {:?} are missing or differ",
+            num_missing + num_failed,
+            listed.len(),
+            output
+        );
+    }
+    Ok(())
+}
+
+/// Main entry point for the `manifest` command.
+///
+/// Writes the checksums of the files of the run folder `settings.manifest.path` with
+/// `settings.manifest.algorithm` to `settings.manifest.output`, computing them in parallel.
+/// With `settings.manifest.verify`, the files are checked against the manifest instead.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client manifest");
+    env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
+
+    let path = Path::new(&settings.manifest.path);
+    if !path.is_dir() {
+        bail!("Run folder {:?} does not exist", path);
+    }
+    let algorithm = Algorithm::from_name(&settings.manifest.algorithm)?;
+    let output = match settings.manifest.output.as_str() {
+        "" => path.join(format!("{}.{}", DEFAULT_NAME, algorithm.name())),
+        output => PathBuf::from(output),
+    };
+
+    if settings.manifest.verify {
+        if settings.manifest.output == "-" {
+            bail!("Cannot verify a manifest from stdout, give it with --output");
+        }
+        verify_manifest(logger, path, &output)
+    } else {
+        let to_stdout = settings.dry_run || settings.manifest.output == "-";
+        if !to_stdout && output.exists() && !settings.manifest.force {
+            bail!("Manifest {:?} exists, use --force to overwrite it", &output);
+        }
+        write_manifest(logger, path, &output, to_stdout, algorithm)
+    }
+}
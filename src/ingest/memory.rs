@@ -0,0 +1,278 @@
+//! Limiting and tracking the memory used for sampling index reads, see `--max-memory`.
+//!
+//! The memory budget is split between the base calls of the tiles sampled at the same time and
+//! the counting of the index sequences.  If the budget does not allow counting all distinct
+//! sequences exactly, e.g., for runs with UMIs in the index reads, the counts of the frequent
+//! sequences are estimated with a count-min sketch of fixed size instead.
+
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+use super::super::errors::*;
+use settings::Settings;
+
+/// Estimated bytes per base call held while sampling a tile: the call as `char`, the raw byte
+/// from the file, and the byte in the read sequence.
+const BYTES_PER_CALL: u64 = 6;
+/// Estimated bytes per distinct sequence in the exact counts: the `String` with its buffer, the
+/// count, and the overhead of the hash map.
+const BYTES_PER_ENTRY: u64 = 96;
+/// Number of clusters assumed per tile for estimating the memory when all reads are sampled,
+/// about the largest tiles of NovaSeq S4 flow cells.
+const ASSUMED_TILE_CLUSTERS: u64 = 5_000_000;
+/// Number of lanes assumed when the lanes are not known in advance, e.g., for FASTQ files.
+pub const MAX_LANES: usize = 8;
+/// Number of rows of the count-min sketch.
+const SKETCH_DEPTH: usize = 4;
+
+/// Parse the memory size `size`: a number of bytes with an optional suffix `K`, `M`, `G`, or
+/// `T` for binary units (optionally followed by `B` or `iB`), e.g., `6G` or `512MiB`.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let upper = size.to_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, shift) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 10),
+        Some('M') => (&number[..number.len() - 1], 20),
+        Some('G') => (&number[..number.len() - 1], 30),
+        Some('T') => (&number[..number.len() - 1], 40),
+        _ => (number, 0),
+    };
+    let value = number
+        .trim()
+        .parse::<f64>()
+        .chain_err(|| format!("Invalid memory size {:?}", size))?;
+    if !value.is_finite() || value < 0.0 {
+        bail!("Invalid memory size {:?}", size);
+    }
+    Ok((value * (1u64 << shift) as f64) as u64)
+}
+
+/// Return the peak resident set size of the process in bytes, if known.
+///
+/// This is the `VmHWM` entry of `/proc/self/status` and thus only available on Linux.
+pub fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// The memory budget for sampling, from `settings.ingest.max_memory`.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBudget {
+    /// The maximal number of bytes, unlimited if 0.
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    /// Construct from `settings.ingest.max_memory`, unlimited if empty or 0.
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let bytes = if settings.ingest.max_memory.trim().is_empty() {
+            0
+        } else {
+            parse_size(&settings.ingest.max_memory).chain_err(|| "Invalid ingest.max_memory")?
+        };
+        Ok(Self { bytes })
+    }
+
+    /// Return the maximal number of bytes, `None` if unlimited.
+    pub fn limit(&self) -> Option<u64> {
+        Some(self.bytes).filter(|bytes| *bytes > 0)
+    }
+
+    /// Return the number of the `num_lanes` lanes to sample at the same time, at least one, such
+    /// that a tile of `num_cycles` cycles with `max_reads` reads (0 for all) of each fits into
+    /// the budget together with its counts.
+    pub fn parallel_lanes(&self, num_lanes: usize, num_cycles: usize, max_reads: usize) -> usize {
+        let limit = match self.limit() {
+            Some(limit) => limit,
+            None => return cmp::max(1, num_lanes),
+        };
+        let reads = if max_reads > 0 {
+            max_reads as u64
+        } else {
+            ASSUMED_TILE_CLUSTERS
+        };
+        let per_lane = cmp::max(1, 2 * reads * num_cycles as u64 * BYTES_PER_CALL);
+        (limit / per_lane).clamp(1, cmp::max(1, num_lanes as u64)) as usize
+    }
+
+    /// Return the maximal number of reads per tile of `num_cycles` cycles for each of `parallel`
+    /// lanes sampled at the same time, 0 if unlimited.
+    pub fn max_tile_reads(&self, num_cycles: usize, parallel: usize) -> usize {
+        match self.limit() {
+            Some(limit) => {
+                let share = limit / 2 / cmp::max(1, parallel) as u64;
+                cmp::max(1, share / (cmp::max(1, num_cycles) as u64 * BYTES_PER_CALL)) as usize
+            }
+            None => 0,
+        }
+    }
+
+    /// Return the bytes for counting the sequences of each of `parallel` lanes sampled at the
+    /// same time, `None` if unlimited.
+    pub fn counter_bytes(&self, parallel: usize) -> Option<u64> {
+        self.limit()
+            .map(|limit| limit / 2 / cmp::max(1, parallel) as u64)
+    }
+}
+
+/// Count-min sketch estimating the counts of sequences in fixed memory.
+///
+/// The estimates are never below the true counts and exceed them by at most a small fraction
+/// of the total count with high probability, depending on the width.
+#[derive(Debug)]
+struct CountMinSketch {
+    /// The number of counters per row.
+    width: usize,
+    /// The counters, `SKETCH_DEPTH` rows of `width` each.
+    counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    /// Construct with the largest width such that the counters fit into `bytes`.
+    fn with_bytes(bytes: u64) -> Self {
+        let width = cmp::max(1024, bytes / (SKETCH_DEPTH * mem::size_of::<u32>()) as u64) as usize;
+        Self {
+            width,
+            counters: vec![0; SKETCH_DEPTH * width],
+        }
+    }
+
+    /// Return the index of the counter of `seq` in `row`.
+    fn index(&self, row: usize, seq: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        seq.hash(&mut hasher);
+        row * self.width + (hasher.finish() % self.width as u64) as usize
+    }
+
+    /// Add `count` occurrences of `seq` and return its new estimated count.
+    fn add(&mut self, seq: &str, count: usize) -> usize {
+        let mut estimate = u32::MAX;
+        for row in 0..SKETCH_DEPTH {
+            let index = self.index(row, seq);
+            let counter = &mut self.counters[index];
+            *counter = counter.saturating_add(count as u32);
+            estimate = cmp::min(estimate, *counter);
+        }
+        estimate as usize
+    }
+}
+
+/// Counts of read sequences and their no-calls, exact as long as the distinct sequences fit
+/// into the given number of bytes and estimated with a `CountMinSketch` afterwards.
+#[derive(Debug)]
+pub struct SeqCounter {
+    /// The number of sequences added.
+    pub num_seqs: usize,
+    /// The number of bases in the sequences added.
+    pub num_bases: usize,
+    /// The number of no-calls in the sequences added.
+    pub num_n: usize,
+    /// The exact counts or, with the sketch, the estimated counts of the frequent sequences.
+    counts: HashMap<String, usize>,
+    /// The sketch once the distinct sequences exceed `max_entries`.
+    sketch: Option<CountMinSketch>,
+    /// The maximal number of entries in `counts`, unlimited if `None`.
+    max_entries: Option<usize>,
+    /// The bytes available for counting, unlimited if `None`.
+    max_bytes: Option<u64>,
+    /// Minimum fraction of the sequences for keeping a sequence with the sketch.
+    min_fraction: f64,
+}
+
+impl SeqCounter {
+    /// Construct for counting in `max_bytes`, unlimited if `None`, keeping sequences seen in
+    /// at least `min_fraction` of the sequences once the sketch is used.
+    pub fn new(max_bytes: Option<u64>, min_fraction: f64) -> Self {
+        Self {
+            num_seqs: 0,
+            num_bases: 0,
+            num_n: 0,
+            counts: HashMap::new(),
+            sketch: None,
+            max_entries: max_bytes.map(|bytes| cmp::max(1, bytes / BYTES_PER_ENTRY) as usize),
+            max_bytes,
+            min_fraction,
+        }
+    }
+
+    /// Return the width of the sketch if the counts are estimated.
+    pub fn sketch_width(&self) -> Option<usize> {
+        self.sketch.as_ref().map(|sketch| sketch.width)
+    }
+
+    /// Add one occurrence of `seq`.
+    pub fn add(&mut self, seq: &str) {
+        self.num_seqs += 1;
+        self.num_bases += seq.len();
+        self.num_n += seq.chars().filter(|c| *c == 'N').count();
+
+        if let Some(sketch) = self.sketch.as_mut() {
+            let estimate = sketch.add(seq, 1);
+            if estimate as f64 > self.num_seqs as f64 * self.min_fraction {
+                self.counts.insert(seq.to_string(), estimate);
+                self.prune();
+            }
+            return;
+        }
+        match self.counts.get_mut(seq) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(seq.to_string(), 1);
+                if self.max_entries.is_some_and(|max| self.counts.len() > max) {
+                    self.start_sketch();
+                }
+            }
+        }
+    }
+
+    /// Switch from exact counts to the sketch, keeping half the bytes for the sketch and half
+    /// for the frequent sequences.
+    fn start_sketch(&mut self) {
+        let max_bytes = self.max_bytes.unwrap_or(0);
+        let mut sketch = CountMinSketch::with_bytes(max_bytes / 2);
+        for (seq, count) in self.counts.iter_mut() {
+            *count = sketch.add(seq, *count);
+        }
+        self.sketch = Some(sketch);
+        self.max_entries = self
+            .max_entries
+            .map(|max_entries| cmp::max(1, max_entries / 2));
+        self.prune();
+    }
+
+    /// Drop the infrequent sequences if there are more than `max_entries`, and the least
+    /// frequent ones if there are still too many.
+    fn prune(&mut self) {
+        let max_entries = match self.max_entries {
+            Some(max_entries) if self.counts.len() > max_entries => max_entries,
+            _ => return,
+        };
+        let threshold = self.num_seqs as f64 * self.min_fraction;
+        self.counts.retain(|_, count| *count as f64 > threshold);
+        if self.counts.len() > max_entries {
+            let mut counts = self.counts.drain().collect::<Vec<(String, usize)>>();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            counts.truncate(max_entries / 2);
+            self.counts = counts.into_iter().collect();
+        }
+    }
+
+    /// Return the counts of the sequences, exact or estimated.
+    pub fn into_counts(self) -> HashMap<String, usize> {
+        self.counts
+    }
+}
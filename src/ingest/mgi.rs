@@ -0,0 +1,292 @@
+//! Meta data of MGI DNBSEQ (e.g., DNBSEQ-G400 and DNBSEQ-T7) output directories.
+//!
+//! The output directory of a flow cell has one directory per lane (`L01`, `L02`, ...) with a
+//! `BioInfo.csv` file of `key,value` lines and a `*.fq.fqStat.txt` file for each read.  These
+//! are mapped onto the `RunInfo` and `RunParameters` of Illumina run folders such that the
+//! flow cells are registered and updated in the same way.
+
+use chrono::NaiveDate;
+use glob::glob;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use super::super::errors::*;
+use ingest::bcl_meta::{meta_file_exists, ReadDescription, RunInfo, RunParameters};
+use ingest::warnings::{Warning, WarningKind};
+
+/// Name of the file with the run information in the lane directories.
+pub const BIO_INFO_FILE: &str = "BioInfo.csv";
+/// Pattern of the lane directories, relative to the output directory.
+const LANE_DIRS: &str = "L0?";
+/// Keys of the software version in `BioInfo.csv` files.
+const VERSION_KEYS: &[&str] = &[
+    "software version",
+    "basecall version",
+    "lane software version",
+];
+/// Date formats seen in `BioInfo.csv` files.
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d"];
+
+/// Return the paths to the lane directories of the output directory at `path`, sorted by lane.
+fn lane_dirs(path: &Path) -> Vec<PathBuf> {
+    let mut result = glob(path.join(LANE_DIRS).to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<PathBuf>>();
+    result.sort();
+    result
+}
+
+/// Return the path to the `BioInfo.csv` of the first lane of the output directory at `path`.
+pub fn bio_info_file(path: &Path) -> Option<PathBuf> {
+    lane_dirs(path)
+        .into_iter()
+        .map(|lane_dir| lane_dir.join(BIO_INFO_FILE))
+        .find(|path| path.exists())
+}
+
+/// Return whether the directory at `path` is the output directory of an MGI flow cell.
+pub fn is_mgi_folder(path: &Path) -> bool {
+    !meta_file_exists(path, "RunInfo.xml") && bio_info_file(path).is_some()
+}
+
+/// The `key,value` lines of a `BioInfo.csv` file, keys are in lower case.
+#[derive(Debug, Clone, Default)]
+pub struct BioInfo {
+    /// The values by lower case key.
+    pub values: HashMap<String, String>,
+}
+
+impl BioInfo {
+    /// Read the `BioInfo.csv` file at `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let file = File::open(path).chain_err(|| format!("Problem reading {:?}", path))?;
+        let mut result = Self::default();
+        for line in BufReader::new(file).lines() {
+            let line = line.chain_err(|| format!("Problem reading {:?}", path))?;
+            let mut fields = line.trim_start_matches('\u{feff}').splitn(2, ',');
+            if let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+                let value = value.trim().trim_end_matches(',').trim();
+                if !key.trim().is_empty() && !value.is_empty() {
+                    result
+                        .values
+                        .insert(key.trim().to_lowercase(), value.to_string());
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Return the value of the first of `keys` that is present.
+    pub fn get(&self, keys: &[&str]) -> Option<&str> {
+        keys.iter()
+            .filter_map(|key| self.values.get(*key))
+            .map(|value| value.as_str())
+            .next()
+    }
+
+    /// Return the value of the first of `keys` that is present, as a number of cycles.
+    fn cycles(&self, keys: &[&str]) -> Option<i32> {
+        self.get(keys).and_then(|value| value.parse::<i32>().ok())
+    }
+}
+
+/// Return the read length from the `#row_readLen` line of the `*.fq.fqStat.txt` file of read
+/// `read_no` in the lane directory `lane_dir`, if any.
+fn fq_stat_read_length(lane_dir: &Path, read_no: i32) -> Option<i32> {
+    let pattern = lane_dir.join(format!("*_{}.fq.fqStat.txt", read_no));
+    let path = glob(pattern.to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .next()?;
+    BufReader::new(File::open(path).ok()?)
+        .lines()
+        .map_while(|line| line.ok())
+        .take_while(|line| line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("#row_readLen") => fields.next()?.parse::<i32>().ok(),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Return the reads described in `bio_info`, falling back to the `*.fq.fqStat.txt` files in
+/// `lane_dir` for the template reads.
+///
+/// The reads are given as template reads 1 and 2 followed by the barcode and the dual barcode.
+fn read_descriptions(
+    bio_info: &BioInfo,
+    lane_dir: &Path,
+    warnings: &mut Vec<Warning>,
+) -> Result<Vec<ReadDescription>> {
+    // The sequence type gives the template reads, e.g., `PE150` or `SE50`.
+    let sequence_type = bio_info.get(&["sequence type", "seq type"]).unwrap_or("");
+    let (type_reads, type_length) = match sequence_type.get(..2) {
+        Some("PE") => (2, sequence_type[2..].parse::<i32>().ok()),
+        Some("SE") => (1, sequence_type[2..].parse::<i32>().ok()),
+        _ => (0, None),
+    };
+
+    let mut cycles = Vec::new();
+    for read_no in 1..=2 {
+        let keys = [
+            format!("read{} cycles", read_no),
+            format!("read{} length", read_no),
+            format!("read {} cycles", read_no),
+        ];
+        let keys = keys.iter().map(|key| key.as_str()).collect::<Vec<&str>>();
+        let num_cycles = match bio_info.cycles(&keys) {
+            Some(num_cycles) => Some(num_cycles),
+            None if read_no <= type_reads => type_length,
+            None => None,
+        };
+        let num_cycles = match num_cycles {
+            Some(num_cycles) => num_cycles,
+            None => match fq_stat_read_length(lane_dir, read_no) {
+                Some(num_cycles) => {
+                    warnings.push(Warning::new(
+                        WarningKind::LayoutQuirk,
+                        format!(
+                            "Length of read {} taken from the fqStat file in {:?}",
+                            read_no, lane_dir
+                        ),
+                    ));
+                    num_cycles
+                }
+                None => 0,
+            },
+        };
+        cycles.push((num_cycles, false));
+    }
+    cycles.push((
+        bio_info
+            .cycles(&["barcode", "barcode cycles", "barcode length"])
+            .unwrap_or(0),
+        true,
+    ));
+    cycles.push((
+        bio_info
+            .cycles(&["dual barcode", "dual barcode cycles", "dual barcode length"])
+            .unwrap_or(0),
+        true,
+    ));
+
+    let mut reads = Vec::new();
+    for (num_cycles, is_index) in cycles {
+        if num_cycles > 0 {
+            reads.push(ReadDescription {
+                number: reads.len() as i32 + 1,
+                num_cycles,
+                is_index,
+                is_reverse_complement: false,
+            });
+        }
+    }
+    if reads.is_empty() {
+        bail!("Could not get the reads from {:?}", lane_dir);
+    }
+    Ok(reads)
+}
+
+/// Read and convert the `BioInfo.csv` of the MGI output directory at `path`.
+///
+/// As MGI sequencers have no run counter, the run number is always 0 and runs are told apart
+/// by their flow cell ID.  All planned reads are taken as current reads.  Quirks that had to be
+/// worked around are added to `warnings`.
+pub fn parse_run_folder(
+    path: &Path,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
+    let bio_info_path =
+        bio_info_file(path).chain_err(|| format!("No {} found in {:?}", BIO_INFO_FILE, path))?;
+    let bio_info = BioInfo::from_path(&bio_info_path)?;
+    let lane_dir = bio_info_path.parent().unwrap();
+
+    let folder_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let flowcell = match bio_info.get(&["flowcell id", "flow cell id", "slide"]) {
+        Some(flowcell) => flowcell.to_string(),
+        None => {
+            warnings.push(Warning::new(
+                WarningKind::LayoutQuirk,
+                format!("No flow cell ID in {}, using folder name", BIO_INFO_FILE),
+            ));
+            folder_name.clone()
+        }
+    };
+    let instrument = bio_info
+        .get(&["machine id", "machine", "sequencer id"])
+        .chain_err(|| format!("No machine ID in {}", BIO_INFO_FILE))?
+        .to_string();
+
+    let date_value = bio_info
+        .get(&["sequence date", "sequence start date", "date"])
+        .unwrap_or("");
+    let date_token = date_value.split_whitespace().next().unwrap_or("");
+    let date = DATE_FORMATS
+        .iter()
+        .filter_map(|fmt| NaiveDate::parse_from_str(date_token, fmt).ok())
+        .map(|date| date.format("%F").to_string())
+        .next()
+        .chain_err(|| format!("Could not parse date from string {}", date_value))?;
+
+    let software_version = bio_info
+        .get(VERSION_KEYS)
+        .unwrap_or("")
+        .trim_start_matches(['v', 'V']);
+    let rta_version = match software_version.split('.').next() {
+        Some(major) if major.parse::<i32>().is_ok() => software_version.to_string(),
+        _ => {
+            warnings.push(Warning::new(
+                WarningKind::LayoutQuirk,
+                format!("Could not parse software version {:?}", software_version),
+            ));
+            "0".to_string()
+        }
+    };
+
+    let run_info = RunInfo {
+        run_id: folder_name,
+        run_number: 0,
+        flowcell,
+        instrument,
+        date,
+        lane_count: lane_dirs(path).len() as i32,
+        reads: read_descriptions(&bio_info, lane_dir, warnings)?,
+        version: 0,
+        geometry: None,
+    };
+    let run_params = RunParameters {
+        planned_reads: read_descriptions(&bio_info, lane_dir, &mut Vec::new())?,
+        rta_version,
+        run_number: 0,
+        flowcell_slot: String::new(),
+        experiment_name: bio_info
+            .get(&["sequence name", "experiment name"])
+            .unwrap_or("")
+            .to_string(),
+        instrument_model: None,
+    };
+    Ok((run_info, run_params))
+}
+
+/// Return whether the MGI output directory at `path` is complete, i.e., each lane has the
+/// `*.fq.fqStat.txt` files that are written at the end of the run.
+pub fn is_complete(path: &Path) -> bool {
+    let lane_dirs = lane_dirs(path);
+    !lane_dirs.is_empty()
+        && lane_dirs.iter().all(|lane_dir| {
+            glob(lane_dir.join("*.fq.fqStat.txt").to_str().unwrap())
+                .map(|mut paths| paths.any(|path| path.is_ok()))
+                .unwrap_or(false)
+        })
+}
@@ -1,22 +1,94 @@
 //! Implementation of flow cell folder analysis and import.
 
+use rayon::prelude::*;
 use restson::RestClient;
-use std::collections::HashMap;
+use serde_json;
+use std::cmp;
+use std::collections::BTreeSet;
 use std::env;
 use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::result;
+use std::thread;
+use std::time::Duration;
 use sxd_document::parser;
+use uuid::Uuid;
 
 use super::errors::*;
-use settings::Settings;
+use anonymize::Anonymizer;
+use settings::{Settings, Web};
 
 mod api;
-mod bcl_meta;
+pub use self::api::ProjectSettings;
+pub mod archive;
+pub mod archive_check;
+pub mod aviti;
+pub mod backfill;
+mod barcodes;
+use self::barcodes::*;
+mod base_composition;
+use self::base_composition::*;
+pub mod bcl_meta;
 use self::bcl_meta::*;
 mod bcl_data;
 use self::bcl_data::*;
+pub mod check;
+mod checksum;
+pub mod compare;
+pub mod delete;
+pub mod demux;
+pub mod export;
+mod hints;
+use self::hints::*;
+mod lock;
+use self::lock::*;
+mod fastq;
+mod folder_cache;
+mod folder_size;
+use self::folder_size::*;
+pub mod healthcheck;
+mod histogram;
+pub mod history;
+pub mod import;
+mod interop;
+pub mod list;
+pub mod manifest;
+mod memory;
+pub mod mgi;
+pub mod nanopore;
+mod provenance;
+use self::provenance::*;
+pub mod reconcile;
+mod report;
+pub mod sample_sheet;
+pub mod samplesheet_cmd;
+use self::report::*;
+mod service;
+pub mod set_status;
+pub mod show;
+mod skip_list;
+pub mod spool;
+use self::skip_list::*;
+mod staging;
+use self::staging::*;
+mod state;
+use self::state::*;
+pub mod status;
+mod streaming;
+mod throttle;
+pub mod verify;
+mod warnings;
+use self::warnings::*;
+pub mod watch;
+
+/// Sample sizes below this value trigger a `WarningKind::LowSampleSize` warning.
+const MIN_SAMPLE_SIZE: usize = 10_000;
+/// Fractions of N calls in index reads above this value trigger a `WarningKind::HighNContent`
+/// warning.
+const MAX_N_FRACTION: f64 = 0.1;
+/// Fractions of a single base in the first template cycles above this value trigger a
+/// `WarningKind::SkewedBaseComposition` warning.
+const MAX_BASE_FRACTION: f64 = 0.9;
 
 /// Build a flow cell from the meta information in `run_info` and `run_params`.
 ///
@@ -28,43 +100,62 @@ fn build_flow_cell(
     status_sequencing: Option<String>,
     settings: &Settings,
 ) -> api::FlowCell {
+    let mut hints = RunHints::from_folder(path, run_params);
+    if settings
+        .ingest
+        .description_template
+        .contains("{folder_size}")
+    {
+        hints.folder_size = Some(DirSize::compute(path).to_string());
+    }
+    // MinKNOW basecalls on the instrument, so the conversion state is known from the run.
+    let (status_conversion, delivery_type) = if nanopore::is_nanopore_folder(path) {
+        nanopore::conversion_and_delivery(path)
+    } else {
+        ("initial".to_string(), "seq".to_string())
+    };
     api::FlowCell {
         sodar_uuid: None,
         run_date: run_info.date.clone(),
         run_number: run_info.run_number,
         slot: run_params.flowcell_slot.clone(),
         vendor_id: run_info.flowcell.clone(),
-        label: Some(run_params.experiment_name.clone()),
+        label: hints.render(&settings.ingest.label_template),
         num_lanes: run_info.lane_count,
-        rta_version: run_params
-            .rta_version
-            .split('.')
-            .next()
-            .expect("Could not get RTA Version")
-            .parse::<i32>()
+        rta_version: rta_major_version(&run_params.rta_version)
             .expect("Could not parse RTA version as integer"),
         planned_reads: Some(string_description(&run_params.planned_reads)),
         current_reads: Some(string_description(&run_info.reads)),
         manual_label: None,
-        description: None,
+        description: with_managed_block(
+            None,
+            hints
+                .render(&settings.ingest.description_template)
+                .as_deref(),
+        ),
         sequencing_machine: run_info.instrument.clone(),
-        operator: Some(settings.ingest.operator.clone()),
+        operator: Some(
+            hints
+                .render(&settings.ingest.operator_template)
+                .unwrap_or_else(|| settings.ingest.operator.clone()),
+        ),
         status_sequencing: get_status_sequencing(
             run_info,
             run_params,
             path,
             &status_sequencing.unwrap_or("initial".to_string()),
         ),
-        status_conversion: "initial".to_string(),
+        status_conversion,
         status_delivery: "initial".to_string(),
-        delivery_type: "seq".to_string(),
+        delivery_type,
+        libraries: Vec::new(),
     }
 }
 
 /// Register a new flow cell with the REST API given the information in `run_info` and `run_params`.
 fn register_flowcell(
     logger: &slog::Logger,
-    client: &mut RestClient,
+    client: &mut api::Client,
     run_info: &RunInfo,
     run_params: &RunParameters,
     path: &Path,
@@ -73,31 +164,49 @@ fn register_flowcell(
     info!(logger, "Registering flow cell...");
 
     let flowcell = build_flow_cell(run_info, run_params, path, None, settings);
+    let api_flowcell = post_new_flowcell(logger, client, &flowcell, settings)?;
+    info!(logger, "Done registering flow cell.");
+
+    Ok(api_flowcell)
+}
+
+/// Register the `flowcell` built from a run folder with the REST API, posting messages about
+/// the reasons for a failed run and about missing index reads.
+fn post_new_flowcell(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    settings: &Settings,
+) -> Result<api::FlowCell> {
     debug!(logger, "Registering flowcell with API as {:?}", &flowcell);
 
     let args = api::ProjectArgs {
-        project_uuid: settings.ingest.project_uuid.clone(),
+        project_uuid: settings.ingest.project_uuid,
     };
     let api_flowcell: api::FlowCell = client
-        .post_capture(&args, &flowcell)
+        .post_flowcell(&args, flowcell)
         .chain_err(|| "Problem registering data")?;
     debug!(logger, "Registered flowcell: {:?}", &flowcell);
 
     if flowcell.status_sequencing == "failed" {
-        if let Some(flowcell_uuid) = api_flowcell.sodar_uuid.clone() {
+        if let Some(flowcell_uuid) = api_flowcell.sodar_uuid {
             debug!(
                 logger,
                 "Posting message about reasons for flow cell failure."
             );
             let args = api::ProjectFlowcellArgs {
-                project_uuid: settings.ingest.project_uuid.clone(),
+                project_uuid: settings.ingest.project_uuid,
                 flowcell_uuid: flowcell_uuid,
             };
             let message = api::FlowCellMessage {
                 subject: Some("Registered flow cell as \"failed\"".to_string()),
-                body: "The main reason for this is presence of the RTAComplete.txt file but the \
-                       indication of performed and planned cycles differ."
-                    .to_string(),
+                body: if flowcell.current_reads != flowcell.planned_reads {
+                    "The main reason for this is presence of the RTAComplete.txt file but the \
+                     indication of performed and planned cycles differ."
+                } else {
+                    "RTA 4 exited (RTAExited.txt) before completing the run (no RTAComplete.txt)."
+                }
+                .to_string(),
                 state: "sent".to_string(),
             };
             client
@@ -108,15 +217,98 @@ fn register_flowcell(
         }
     }
 
-    info!(logger, "Done registering flow cell.");
+    // Runs without any reads (e.g., MinKNOW runs) have no cycles at all.
+    let current_reads = flowcell.current_reads.clone().unwrap_or_default();
+    if !current_reads.is_empty() && !current_reads.contains('B') {
+        post_message(
+            logger,
+            client,
+            &api_flowcell,
+            "Flow cell without index reads",
+            "The run has no index reads (e.g., a PhiX-only or single-library run), so no index \
+             histograms are computed for it."
+                .to_string(),
+            settings,
+        )?;
+    }
 
     Ok(api_flowcell)
 }
 
+/// Check the number of lanes of the existing `flowcell` against the `lane_count` from
+/// `RunInfo.xml`.
+///
+/// A disagreement (e.g., after a manual edit of the flow cell) fails unless
+/// `settings.ingest.allow_lane_count_change` is set, as changing the number of lanes breaks the
+/// sample sheets of the flow cell.  If allowed, the change is recorded in `warnings`.
+fn check_lane_count(
+    logger: &slog::Logger,
+    flowcell: &api::FlowCell,
+    lane_count: i32,
+    settings: &Settings,
+    warnings: &mut Vec<Warning>,
+) -> Result<()> {
+    if flowcell.num_lanes == lane_count {
+        return Ok(());
+    }
+    let message = format!(
+        "Flow cell has {} lane(s) on the server but {} in RunInfo.xml",
+        flowcell.num_lanes, lane_count
+    );
+    if !settings.ingest.allow_lane_count_change {
+        error!(logger, "{}", &message);
+        bail!("{}; use --allow-lane-count-change to update it", &message);
+    }
+    warn!(logger, "{}, updating the number of lanes", &message);
+    warnings.push(Warning::new(WarningKind::LaneCountChange, message));
+    Ok(())
+}
+
+/// Check the number of cycles of each read in `run_info` against the thresholds in
+/// `settings.ingest`, returning a warning for each read outside of them.
+///
+/// This catches reads that were mis-specified when setting up the run or that were truncated,
+/// before demultiplexing fails on them.  Thresholds that are not positive are not checked.
+fn check_read_cycles(run_info: &RunInfo, settings: &Settings) -> Vec<Warning> {
+    let args = &settings.ingest;
+    let mut warnings = Vec::new();
+    let mut index_no = 0;
+    let mut template_no = 0;
+    for desc in run_info.reads.iter().filter(|desc| desc.num_cycles > 0) {
+        let (kind, read_no, min_cycles, max_cycles) = if desc.is_index {
+            index_no += 1;
+            (
+                "Index",
+                index_no,
+                args.index_read_min_cycles,
+                args.index_read_max_cycles,
+            )
+        } else {
+            template_no += 1;
+            ("Template", template_no, args.template_read_min_cycles, 0)
+        };
+        let expected = if min_cycles > 0 && desc.num_cycles < min_cycles {
+            format!("at least {}", min_cycles)
+        } else if max_cycles > 0 && desc.num_cycles > max_cycles {
+            format!("at most {}", max_cycles)
+        } else {
+            continue;
+        };
+        warnings.push(Warning::new(
+            WarningKind::SuspiciousReadLength,
+            format!(
+                "{} read {} has {} cycles, expected {}",
+                kind, read_no, desc.num_cycles, expected
+            ),
+        ));
+    }
+    warnings
+}
+
 /// Register an existing flow cell with the REST API given the information in `run_info` and `run_params`.
 fn update_flowcell(
     logger: &slog::Logger,
-    client: &mut RestClient,
+    client: &mut api::Client,
     flowcell: &api::FlowCell,
     run_info: &RunInfo,
     run_params: &RunParameters,
@@ -133,32 +325,71 @@ fn update_flowcell(
         settings,
     );
     debug!(logger, "Rebuilt flowcell is {:?}", &rebuilt_flowcell);
+    put_updated_flowcell(
+        logger,
+        client,
+        flowcell,
+        &rebuilt_flowcell,
+        nanopore::is_nanopore_folder(path),
+        settings,
+    )
+}
 
+/// Update the existing `flowcell` with the REST API to the `rebuilt_flowcell` from its run
+/// folder, keeping the fields that users may edit.
+fn put_updated_flowcell(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    rebuilt_flowcell: &api::FlowCell,
+    is_nanopore: bool,
+    settings: &Settings,
+) -> Result<api::FlowCell> {
+    // Only the managed block of the description is updated, the rest may be edited by users.
+    let description = if settings.ingest.description_template.is_empty() {
+        flowcell.description.clone()
+    } else {
+        with_managed_block(
+            flowcell.description.as_deref(),
+            rebuilt_flowcell
+                .description
+                .as_ref()
+                .and_then(|s| managed_block_content(s)),
+        )
+    };
     let updated_flowcell = api::FlowCell {
+        description,
         planned_reads: rebuilt_flowcell.planned_reads.clone(),
         current_reads: rebuilt_flowcell.current_reads.clone(),
+        num_lanes: rebuilt_flowcell.num_lanes,
         status_sequencing: rebuilt_flowcell.status_sequencing.clone(),
+        status_conversion: updated_status_conversion(flowcell, rebuilt_flowcell, is_nanopore),
         ..flowcell.clone()
     };
     info!(logger, "Updating flow cell via API");
     debug!(
         logger,
-        "  {:?} => {:?}", &updated_flowcell, &rebuilt_flowcell
+        "  {:?} => {:?}", &updated_flowcell, rebuilt_flowcell
     );
 
     let args = api::ProjectFlowcellArgs {
-        project_uuid: settings.ingest.project_uuid.clone(),
-        flowcell_uuid: updated_flowcell.sodar_uuid.clone().unwrap(),
+        project_uuid: settings.ingest.project_uuid,
+        flowcell_uuid: updated_flowcell.sodar_uuid.unwrap(),
+    };
+    let api_flowcell = match client
+        .put_flowcell(&args, &updated_flowcell)
+        .chain_err(|| "Problem updating")?
+    {
+        api::Delivery::Received(api_flowcell) => api_flowcell,
+        // The update is sent by `replay`, go on with the flow cell as it will be stored.
+        api::Delivery::Spooled => updated_flowcell.clone(),
     };
-    let api_flowcell = client
-        .put_capture(&args, &updated_flowcell)
-        .chain_err(|| "Problem updating")?;
 
     if flowcell.status_sequencing == "failed" && updated_flowcell.status_sequencing == "complete" {
-        if let Some(flowcell_uuid) = updated_flowcell.sodar_uuid.clone() {
+        if let Some(flowcell_uuid) = updated_flowcell.sodar_uuid {
             debug!(logger, "Post message about un-marking as failed.");
             let args = api::ProjectFlowcellArgs {
-                project_uuid: settings.ingest.project_uuid.clone(),
+                project_uuid: settings.ingest.project_uuid,
                 flowcell_uuid: flowcell_uuid,
             };
             let message = api::FlowCellMessage {
@@ -179,203 +410,879 @@ fn update_flowcell(
     Ok(api_flowcell)
 }
 
+/// Return the conversion status for updating `flowcell` to `rebuilt_flowcell`.
+///
+/// For MinKNOW runs (`is_nanopore`), the status follows the basecalling on the instrument unless
+/// it has been set to a final state; otherwise, it is left to the users.
+fn updated_status_conversion(
+    flowcell: &api::FlowCell,
+    rebuilt_flowcell: &api::FlowCell,
+    is_nanopore: bool,
+) -> String {
+    let is_open =
+        flowcell.status_conversion == "initial" || flowcell.status_conversion == "in_progress";
+    if is_open && is_nanopore {
+        rebuilt_flowcell.status_conversion.clone()
+    } else {
+        flowcell.status_conversion.clone()
+    }
+}
+
+/// Return the number of index histograms expected for `flowcell`, one per lane and index read.
+///
+/// With merged lanes, there is an additional histogram (lane 0) for each index read.
+fn expected_histograms(flowcell: &api::FlowCell, settings: &Settings) -> usize {
+    let num_lanes = flowcell.num_lanes as usize + if settings.ingest.merge_lanes { 1 } else { 0 };
+    num_lanes
+        * flowcell
+            .planned_reads
+            .clone()
+            .unwrap()
+            .chars()
+            .filter(|x| *x == 'B')
+            .count()
+}
+
 /// Kick of analyzing the adatpers and then update through API if configured to do so in `settings`.
+///
+/// Returns the statistics for each sampled lane and index read.
 fn analyze_adapters(
     logger: &slog::Logger,
     flowcell: &api::FlowCell,
-    client: &mut RestClient,
+    client: &mut api::Client,
     run_info: &RunInfo,
     path: &Path,
     folder_layout: FolderLayout,
     settings: &Settings,
-) -> Result<()> {
+) -> Result<Vec<LaneIndexStats>> {
+    let mut lane_index_stats = Vec::new();
+    let missing_cycles = truncated_cycles(path, run_info).unwrap_or_default();
+
+    // Collect the index reads whose cycles are on disk.
+    let mut index_reads = Vec::new();
     let mut index_no = 0i32;
     let mut cycle = 1i32; // always throw away first cycle
-    for ref desc in &run_info.reads {
+    for desc in &run_info.reads {
         if desc.is_index {
             index_no += 1;
 
-            // Get existing index histograms.
+            let num_cycles = sampled_index_cycles(desc, index_no, settings);
+            if (cycle..(cycle + num_cycles)).any(|c| missing_cycles.contains(&c)) {
+                warn!(
+                    logger,
+                    "Cycles of index read {} are missing, cannot analyze adapters.", index_no
+                );
+            } else {
+                index_reads.push((index_no, desc, cycle));
+            }
+        }
+        cycle += desc.num_cycles;
+    }
+    if index_reads.is_empty() {
+        info!(logger, "Done analyzing adapters.");
+        return Ok(lane_index_stats);
+    }
+
+    // Get existing index histograms.
+    info!(
+        logger,
+        "Getting existing index histograms for flow cell from API"
+    );
+    let hist_arr: api::LaneIndexHistogramArray = client
+        .get(&api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            flowcell_uuid: flowcell.sodar_uuid.unwrap(),
+        })
+        .chain_err(|| "Could not query index histograms from server")?;
+    let num_hists = match &hist_arr {
+        api::LaneIndexHistogramArray::Array(hists) => {
             info!(
                 logger,
-                "Getting existing index histograms for flow cell from API"
+                "=> flow cell has {} histograms already",
+                hists.len()
             );
-            let hist_arr: api::LaneIndexHistogramArray = client
-                .get(&api::ProjectFlowcellArgs {
-                    project_uuid: settings.ingest.project_uuid.clone(),
-                    flowcell_uuid: flowcell.sodar_uuid.clone().unwrap(),
-                })
-                .chain_err(|| "Could not query index histograms from server")?;
-            let num_hists = match &hist_arr {
-                api::LaneIndexHistogramArray::Array(hists) => {
-                    info!(
-                        logger,
-                        "=> flow cell has {} histograms already",
-                        hists.len()
-                    );
-                    hists.len()
-                }
-            };
+            hists.len()
+        }
+    };
 
-            // Number of adapters that are expected.  Will only analyzes
-            let expected_adapters = flowcell.num_lanes as usize
-                * flowcell
-                    .planned_reads
-                    .clone()
-                    .unwrap()
-                    .chars()
-                    .filter(|x| *x == 'B')
-                    .count();
-            debug!(logger, "expected adapters: {}", expected_adapters);
-
-            if num_hists == expected_adapters && !settings.ingest.force_analyze_adapters {
-                info!(
+    // Number of adapters that are expected.  Will only analyzes
+    let expected_adapters = expected_histograms(flowcell, settings);
+    debug!(logger, "expected adapters: {}", expected_adapters);
+
+    if num_hists == expected_adapters && !settings.ingest.force_analyze_adapters {
+        info!(
+            logger,
+            "There already is the expected number of adapters in the API ({}) \
+             and you did not force analyzing of adapters. NOT analysing adapters.",
+            expected_adapters
+        );
+        return Ok(lane_index_stats);
+    }
+    if num_hists == expected_adapters {
+        info!(
+            logger,
+            "You are enforcing the analysis of adapters regardless of existing ones in API..."
+        )
+    }
+
+    throttle::configure(settings)?;
+
+    // Copy the files needed for sampling to the staging directory, if configured.  Runs without
+    // base calls are sampled from their FASTQ files directly.
+    let base_calls = path.join("Data").join("Intensities").join("BaseCalls");
+    let staged = if settings.ingest.staging_dir.is_empty() || !base_calls.exists() {
+        None
+    } else {
+        let files = files_to_stage(logger, path, folder_layout, &index_reads, settings)?;
+        info!(
+            logger,
+            "Staging {} files to {:?}...",
+            files.len(),
+            &settings.ingest.staging_dir
+        );
+        let staged = StagedFolder::create(
+            logger,
+            Path::new(&settings.ingest.staging_dir),
+            path,
+            &files,
+        )
+        .chain_err(|| "Problem staging files for sampling")?;
+        info!(logger, "=> staged {} bytes", staged.bytes);
+        Some(staged)
+    };
+    let sample_path = staged.as_ref().map(|staged| staged.path()).unwrap_or(path);
+
+    // The filter and position files are read once and shared between the index reads.
+    let cluster_counts = lane_cluster_counts(logger, sample_path);
+    debug!(logger, "Clusters per lane: {:?}", &cluster_counts);
+    let quality_bins = quality_bins(logger, sample_path);
+
+    // Sample all index reads concurrently, each of them is sampled from all lanes in parallel.
+    // With a memory limit, the index reads are sampled one after the other such that each of
+    // them can use all of the budget.
+    info!(
+        logger,
+        "Analyzing adapters of {} index read(s)...",
+        index_reads.len()
+    );
+    let sample = |index_read: &(i32, &ReadDescription, i32)| {
+        sample_adapters(
+            logger,
+            sample_path,
+            &cluster_counts,
+            *index_read,
+            folder_layout,
+            settings,
+        )
+    };
+    let sampled = if settings.ingest.max_memory.trim().is_empty() {
+        index_reads
+            .par_iter()
+            .map(sample)
+            .collect::<Vec<Result<Vec<IndexCounts>>>>()
+    } else {
+        index_reads
+            .iter()
+            .map(sample)
+            .collect::<Vec<Result<Vec<IndexCounts>>>>()
+    };
+    drop(staged);
+
+    for ((index_no, _, _), index_counts) in index_reads.iter().zip(sampled) {
+        let index_no = *index_no;
+        let index_counts = index_counts?;
+        let mut stats_for_index = Vec::new();
+        for index_info in &index_counts {
+            let total_clusters = cluster_counts.get(&index_info.lane_no).cloned();
+            let coverage = total_clusters
+                .filter(|total| *total > 0)
+                .map(|total| index_info.sample_size as f64 / total as f64);
+            info!(
+                logger,
+                "Lane {}, index read {}: {:.2}% N calls in {} reads ({} of {:?} clusters)",
+                index_info.lane_no,
+                index_no,
+                100.0 * index_info.n_fraction,
+                index_info.sample_size,
+                coverage
+                    .map(|c| format!("{:.4}%", 100.0 * c))
+                    .unwrap_or_else(|| "?".to_string()),
+                total_clusters
+            );
+            stats_for_index.push(LaneIndexStats {
+                lane: index_info.lane_no,
+                index_read_no: index_info.index_no,
+                sample_size: index_info.sample_size,
+                total_clusters,
+                coverage,
+                n_fraction: index_info.n_fraction,
+                top_index: index_info.hist.0.first().map(|(seq, _)| seq.clone()),
+                top_index_fraction: index_info
+                    .hist
+                    .0
+                    .first()
+                    .map(|(_, count)| *count as f64 / cmp::max(1, index_info.sample_size) as f64),
+                io: index_info.io,
+                clusters_per_second: index_info
+                    .io
+                    .filter(|io| io.seconds > 0.0)
+                    .map(|io| index_info.sample_size as f64 / io.seconds),
+            });
+        }
+
+        // Push results to API
+        if settings.dry_run {
+            info!(logger, "Dry run mode active, not updating adapters.",);
+        } else if settings.ingest.post_adapters {
+            info!(
+                logger,
+                "Updating adapter information via API {:?}", &flowcell
+            );
+            // Merge before the per-lane histograms are moved into their payloads, which avoids
+            // copies of large histograms, e.g., with UMIs in the index reads.
+            let merged = if settings.ingest.merge_lanes && !index_counts.is_empty() {
+                Some(IndexCounts::merge_lanes(
+                    index_no,
+                    &index_counts,
+                    settings.ingest.min_index_fraction,
+                ))
+            } else {
+                None
+            };
+            for (index_info, stats) in index_counts.into_iter().zip(stats_for_index.iter()) {
+                let lane_no = index_info.lane_no;
+                let api_hist = api::LaneIndexHistogram {
+                    sodar_uuid: None,
+                    flowcell: flowcell.sodar_uuid.unwrap().to_string(),
+                    lane: lane_no,
+                    index_read_no: index_info.index_no,
+                    min_index_fraction: settings.ingest.min_index_fraction,
+                    sample_size: index_info.sample_size,
+                    n_fraction: Some(index_info.n_fraction),
+                    total_clusters: stats.total_clusters,
+                    coverage: stats.coverage,
+                    quality_bins: quality_bins.clone(),
+                    sampling: Some(index_info.sampling),
+                    histogram: index_info.hist,
+                };
+                debug!(
                     logger,
-                    "There already is the expected number of adapters in the API ({}) \
-                     and you did not force analyzing of adapters. NOT analysing adapters.",
-                    expected_adapters
+                    "Posting histogram of lane {}, index read {} with {} entries",
+                    lane_no,
+                    index_no,
+                    api_hist
+                        .filtered(settings.ingest.max_histogram_entries)
+                        .num_entries()
                 );
-            } else {
-                if num_hists == expected_adapters {
-                    info!(logger, "You are enforcing the analysis of adapters regardless of existing ones in API...")
-                }
-                info!(logger, "Analyzing adapters...");
-                let index_counts = sample_adapters(
+                client
+                    .post_histogram(
+                        &api::ProjectFlowcellArgs {
+                            project_uuid: settings.ingest.project_uuid,
+                            flowcell_uuid: flowcell.sodar_uuid.unwrap(),
+                        },
+                        &api_hist,
+                        settings.ingest.max_histogram_entries,
+                    )
+                    .chain_err(|| "Could not update adapter on server")?
+            }
+            if let Some(merged) = merged {
+                let total_clusters = stats_for_index
+                    .iter()
+                    .map(|stats| stats.total_clusters)
+                    .sum::<Option<u64>>();
+                let api_hist = api::LaneIndexHistogram {
+                    sodar_uuid: None,
+                    flowcell: flowcell.sodar_uuid.unwrap().to_string(),
+                    lane: merged.lane_no,
+                    index_read_no: merged.index_no,
+                    min_index_fraction: settings.ingest.min_index_fraction,
+                    sample_size: merged.sample_size,
+                    n_fraction: Some(merged.n_fraction),
+                    total_clusters,
+                    coverage: total_clusters
+                        .filter(|total| *total > 0)
+                        .map(|total| merged.sample_size as f64 / total as f64),
+                    quality_bins: quality_bins.clone(),
+                    sampling: Some(merged.sampling),
+                    histogram: merged.hist,
+                };
+                debug!(
                     logger,
-                    path,
-                    &desc,
-                    folder_layout,
-                    settings,
+                    "Posting histogram of merged lanes, index read {} with {} entries",
                     index_no,
-                    cycle,
-                )?;
-
-                // Push results to API
-                if settings.dry_run {
-                    info!(logger, "Dry run mode active, not updating adapters.",);
-                } else if settings.ingest.post_adapters {
-                    info!(
-                        logger,
-                        "Updating adapter information via API {:?}", &flowcell
-                    );
-                    for (i, index_info) in index_counts.iter().enumerate() {
-                        let lane_no = i + 1;
-                        let api_hist = api::LaneIndexHistogram {
-                            sodar_uuid: None,
-                            flowcell: flowcell.sodar_uuid.clone().unwrap(),
-                            lane: lane_no as i32,
-                            index_read_no: index_no,
-                            min_index_fraction: settings.ingest.min_index_fraction,
-                            sample_size: index_info.sample_size,
-                            histogram: index_info.hist.clone(),
-                        };
-                        debug!(logger, "Posting {:?}", &api_hist);
-                        client
-                            .post(
-                                &api::ProjectFlowcellArgs {
-                                    project_uuid: settings.ingest.project_uuid.clone(),
-                                    flowcell_uuid: flowcell.sodar_uuid.clone().unwrap(),
-                                },
-                                &api_hist,
-                            )
-                            .chain_err(|| "Could not update adapter on server")?
-                    }
-                }
+                    api_hist
+                        .filtered(settings.ingest.max_histogram_entries)
+                        .num_entries()
+                );
+                client
+                    .post_histogram(
+                        &api::ProjectFlowcellArgs {
+                            project_uuid: settings.ingest.project_uuid,
+                            flowcell_uuid: flowcell.sodar_uuid.unwrap(),
+                        },
+                        &api_hist,
+                        settings.ingest.max_histogram_entries,
+                    )
+                    .chain_err(|| "Could not update merged adapter on server")?
             }
         }
-        cycle += desc.num_cycles;
+        lane_index_stats.extend(stats_for_index);
     }
 
     info!(logger, "Done analyzing adapters.");
-    Ok(())
+    Ok(lane_index_stats)
 }
 
-/// Process the sequencer output folder at `path` with the given `settings`.
-fn process_folder(
+/// Search for the flow cell from `run_info` in the projects other than the configured one that
+/// can be read with the API token.
+///
+/// Returns the first project and flow cell found, if any.  Servers that do not list the
+/// projects (`api/projects/` answering with HTTP 403 or 404, or with an unexpected response) and
+/// projects that cannot be read are skipped, such that the flow cell is treated as not found.
+/// Other errors fail, as registering the flow cell could then create a duplicate.
+fn resolve_in_other_projects(
     logger: &slog::Logger,
-    path: &Path,
-    client: &mut RestClient,
+    client: &mut api::Client,
+    run_info: &RunInfo,
+    settings: &Settings,
+) -> Result<Option<(api::Project, api::FlowCell)>> {
+    let projects = match client.get(()) {
+        Ok(api::ProjectArray::Array(projects)) => projects,
+        Err(restson::Error::HttpError(code, _)) if code == 403 || code == 404 => {
+            info!(
+                logger,
+                "Server does not list the projects (HTTP {}), not looking for the flow cell in \
+                 other projects",
+                code
+            );
+            return Ok(None);
+        }
+        Err(restson::Error::ParseError(e)) => {
+            info!(
+                logger,
+                "Unexpected list of projects from server, not looking for the flow cell in other \
+                 projects: {}",
+                e
+            );
+            return Ok(None);
+        }
+        Err(e) => bail!("Problem listing projects: {:?}", &e),
+    };
+    for project in projects
+        .into_iter()
+        .filter(|project| project.sodar_uuid != settings.ingest.project_uuid)
+    {
+        debug!(
+            logger,
+            "Looking for flow cell in project {}", &project.sodar_uuid
+        );
+        let result: result::Result<api::FlowCell, restson::Error> =
+            client.get(&api::ResolveFlowCellArgs {
+                project_uuid: project.sodar_uuid,
+                instrument: run_info.instrument.clone(),
+                run_number: run_info.run_number,
+                flowcell: run_info.flowcell.clone(),
+            });
+        match result {
+            Ok(flowcell) => return Ok(Some((project, flowcell))),
+            Err(restson::Error::HttpError(403, _)) | Err(restson::Error::HttpError(404, _)) => (),
+            Err(e) => bail!(
+                "Problem resolving flow cell in project {}: {:?}",
+                &project.sodar_uuid,
+                &e
+            ),
+        }
+    }
+    Ok(None)
+}
+
+/// Return the flow cells of the project with the vendor ID of the run in `run_info` but from
+/// another run, e.g., earlier runs of a re-hybridized flow cell.
+fn find_prior_runs(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    run_info: &RunInfo,
+    settings: &Settings,
+) -> Vec<api::FlowCell> {
+    match client.get(&api::ProjectArgs {
+        project_uuid: settings.ingest.project_uuid,
+    }) {
+        Ok(api::FlowCellArray::Array(flowcells)) => flowcells
+            .into_iter()
+            .filter(|flowcell| {
+                flowcell.vendor_id == run_info.flowcell
+                    && (flowcell.run_number != run_info.run_number
+                        || flowcell.sequencing_machine != run_info.instrument)
+            })
+            .collect(),
+        Err(e) => {
+            debug!(logger, "Could not list flow cells: {:?}", &e);
+            Vec::new()
+        }
+    }
+}
+
+/// Post messages linking the newly registered `flowcell` and the `prior_runs` of the same
+/// physical flow cell to each of them.
+fn link_prior_runs(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    prior_runs: &[api::FlowCell],
     settings: &Settings,
 ) -> Result<()> {
-    info!(logger, "Starting to process folder {:?}...", path);
+    let describe = |flowcell: &api::FlowCell| {
+        format!(
+            "- run {} on {} ({}), UUID {}",
+            flowcell.run_number,
+            &flowcell.sequencing_machine,
+            &flowcell.run_date,
+            flowcell.sodar_uuid.unwrap_or_default()
+        )
+    };
+    post_message(
+        logger,
+        client,
+        flowcell,
+        "Flow cell has been sequenced before",
+        format!(
+            "The flow cell {} has been sequenced before (e.g., before re-hybridization) and was \
+             registered again for this run.  The earlier runs are:\n\n{}",
+            &flowcell.vendor_id,
+            prior_runs
+                .iter()
+                .map(describe)
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+        settings,
+    )?;
+    for prior in prior_runs {
+        post_message(
+            logger,
+            client,
+            prior,
+            "Flow cell has been sequenced again",
+            format!(
+                "The flow cell {} has been sequenced again and was registered separately for the \
+                 new run:\n\n{}",
+                &flowcell.vendor_id,
+                describe(flowcell)
+            ),
+            settings,
+        )?;
+    }
+    Ok(())
+}
 
-    // Ensure that `RunInfo.xml` exists and try to guess folder layout.
-    if !path.join("RunInfo.xml").exists() {
-        error!(
+/// Post a message with `subject` and `body` to the given `flowcell`.
+///
+/// Flow cells without UUID (e.g., not registered in dry-run mode) get no message.
+fn post_message(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    subject: &str,
+    body: String,
+    settings: &Settings,
+) -> Result<()> {
+    if let Some(flowcell_uuid) = flowcell.sodar_uuid {
+        debug!(
             logger,
-            "Path {:?}/RunInfo.xml does not exist! Skipping directory.", path
+            "Posting message {:?} to flow cell {}", subject, &flowcell_uuid
         );
-        bail!("RunInfo.xml missing");
+        let args = api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            flowcell_uuid,
+        };
+        let message = api::FlowCellMessage {
+            subject: Some(subject.to_string()),
+            body,
+            state: "sent".to_string(),
+        };
+        client
+            .post(&args, &message)
+            .chain_err(|| "Problem posting message")?;
+    } else {
+        debug!(logger, "Flow cell has no UUID, cannot post message.");
     }
-    let folder_layout = match guess_folder_layout(path) {
-        Ok(layout) => {
-            info!(logger, "Guessed folder layout to be {:?}", layout);
-            layout
-        }
-        Err(_e) => {
-            warn!(
-                logger,
-                "Could not guess folder layout from {:?}. Skipping.", path
-            );
+    Ok(())
+}
+
+/// Post the collected `warnings` as a single message to the given `flowcell`.
+fn post_warnings(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    warnings: &[Warning],
+    settings: &Settings,
+) -> Result<()> {
+    post_message(
+        logger,
+        client,
+        flowcell,
+        &format!("{} warning(s) during ingest", warnings.len()),
+        message_body(warnings),
+        settings,
+    )
+}
+
+/// Post the quality indicators of the `lanes` as a message to the given `flowcell`.
+fn post_lane_quality(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    lanes: &[interop::LaneQuality],
+    settings: &Settings,
+) -> Result<()> {
+    let failed = lanes
+        .iter()
+        .filter(|lane| lane.is_failed())
+        .map(|lane| lane.lane.to_string())
+        .collect::<Vec<String>>();
+    post_message(
+        logger,
+        client,
+        flowcell,
+        &format!("Failed lane(s): {}", failed.join(", ")),
+        interop::message_body(lanes),
+        settings,
+    )
+}
+
+/// Post the `provenance` of the current modifications as a message to the given `flowcell`.
+fn post_provenance(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    provenance: &Provenance,
+    settings: &Settings,
+) -> Result<()> {
+    post_message(
+        logger,
+        client,
+        flowcell,
+        "Ingest provenance",
+        format!(
+            "The flow cell information was updated by the following ingest:\n\n{}",
+            provenance
+        ),
+        settings,
+    )
+}
 
-            bail!("Could not guess folder layout");
+/// Parse the `RunInfo.xml` and run parameters files of the run folder at `path` with the given
+/// `folder_layout`, and cross-check the flow cell slot with the folder name.
+///
+/// Quirks that had to be worked around are added to `warnings`.
+fn parse_run_folder(
+    logger: &slog::Logger,
+    path: &Path,
+    folder_layout: FolderLayout,
+    date_format: &str,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
+    let (run_info, mut run_params) = match folder_layout {
+        FolderLayout::Aviti => {
+            info!(logger, "Parsing {}...", aviti::RUN_PARAMETERS_FILE);
+            aviti::parse_run_folder(path, warnings)?
+        }
+        FolderLayout::Mgi => {
+            info!(logger, "Parsing {}...", mgi::BIO_INFO_FILE);
+            mgi::parse_run_folder(path, warnings)?
         }
+        FolderLayout::Nanopore => {
+            info!(logger, "Parsing MinKNOW final summary and report...");
+            nanopore::parse_run_folder(path, warnings)?
+        }
+        _ => parse_xml_files(logger, path, folder_layout, date_format, warnings)?,
     };
+    resolve_flowcell_slot(path, &run_info, &mut run_params, warnings);
+    Ok((run_info, run_params))
+}
 
-    // Parse the run info and run parameters XML files
+/// Parse the `RunInfo.xml` and run parameters XML files of the run folder at `path` with the
+/// given `folder_layout`, trying the custom `date_format` for the run date first.
+fn parse_xml_files(
+    logger: &slog::Logger,
+    path: &Path,
+    folder_layout: FolderLayout,
+    date_format: &str,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
     info!(logger, "Parsing XML files...");
     let info_pkg = {
-        let mut xmlf =
-            File::open(path.join("RunInfo.xml")).chain_err(|| "Problem reading RunInfo.xml")?;
-        let mut contents = String::new();
-        xmlf.read_to_string(&mut contents)
-            .chain_err(|| "Problem reading XML from RunInfo.xml")?;
+        let contents = read_meta_file(path, "RunInfo.xml")?;
         parser::parse(&contents).chain_err(|| "Problem parsing XML from RunInfo.xml")?
     };
     let info_doc = info_pkg.as_document();
 
     let param_pkg = {
-        let filename = match folder_layout {
-            FolderLayout::MiSeqDep => "runParameters.xml",
-            FolderLayout::MiSeq => "RunParameters.xml",
-            FolderLayout::MiniSeq => "RunParameters.xml",
-            FolderLayout::HiSeqX => "RunParameters.xml",
-            FolderLayout::NovaSeq => "RunParameters.xml",
-            FolderLayout::NovaSeqXplus => "RunParameters.xml",
-            FolderLayout::NextSeq2000 => "RunParameters.xml",
-        };
-        let mut xmlf = File::open(path.join(filename))
-            .chain_err(|| format!("Problem reading {}", &filename))?;
-        let mut contents = String::new();
-        xmlf.read_to_string(&mut contents)
-            .chain_err(|| format!("Problem reading XML from {}", &filename))?;
+        let filename = run_parameters_file(folder_layout);
+        let mut contents = read_meta_file(path, filename)?;
+        if contents.starts_with('\u{feff}') {
+            warnings.push(Warning::new(
+                WarningKind::LayoutQuirk,
+                format!("Stripped byte order mark from {}", &filename),
+            ));
+        }
         contents = contents.to_string().trim_start_matches("\u{feff}").to_owned();
         parser::parse(&contents).chain_err(|| format!("Problem parsing XML from {}", &filename))?
     };
     let param_doc = param_pkg.as_document();
 
     // Process the XML files.
-    let (run_info, run_params) = process_xml(logger, folder_layout, &info_doc, &param_doc)?;
+    process_xml(
+        logger,
+        folder_layout,
+        &info_doc,
+        &param_doc,
+        date_format,
+        warnings,
+    )
+}
 
-    debug!(logger, "Run info is {:?}", &run_info);
-    debug!(logger, "Run params is {:?}", &run_params);
+/// Return the folder layout configured in `settings` for the run folder at `path`, if any.
+///
+/// A `PATH=LAYOUT` entry for `path` takes precedence over a `LAYOUT` entry for all paths.
+fn configured_folder_layout(path: &Path, settings: &Settings) -> Result<Option<FolderLayout>> {
+    let mut result = None;
+    for entry in &settings.ingest.folder_layout {
+        let mut fields = entry.rsplitn(2, '=');
+        let name = fields.next().unwrap_or("");
+        let layout = FolderLayout::from_name(name)?;
+        match fields.next() {
+            Some(layout_path) if Path::new(layout_path) == path => return Ok(Some(layout)),
+            Some(_) => (),
+            None => result = Some(layout),
+        }
+    }
+    Ok(result)
+}
 
-    // Try to get the flow cell information from API.
-    debug!(logger, "Connecting to \"{}\"", &settings.web.url);
-    if settings.log_token {
-        debug!(
-            logger,
-            "  (using header 'Authorization: Token {}')", &settings.web.token
-        );
+/// Return the path to the file with the run information of the run folder at `path`.
+///
+/// This is the `RunInfo.xml` file (or `RunInfo.xml.gz` of archived run folders) except for AVITI
+/// run folders which only have a `RunParameters.json` file, MGI output directories which have a
+/// `BioInfo.csv` file per lane, and MinKNOW run directories which have a final summary or JSON
+/// report.
+fn run_info_file(path: &Path) -> PathBuf {
+    if aviti::is_aviti_folder(path) {
+        path.join(aviti::RUN_PARAMETERS_FILE)
+    } else if let Some(bio_info) = mgi::bio_info_file(path).filter(|_| mgi::is_mgi_folder(path)) {
+        bio_info
+    } else if let Some(summary) =
+        nanopore::run_info_file(path).filter(|_| nanopore::is_nanopore_folder(path))
+    {
+        summary
+    } else {
+        meta_file(path, "RunInfo.xml")
     }
-    let result: result::Result<api::FlowCell, restson::Error> =
-        client.get(&api::ResolveFlowCellArgs {
-            project_uuid: settings.ingest.project_uuid.clone(),
-            instrument: run_info.instrument.clone(),
+}
+
+/// Read the `RunInfo.xml` file (or the `RunParameters.json` file of AVITI run folders, the
+/// `BioInfo.csv` files of MGI output directories, and the final summary of MinKNOW run
+/// directories) of the run folder at `path`, trying the custom `date_format` for the run date
+/// first.
+fn read_run_info(path: &Path, date_format: &str) -> Result<RunInfo> {
+    if aviti::is_aviti_folder(path) {
+        return aviti::parse_run_folder(path, &mut Vec::new()).map(|(run_info, _)| run_info);
+    } else if mgi::is_mgi_folder(path) {
+        return mgi::parse_run_folder(path, &mut Vec::new()).map(|(run_info, _)| run_info);
+    } else if nanopore::is_nanopore_folder(path) {
+        return nanopore::parse_run_folder(path, &mut Vec::new()).map(|(run_info, _)| run_info);
+    }
+    let contents = read_meta_file(path, "RunInfo.xml")?;
+    let package = parser::parse(&contents).chain_err(|| "Problem parsing XML from RunInfo.xml")?;
+    process_xml_run_info(&package.as_document(), date_format, &mut Vec::new())
+}
+
+/// Return `paths` ordered by the run date from their run information files, oldest first.
+///
+/// Folders with the same run date are ordered by path, folders whose run info cannot be read
+/// come last.
+fn order_by_run_date(logger: &slog::Logger, paths: &[String], date_format: &str) -> Vec<String> {
+    let mut keyed = paths
+        .iter()
+        .map(|path| match read_run_info(Path::new(path), date_format) {
+            Ok(run_info) => (false, run_info.date, path.clone()),
+            Err(e) => {
+                debug!(logger, "Could not read run date of {}: {:?}", path, &e);
+                (true, String::new(), path.clone())
+            }
+        })
+        .collect::<Vec<(bool, String, String)>>();
+    keyed.sort();
+    keyed.into_iter().map(|(_, _, path)| path).collect()
+}
+
+/// Process the sequencer output folder at `path` with the given `settings`.
+///
+/// Information about the folder and the warnings that occured are written to `report`.
+fn process_folder(
+    logger: &slog::Logger,
+    path: &Path,
+    client: &mut api::Client,
+    settings: &Settings,
+    report: &mut FolderReport,
+) -> Result<()> {
+    info!(logger, "Starting to process folder {:?}...", path);
+
+    // Ensure that `RunInfo.xml` (or `RunParameters.json` for AVITI) exists and try to guess
+    // folder layout.
+    let info_file = run_info_file(path);
+    if !info_file.exists() {
+        error!(
+            logger,
+            "Path {:?} does not exist! Skipping directory.", &info_file
+        );
+        bail!("RunInfo.xml missing");
+    }
+
+    // Overlapping calls would post the index histograms twice, nothing is posted for dry runs
+    // and without network access.
+    let _lock = if settings.dry_run || settings.ingest.no_net {
+        None
+    } else {
+        match lock_folder(logger, path, settings.ingest.break_stale_locks)? {
+            Locking::Acquired(lock) => Some(lock),
+            Locking::Held(holder) => {
+                info!(
+                    logger,
+                    "Folder {:?} is being processed by {}, skipping", path, &holder
+                );
+                report.locked_by = Some(holder);
+                return Ok(());
+            }
+            Locking::Unsupported(reason) => {
+                warn!(
+                    logger,
+                    "Cannot write lock file to {:?}, processing without lock: {}", path, reason
+                );
+                None
+            }
+        }
+    };
+
+    let rules = layout_rules(&settings.configured_layout_rules());
+    let folder_layout = match configured_folder_layout(path, settings)? {
+        Some(layout) => {
+            info!(logger, "Using configured folder layout {:?}", layout);
+            layout
+        }
+        None => match guess_folder_layout(path, &rules) {
+            Ok(layout) => {
+                info!(logger, "Guessed folder layout to be {:?}", layout);
+                layout
+            }
+            Err(_e) => {
+                warn!(
+                    logger,
+                    "Could not guess folder layout from {:?}. Skipping.", path
+                );
+                report.permanent_failure = true;
+                bail!("Could not guess folder layout");
+            }
+        },
+    };
+    report.folder_layout = Some(folder_layout);
+    if settings.ingest.folder_size {
+        let folder_size = FolderSize::compute(path);
+        info!(logger, "Folder size is {}", &folder_size.total);
+        report.folder_size = Some(folder_size);
+    }
+    report.quality_bins = quality_bins(logger, path);
+    debug!(logger, "Quality bins: {:?}", &report.quality_bins);
+
+    // Parse the run info and run parameters XML files
+    let (run_info, run_params) = match parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut report.warnings,
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            report.permanent_failure = true;
+            return Err(e);
+        }
+    };
+    report.vendor_id = Some(run_info.flowcell.clone());
+    report.instrument_model = run_params.instrument_model;
+    report
+        .warnings
+        .extend(check_read_cycles(&run_info, settings));
+
+    debug!(logger, "Run info is {:?}", &run_info);
+    debug!(logger, "Run params is {:?}", &run_params);
+
+    // Try to get the flow cell information from API.
+    debug!(logger, "Connecting to \"{}\"", &settings.web.url);
+    if settings.log_token {
+        debug!(
+            logger,
+            "  (using header 'Authorization: Token {}')", &settings.web.token
+        );
+    }
+    let result: result::Result<api::FlowCell, restson::Error> =
+        client.get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: run_info.instrument.clone(),
             run_number: run_info.run_number,
             flowcell: run_info.flowcell.clone(),
         });
+    if settings.ingest.no_net && result.is_err() {
+        bail!(
+            "Flow cell {} (run {} on {}) is not the one from {:?}",
+            &run_info.flowcell,
+            run_info.run_number,
+            &run_info.instrument,
+            &settings.ingest.flowcell_json
+        );
+    }
+
+    // Look for the flow cell in the other projects before registering a duplicate.  If allowed,
+    // the remaining processing happens in the project the flow cell was found in.
+    let cross_project_settings;
+    let (result, settings) = match result {
+        Err(restson::Error::HttpError(404, msg)) => {
+            match resolve_in_other_projects(logger, client, &run_info, settings)? {
+                Some((project, flowcell)) => {
+                    if !settings.ingest.allow_cross_project {
+                        bail!(
+                            "Flow cell {} not found in project {} but in project {:?} ({}); \
+                             use --allow-cross-project to update it there",
+                            &run_info.flowcell,
+                            &settings.ingest.project_uuid,
+                            &project.title,
+                            &project.sodar_uuid
+                        );
+                    }
+                    report.warnings.push(Warning::new(
+                        WarningKind::CrossProject,
+                        format!(
+                            "Flow cell found in project {:?} ({}) instead of {}",
+                            &project.title, &project.sodar_uuid, &settings.ingest.project_uuid
+                        ),
+                    ));
+                    let mut project_settings = settings.clone();
+                    project_settings.ingest.project_uuid = project.sodar_uuid;
+                    cross_project_settings = project_settings;
+                    (Ok(flowcell), &cross_project_settings)
+                }
+                None => (Err(restson::Error::HttpError(404, msg)), settings),
+            }
+        }
+        _ => (result, settings),
+    };
 
+    // Whether or not the flow cell or its histograms were modified via the API.
+    let mut modified = false;
+    let previous_status = result
+        .as_ref()
+        .ok()
+        .map(|flowcell| flowcell.status_sequencing.clone());
     let flowcell: api::FlowCell = if settings.ingest.register || settings.ingest.update {
         // Update or create if necessary.
         match result {
@@ -398,6 +1305,14 @@ fn process_folder(
                             );
                             flowcell
                         } else {
+                            check_lane_count(
+                                logger,
+                                &flowcell,
+                                run_info.lane_count,
+                                settings,
+                                &mut report.warnings,
+                            )?;
+                            modified = true;
                             update_flowcell(
                                 logger,
                                 client,
@@ -409,6 +1324,14 @@ fn process_folder(
                             )?
                         }
                     } else {
+                        check_lane_count(
+                            logger,
+                            &flowcell,
+                            run_info.lane_count,
+                            settings,
+                            &mut report.warnings,
+                        )?;
+                        modified = true;
                         update_flowcell(
                             logger,
                             client,
@@ -425,10 +1348,31 @@ fn process_folder(
             }
             Err(restson::Error::HttpError(404, _msg)) => {
                 debug!(logger, "Flow cell was not found!");
+                // Never update the records of earlier runs of the same flow cell.
+                let prior_runs = find_prior_runs(logger, client, &run_info, settings);
+                for prior in &prior_runs {
+                    warn!(
+                        logger,
+                        "Flow cell {} was sequenced before as run {} on {}",
+                        &run_info.flowcell,
+                        prior.run_number,
+                        &prior.sequencing_machine
+                    );
+                    report.warnings.push(Warning::new(
+                        WarningKind::RerunFlowCell,
+                        format!(
+                            "Flow cell was sequenced before as run {} on {} ({})",
+                            prior.run_number,
+                            &prior.sequencing_machine,
+                            prior.sodar_uuid.unwrap_or_default()
+                        ),
+                    ));
+                }
                 if settings.dry_run {
                     info!(logger, "Dry run mode activated. Not registering.");
                     return Ok(());
                 } else if settings.ingest.register {
+                    modified = true;
                     let flowcell = register_flowcell(
                         logger,
                         client,
@@ -438,6 +1382,9 @@ fn process_folder(
                         &settings,
                     )?;
                     debug!(logger, "Flow cell registered as {:?}", &flowcell);
+                    if !prior_runs.is_empty() {
+                        link_prior_runs(logger, client, &flowcell, &prior_runs, settings)?;
+                    }
                     flowcell
                 } else {
                     info!(
@@ -455,10 +1402,58 @@ fn process_folder(
         // TODO: improve error handling
         result.expect("Flowcell not found but we are not supposed to register")
     };
+    report.flowcell_uuid = flowcell.sodar_uuid;
+    report.status_sequencing = Some(flowcell.status_sequencing.clone());
+
+    if let Some(missing) = truncated_cycles(path, &run_info) {
+        let index_missing = index_cycles(&run_info)
+            .iter()
+            .any(|cycle| missing.contains(cycle));
+        report.warnings.push(Warning::new(
+            WarningKind::MissingCycles,
+            format!(
+                "Run folder is truncated, cycles {} are missing{}",
+                format_cycle_ranges(&missing),
+                if index_missing {
+                    " (including index cycles)"
+                } else {
+                    ""
+                }
+            ),
+        ));
+    }
 
     // Check if we should skip this directory.
-    if settings.ingest.analyze_adapters {
-        analyze_adapters(
+    report.no_index_reads = !has_index_reads(&run_info);
+    if report.no_index_reads {
+        info!(
+            logger,
+            "Run has no index reads, not analyzing adapters: {}",
+            string_description(&run_info.reads)
+        );
+    } else if settings.ingest.analyze_adapters && folder_layout == FolderLayout::Mgi {
+        info!(
+            logger,
+            "Cannot analyze adapters of MGI output directories, not analyzing adapters."
+        );
+    } else if settings.ingest.analyze_adapters
+        && settings.ingest.wait_for_copy_complete
+        && !meta_file_exists(path, copy_complete_file(folder_layout))
+    {
+        info!(
+            logger,
+            "No {} yet, postponing adapter analysis.",
+            copy_complete_file(folder_layout)
+        );
+        report.adapters_postponed = true;
+    } else if settings.ingest.analyze_adapters {
+        for lane_no in lanes_without_filter_files(logger, path) {
+            report.warnings.push(Warning::new(
+                WarningKind::MissingFilterFile,
+                format!("No filter files found for lane {}", lane_no),
+            ));
+        }
+        let lane_index_stats = analyze_adapters(
             logger,
             &flowcell,
             client,
@@ -467,14 +1462,660 @@ fn process_folder(
             folder_layout,
             &settings,
         )?;
+        for stats in &lane_index_stats {
+            if stats.sample_size < MIN_SAMPLE_SIZE {
+                report.warnings.push(Warning::new(
+                    WarningKind::LowSampleSize,
+                    format!(
+                        "Only {} reads sampled for lane {}, index read {}",
+                        stats.sample_size, stats.lane, stats.index_read_no
+                    ),
+                ));
+            }
+            if stats.n_fraction > MAX_N_FRACTION {
+                report.warnings.push(Warning::new(
+                    WarningKind::HighNContent,
+                    format!(
+                        "{:.1}% N calls in lane {}, index read {}",
+                        100.0 * stats.n_fraction,
+                        stats.lane,
+                        stats.index_read_no
+                    ),
+                ));
+            }
+        }
+        let expected_barcodes = ExpectedBarcodes::load(&flowcell, path);
+        if expected_barcodes.is_empty() {
+            debug!(
+                logger,
+                "No expected barcodes, not checking for unassigned ones."
+            );
+        }
+        for stats in &lane_index_stats {
+            if let (Some(seq), Some(fraction)) = (&stats.top_index, stats.top_index_fraction) {
+                if !expected_barcodes
+                    .for_index_read(stats.index_read_no)
+                    .is_empty()
+                    && fraction > settings.ingest.unassigned_barcode_threshold
+                    && !expected_barcodes.matches(stats.index_read_no, seq)
+                {
+                    report.warnings.push(Warning::new(
+                        WarningKind::UnassignedBarcode,
+                        format!(
+                            "Most frequent index {} ({:.1}% of reads) in lane {}, index read {} \
+                             does not match any expected barcode",
+                            seq,
+                            100.0 * fraction,
+                            stats.lane,
+                            stats.index_read_no
+                        ),
+                    ));
+                }
+            }
+        }
+        if !lane_index_stats.is_empty() && settings.ingest.post_adapters && !settings.dry_run {
+            modified = true;
+        }
+        report.lane_index_stats = lane_index_stats;
     } else {
         info!(logger, "You asked me to not analyze adapters.");
     }
 
+    // Failed clustering shows as a single base dominating the first template cycles, which the
+    // index histograms do not show.
+    if settings.ingest.analyze_adapters && !report.adapters_postponed {
+        match sample_base_composition(logger, path, folder_layout, &run_info) {
+            Ok(compositions) => {
+                for composition in &compositions {
+                    let (base, fraction) = composition.dominant_base();
+                    if fraction > MAX_BASE_FRACTION {
+                        report.warnings.push(Warning::new(
+                            WarningKind::SkewedBaseComposition,
+                            format!(
+                                "{:.1}% {} calls in lane {}, cycle {}; clustering may have failed",
+                                100.0 * fraction,
+                                base,
+                                composition.lane,
+                                composition.cycle
+                            ),
+                        ));
+                    }
+                }
+                report.base_composition = compositions;
+            }
+            Err(e) => warn!(logger, "Could not sample base composition: {:?}", &e),
+        }
+    }
+
+    // Lanes can fail on their own (e.g., when clogged), which the sequencing status of the flow
+    // cell does not show.
+    if settings.ingest.lane_quality {
+        let thresholds = interop::LaneThresholds::from_settings(settings);
+        match interop::lane_quality(path, &thresholds) {
+            Ok(lanes) => {
+                for lane in lanes.iter().filter(|lane| lane.is_failed()) {
+                    report.warnings.push(Warning::new(
+                        WarningKind::FailedLane,
+                        format!("Lane {} failed: {}", lane.lane, lane.failures.join("; ")),
+                    ));
+                }
+                // The metrics are final once the run is complete, the note is posted only then.
+                let completed = flowcell.status_sequencing == "complete"
+                    && previous_status.as_deref() != Some("complete");
+                if completed && lanes.iter().any(|lane| lane.is_failed()) {
+                    if settings.dry_run {
+                        info!(logger, "Dry run mode active, not posting lane quality.");
+                    } else {
+                        modified = true;
+                        post_lane_quality(logger, client, &flowcell, &lanes, settings)?;
+                    }
+                }
+                report.lane_quality = lanes;
+            }
+            Err(e) => warn!(logger, "Could not read InterOp metrics: {:?}", &e),
+        }
+    }
+
+    if settings.ingest.post_warnings && !report.warnings.is_empty() {
+        if settings.dry_run {
+            info!(logger, "Dry run mode active, not posting warnings.");
+        } else {
+            post_warnings(logger, client, &flowcell, &report.warnings, settings)?;
+        }
+    }
+
+    report.provenance.finish();
+    report.modified = modified;
+    if modified && !settings.dry_run {
+        post_provenance(logger, client, &flowcell, &report.provenance, settings)?;
+    }
+
     info!(logger, "Done processing folder {:?}.", path);
     Ok(())
 }
 
+/// Number of attempts for reaching a server before considering it as down.
+const PROBE_ATTEMPTS: u32 = 3;
+/// Delay between attempts for reaching a server.
+const PROBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Construct a client for the Digestiflow Web server configured in `web`, logging each call to
+/// `logger` if `trace_api` is set and archiving the payloads sent to `archive_dir` if not empty.
+fn build_client(
+    logger: &slog::Logger,
+    web: &Web,
+    trace_api: bool,
+    archive_dir: &str,
+) -> Result<api::Client> {
+    if !api::API_SCHEMAS.contains(&web.api_schema) {
+        bail!(
+            "Invalid API schema {} for server {:?}, supported are {:?}",
+            web.api_schema,
+            &web.url,
+            api::API_SCHEMAS
+        );
+    }
+    let token = if web.token_command.is_empty() {
+        web.token.clone()
+    } else {
+        api::run_token_command(&web.token_command)
+            .chain_err(|| format!("Could not fetch API token for server {:?}", &web.url))?
+    };
+    let mut client =
+        RestClient::new(&web.url).chain_err(|| format!("Invalid server URL {:?}", &web.url))?;
+    client
+        .set_header("Authorization", &format!("Token {}", &token))
+        .chain_err(|| "Problem configuring REST client")?;
+    let mut client = api::Client::new(client, web.requests_per_second, web.api_schema);
+    client.set_streaming(
+        streaming::StreamingClient::new(&web.url, &token)
+            .chain_err(|| format!("Invalid server URL {:?}", &web.url))?,
+    );
+    if !web.token_command.is_empty() {
+        client.set_token_command(logger, &web.token_command);
+    }
+    if trace_api {
+        client.set_trace(logger.clone());
+    }
+    if !archive_dir.is_empty() {
+        client.set_archive(archive::PayloadArchive::new(
+            logger,
+            Path::new(archive_dir),
+            &web.url,
+        )?);
+    }
+    Ok(client)
+}
+
+/// Fetch the ingest preferences of the project `settings.ingest.project_uuid` from the server.
+///
+/// Returns `None` if disabled with `settings.ingest.project_defaults`, if no project is given,
+/// without network access, or if the server provides no preferences for the project.  Servers
+/// that cannot be queried only result in a warning, the local configuration is used then.
+pub fn fetch_project_settings(
+    logger: &slog::Logger,
+    settings: &Settings,
+) -> Result<Option<ProjectSettings>> {
+    if !settings.ingest.project_defaults
+        || settings.ingest.project_uuid.is_nil()
+        || settings.ingest.no_net
+    {
+        return Ok(None);
+    }
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let result: result::Result<ProjectSettings, restson::Error> = client.get(&api::ProjectArgs {
+        project_uuid: settings.ingest.project_uuid,
+    });
+    match result {
+        Ok(project) => {
+            info!(
+                logger,
+                "Using ingest preferences of project {} as defaults: {}",
+                &settings.ingest.project_uuid,
+                serde_json::to_string(&project).chain_err(|| "Problem serializing to JSON")?
+            );
+            Ok(Some(project))
+        }
+        Err(restson::Error::HttpError(404, _)) => {
+            debug!(logger, "Server provides no ingest preferences for project");
+            Ok(None)
+        }
+        Err(e) => {
+            warn!(
+                logger,
+                "Could not fetch ingest preferences of project, using local configuration: {}", e
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Return the flow cell `key` of the project `project_uuid`, given by its UUID or by its vendor
+/// ID.
+///
+/// A vendor ID is resolved with the `instrument` and `run_number` if both are given, otherwise
+/// they are taken from the flow cell with this vendor ID (and the given instrument or run number)
+/// in the list of flow cells of the project.
+fn find_flowcell(
+    client: &mut api::Client,
+    project_uuid: Uuid,
+    key: &str,
+    instrument: &str,
+    run_number: i32,
+) -> Result<api::FlowCell> {
+    if let Ok(flowcell_uuid) = Uuid::parse_str(key) {
+        return client
+            .get(&api::ProjectFlowcellArgs {
+                project_uuid,
+                flowcell_uuid,
+            })
+            .chain_err(|| format!("Could not get flow cell {}", flowcell_uuid));
+    }
+
+    let (instrument, run_number) = if !instrument.is_empty() && run_number > 0 {
+        (instrument.to_string(), run_number)
+    } else {
+        let flowcell_array: api::FlowCellArray = client
+            .get(&api::ProjectArgs { project_uuid })
+            .chain_err(|| "Could not list flow cells of project")?;
+        let api::FlowCellArray::Array(flowcells) = flowcell_array;
+        let candidates = flowcells
+            .iter()
+            .filter(|flowcell| {
+                flowcell.vendor_id == key
+                    && (instrument.is_empty() || flowcell.sequencing_machine == instrument)
+                    && (run_number <= 0 || flowcell.run_number == run_number)
+            })
+            .collect::<Vec<&api::FlowCell>>();
+        match candidates.as_slice() {
+            [] => bail!("No flow cell with vendor ID {:?} in project", key),
+            [flowcell] => (flowcell.sequencing_machine.clone(), flowcell.run_number),
+            _ => bail!(
+                "Several flow cells with vendor ID {:?}, select one with --instrument and \
+                 --run-number: {}",
+                key,
+                candidates
+                    .iter()
+                    .map(|flowcell| format!(
+                        "run {} on {}",
+                        flowcell.run_number, &flowcell.sequencing_machine
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    };
+    client
+        .get(&api::ResolveFlowCellArgs {
+            project_uuid,
+            instrument: instrument.clone(),
+            run_number,
+            flowcell: key.to_string(),
+        })
+        .chain_err(|| {
+            format!(
+                "Could not resolve flow cell {} (run {} on {})",
+                key, run_number, &instrument
+            )
+        })
+}
+
+/// URL recorded with the captured payloads without network access if no server is configured.
+const OFFLINE_URL: &str = "http://localhost/";
+
+/// Construct a client without network access that knows the flow cell from the JSON file
+/// `settings.ingest.flowcell_json`, e.g., as fetched from the API on a machine with access.
+///
+/// The flow cell is found for its own instrument, run number, and vendor ID in the configured
+/// project, and it has no index histograms yet such that the adapters are always analyzed.
+fn build_offline_client(logger: &slog::Logger, settings: &Settings) -> Result<api::Client> {
+    let path = &settings.ingest.flowcell_json;
+    let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+    let flowcell: api::FlowCell = serde_json::from_reader(file)
+        .chain_err(|| format!("Problem reading flow cell JSON from {:?}", path))?;
+    let flowcell_uuid = match flowcell.sodar_uuid {
+        Some(uuid) => uuid,
+        None => bail!("Flow cell from {:?} has no sodar_uuid", path),
+    };
+    info!(
+        logger,
+        "Using flow cell {} (run {} on {}) from {:?} without network access",
+        &flowcell.vendor_id,
+        flowcell.run_number,
+        &flowcell.sequencing_machine,
+        path
+    );
+
+    let mut web = settings.web.clone();
+    if web.url.is_empty() {
+        web.url = OFFLINE_URL.to_string();
+    }
+    let mut client = build_client(logger, &web, settings.trace_api, "")?;
+    client.set_offline(&web.url);
+    client.add_response(
+        &api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: flowcell.sequencing_machine.clone(),
+            run_number: flowcell.run_number,
+            flowcell: flowcell.vendor_id.clone(),
+        },
+        &flowcell,
+    )?;
+    client.add_response(
+        &api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            flowcell_uuid,
+        },
+        &api::LaneIndexHistogramArray::Array(Vec::new()),
+    )?;
+    Ok(client)
+}
+
+/// Construct the client for the server in `server_settings`, without network access if
+/// `settings.ingest.no_net` is set.  The payloads that cannot be sent as the server cannot be
+/// reached are spooled to `settings.spool_dir` if given.
+fn build_server_client(
+    logger: &slog::Logger,
+    server_settings: &Settings,
+    settings: &Settings,
+) -> Result<api::Client> {
+    if settings.ingest.no_net {
+        build_offline_client(logger, server_settings)
+    } else {
+        let mut client = build_client(
+            logger,
+            &server_settings.web,
+            settings.trace_api,
+            &settings.archive_dir,
+        )?;
+        if !settings.spool_dir.is_empty() {
+            client.set_spool(spool::PayloadSpool::new(
+                logger,
+                Path::new(&settings.spool_dir),
+                &server_settings.web.url,
+            )?);
+        }
+        Ok(client)
+    }
+}
+
+/// Send the payloads spooled for the server in `server_settings` by earlier calls with a client
+/// that does not spool them again, and return the run folders that still have spooled payloads.
+///
+/// Nothing is sent for dry runs and without network access.
+fn replay_spool(
+    logger: &slog::Logger,
+    server_settings: &Settings,
+    settings: &Settings,
+) -> Result<BTreeSet<String>> {
+    if settings.spool_dir.is_empty() || settings.ingest.no_net || settings.dry_run {
+        return Ok(BTreeSet::new());
+    }
+    let dir = Path::new(&settings.spool_dir);
+    let mut client = build_client(
+        logger,
+        &server_settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    if let Err(e) = spool::replay(logger, &mut client, &server_settings.web.url, dir, settings) {
+        warn!(logger, "Could not replay spooled payloads: {:?}", &e);
+    }
+    spool::spooled_folders(dir, &server_settings.web.url)
+}
+
+/// Return whether the server behind `client` can be reached, trying `PROBE_ATTEMPTS` times.
+///
+/// Client errors (e.g., 404 for servers without the endpoint) count as reachable, server errors
+/// and connection problems do not.
+fn probe_server(logger: &slog::Logger, client: &mut api::Client) -> bool {
+    for attempt in 1..=PROBE_ATTEMPTS {
+        let result: result::Result<api::ProjectArray, restson::Error> = client.get(());
+        match result {
+            Ok(_) => return true,
+            Err(restson::Error::HttpError(code, _)) if code < 500 => return true,
+            Err(e) => warn!(
+                logger,
+                "Server not reachable (attempt {} of {}): {:?}", attempt, PROBE_ATTEMPTS, &e
+            ),
+        }
+        if attempt < PROBE_ATTEMPTS {
+            thread::sleep(PROBE_DELAY);
+        }
+    }
+    false
+}
+
+/// Return the settings for each server that the folders are to be processed with.
+///
+/// Without a secondary server, this is the primary one.  Otherwise, `settings.server_mode`
+/// selects between using the secondary server only when the primary cannot be reached
+/// (`"failover"`) and using both servers (`"dual"`).
+fn select_servers(logger: &slog::Logger, settings: &Settings) -> Result<Vec<Settings>> {
+    // Without network access, the payloads are only captured for the primary server.
+    if settings.secondary_web.url.is_empty() || settings.ingest.no_net {
+        return Ok(vec![settings.clone()]);
+    }
+    let mut secondary = settings.clone();
+    secondary.web = settings.secondary_web.clone();
+
+    match settings.server_mode.as_str() {
+        "dual" => Ok(vec![settings.clone(), secondary]),
+        "failover" => {
+            if probe_server(
+                logger,
+                &mut build_client(logger, &settings.web, settings.trace_api, "")?,
+            ) {
+                Ok(vec![settings.clone()])
+            } else {
+                warn!(
+                    logger,
+                    "Primary server {} cannot be reached, failing over to {}",
+                    &settings.web.url,
+                    &settings.secondary_web.url
+                );
+                Ok(vec![secondary])
+            }
+        }
+        mode => bail!("Invalid server mode {:?}", mode),
+    }
+}
+
+/// Process the run folder at `path_str` with `client` and the settings of its server in
+/// `server_settings`, returning the report.
+///
+/// Errors are logged and recorded in the report, such that the other folders can be processed.
+fn process_path(
+    logger: &slog::Logger,
+    path_str: &str,
+    client: &mut api::Client,
+    server_settings: &Settings,
+    settings: &Settings,
+) -> FolderReport {
+    let path = Path::new(path_str);
+    let mut report = FolderReport::new(path, Provenance::new(settings));
+    report.server = server_settings.web.url.clone();
+    client.set_archive_folder(path_str);
+    match process_folder(logger, path, client, server_settings, &mut report) {
+        Err(e) => {
+            report.provenance.finish();
+            warn!(
+                logger,
+                "Processing folder {:?} failed. Will go on with other paths but the program \
+                 call will not have return code 0!: {:?}",
+                &path,
+                &e
+            );
+            report.error = Some(e.to_string());
+        }
+        _ => report.success = true,
+    }
+    report.payloads = client.take_payloads();
+    for payload in &report.payloads {
+        info!(
+            logger,
+            "Would {} /{} without network access", &payload.method, &payload.path
+        );
+    }
+    let api_stats = client.take_stats();
+    report.api_calls = api_stats.calls;
+    report.api_seconds = api_stats.duration.as_secs_f64();
+    report.api_spooled = api_stats.spooled;
+    if settings.trace_api {
+        info!(
+            logger,
+            "API time for folder {:?}: {} call(s) in {:.3}s",
+            &path,
+            report.api_calls,
+            report.api_seconds
+        );
+    }
+    report.peak_rss_bytes = memory::peak_rss();
+    if let Some(peak_rss) = report.peak_rss_bytes {
+        info!(
+            logger,
+            "Peak memory usage (RSS) after folder {:?}: {}",
+            &path,
+            format_bytes(peak_rss)
+        );
+    }
+    report
+}
+
+/// Process the run folder at `path_str` as with `process_path()` unless `state` shows that it is
+/// unchanged since it was last processed successfully with the server, and record the outcome in
+/// `state`.
+fn process_path_with_state(
+    logger: &slog::Logger,
+    path_str: &str,
+    client: &mut api::Client,
+    server_settings: &Settings,
+    settings: &Settings,
+    state: &mut IngestState,
+) -> FolderReport {
+    // Folders that cannot be listed fail in `process_path()` with a proper error.
+    let fingerprint = match Fingerprint::compute(Path::new(path_str)) {
+        Ok(fingerprint) => fingerprint,
+        Err(_) => return process_path(logger, path_str, client, server_settings, settings),
+    };
+    if let Some(folder_state) = state.get(&server_settings.web.url, path_str) {
+        if is_unchanged(folder_state, &fingerprint, settings) {
+            info!(
+                logger,
+                "Folder {} is unchanged since it was processed at {} (status {:?}), skipping",
+                path_str,
+                &folder_state.processed,
+                &folder_state.status_sequencing
+            );
+            let mut report = FolderReport::new(Path::new(path_str), Provenance::new(settings));
+            report.server = server_settings.web.url.clone();
+            report.success = true;
+            report.unchanged = true;
+            report.status_sequencing = Some(folder_state.status_sequencing.clone());
+            return report;
+        }
+    }
+    let report = process_path(logger, path_str, client, server_settings, settings);
+    state.record(&report, fingerprint, settings);
+    report
+}
+
+/// Load the state of the processed folders from `settings.ingest.state_file`, if configured.
+///
+/// The state is not used for dry runs and without network access, as nothing is sent then.
+fn load_state(logger: &slog::Logger, settings: &Settings) -> Result<Option<IngestState>> {
+    if settings.ingest.state_file.is_empty() {
+        Ok(None)
+    } else if settings.dry_run || settings.ingest.no_net {
+        warn!(
+            logger,
+            "Ignoring state file {} as nothing is sent to the server", &settings.ingest.state_file
+        );
+        Ok(None)
+    } else {
+        Ok(Some(IngestState::load(Path::new(
+            &settings.ingest.state_file,
+        ))?))
+    }
+}
+
+/// Log the summary of `reports` for each of the `servers` and the warnings, and write the
+/// JSON output if configured in `settings`.
+///
+/// Returns an error if processing any of the folders failed.
+fn summarize_reports(
+    logger: &slog::Logger,
+    servers: &[Settings],
+    reports: &[FolderReport],
+    settings: &Settings,
+) -> Result<()> {
+    // Print per-server summary.
+    for server_settings in servers {
+        let url = &server_settings.web.url;
+        let server_reports = reports
+            .iter()
+            .filter(|report| &report.server == url)
+            .collect::<Vec<&FolderReport>>();
+        let num_failed = server_reports
+            .iter()
+            .filter(|report| !report.success)
+            .count();
+        info!(
+            logger,
+            "Server {}: {} folder(s) succeeded, {} failed",
+            url,
+            server_reports.len() - num_failed,
+            num_failed
+        );
+    }
+
+    // Print summary of warnings.
+    for report in reports {
+        if !report.warnings.is_empty() {
+            warn!(
+                logger,
+                "{} warning(s) for folder {}:",
+                report.warnings.len(),
+                &report.path
+            );
+            for warning in &report.warnings {
+                warn!(logger, "  {}", warning);
+            }
+        }
+    }
+
+    if !settings.ingest.json_output.is_empty() {
+        info!(
+            logger,
+            "Writing JSON output to {}", &settings.ingest.json_output
+        );
+        let mut anonymizer = Anonymizer::new(settings);
+        for report in reports {
+            anonymizer.add_run_folder(logger, Path::new(&report.path), settings);
+            for payload in &report.payloads {
+                anonymizer.add_payload(&payload.payload);
+            }
+        }
+        write_json(
+            Path::new(&settings.ingest.json_output),
+            reports,
+            &anonymizer,
+        )?;
+    }
+
+    let num_failed = reports.iter().filter(|report| !report.success).count();
+    if num_failed > 0 {
+        bail!("Processing of at {} folders failed!", num_failed)
+    } else {
+        Ok(())
+    }
+}
+
 /// Main entry point for the `ingest` command.
 ///
 /// The function will skip folders for which errors occured but only return `Ok(())` if processing
@@ -485,46 +2126,123 @@ pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
     env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
 
     // Bail out in case of missing project UUID.
-    if settings.ingest.project_uuid.is_empty() {
+    if settings.ingest.project_uuid.is_nil() {
         bail!("You have to specify the project UUID");
     }
 
+    // Bail out on unknown configured folder layouts before processing any folder, all entries
+    // are parsed for a path that none of them is given for.
+    configured_folder_layout(Path::new(""), settings)?;
+
+    if settings.ingest.no_net {
+        if settings.ingest.flowcell_json.is_empty() {
+            bail!("You have to specify the flow cell with --flowcell-json for --no-net");
+        }
+        if settings.ingest.json_output.is_empty() {
+            warn!(
+                logger,
+                "Without --json-output, the payloads that would be sent are only logged"
+            );
+        }
+    }
+
     // Setting number of threads to use in Rayon.
     debug!(logger, "Using {} threads", settings.threads);
     env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
 
-    // Create shared client.
-    let mut client = RestClient::new(&settings.web.url).unwrap();
-    client
-        .set_header("Authorization", &format!("Token {}", &settings.web.token))
-        .chain_err(|| "Problem configuring REST client")?;
+    // Each folder is processed with each of the servers, each with its own settings.
+    let servers = select_servers(logger, settings)?;
 
-    let num_failed = settings
-        .ingest
-        .path
-        .iter()
-        .map(|ref path| {
-            let path = Path::new(path);
-            match process_folder(logger, &path, &mut client, settings) {
-                Err(e) => {
-                    warn!(
+    // Load the list of folders that failed permanently before.
+    let skip_list_path = Path::new(&settings.ingest.skip_list);
+    let mut skip_list = if settings.ingest.skip_list.is_empty() {
+        SkipList::default()
+    } else {
+        SkipList::load(skip_list_path)?
+    };
+    // Load the state of the folders processed before, for skipping unchanged ones.
+    let mut state = load_state(logger, settings)?;
+
+    // Process the oldest runs first, such that limited batches make steady progress.
+    let paths = order_by_run_date(logger, &settings.ingest.path, &settings.ingest.date_format);
+
+    let mut reports = Vec::new();
+    for server_settings in &servers {
+        info!(
+            logger,
+            "Processing folders with server {}", &server_settings.web.url
+        );
+        let mut client = build_server_client(logger, server_settings, settings)?;
+        // Processing folders whose payloads are still spooled would send them again.
+        let spooled = replay_spool(logger, server_settings, settings)?;
+        let mut num_processed = 0;
+        for path_str in &paths {
+            if settings.ingest.max_folders > 0 && num_processed >= settings.ingest.max_folders {
+                info!(
                     logger,
-                    "Processing folder {:?} failed. Will go on with other paths but the program \
-                     call will not have return code 0!: {:?}",
-                    &path,
-                    &e
+                    "Processed the maximal number of {} folder(s), leaving the remaining ones \
+                     for the next call",
+                    settings.ingest.max_folders
                 );
-                    true // == any failed
+                break;
+            }
+            if let Some(skipped) = skip_list.get(path_str) {
+                if settings.ingest.retry_skipped {
+                    info!(logger, "Retrying skipped folder {}", path_str);
+                } else {
+                    info!(
+                        logger,
+                        "Skipping folder {} (failed permanently since {}: {}), use \
+                         --retry-skipped for processing it again",
+                        path_str,
+                        &skipped.since,
+                        &skipped.reason
+                    );
+                    continue;
                 }
-                _ => false, // == any failed
             }
-        })
-        .filter(|failed| *failed)
-        .count();
+            if spooled.contains(path_str) {
+                info!(
+                    logger,
+                    "Skipping folder {} as payloads for it are still spooled for {}",
+                    path_str,
+                    &server_settings.web.url
+                );
+                continue;
+            }
+            let report = match state.as_mut() {
+                Some(state) => process_path_with_state(
+                    logger,
+                    path_str,
+                    &mut client,
+                    server_settings,
+                    settings,
+                    state,
+                ),
+                None => process_path(logger, path_str, &mut client, server_settings, settings),
+            };
+            if report.permanent_failure {
+                skip_list.add(path_str, report.error.as_deref().unwrap_or(""));
+            }
+            if report.modified || !report.success {
+                num_processed += 1;
+            }
+            reports.push(report);
+        }
+    }
 
-    if num_failed > 0 {
-        bail!("Processing of at {} folders failed!", num_failed)
-    } else {
-        Ok(())
+    // Folders that succeeded with any server are removed from the skip list.
+    if !settings.ingest.skip_list.is_empty() {
+        for report in reports.iter().filter(|report| report.success) {
+            if skip_list.remove(&report.path) {
+                info!(logger, "Removed folder {} from skip list", &report.path);
+            }
+        }
+        skip_list.save(skip_list_path)?;
     }
+    if let Some(state) = state.as_ref() {
+        state.save(Path::new(&settings.ingest.state_file))?;
+    }
+
+    summarize_reports(logger, &servers, &reports, settings)
 }
@@ -0,0 +1,224 @@
+//! Meta data of Oxford Nanopore MinKNOW run directories.
+//!
+//! MinKNOW writes a `final_summary_*.txt` file of `key=value` lines and a `report_*.json` file
+//! at the end of a run.  These are mapped onto the `RunInfo` and `RunParameters` of Illumina run
+//! folders such that the flow cells are registered and updated in the same way.  Nanopore runs
+//! have no cycles, so the run has no reads and no index histograms are computed.
+
+use chrono::DateTime;
+use glob::glob;
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use super::super::errors::*;
+use ingest::bcl_meta::{meta_file_exists, RunInfo, RunParameters};
+use ingest::warnings::{Warning, WarningKind};
+
+/// Pattern of the final summary file, relative to the run directory.
+pub const FINAL_SUMMARY_PATTERN: &str = "final_summary_*.txt";
+/// Pattern of the JSON report file, relative to the run directory.
+pub const REPORT_PATTERN: &str = "report_*.json";
+
+/// The run information of a MinKNOW run directory.
+#[derive(Debug, Clone, Default)]
+pub struct NanoporeRun {
+    /// The serial number of the device or host, e.g., `MN12345` or `PC24B123`.
+    pub instrument: String,
+    /// The position of the flow cell on the device, e.g., `1A` or `MN12345`.
+    pub position: String,
+    /// The flow cell ID, e.g., `FAQ12345`.
+    pub flow_cell_id: String,
+    /// The protocol run ID.
+    pub protocol_run_id: String,
+    /// The experiment name given in MinKNOW (the protocol group ID).
+    pub experiment_name: String,
+    /// The start time of the run in RFC 3339 format.
+    pub started: String,
+    /// Whether the run has been stopped, i.e., the final summary has been written.
+    pub stopped: bool,
+    /// Whether the reads were basecalled on the instrument.
+    pub basecalling: bool,
+    /// The MinKNOW version, e.g., `23.04.5`.
+    pub minknow_version: String,
+}
+
+/// Return the first path matching the glob `pattern` below the run directory at `path`.
+fn find_file(path: &Path, pattern: &str) -> Option<PathBuf> {
+    glob(path.join(pattern).to_str().unwrap())
+        .expect("Failed to read glob pattern")
+        .filter_map(|path| path.ok())
+        .next()
+}
+
+/// Return whether the directory at `path` is a MinKNOW run directory.
+pub fn is_nanopore_folder(path: &Path) -> bool {
+    !meta_file_exists(path, "RunInfo.xml")
+        && (find_file(path, FINAL_SUMMARY_PATTERN).is_some()
+            || find_file(path, REPORT_PATTERN).is_some())
+}
+
+/// Return whether the MinKNOW run at `path` has been stopped, i.e., the final summary exists.
+pub fn is_complete(path: &Path) -> bool {
+    !meta_file_exists(path, "RunInfo.xml") && find_file(path, FINAL_SUMMARY_PATTERN).is_some()
+}
+
+/// Return the path of the file to take the run information from, the final summary if present.
+pub fn run_info_file(path: &Path) -> Option<PathBuf> {
+    find_file(path, FINAL_SUMMARY_PATTERN).or_else(|| find_file(path, REPORT_PATTERN))
+}
+
+/// Read the `key=value` lines of the final summary file at `path`.
+fn read_final_summary(path: &Path) -> Result<HashMap<String, String>> {
+    let file = File::open(path).chain_err(|| format!("Problem reading {:?}", path))?;
+    let mut result = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.chain_err(|| format!("Problem reading {:?}", path))?;
+        let mut fields = line.splitn(2, '=');
+        if let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+            result.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    Ok(result)
+}
+
+/// Return the string at the JSON `pointer` of `report`, empty if missing.
+fn report_value(report: &Value, pointer: &str) -> String {
+    report
+        .pointer(pointer)
+        .and_then(|value| value.as_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Read the run information of the MinKNOW run directory at `path` from the final summary,
+/// falling back to the JSON report for missing values.
+pub fn read_run(path: &Path) -> Result<NanoporeRun> {
+    let mut result = NanoporeRun::default();
+    if let Some(summary_path) = find_file(path, FINAL_SUMMARY_PATTERN) {
+        let summary = read_final_summary(&summary_path)?;
+        let get = |key: &str| summary.get(key).cloned().unwrap_or_default();
+        result.instrument = get("instrument");
+        result.position = get("position");
+        result.flow_cell_id = get("flow_cell_id");
+        result.protocol_run_id = get("protocol_run_id");
+        result.experiment_name = get("protocol_group_id");
+        result.started = get("started");
+        result.stopped = true;
+        result.basecalling = get("basecalling_enabled") == "1";
+    }
+
+    if let Some(report_path) = find_file(path, REPORT_PATTERN) {
+        let file =
+            File::open(&report_path).chain_err(|| format!("Problem reading {:?}", &report_path))?;
+        let report: Value = serde_json::from_reader(file)
+            .chain_err(|| format!("Problem parsing JSON from {:?}", &report_path))?;
+        let fallbacks = [
+            (&mut result.instrument, "/host/serial"),
+            (&mut result.position, "/protocol_run_info/device/device_id"),
+            (
+                &mut result.flow_cell_id,
+                "/protocol_run_info/flow_cell/flow_cell_id",
+            ),
+            (&mut result.protocol_run_id, "/protocol_run_info/run_id"),
+            (
+                &mut result.experiment_name,
+                "/protocol_run_info/user_info/protocol_group_id",
+            ),
+            (&mut result.started, "/protocol_run_info/start_time"),
+            (
+                &mut result.minknow_version,
+                "/software_versions/minknow/full",
+            ),
+        ];
+        for (value, pointer) in fallbacks {
+            if value.is_empty() {
+                *value = report_value(&report, pointer);
+            }
+        }
+        if result.instrument.is_empty() {
+            // MinION devices are their own instrument.
+            result.instrument = result.position.clone();
+        }
+    }
+
+    if result.flow_cell_id.is_empty() || result.instrument.is_empty() {
+        bail!(
+            "Could not get flow cell ID and instrument of MinKNOW run {:?}",
+            path
+        );
+    }
+    Ok(result)
+}
+
+/// Convert the MinKNOW run information `run` into run info and run parameters.
+///
+/// As flow cells can be reused after washing but MinKNOW has no run counter, the run number is
+/// always 0.  The flow cell position is used as the slot and the MinKNOW version as the RTA
+/// version.
+pub fn process_run(
+    run: &NanoporeRun,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
+    let date = DateTime::parse_from_rfc3339(&run.started)
+        .map(|date| date.format("%F").to_string())
+        .chain_err(|| format!("Could not parse date from string {}", &run.started))?;
+
+    let major = run.minknow_version.split('.').next().unwrap_or("");
+    let rta_version = if major.parse::<i32>().is_ok() {
+        run.minknow_version.clone()
+    } else {
+        warnings.push(Warning::new(
+            WarningKind::LayoutQuirk,
+            format!("Could not parse MinKNOW version {:?}", &run.minknow_version),
+        ));
+        "0".to_string()
+    };
+
+    let run_info = RunInfo {
+        run_id: run.protocol_run_id.clone(),
+        run_number: 0,
+        flowcell: run.flow_cell_id.clone(),
+        instrument: run.instrument.clone(),
+        date,
+        lane_count: 1,
+        reads: Vec::new(),
+        version: 0,
+        geometry: None,
+    };
+    let run_params = RunParameters {
+        planned_reads: Vec::new(),
+        rta_version,
+        run_number: 0,
+        flowcell_slot: run.position.clone(),
+        experiment_name: run.experiment_name.clone(),
+        instrument_model: None,
+    };
+    Ok((run_info, run_params))
+}
+
+/// Read and convert the run information of the MinKNOW run directory at `path`.
+///
+/// Quirks that had to be worked around are added to `warnings`.
+pub fn parse_run_folder(
+    path: &Path,
+    warnings: &mut Vec<Warning>,
+) -> Result<(RunInfo, RunParameters)> {
+    process_run(&read_run(path)?, warnings)
+}
+
+/// Return the conversion status and delivery type of flow cells for the MinKNOW run at `path`.
+///
+/// With basecalling on the instrument, the conversion follows the sequencing and the FASTQ
+/// files are delivered; otherwise, the raw signal is delivered and conversion has not started.
+pub fn conversion_and_delivery(path: &Path) -> (String, String) {
+    let (conversion, delivery) = match read_run(path) {
+        Ok(ref run) if run.basecalling && run.stopped => ("complete", "seq"),
+        Ok(ref run) if run.basecalling => ("in_progress", "seq"),
+        _ => ("initial", "bcl"),
+    };
+    (conversion.to_string(), delivery.to_string())
+}
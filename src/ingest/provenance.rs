@@ -0,0 +1,160 @@
+//! Provenance information describing how the data posted for a flow cell was produced.
+
+use chrono::Local;
+use hostname;
+use std::env;
+use std::fmt;
+
+use settings::Settings;
+
+/// Command line options whose values are replaced by `REDACTED` in the recorded command line.
+const SECRET_OPTIONS: &[&str] = &["--token", "--web-token", "--password"];
+/// Substrings of `key=value` keys whose values are replaced by `REDACTED`.
+const SECRET_KEYS: &[&str] = &["token", "password", "secret"];
+/// The replacement for secret values.
+const REDACTED: &str = "REDACTED";
+
+/// The parameters used for sampling the index reads.
+#[derive(Debug, Clone, Serialize)]
+pub struct SamplingParams {
+    /// Number of tiles sampled per lane.
+    pub sample_tiles: i32,
+    /// Number of reads sampled per tile.
+    pub sample_reads_per_tile: i32,
+    /// Fraction of each lane's clusters sampled, if positive.
+    pub sample_fraction: f64,
+    /// Fraction of each lane's tiles sampled, if positive.
+    pub tile_fraction: f64,
+    /// Minimum fraction for an index to be included in the histogram.
+    pub min_index_fraction: f64,
+    /// Maximal number of cycles sampled for each index read, all if missing or not positive.
+    pub max_index_cycles: Vec<i32>,
+    /// Seed of the random number generator.
+    pub seed: u64,
+}
+
+/// Provenance of one run of processing a flow cell folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    /// Version of the CLI client.
+    pub version: String,
+    /// Name of the host the client was run on.
+    pub hostname: String,
+    /// The command line with secrets redacted.
+    pub command_line: String,
+    /// Time processing the folder started at.
+    pub started_at: String,
+    /// Time processing the folder finished at, if it did.
+    pub finished_at: Option<String>,
+    /// The sampling parameters.
+    pub sampling: SamplingParams,
+}
+
+impl Provenance {
+    /// Construct with the current time as start time.
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname: hostname::get()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            command_line: redact_args(env::args()).join(" "),
+            started_at: Local::now().to_rfc3339(),
+            finished_at: None,
+            sampling: SamplingParams {
+                sample_tiles: settings.ingest.sample_tiles,
+                sample_reads_per_tile: settings.ingest.sample_reads_per_tile,
+                sample_fraction: settings.ingest.sample_fraction,
+                tile_fraction: settings.ingest.tile_fraction,
+                min_index_fraction: settings.ingest.min_index_fraction,
+                max_index_cycles: settings.ingest.max_index_cycles.clone(),
+                seed: settings.seed,
+            },
+        }
+    }
+
+    /// Set the finishing time to the current time.
+    pub fn finish(&mut self) {
+        self.finished_at = Some(Local::now().to_rfc3339());
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "- digestiflow-cli version: {}", self.version)?;
+        writeln!(f, "- host: {}", self.hostname)?;
+        writeln!(f, "- command line: {}", self.command_line)?;
+        writeln!(f, "- started: {}", self.started_at)?;
+        if let Some(finished_at) = &self.finished_at {
+            writeln!(f, "- finished: {}", finished_at)?;
+        }
+        let reads = if self.sampling.sample_fraction > 0.0 {
+            format!(
+                "{}% of lane clusters",
+                100.0 * self.sampling.sample_fraction
+            )
+        } else {
+            format!("{} reads per tile", self.sampling.sample_reads_per_tile)
+        };
+        let tiles = if self.sampling.tile_fraction > 0.0 {
+            format!("{}% of tiles", 100.0 * self.sampling.tile_fraction)
+        } else {
+            format!("{} tile(s)", self.sampling.sample_tiles)
+        };
+        write!(
+            f,
+            "- sampling: {}, {}, min. index fraction {}, seed {}",
+            tiles, reads, self.sampling.min_index_fraction, self.sampling.seed
+        )?;
+        if !self.sampling.max_index_cycles.is_empty() {
+            write!(
+                f,
+                ", max. index cycles {:?}",
+                self.sampling.max_index_cycles
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Redact a single argument, e.g., `token=...` or credentials in URLs.
+fn redact_arg(arg: &str) -> String {
+    if let Some(pos) = arg.find('=') {
+        let key = arg[..pos].to_lowercase();
+        if SECRET_KEYS.iter().any(|secret| key.contains(secret)) {
+            return format!("{}={}", &arg[..pos], REDACTED);
+        }
+    }
+    if let Some(scheme_end) = arg.find("://") {
+        let rest = &arg[(scheme_end + 3)..];
+        let host_start = rest.find('/').unwrap_or(rest.len());
+        if let Some(at) = rest[..host_start].rfind('@') {
+            return format!(
+                "{}{}@{}",
+                &arg[..(scheme_end + 3)],
+                REDACTED,
+                &rest[(at + 1)..]
+            );
+        }
+    }
+    arg.to_string()
+}
+
+/// Return the command line `args` with secrets redacted.
+fn redact_args<I>(args: I) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut result = Vec::new();
+    let mut redact_next = false;
+    for arg in args {
+        if redact_next {
+            result.push(REDACTED.to_string());
+            redact_next = false;
+        } else {
+            redact_next = SECRET_OPTIONS.contains(&arg.as_str());
+            result.push(redact_arg(&arg));
+        }
+    }
+    result
+}
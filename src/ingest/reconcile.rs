@@ -0,0 +1,220 @@
+//! Implementation of the `reconcile` command that checks the flow cells of a project against
+//! their run folders, e.g., after server migrations or bug fixes in the client.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::folder_cache::*;
+use super::*;
+
+/// Key for matching flow cells to run folders: instrument, run number, and vendor ID.
+type RunKey = (String, i32, String);
+
+/// Find the run folders directly below the directories `roots`, by instrument, run number, and
+/// vendor ID, together with their layout if detected.
+///
+/// With `settings.reconcile.folder_cache`, only the folders changed since the last call are
+/// examined.
+fn find_run_folders(
+    logger: &slog::Logger,
+    roots: &[String],
+    settings: &Settings,
+) -> Result<HashMap<RunKey, (PathBuf, Option<FolderLayout>)>> {
+    let cache_path = Path::new(&settings.reconcile.folder_cache);
+    let cache = if settings.reconcile.folder_cache.is_empty() {
+        FolderCache::default()
+    } else {
+        FolderCache::load(cache_path)?
+    };
+    let rules = layout_rules(&settings.configured_layout_rules());
+    let cache = cache.scan(logger, roots, &rules, &settings.ingest.date_format);
+    if !settings.reconcile.folder_cache.is_empty() {
+        cache.save(cache_path)?;
+    }
+
+    Ok(cache
+        .run_folders()
+        .into_iter()
+        .map(|(path, run, layout)| {
+            let key = (run.instrument.clone(), run.run_number, run.flowcell.clone());
+            (key, (path, layout))
+        })
+        .collect())
+}
+
+/// Return the discrepancies between `flowcell` in Digestiflow Web and its run folder at `path`.
+fn check_flowcell(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    flowcell: &api::FlowCell,
+    path: &Path,
+    folder_layout: Option<FolderLayout>,
+    settings: &Settings,
+) -> Result<Vec<String>> {
+    let folder_layout = match folder_layout {
+        Some(folder_layout) => folder_layout,
+        None => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules()))
+            .chain_err(|| format!("Could not guess folder layout from {:?}", path))?,
+    };
+    let (run_info, run_params) = parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut Vec::new(),
+    )?;
+    let expected = build_flow_cell(
+        &run_info,
+        &run_params,
+        path,
+        Some(flowcell.status_sequencing.clone()),
+        settings,
+    );
+
+    let mut result = Vec::new();
+    if flowcell.status_sequencing != expected.status_sequencing {
+        result.push(format!(
+            "sequencing status is {:?} but should be {:?}",
+            &flowcell.status_sequencing, &expected.status_sequencing
+        ));
+    }
+    if flowcell.planned_reads != expected.planned_reads {
+        result.push(format!(
+            "planned reads are {:?} but should be {:?}",
+            &flowcell.planned_reads, &expected.planned_reads
+        ));
+    }
+    if flowcell.current_reads != expected.current_reads {
+        result.push(format!(
+            "current reads are {:?} but should be {:?}",
+            &flowcell.current_reads, &expected.current_reads
+        ));
+    }
+
+    if settings.ingest.analyze_adapters && has_index_reads(&run_info) {
+        let hist_arr: api::LaneIndexHistogramArray = client
+            .get(&api::ProjectFlowcellArgs {
+                project_uuid: settings.ingest.project_uuid,
+                flowcell_uuid: flowcell.sodar_uuid.unwrap_or_default(),
+            })
+            .chain_err(|| "Could not query index histograms from server")?;
+        let num_hists = match &hist_arr {
+            api::LaneIndexHistogramArray::Array(hists) => hists.len(),
+        };
+        let expected_hists = expected_histograms(&expected, settings);
+        if num_hists < expected_hists {
+            result.push(format!(
+                "{} of {} index histograms are missing",
+                expected_hists - num_hists,
+                expected_hists
+            ));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Main entry point for the `reconcile` command.
+///
+/// Lists all flow cells of the project, matches them to the run folders below the configured
+/// roots, and prints the discrepancies found.  With `settings.reconcile.fix`, the folders of
+/// flow cells with discrepancies are processed as with `ingest`, updating flow cells regardless
+/// of their sequencing status.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client reconcile");
+    info!(logger, "Options: {:?}", settings);
+    env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.reconcile.roots.is_empty() {
+        bail!("You have to specify at least one run folder root");
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let api::FlowCellArray::Array(flowcells) = client
+        .get(&api::ProjectArgs {
+            project_uuid: settings.ingest.project_uuid,
+        })
+        .chain_err(|| "Could not list flow cells of project")?;
+    info!(logger, "Found {} flow cells in project", flowcells.len());
+    let folders = find_run_folders(logger, &settings.reconcile.roots, settings)?;
+    info!(logger, "Found {} run folders", folders.len());
+
+    // Fixes are applied by processing the folder as `ingest` does.
+    let mut fix_settings = settings.clone();
+    fix_settings.ingest.skip_if_status_final = false;
+
+    let mut num_discrepant = 0;
+    let mut num_failed = 0;
+    for flowcell in &flowcells {
+        let key = (
+            flowcell.sequencing_machine.clone(),
+            flowcell.run_number,
+            flowcell.vendor_id.clone(),
+        );
+        let (path, folder_layout) = match folders.get(&key) {
+            Some((path, folder_layout)) => (path, *folder_layout),
+            None => {
+                println!("{}: no run folder found", &flowcell.vendor_id);
+                num_discrepant += 1;
+                continue;
+            }
+        };
+
+        let checked = check_flowcell(logger, &mut client, flowcell, path, folder_layout, settings);
+        let discrepancies = match checked {
+            Ok(discrepancies) => discrepancies,
+            Err(e) => {
+                println!(
+                    "{} ({}): check failed: {}",
+                    &flowcell.vendor_id,
+                    path.display(),
+                    e
+                );
+                num_failed += 1;
+                continue;
+            }
+        };
+        if discrepancies.is_empty() {
+            println!("{} ({}): OK", &flowcell.vendor_id, path.display());
+            continue;
+        }
+        num_discrepant += 1;
+        println!("{} ({}):", &flowcell.vendor_id, path.display());
+        for discrepancy in &discrepancies {
+            println!("  - {}", discrepancy);
+        }
+
+        if settings.reconcile.fix {
+            info!(logger, "Fixing flow cell {}...", &flowcell.vendor_id);
+            let mut report = FolderReport::new(path, Provenance::new(settings));
+            client.set_archive_folder(&path.to_string_lossy());
+            if let Err(e) = process_folder(logger, path, &mut client, &fix_settings, &mut report) {
+                warn!(
+                    logger,
+                    "Fixing flow cell {} failed: {:?}", &flowcell.vendor_id, &e
+                );
+                num_failed += 1;
+            }
+        }
+    }
+
+    info!(
+        logger,
+        "{} of {} flow cell(s) with discrepancies",
+        num_discrepant,
+        flowcells.len()
+    );
+    if num_failed > 0 {
+        bail!("Checking or fixing of {} flow cell(s) failed!", num_failed)
+    } else {
+        Ok(())
+    }
+}
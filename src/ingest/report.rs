@@ -0,0 +1,148 @@
+//! Per-folder processing results for the summary and the JSON output.
+
+use serde_json;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use uuid::Uuid;
+
+use super::super::errors::*;
+use anonymize::Anonymizer;
+use ingest::archive::ArchivedPayload;
+use ingest::base_composition::BaseComposition;
+use ingest::bcl_data::{IoStats, QValBinInfo};
+use ingest::bcl_meta::{FolderLayout, InstrumentModel};
+use ingest::folder_size::FolderSize;
+use ingest::interop::LaneQuality;
+use ingest::provenance::Provenance;
+use ingest::warnings::Warning;
+
+/// Statistics from sampling one index read of one lane.
+#[derive(Debug, Serialize)]
+pub struct LaneIndexStats {
+    /// The number of the lane.
+    pub lane: i32,
+    /// The number of the index read.
+    pub index_read_no: i32,
+    /// The number of sampled reads.
+    pub sample_size: usize,
+    /// The total number of clusters in the lane, if known.
+    pub total_clusters: Option<u64>,
+    /// The fraction of the lane's clusters that was sampled, if known.
+    pub coverage: Option<f64>,
+    /// Fraction of N calls in the sampled index read bases.
+    pub n_fraction: f64,
+    /// The most frequent index sequence, if any.
+    pub top_index: Option<String>,
+    /// The fraction of sampled reads showing `top_index`, if any.
+    pub top_index_fraction: Option<f64>,
+    /// I/O statistics, if sampled from base call files.
+    pub io: Option<IoStats>,
+    /// The number of sampled clusters per second of wall-clock time, if known.
+    pub clusters_per_second: Option<f64>,
+}
+
+/// The outcome of processing one flow cell folder.
+#[derive(Debug, Serialize)]
+pub struct FolderReport {
+    /// Path to the folder.
+    pub path: String,
+    /// URL of the Digestiflow Web server the folder was processed with.
+    pub server: String,
+    /// Whether or not processing the folder succeeded.
+    pub success: bool,
+    /// Error message in case processing failed.
+    pub error: Option<String>,
+    /// Whether or not the flow cell or its index histograms were modified via the API.
+    pub modified: bool,
+    /// Whether processing was skipped as the folder is unchanged since it was last processed.
+    pub unchanged: bool,
+    /// The holder of the lock on the folder, if processing was skipped as another call holds
+    /// it.
+    pub locked_by: Option<String>,
+    /// Whether processing failed for a reason that retrying will not fix, e.g., an unknown
+    /// folder layout or broken XML files.
+    pub permanent_failure: bool,
+    /// The detected folder layout, if any.
+    pub folder_layout: Option<FolderLayout>,
+    /// The instrument model, for layouts shared by several models.
+    pub instrument_model: Option<InstrumentModel>,
+    /// The flow cell vendor ID from `RunInfo.xml`.
+    pub vendor_id: Option<String>,
+    /// The UUID of the flow cell in Digestiflow Web.
+    pub flowcell_uuid: Option<Uuid>,
+    /// The sequencing status of the flow cell in Digestiflow Web after processing.
+    pub status_sequencing: Option<String>,
+    /// The size and file counts of the folder, if computed.
+    pub folder_size: Option<FolderSize>,
+    /// The quality binning table from the CBCL files, if any.
+    pub quality_bins: Option<Vec<QValBinInfo>>,
+    /// Whether the run has no index reads and thus no adapters were analyzed.
+    pub no_index_reads: bool,
+    /// Whether adapter analysis was postponed as the copy-complete marker file is missing.
+    pub adapters_postponed: bool,
+    /// Statistics for each sampled lane and index read.
+    pub lane_index_stats: Vec<LaneIndexStats>,
+    /// The base composition of the first template cycles of each lane, if sampled.
+    pub base_composition: Vec<BaseComposition>,
+    /// The quality indicators of each lane from the InterOp metrics, if checked.
+    pub lane_quality: Vec<LaneQuality>,
+    /// Warnings collected while processing the folder.
+    pub warnings: Vec<Warning>,
+    /// The number of API calls made for the folder.
+    pub api_calls: usize,
+    /// The total duration of the API calls made for the folder, in seconds.
+    pub api_seconds: f64,
+    /// The number of payloads for the folder spooled as the server could not be reached.
+    pub api_spooled: usize,
+    /// Peak resident set size of the process after processing the folder, in bytes, if known.
+    pub peak_rss_bytes: Option<u64>,
+    /// The payloads that would have been sent without network access.
+    pub payloads: Vec<ArchivedPayload>,
+    /// Provenance of the processing.
+    pub provenance: Provenance,
+}
+
+impl FolderReport {
+    /// Construct new, empty report for the given `path` with the given `provenance`.
+    pub fn new(path: &Path, provenance: Provenance) -> Self {
+        Self {
+            path: path.to_string_lossy().to_string(),
+            server: String::new(),
+            success: false,
+            error: None,
+            modified: false,
+            unchanged: false,
+            locked_by: None,
+            permanent_failure: false,
+            folder_layout: None,
+            instrument_model: None,
+            vendor_id: None,
+            flowcell_uuid: None,
+            status_sequencing: None,
+            folder_size: None,
+            quality_bins: None,
+            no_index_reads: false,
+            adapters_postponed: false,
+            lane_index_stats: Vec::new(),
+            base_composition: Vec::new(),
+            lane_quality: Vec::new(),
+            warnings: Vec::new(),
+            api_calls: 0,
+            api_seconds: 0.0,
+            api_spooled: 0,
+            peak_rss_bytes: None,
+            payloads: Vec::new(),
+            provenance,
+        }
+    }
+}
+
+/// Write `reports` as a JSON array to the file at `path`, anonymized with `anonymizer`.
+pub fn write_json(path: &Path, reports: &[FolderReport], anonymizer: &Anonymizer) -> Result<()> {
+    let json = serde_json::to_string_pretty(reports).chain_err(|| "Problem serializing to JSON")?;
+    let mut file = File::create(path).chain_err(|| format!("Problem creating {:?}", path))?;
+    file.write_all(anonymizer.apply(&json).as_bytes())
+        .chain_err(|| format!("Problem writing JSON to {:?}", path))?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Minimal reading of Illumina `SampleSheet.csv` files and AVITI `RunManifest.csv` files.
+//!
+//! Writing sample sheets is implemented in `samplesheet_cmd`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+
+/// The sections of a sample sheet that are used.
+#[derive(Debug, Clone, Default)]
+pub struct SampleSheet {
+    /// The non-empty `(key, value)` pairs from the `[Header]` section, keys are in lower case.
+    pub header: Vec<(String, String)>,
+    /// The rows of the `[Data]` section (`[Samples]` for run manifests), keyed by the lower case
+    /// column names.
+    pub data: Vec<HashMap<String, String>>,
+}
+
+impl SampleSheet {
+    /// Read the `SampleSheet.csv` file (or the `RunManifest.csv` file of AVITI runs) from the
+    /// run folder at `path`, if any.
+    pub fn from_folder(path: &Path) -> Option<Self> {
+        File::open(path.join("SampleSheet.csv"))
+            .or_else(|_| File::open(path.join("RunManifest.csv")))
+            .ok()
+            .map(|file| Self::from_reader(BufReader::new(file)))
+    }
+
+    /// Read the sample sheet from `reader`.
+    pub fn from_reader<R: BufRead>(reader: R) -> Self {
+        let mut result = Self::default();
+        let mut section = String::new();
+        let mut columns: Vec<String> = Vec::new();
+        for line in reader.lines().map_while(|line| line.ok()) {
+            let line = line.trim_start_matches('\u{feff}').trim();
+            if line.starts_with('[') {
+                section = line
+                    .trim_end_matches(',')
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .to_lowercase();
+                columns.clear();
+                continue;
+            }
+            let fields = line.split(',').map(|s| s.trim()).collect::<Vec<&str>>();
+            if fields.iter().all(|field| field.is_empty()) {
+                continue;
+            }
+            match section.as_str() {
+                "header" => {
+                    let value = fields[1..]
+                        .iter()
+                        .filter(|field| !field.is_empty())
+                        .cloned()
+                        .collect::<Vec<&str>>()
+                        .join(",");
+                    if !fields[0].is_empty() && !value.is_empty() {
+                        result.header.push((fields[0].to_lowercase(), value));
+                    }
+                }
+                "data" | "samples" => {
+                    if columns.is_empty() {
+                        columns = fields.iter().map(|field| field.to_lowercase()).collect();
+                    } else {
+                        result.data.push(
+                            columns
+                                .iter()
+                                .cloned()
+                                .zip(fields.iter().map(|field| field.to_string()))
+                                .collect(),
+                        );
+                    }
+                }
+                _ => (),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_reader_sample_sheet() {
+        let contents = "\u{feff}[Header],,\r\n\
+                        IEMFileVersion,4,\r\n\
+                        Experiment Name,Run 42,\r\n\
+                        Description,,\r\n\
+                        ,,\r\n\
+                        [Reads],,\r\n\
+                        151,,\r\n\
+                        [Data],,\r\n\
+                        Lane,Sample_ID,index,index2\r\n\
+                        1,S1,ACGTACGT,AACCGGTT\r\n\
+                        2,S2,TTGGCCAA,\r\n";
+        let sample_sheet = SampleSheet::from_reader(contents.as_bytes());
+        assert_eq!(
+            sample_sheet.header,
+            vec![
+                ("iemfileversion".to_string(), "4".to_string()),
+                ("experiment name".to_string(), "Run 42".to_string()),
+            ]
+        );
+        assert_eq!(sample_sheet.data.len(), 2);
+        assert_eq!(sample_sheet.data[0]["sample_id"], "S1");
+        assert_eq!(sample_sheet.data[0]["index2"], "AACCGGTT");
+        assert_eq!(sample_sheet.data[1]["lane"], "2");
+        assert_eq!(sample_sheet.data[1]["index2"], "");
+    }
+
+    #[test]
+    fn from_reader_run_manifest() {
+        let contents = "[RUNVALUES]\nKeyName,Value\n\n[SAMPLES]\nSampleName,Index1,Index2,Lane\n\
+                        S1,ACGTACGT,AACCGGTT,1+2\n";
+        let sample_sheet = SampleSheet::from_reader(contents.as_bytes());
+        assert!(sample_sheet.header.is_empty());
+        assert_eq!(sample_sheet.data.len(), 1);
+        assert_eq!(sample_sheet.data[0]["samplename"], "S1");
+        assert_eq!(sample_sheet.data[0]["index1"], "ACGTACGT");
+    }
+}
@@ -0,0 +1,564 @@
+//! Implementation of the `samplesheet` command that writes a `SampleSheet.csv` from the libraries
+//! of a flow cell in Digestiflow Web, either classic (v1) for bcl2fastq or v2 for BCL Convert.
+//!
+//! Reading sample sheets is implemented in `sample_sheet`.
+
+use std::fs;
+
+use super::*;
+
+/// Return whether bcl2fastq expects the reverse complement of the i5 barcodes in the sample
+/// sheet for run folders with `folder_layout`.
+///
+/// The MiSeq and HiSeq 2000/2500 read the second index on the forward strand, the other
+/// instruments read its reverse complement ("reverse complement workflow").  The NovaSeq 6000
+/// only uses the forward strand with the v1.0 reagents, which are no longer sold.
+fn is_index2_reverse_complement(folder_layout: FolderLayout) -> Result<bool> {
+    match folder_layout {
+        FolderLayout::MiSeqDep | FolderLayout::MiSeq => Ok(false),
+        FolderLayout::MiniSeq
+        | FolderLayout::HiSeqX
+        | FolderLayout::HiSeq3000
+        | FolderLayout::NextSeq500
+        | FolderLayout::NovaSeq
+        | FolderLayout::NovaSeqXplus
+        | FolderLayout::NextSeq2000 => Ok(true),
+        FolderLayout::Aviti | FolderLayout::Mgi | FolderLayout::Nanopore => bail!(
+            "Sample sheets can only be written for Illumina run folders, not for {:?}",
+            folder_layout
+        ),
+    }
+}
+
+/// Return `name` with the characters that bcl2fastq does not allow in sample IDs replaced by
+/// `_`.
+fn sample_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Return the barcode `seq` in upper case and cut to the `cycles` of its index read.
+fn index_seq(seq: &str, cycles: i32) -> String {
+    seq.trim()
+        .to_uppercase()
+        .chars()
+        .take(cycles.max(0) as usize)
+        .collect()
+}
+
+/// Return the barcodes of `library` for the index reads with `index_cycles`, with the i5 barcode
+/// reverse complemented if `revcomp_index2` is set.
+fn library_indexes(
+    library: &api::Library,
+    index_cycles: &[i32],
+    revcomp_index2: bool,
+) -> Vec<String> {
+    let mut result = Vec::new();
+    if let Some(cycles) = index_cycles.first() {
+        result.push(index_seq(
+            library.barcode_seq.as_deref().unwrap_or(""),
+            *cycles,
+        ));
+    }
+    if let Some(cycles) = index_cycles.get(1) {
+        let seq = library.barcode_seq2.as_deref().unwrap_or("").trim();
+        if revcomp_index2 {
+            result.push(index_seq(&revcomp(&seq.to_uppercase()), *cycles));
+        } else {
+            result.push(index_seq(seq, *cycles));
+        }
+    }
+    result
+}
+
+/// Return the numbers of cycles of the index reads in `run_info`.
+fn index_cycles(run_info: &RunInfo) -> Vec<i32> {
+    run_info
+        .reads
+        .iter()
+        .filter(|desc| desc.is_index && desc.num_cycles > 0)
+        .map(|desc| desc.num_cycles)
+        .collect()
+}
+
+/// Return the `OverrideCycles` setting of BCL Convert for the reads in `run_info`, e.g.,
+/// `Y151;I6N2;I8;Y151`.
+///
+/// Index cycles beyond the longest barcode of the `libraries` are masked, index reads without
+/// barcodes completely.
+fn override_cycles(libraries: &[api::Library], run_info: &RunInfo) -> String {
+    let index_cycles = index_cycles(run_info);
+    let mut index_no = 0;
+    run_info
+        .reads
+        .iter()
+        .filter(|desc| desc.num_cycles > 0)
+        .map(|desc| {
+            if !desc.is_index {
+                return format!("Y{}", desc.num_cycles);
+            }
+            let used = libraries
+                .iter()
+                .filter_map(|library| {
+                    library_indexes(library, &index_cycles, false)
+                        .get(index_no)
+                        .map(|seq| seq.len() as i32)
+                })
+                .max()
+                .unwrap_or(0);
+            index_no += 1;
+            match (used, desc.num_cycles - used) {
+                (0, masked) => format!("N{}", masked),
+                (used, 0) => format!("I{}", used),
+                (used, masked) => format!("I{}N{}", used, masked),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// Return the name of the run for the sample sheet header, the label of `flowcell` or its
+/// vendor ID.
+fn run_name(flowcell: &api::FlowCell) -> &str {
+    flowcell
+        .label
+        .as_deref()
+        .filter(|label| !label.is_empty())
+        .unwrap_or(&flowcell.vendor_id)
+}
+
+/// Return the classic (v1) sample sheet for the `libraries` of `flowcell` sequenced with the reads
+/// in `run_info`, with the i5 barcodes reverse complemented if `revcomp_index2` is set.
+fn format_sample_sheet(
+    flowcell: &api::FlowCell,
+    run_info: &RunInfo,
+    revcomp_index2: bool,
+) -> String {
+    let index_cycles = index_cycles(run_info);
+    let with_lanes = flowcell
+        .libraries
+        .iter()
+        .any(|library| !library.lane_numbers.is_empty());
+
+    let mut lines = vec![
+        "[Header]".to_string(),
+        "IEMFileVersion,4".to_string(),
+        format!("Date,{}", &run_info.date),
+        "Workflow,GenerateFASTQ".to_string(),
+        "Application,FASTQ Only".to_string(),
+        format!("Experiment Name,{}", run_name(flowcell).replace(',', " ")),
+        format!(
+            "Description,Written by digestiflow-cli {}",
+            env!("CARGO_PKG_VERSION")
+        ),
+        String::new(),
+        "[Reads]".to_string(),
+    ];
+    lines.extend(
+        run_info
+            .reads
+            .iter()
+            .filter(|desc| !desc.is_index)
+            .map(|desc| desc.num_cycles.to_string()),
+    );
+    lines.extend(vec![
+        String::new(),
+        "[Settings]".to_string(),
+        String::new(),
+        "[Data]".to_string(),
+    ]);
+
+    let mut columns = vec!["Sample_ID", "Sample_Name", "Description"];
+    columns.extend(["index", "index2"].iter().take(index_cycles.len()));
+    if with_lanes {
+        columns.insert(0, "Lane");
+    }
+    lines.push(columns.join(","));
+    for library in &flowcell.libraries {
+        let mut fields = vec![
+            sample_id(&library.name),
+            library.name.replace(',', " "),
+            library
+                .reference
+                .clone()
+                .unwrap_or_default()
+                .replace(',', " "),
+        ];
+        fields.extend(library_indexes(library, &index_cycles, revcomp_index2));
+        if with_lanes {
+            for lane in &library.lane_numbers {
+                lines.push(format!("{},{}", lane, fields.join(",")));
+            }
+        } else {
+            lines.push(fields.join(","));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Return whether the second index read is marked as reverse complement in `run_info` (e.g., on
+/// the NovaSeq X), in which case BCL Convert reverse complements the i5 barcodes itself.
+fn is_index2_marked_reverse_complement(run_info: &RunInfo) -> bool {
+    run_info
+        .reads
+        .iter()
+        .filter(|desc| desc.is_index && desc.num_cycles > 0)
+        .nth(1)
+        .is_some_and(|desc| desc.is_reverse_complement)
+}
+
+/// Return the v2 sample sheet for BCL Convert for the `libraries` of `flowcell` sequenced with
+/// the reads in `run_info`, with the i5 barcodes reverse complemented if `revcomp_index2` is set.
+fn format_sample_sheet_v2(
+    flowcell: &api::FlowCell,
+    run_info: &RunInfo,
+    revcomp_index2: bool,
+) -> String {
+    let index_cycles = index_cycles(run_info);
+    let with_lanes = flowcell
+        .libraries
+        .iter()
+        .any(|library| !library.lane_numbers.is_empty());
+
+    let mut lines = vec![
+        "[Header]".to_string(),
+        "FileFormatVersion,2".to_string(),
+        format!("RunName,{}", sample_id(run_name(flowcell))),
+        format!(
+            "RunDescription,Written by digestiflow-cli {}",
+            env!("CARGO_PKG_VERSION")
+        ),
+        String::new(),
+        "[Reads]".to_string(),
+    ];
+    let non_index = run_info.reads.iter().filter(|desc| !desc.is_index);
+    for (i, desc) in non_index.enumerate() {
+        lines.push(format!("Read{}Cycles,{}", i + 1, desc.num_cycles));
+    }
+    for (i, cycles) in index_cycles.iter().enumerate() {
+        lines.push(format!("Index{}Cycles,{}", i + 1, cycles));
+    }
+    lines.extend(vec![
+        String::new(),
+        "[BCLConvert_Settings]".to_string(),
+        format!(
+            "OverrideCycles,{}",
+            override_cycles(&flowcell.libraries, run_info)
+        ),
+        String::new(),
+        "[BCLConvert_Data]".to_string(),
+    ]);
+
+    let mut columns = vec!["Sample_ID"];
+    columns.extend(["Index", "Index2"].iter().take(index_cycles.len()));
+    if with_lanes {
+        columns.insert(0, "Lane");
+    }
+    lines.push(columns.join(","));
+    for library in &flowcell.libraries {
+        let mut fields = vec![sample_id(&library.name)];
+        fields.extend(library_indexes(library, &index_cycles, revcomp_index2));
+        if with_lanes {
+            for lane in &library.lane_numbers {
+                lines.push(format!("{},{}", lane, fields.join(",")));
+            }
+        } else {
+            lines.push(fields.join(","));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Resolve the flow cell of the run folder at `path` in the project
+/// `settings.ingest.project_uuid` with `client` and return it with its sample sheet in `format`
+/// (`v1` or `v2`).
+pub fn build_sample_sheet(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    path: &Path,
+    format: &str,
+    settings: &Settings,
+) -> Result<(api::FlowCell, String)> {
+    let format_sample_sheet = match format {
+        "v1" => format_sample_sheet,
+        "v2" => format_sample_sheet_v2,
+        format => bail!("Invalid sample sheet format {:?}", format),
+    };
+    let folder_layout = match configured_folder_layout(path, settings)? {
+        Some(folder_layout) => folder_layout,
+        None => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules()))
+            .chain_err(|| format!("Could not guess folder layout from {:?}", path))?,
+    };
+    let revcomp_index2 = is_index2_reverse_complement(folder_layout)?;
+    let (run_info, _) = parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut Vec::new(),
+    )?;
+
+    let flowcell: api::FlowCell = client
+        .get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: run_info.instrument.clone(),
+            run_number: run_info.run_number,
+            flowcell: run_info.flowcell.clone(),
+        })
+        .chain_err(|| format!("Could not resolve flow cell {}", &run_info.flowcell))?;
+    let revcomp_index2 =
+        revcomp_index2 && !(format == "v2" && is_index2_marked_reverse_complement(&run_info));
+    if flowcell.libraries.is_empty() {
+        bail!("Flow cell {} has no libraries", &flowcell.vendor_id);
+    }
+    info!(
+        logger,
+        "Writing {} sample sheet with {} libraries, {} i5 barcodes for {:?}",
+        format,
+        flowcell.libraries.len(),
+        if revcomp_index2 {
+            "reverse complemented"
+        } else {
+            "forward"
+        },
+        folder_layout
+    );
+
+    let sample_sheet = format_sample_sheet(&flowcell, &run_info, revcomp_index2);
+    Ok((flowcell, sample_sheet))
+}
+
+/// Main entry point for the `samplesheet` command.
+///
+/// Resolves the flow cell of the run folder `settings.samplesheet.path` in the project
+/// `settings.ingest.project_uuid` and writes a sample sheet in `settings.samplesheet.format` with
+/// its libraries to `settings.samplesheet.output`.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client samplesheet");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    let path = Path::new(&settings.samplesheet.path);
+    let output = match settings.samplesheet.output.as_str() {
+        "" => path.join("SampleSheet.csv"),
+        output => PathBuf::from(output),
+    };
+    let to_stdout = settings.dry_run || settings.samplesheet.output == "-";
+    if !to_stdout && output.exists() && !settings.samplesheet.force {
+        bail!(
+            "Sample sheet {:?} exists, use --force to overwrite it",
+            &output
+        );
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let (_, sample_sheet) = build_sample_sheet(
+        logger,
+        &mut client,
+        path,
+        &settings.samplesheet.format,
+        settings,
+    )?;
+    if to_stdout {
+        print!("{}", sample_sheet);
+    } else {
+        fs::write(&output, sample_sheet)
+            .chain_err(|| format!("Problem writing sample sheet {:?}", &output))?;
+        info!(logger, "Wrote sample sheet {:?}", &output);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the run info with reads of the `(num_cycles, is_index, is_reverse_complement)`.
+    fn run_info(reads: &[(i32, bool, bool)]) -> RunInfo {
+        RunInfo {
+            run_id: "240101_LH00123_0042_A22XXXXLT3".to_string(),
+            run_number: 42,
+            flowcell: "22XXXXLT3".to_string(),
+            instrument: "LH00123".to_string(),
+            date: "2024-01-01".to_string(),
+            lane_count: 1,
+            reads: reads
+                .iter()
+                .enumerate()
+                .map(
+                    |(i, (num_cycles, is_index, is_reverse_complement))| ReadDescription {
+                        number: i as i32 + 1,
+                        num_cycles: *num_cycles,
+                        is_index: *is_index,
+                        is_reverse_complement: *is_reverse_complement,
+                    },
+                )
+                .collect(),
+            version: 6,
+            geometry: None,
+        }
+    }
+
+    /// Return a library with the barcodes `barcode_seq` and `barcode_seq2`.
+    fn library(barcode_seq: Option<&str>, barcode_seq2: Option<&str>) -> api::Library {
+        api::Library {
+            name: "Sample 1".to_string(),
+            reference: None,
+            barcode_seq: barcode_seq.map(|seq| seq.to_string()),
+            barcode_seq2: barcode_seq2.map(|seq| seq.to_string()),
+            lane_numbers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn library_indexes_for_each_folder_layout() {
+        let dual = library(Some("acgtacgt"), Some("AACCGGTTCA"));
+        let table: &[(FolderLayout, Option<&str>)] = &[
+            (FolderLayout::MiSeqDep, Some("AACCGGTT")),
+            (FolderLayout::MiSeq, Some("AACCGGTT")),
+            (FolderLayout::MiniSeq, Some("TGAACCGG")),
+            (FolderLayout::HiSeqX, Some("TGAACCGG")),
+            (FolderLayout::HiSeq3000, Some("TGAACCGG")),
+            (FolderLayout::NextSeq500, Some("TGAACCGG")),
+            (FolderLayout::NovaSeq, Some("TGAACCGG")),
+            (FolderLayout::NovaSeqXplus, Some("TGAACCGG")),
+            (FolderLayout::NextSeq2000, Some("TGAACCGG")),
+            (FolderLayout::Aviti, None),
+            (FolderLayout::Mgi, None),
+            (FolderLayout::Nanopore, None),
+        ];
+        for (folder_layout, index2) in table {
+            match (is_index2_reverse_complement(*folder_layout), index2) {
+                (Ok(revcomp_index2), Some(index2)) => assert_eq!(
+                    library_indexes(&dual, &[8, 8], revcomp_index2),
+                    vec!["ACGTACGT".to_string(), index2.to_string()],
+                    "{:?}",
+                    folder_layout
+                ),
+                (Err(_), None) => (),
+                (result, _) => panic!("{:?}: unexpected {:?}", folder_layout, result),
+            }
+        }
+    }
+
+    #[test]
+    fn library_indexes_cut_to_index_reads() {
+        let dual = library(Some("ACGTACGT"), Some("AACCGGTT"));
+        assert_eq!(library_indexes(&dual, &[6], true), vec!["ACGTAC"]);
+        assert_eq!(library_indexes(&dual, &[], true), Vec::<String>::new());
+        let single = library(Some("ACGTACGT"), None);
+        assert_eq!(
+            library_indexes(&single, &[8, 8], true),
+            vec!["ACGTACGT", ""]
+        );
+    }
+
+    #[test]
+    fn override_cycles_masks_unused_index_cycles() {
+        let table: &[(&[(i32, bool, bool)], &[(Option<&str>, Option<&str>)], &str)] = &[
+            (
+                &[
+                    (151, false, false),
+                    (8, true, false),
+                    (8, true, false),
+                    (151, false, false),
+                ],
+                &[(Some("ACGTACGT"), Some("AACCGGTT"))],
+                "Y151;I8;I8;Y151",
+            ),
+            (
+                &[
+                    (151, false, false),
+                    (10, true, false),
+                    (10, true, false),
+                    (151, false, false),
+                ],
+                &[(Some("ACGTAC"), Some("AACCGGTT")), (Some("ACGTACGT"), None)],
+                "Y151;I8N2;I8N2;Y151",
+            ),
+            (
+                &[
+                    (151, false, false),
+                    (8, true, false),
+                    (8, true, false),
+                    (151, false, false),
+                ],
+                &[(Some("ACGTACGT"), None)],
+                "Y151;I8;N8;Y151",
+            ),
+            (
+                &[(76, false, false), (0, true, false), (8, true, false)],
+                &[(Some("ACGTACGT"), None)],
+                "Y76;I8",
+            ),
+        ];
+        for (reads, barcodes, expected) in table {
+            let libraries = barcodes
+                .iter()
+                .map(|(seq, seq2)| library(*seq, *seq2))
+                .collect::<Vec<api::Library>>();
+            assert_eq!(override_cycles(&libraries, &run_info(reads)), *expected);
+        }
+    }
+
+    #[test]
+    fn index2_marked_reverse_complement_for_v2() {
+        let table: &[(&[(i32, bool, bool)], bool)] = &[
+            (
+                &[
+                    (151, false, false),
+                    (10, true, false),
+                    (10, true, true),
+                    (151, false, false),
+                ],
+                true,
+            ),
+            (
+                &[
+                    (151, false, false),
+                    (10, true, false),
+                    (10, true, false),
+                    (151, false, false),
+                ],
+                false,
+            ),
+            (
+                &[(151, false, false), (10, true, true), (151, false, false)],
+                false,
+            ),
+            (
+                &[
+                    (151, false, false),
+                    (0, true, false),
+                    (10, true, true),
+                    (151, false, false),
+                ],
+                false,
+            ),
+        ];
+        for (reads, expected) in table {
+            assert_eq!(
+                is_index2_marked_reverse_complement(&run_info(reads)),
+                *expected,
+                "{:?}",
+                reads
+            );
+        }
+    }
+}
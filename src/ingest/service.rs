@@ -0,0 +1,170 @@
+//! Running `watch` as a service: systemd notifications, shutdown on `SIGTERM` and `SIGINT`, and
+//! the PID file.
+//!
+//! The notifications implement the `sd_notify()` protocol, i.e., datagrams sent to the socket in
+//! `$NOTIFY_SOCKET`, such that no systemd library is needed.  They are only sent when systemd set
+//! the variable, e.g., for services with `Type=notify`.
+
+use libc;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::errors::*;
+use super::lock::is_running;
+
+/// Set by the signal handler when `SIGTERM` or `SIGINT` was received.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Handler for `SIGTERM` and `SIGINT`, only sets `SHUTDOWN`.
+extern "C" fn handle_signal(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Install the handler for `SIGTERM` and `SIGINT` that requests a shutdown.
+///
+/// The handler is reset after the first signal, so a second signal terminates immediately.
+pub fn install_shutdown_handler() -> Result<()> {
+    for signal in &[libc::SIGTERM, libc::SIGINT] {
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(*signal, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error())
+                .chain_err(|| format!("Problem installing handler for signal {}", signal));
+        }
+    }
+    Ok(())
+}
+
+/// Return whether `SIGTERM` or `SIGINT` was received.
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Sleep for `timeout` or until a shutdown is requested.
+pub fn sleep_unless_shutdown(timeout: Duration) {
+    let start = Instant::now();
+    while !shutdown_requested() {
+        match timeout.checked_sub(start.elapsed()) {
+            Some(left) if left > Duration::from_secs(0) => {
+                thread::sleep(left.min(Duration::from_secs(1)))
+            }
+            _ => break,
+        }
+    }
+}
+
+/// Sender of notifications to systemd.
+#[derive(Debug)]
+pub struct Notifier {
+    /// The socket to send to, `None` if not running under systemd.
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    /// Timeout of the watchdog, `None` if the watchdog is not enabled.
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Construct from the environment set by systemd.
+    pub fn from_env(logger: &slog::Logger) -> Self {
+        let socket = env::var_os("NOTIFY_SOCKET").and_then(|path| {
+            let path = path.to_string_lossy().to_string();
+            let addr = match path.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+                None => SocketAddr::from_pathname(&path),
+            };
+            match (UnixDatagram::unbound(), addr) {
+                (Ok(socket), Ok(addr)) => Some((socket, addr)),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!(logger, "Could not connect to {:?}: {}", &path, e);
+                    None
+                }
+            }
+        });
+        // The watchdog applies to this process unless `$WATCHDOG_PID` names another one.
+        let for_this_process = env::var("WATCHDOG_PID")
+            .ok()
+            .is_none_or(|pid| pid.parse::<u32>().ok() == Some(process::id()));
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && for_this_process && socket.is_some())
+            .map(Duration::from_micros);
+        Self { socket, watchdog }
+    }
+
+    /// Send the notification `state`, e.g., `READY=1`.  Errors are logged only.
+    pub fn notify(&self, logger: &slog::Logger, state: &str) {
+        if let Some((socket, addr)) = self.socket.as_ref() {
+            if let Err(e) = socket.send_to_addr(state.as_bytes(), addr) {
+                warn!(logger, "Could not notify systemd of {:?}: {}", state, e);
+            }
+        }
+    }
+
+    /// Return the longest time to wait between two calls of `ping()`, `None` without watchdog.
+    pub fn ping_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|watchdog| watchdog / 2)
+    }
+
+    /// Send a watchdog ping if the watchdog is enabled.
+    pub fn ping(&self, logger: &slog::Logger) {
+        if self.watchdog.is_some() {
+            self.notify(logger, "WATCHDOG=1");
+        }
+    }
+}
+
+/// A file with the process ID, removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    /// Path to the file.
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the ID of this process to the file at `path`.
+    ///
+    /// Fails if the file names another process that still runs on this host.
+    pub fn create(logger: &slog::Logger, path: &Path) -> Result<Self> {
+        if let Ok(contents) = fs::read_to_string(path) {
+            match contents.trim().parse::<u32>() {
+                Ok(pid) if pid != process::id() && is_running(pid) => {
+                    bail!("Process {} of PID file {:?} is still running", pid, path)
+                }
+                _ => warn!(logger, "Replacing stale PID file {:?}", path),
+            }
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .chain_err(|| format!("Problem creating {:?}", &tmp_path))?;
+        writeln!(file, "{}", process::id())
+            .chain_err(|| format!("Problem writing {:?}", &tmp_path))?;
+        fs::rename(&tmp_path, path).chain_err(|| format!("Problem replacing {:?}", path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
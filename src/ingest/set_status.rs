@@ -0,0 +1,429 @@
+//! Implementation of the `set-status` command that changes the sequencing, conversion, and
+//! delivery status of a flow cell in Digestiflow Web by hand, e.g., after a failed run or a
+//! manual demultiplexing, or of all flow cells matching filters, e.g., for closing old runs.
+
+use chrono::{Duration, Local, NaiveDate};
+use hostname;
+
+use super::*;
+
+/// Values of `status_sequencing` accepted by the server.
+pub const SEQUENCING_STATUSES: &[&str] = &[
+    "initial",
+    "in_progress",
+    "complete",
+    "complete_warnings",
+    "failed",
+    "closed",
+];
+/// Values of `status_conversion` accepted by the server.
+pub const CONVERSION_STATUSES: &[&str] = &[
+    "initial",
+    "ready",
+    "in_progress",
+    "complete",
+    "complete_warnings",
+    "failed",
+    "closed",
+    "canceled",
+    "skipped",
+];
+/// Values of `status_delivery` accepted by the server.
+pub const DELIVERY_STATUSES: &[&str] = &[
+    "initial",
+    "in_progress",
+    "complete",
+    "complete_warnings",
+    "failed",
+    "closed",
+    "skipped",
+];
+
+/// Fields that flow cells can be selected by with `--filter`.
+const FILTER_FIELDS: &[&str] = &[
+    "status_sequencing",
+    "status_conversion",
+    "status_delivery",
+    "instrument",
+];
+
+/// Return the new value `value` of the status `field` if it is given, failing if it is not one
+/// of the `allowed` values.
+fn checked_status(field: &str, value: &str, allowed: &[&str]) -> Result<Option<String>> {
+    if value.is_empty() {
+        Ok(None)
+    } else if allowed.contains(&value) {
+        Ok(Some(value.to_string()))
+    } else {
+        bail!(
+            "Invalid {} {:?}, expected one of: {}",
+            field,
+            value,
+            allowed.join(", ")
+        )
+    }
+}
+
+/// The new status of flow cells, `None` for fields that are left unchanged.
+struct NewStatus {
+    sequencing: Option<String>,
+    conversion: Option<String>,
+    delivery: Option<String>,
+}
+
+impl NewStatus {
+    /// Return the update of `flowcell` to the new status, together with the changes as
+    /// `field: old -> new`, empty if the flow cell already has the new status.
+    fn update_of(&self, flowcell: &api::FlowCell) -> (api::FlowCellStatus, Vec<String>) {
+        let mut status = api::FlowCellStatus {
+            vendor_id: flowcell.vendor_id.clone(),
+            ..api::FlowCellStatus::default()
+        };
+        let mut changes = Vec::new();
+        for (field, current, value, target) in [
+            (
+                "status_sequencing",
+                &flowcell.status_sequencing,
+                &self.sequencing,
+                &mut status.status_sequencing,
+            ),
+            (
+                "status_conversion",
+                &flowcell.status_conversion,
+                &self.conversion,
+                &mut status.status_conversion,
+            ),
+            (
+                "status_delivery",
+                &flowcell.status_delivery,
+                &self.delivery,
+                &mut status.status_delivery,
+            ),
+        ] {
+            if let Some(value) = value.as_ref().filter(|value| *value != current) {
+                changes.push(format!("{}: {} -> {}", field, current, value));
+                *target = Some(value.clone());
+            }
+        }
+        (status, changes)
+    }
+}
+
+/// Parse the `--filter` values `filters` of the form `FIELD=VALUE`.
+fn parse_filters(filters: &[String]) -> Result<Vec<(String, String)>> {
+    filters
+        .iter()
+        .map(|filter| match filter.find('=') {
+            Some(pos) if FILTER_FIELDS.contains(&&filter[..pos]) => {
+                Ok((filter[..pos].to_string(), filter[(pos + 1)..].to_string()))
+            }
+            _ => bail!(
+                "Invalid filter {:?}, expected FIELD=VALUE with FIELD one of: {}",
+                filter,
+                FILTER_FIELDS.join(", ")
+            ),
+        })
+        .collect()
+}
+
+/// Parse the age `value` given in days or weeks, e.g., `90d` or `12w`.
+fn parse_age(value: &str) -> Result<Duration> {
+    let invalid = || format!("Invalid age {:?}, expected, e.g., 90d or 12w", value);
+    let value = value.trim();
+    let age = if let Some(number) = value.strip_suffix('d') {
+        number
+            .parse::<u32>()
+            .ok()
+            .and_then(|n| Duration::try_days(n.into()))
+    } else if let Some(number) = value.strip_suffix('w') {
+        number
+            .parse::<u32>()
+            .ok()
+            .and_then(|n| Duration::try_weeks(n.into()))
+    } else {
+        None
+    };
+    age.chain_err(invalid)
+}
+
+/// Return whether `flowcell` matches all `filters` and has a run date before `before`.
+///
+/// Flow cells without a valid run date are never older than the given age.
+fn is_selected(
+    flowcell: &api::FlowCell,
+    filters: &[(String, String)],
+    before: Option<NaiveDate>,
+) -> bool {
+    filters.iter().all(|(field, value)| {
+        let current = match field.as_str() {
+            "status_sequencing" => &flowcell.status_sequencing,
+            "status_conversion" => &flowcell.status_conversion,
+            "status_delivery" => &flowcell.status_delivery,
+            _ => &flowcell.sequencing_machine,
+        };
+        current == value
+    }) && before.is_none_or(|before| {
+        list::parse_date(&flowcell.run_date).is_some_and(|run_date| run_date < before)
+    })
+}
+
+/// Apply the `status` update with the `changes` to `flowcell` with a PATCH request, and post a
+/// message recording the changes to it.
+fn update_flowcell(
+    client: &mut api::Client,
+    project_uuid: Uuid,
+    flowcell: &api::FlowCell,
+    status: &api::FlowCellStatus,
+    changes: &[String],
+) -> Result<()> {
+    let args = api::ProjectFlowcellArgs {
+        project_uuid,
+        flowcell_uuid: flowcell
+            .sodar_uuid
+            .chain_err(|| "Flow cell from server has no UUID")?,
+    };
+    client
+        .patch(&args, status)
+        .chain_err(|| "Problem updating status of flow cell")?;
+    let message = api::FlowCellMessage {
+        subject: Some("Status changed".to_string()),
+        body: format!(
+            "The status was changed by hand with digestiflow-cli {} on {}:\n\n{}",
+            env!("CARGO_PKG_VERSION"),
+            hostname::get()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            changes
+                .iter()
+                .map(|change| format!("- {}", change))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+        state: "sent".to_string(),
+    };
+    client
+        .post(&args, &message)
+        .chain_err(|| "Problem posting message")?;
+    Ok(())
+}
+
+/// Change the status of the single flow cell `settings.set_status.flowcell` to `new_status`.
+fn set_single(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    settings: &Settings,
+    new_status: &NewStatus,
+) -> Result<()> {
+    let args = &settings.set_status;
+    let flowcell = find_flowcell(
+        client,
+        settings.ingest.project_uuid,
+        &args.flowcell,
+        &args.instrument,
+        args.run_number,
+    )?;
+    let (status, changes) = new_status.update_of(&flowcell);
+    if changes.is_empty() {
+        info!(
+            logger,
+            "Flow cell {} already has the given status, nothing to do", &flowcell.vendor_id
+        );
+        return Ok(());
+    }
+    for change in &changes {
+        info!(logger, "{} {}", &flowcell.vendor_id, change);
+    }
+    if settings.dry_run {
+        info!(logger, "Not updating flow cell in dry-run mode");
+        return Ok(());
+    }
+
+    update_flowcell(
+        client,
+        settings.ingest.project_uuid,
+        &flowcell,
+        &status,
+        &changes,
+    )?;
+    info!(
+        logger,
+        "Updated status of flow cell {}", &flowcell.vendor_id
+    );
+    Ok(())
+}
+
+/// Change the status of all flow cells of the project that match `settings.set_status.filter`
+/// and `settings.set_status.older_than` to `new_status`.
+///
+/// The flow cells to change are printed first, problems updating one of them do not stop the
+/// others from being updated.
+fn set_bulk(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    settings: &Settings,
+    new_status: &NewStatus,
+) -> Result<()> {
+    let args = &settings.set_status;
+    let filters = parse_filters(&args.filter)?;
+    let before = if args.older_than.is_empty() {
+        None
+    } else {
+        let age = parse_age(&args.older_than)?;
+        Some(
+            Local::now()
+                .date_naive()
+                .checked_sub_signed(age)
+                .chain_err(|| format!("Age {:?} is too large", &args.older_than))?,
+        )
+    };
+
+    let api::FlowCellArray::Array(flowcells) = client
+        .get(&api::ProjectArgs {
+            project_uuid: settings.ingest.project_uuid,
+        })
+        .chain_err(|| "Could not list flow cells of project")?;
+    let mut flowcells = flowcells
+        .into_iter()
+        .filter(|flowcell| is_selected(flowcell, &filters, before))
+        .collect::<Vec<api::FlowCell>>();
+    flowcells.sort_by(|a, b| {
+        a.run_date
+            .cmp(&b.run_date)
+            .then_with(|| a.vendor_id.cmp(&b.vendor_id))
+    });
+    let updates = flowcells
+        .iter()
+        .map(|flowcell| (flowcell, new_status.update_of(flowcell)))
+        .filter(|(_, (_, changes))| !changes.is_empty())
+        .collect::<Vec<_>>();
+    info!(
+        logger,
+        "{} flow cell(s) match, {} of them need changes",
+        flowcells.len(),
+        updates.len()
+    );
+    for (flowcell, (_, changes)) in &updates {
+        println!(
+            "{}  {}  {}  {}",
+            &flowcell.vendor_id,
+            &flowcell.run_date,
+            &flowcell.sequencing_machine,
+            changes.join(", ")
+        );
+    }
+    if settings.dry_run {
+        info!(logger, "Not updating flow cells in dry-run mode");
+        return Ok(());
+    }
+
+    let mut num_failed = 0;
+    for (flowcell, (status, changes)) in &updates {
+        match update_flowcell(
+            client,
+            settings.ingest.project_uuid,
+            flowcell,
+            status,
+            changes,
+        ) {
+            Ok(()) => info!(
+                logger,
+                "Updated status of flow cell {}", &flowcell.vendor_id
+            ),
+            Err(e) => {
+                error!(
+                    logger,
+                    "Problem updating flow cell {}: {}", &flowcell.vendor_id, e
+                );
+                num_failed += 1;
+            }
+        }
+    }
+    if num_failed > 0 {
+        bail!(
+            "Could not update {} of {} flow cell(s)",
+            num_failed,
+            updates.len()
+        );
+    }
+    Ok(())
+}
+
+/// Main entry point for the `set-status` command.
+///
+/// Resolves the flow cell `settings.set_status.flowcell` (vendor ID or UUID) of the project
+/// `settings.ingest.project_uuid`, updates the status fields given in `settings.set_status` with
+/// a PATCH request, and posts a message recording the change to the flow cell.  Without a flow
+/// cell, all flow cells matching `settings.set_status.filter` and `older_than` are updated.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client set-status");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    let args = &settings.set_status;
+    let is_bulk = !args.filter.is_empty() || !args.older_than.is_empty();
+    if args.flowcell.is_empty() && !is_bulk {
+        bail!(
+            "You have to specify the flow cell or select flow cells with --filter or --older-than"
+        );
+    } else if !args.flowcell.is_empty() && is_bulk {
+        bail!("You cannot combine a flow cell with --filter or --older-than");
+    }
+    let new_status = NewStatus {
+        sequencing: checked_status(
+            "status_sequencing",
+            &args.status_sequencing,
+            SEQUENCING_STATUSES,
+        )?,
+        conversion: checked_status(
+            "status_conversion",
+            &args.status_conversion,
+            CONVERSION_STATUSES,
+        )?,
+        delivery: checked_status("status_delivery", &args.status_delivery, DELIVERY_STATUSES)?,
+    };
+    if new_status.sequencing.is_none()
+        && new_status.conversion.is_none()
+        && new_status.delivery.is_none()
+    {
+        bail!("You have to specify the new status with --sequencing, --conversion, --delivery, or --set");
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    if is_bulk {
+        set_bulk(logger, &mut client, settings, &new_status)
+    } else {
+        set_single(logger, &mut client, settings, &new_status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_age_cases() {
+        assert_eq!(parse_age("90d").unwrap(), Duration::days(90));
+        assert_eq!(parse_age(" 12w ").unwrap(), Duration::weeks(12));
+        assert_eq!(parse_age("0d").unwrap(), Duration::days(0));
+        for invalid in &[
+            "",
+            "d",
+            "90",
+            "-1d",
+            "12x",
+            "1.5w",
+            "90дн",
+            "9é",
+            "é",
+            "4294967296w",
+        ] {
+            assert!(parse_age(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Implementation of the `show` command that prints a single flow cell of a project in
+//! Digestiflow Web together with its lane index histograms.
+
+use serde_json::{self, Value};
+
+use super::*;
+use anonymize::Anonymizer;
+
+/// Number of most frequent index sequences shown per histogram in the human-readable output.
+const SHOWN_ENTRIES: usize = 10;
+
+/// Return the `flowcell` and its `histograms` as human-readable text.
+fn format_text(flowcell: &Value, histograms: &[api::LaneIndexHistogram]) -> String {
+    let mut lines = vec!["Flow cell:".to_string()];
+    if let Value::Object(fields) = flowcell {
+        let width = fields.keys().map(|key| key.len()).max().unwrap_or(0);
+        for (key, value) in fields {
+            let value = match value {
+                Value::Null => "-".to_string(),
+                Value::String(value) if value.is_empty() => "-".to_string(),
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            lines.push(format!("  {:width$}  {}", key, value, width = width));
+        }
+    }
+
+    lines.push(String::new());
+    if histograms.is_empty() {
+        lines.push("Index histograms: none".to_string());
+    } else {
+        lines.push("Index histograms:".to_string());
+    }
+    for hist in histograms {
+        let mut details = vec![format!("{} clusters sampled", hist.sample_size)];
+        if let Some(coverage) = hist.coverage {
+            details.push(format!("{:.2}% of lane", 100.0 * coverage));
+        }
+        if let Some(n_fraction) = hist.n_fraction {
+            details.push(format!("{:.2}% N", 100.0 * n_fraction));
+        }
+        details.push(format!("min. index fraction {}", hist.min_index_fraction));
+        lines.push(format!(
+            "  Lane {}, index read {}: {}",
+            hist.lane,
+            hist.index_read_no,
+            details.join(", ")
+        ));
+        let total: usize = hist.histogram.0.iter().map(|(_, count)| count).sum();
+        for (seq, count) in hist.histogram.0.iter().take(SHOWN_ENTRIES) {
+            lines.push(format!(
+                "    {}  {:>9}  {:6.2}%",
+                seq,
+                count,
+                100.0 * *count as f64 / total.max(1) as f64
+            ));
+        }
+        if hist.histogram.0.len() > SHOWN_ENTRIES {
+            lines.push(format!(
+                "    ({} more sequences)",
+                hist.histogram.0.len() - SHOWN_ENTRIES
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Main entry point for the `show` command.
+///
+/// Prints all fields of the flow cell `settings.show.flowcell` (vendor ID or UUID) of the project
+/// `settings.ingest.project_uuid` and its lane index histograms.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client show");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.show.flowcell.is_empty() {
+        bail!("You have to specify the flow cell");
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let flowcell = find_flowcell(
+        &mut client,
+        settings.ingest.project_uuid,
+        &settings.show.flowcell,
+        &settings.show.instrument,
+        settings.show.run_number,
+    )?;
+    let flowcell_uuid = flowcell
+        .sodar_uuid
+        .chain_err(|| "Flow cell from server has no UUID")?;
+    let api::LaneIndexHistogramArray::Array(mut histograms) = client
+        .get(&api::ProjectFlowcellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            flowcell_uuid,
+        })
+        .chain_err(|| "Could not query index histograms from server")?;
+    histograms.sort_by_key(|hist| (hist.lane, hist.index_read_no));
+    info!(logger, "Found {} index histograms", histograms.len());
+
+    let flowcell = serde_json::to_value(&flowcell).chain_err(|| "Problem serializing to JSON")?;
+    let mut anonymizer = Anonymizer::new(settings);
+    anonymizer.add_payload(&flowcell);
+    let output = if settings.show.json {
+        let value = json!({
+            "flowcell": flowcell,
+            "histograms": histograms,
+        });
+        format!(
+            "{}\n",
+            serde_json::to_string_pretty(&value).chain_err(|| "Problem serializing to JSON")?
+        )
+    } else {
+        format_text(&flowcell, &histograms)
+    };
+    print!("{}", anonymizer.apply(&output));
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! Persistent list of folders that failed permanently and are skipped by `ingest`.
+
+use chaos;
+use chrono::Local;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use super::super::errors::*;
+
+/// A folder on the skip list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedFolder {
+    /// The reason processing the folder failed.
+    pub reason: String,
+    /// Time of the first failure.
+    pub since: String,
+    /// Time of the latest failure.
+    pub last_failure: String,
+}
+
+/// Folders that failed with non-transient errors, by path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SkipList {
+    /// The skipped folders by path.
+    pub folders: BTreeMap<String, SkippedFolder>,
+}
+
+impl SkipList {
+    /// Load the skip list from the JSON file at `path`, empty if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+        serde_json::from_reader(file).chain_err(|| format!("Problem reading JSON from {:?}", path))
+    }
+
+    /// Write the skip list as JSON to the file at `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = chaos::create(path).chain_err(|| format!("Problem creating {:?}", path))?;
+        serde_json::to_writer_pretty(file, self)
+            .chain_err(|| format!("Problem writing JSON to {:?}", path))
+    }
+
+    /// Return the entry for the folder at `path`, if any.
+    pub fn get(&self, path: &str) -> Option<&SkippedFolder> {
+        self.folders.get(path)
+    }
+
+    /// Record a permanent failure of the folder at `path`, keeping the time of the first one.
+    pub fn add(&mut self, path: &str, reason: &str) {
+        let now = Local::now().to_rfc3339();
+        let entry = self
+            .folders
+            .entry(path.to_string())
+            .or_insert_with(|| SkippedFolder {
+                reason: String::new(),
+                since: now.clone(),
+                last_failure: String::new(),
+            });
+        entry.reason = reason.to_string();
+        entry.last_failure = now;
+    }
+
+    /// Remove the folder at `path`, returning whether it was on the list.
+    pub fn remove(&mut self, path: &str) -> bool {
+        self.folders.remove(path).is_some()
+    }
+}
@@ -0,0 +1,261 @@
+//! Spool of the payloads that could not be sent to the API because the server could not be
+//! reached, and the `replay` command that sends them once it can.
+//!
+//! The payloads are written in the format of the archive (see `archive`), named after the time
+//! they were spooled, and removed once sent.  Payloads that the server rejects when replaying
+//! them are moved to the sub directory `rejected`, such that they do not block the others.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+
+use super::archive::{payload_files, read_payload, write_payload, ArchivedPayload};
+use super::*;
+
+/// Name of the sub directory for the payloads rejected by the server when replaying them.
+const REJECTED_DIR: &str = "rejected";
+
+/// Return whether `error` shows that the server could not be reached (connection problems,
+/// timeouts, and server errors), such that sending the payload again later may succeed.
+pub fn is_unreachable(error: &restson::Error) -> bool {
+    match error {
+        restson::Error::RequestError | restson::Error::TimeoutError => true,
+        restson::Error::HttpError(code, _) => *code >= 500,
+        _ => false,
+    }
+}
+
+/// Writer of payloads that could not be sent to a spool directory.
+pub struct PayloadSpool {
+    logger: slog::Logger,
+    /// The directory to write to.
+    dir: PathBuf,
+    /// The URL of the server the payloads are to be sent to.
+    server: String,
+    /// The run folder currently processed.
+    folder: String,
+}
+
+impl PayloadSpool {
+    /// Construct for writing the payloads that could not be sent to `server` to the directory
+    /// `dir`, which is created if necessary.
+    pub fn new(logger: &slog::Logger, dir: &Path, server: &str) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .chain_err(|| format!("Problem creating spool directory {:?}", dir))?;
+        Ok(Self {
+            logger: logger.clone(),
+            dir: dir.to_path_buf(),
+            server: server.to_string(),
+            folder: String::new(),
+        })
+    }
+
+    /// Record `folder` as the run folder for the payloads written from now on.
+    pub fn set_folder(&mut self, folder: &str) {
+        self.folder = folder.to_string();
+    }
+
+    /// Write the `payload` that could not be sent with `method` to `path`.
+    pub fn write<T: Serialize>(&self, method: &str, path: &str, payload: &T) -> Result<()> {
+        match write_payload(&self.dir, &self.server, &self.folder, method, path, payload) {
+            Ok(target) => {
+                warn!(
+                    self.logger,
+                    "Server not reachable, spooled {} /{} to {:?}", method, path, &target
+                );
+                Ok(())
+            }
+            Err(e) => {
+                error!(self.logger, "Could not spool payload: {:?}", &e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Number of spooled payloads by outcome of a replay.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplayStats {
+    /// The payloads sent successfully.
+    pub sent: usize,
+    /// The payloads rejected by the server.
+    pub rejected: usize,
+    /// The payloads left in the spool, as the server could not be reached.
+    pub remaining: usize,
+}
+
+/// Return the run folders with payloads spooled in the directory `dir` for the server at
+/// `server`.
+pub fn spooled_folders(dir: &Path, server: &str) -> Result<BTreeSet<String>> {
+    let mut folders = BTreeSet::new();
+    if !dir.exists() {
+        return Ok(folders);
+    }
+    for path in payload_files(dir)? {
+        let payload = read_payload(&path)?;
+        if payload.server == server {
+            folders.insert(payload.folder);
+        }
+    }
+    Ok(folders)
+}
+
+/// Send the `payload` with its method to its path with `client`.
+fn send(client: &mut api::Client, payload: &ArchivedPayload) -> result::Result<(), restson::Error> {
+    let args = api::PathArgs {
+        path: payload.path.clone(),
+    };
+    let data = api::RawPayload(payload.payload.clone());
+    match payload.method.as_str() {
+        "POST" => client.post(&args, &data),
+        "PUT" => client.put_capture::<_, _, Value>(&args, &data).map(|_| ()),
+        "PATCH" => client.patch(&args, &data),
+        _ => Err(restson::Error::InvalidValue),
+    }
+}
+
+/// Move the spooled payload file at `path` to the sub directory for rejected payloads.
+fn reject(dir: &Path, path: &Path) -> Result<()> {
+    let rejected_dir = dir.join(REJECTED_DIR);
+    fs::create_dir_all(&rejected_dir)
+        .chain_err(|| format!("Problem creating directory {:?}", &rejected_dir))?;
+    let target = rejected_dir.join(path.file_name().unwrap());
+    fs::rename(path, &target).chain_err(|| format!("Problem moving {:?} to {:?}", path, &target))
+}
+
+/// Send the payloads spooled in the directory `dir` for the server at `server` with `client`,
+/// in the order they were spooled, and remove them from the spool.
+///
+/// Stops at the first payload that cannot be sent because the server cannot be reached, the
+/// later payloads are kept to preserve the order of the updates.  `client` must not spool the
+/// payloads itself.  In dry-run mode, the payloads are only listed.
+pub fn replay(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    server: &str,
+    dir: &Path,
+    settings: &Settings,
+) -> Result<ReplayStats> {
+    let mut stats = ReplayStats::default();
+    if !dir.exists() {
+        return Ok(stats);
+    }
+    let mut spooled = Vec::new();
+    for path in payload_files(dir)? {
+        let payload = read_payload(&path)?;
+        if payload.server == server {
+            spooled.push((path, payload));
+        }
+    }
+    if spooled.is_empty() {
+        return Ok(stats);
+    }
+    info!(
+        logger,
+        "Replaying {} spooled payload(s) for {}",
+        spooled.len(),
+        server
+    );
+
+    for (i, (path, payload)) in spooled.iter().enumerate() {
+        if settings.dry_run {
+            info!(
+                logger,
+                "Dry running activated, not sending {} /{} spooled at {} for {:?}",
+                &payload.method,
+                &payload.path,
+                &payload.time,
+                &payload.folder
+            );
+            stats.remaining += 1;
+            continue;
+        }
+        client.set_archive_folder(&payload.folder);
+        match send(client, payload) {
+            Ok(()) => {
+                debug!(logger, "Sent {} /{}", &payload.method, &payload.path);
+                fs::remove_file(path).chain_err(|| format!("Problem removing {:?}", path))?;
+                stats.sent += 1;
+            }
+            Err(ref e) if is_unreachable(e) => {
+                warn!(
+                    logger,
+                    "Server {} still not reachable, keeping {} spooled payload(s): {:?}",
+                    server,
+                    spooled.len() - i,
+                    e
+                );
+                stats.remaining = spooled.len() - i;
+                break;
+            }
+            Err(e) => {
+                warn!(
+                    logger,
+                    "Server rejected {} /{} spooled at {}, moving it to {:?}: {:?}",
+                    &payload.method,
+                    &payload.path,
+                    &payload.time,
+                    dir.join(REJECTED_DIR),
+                    e
+                );
+                reject(dir, path)?;
+                stats.rejected += 1;
+            }
+        }
+    }
+    info!(
+        logger,
+        "Replayed spooled payloads for {}: {} sent, {} rejected, {} remaining",
+        server,
+        stats.sent,
+        stats.rejected,
+        stats.remaining
+    );
+    Ok(stats)
+}
+
+/// Main entry point for the `replay` command.
+///
+/// Sends the payloads spooled in `settings.spool_dir` to the configured servers, and fails if
+/// any payload is rejected or cannot be sent yet.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client replay");
+
+    if settings.spool_dir.is_empty() {
+        bail!("You have to specify the spool directory with --spool-dir");
+    }
+    let dir = Path::new(&settings.spool_dir);
+    if !dir.is_dir() {
+        bail!("Spool directory {:?} does not exist", dir);
+    }
+
+    let mut webs = vec![&settings.web];
+    if !settings.secondary_web.url.is_empty() {
+        webs.push(&settings.secondary_web);
+    }
+    let mut total = ReplayStats::default();
+    for web in webs {
+        let mut client = build_client(logger, web, settings.trace_api, &settings.archive_dir)?;
+        let stats = replay(logger, &mut client, &web.url, dir, settings)?;
+        total.sent += stats.sent;
+        total.rejected += stats.rejected;
+        total.remaining += stats.remaining;
+    }
+
+    let num_other = payload_files(dir)?.len() - total.remaining;
+    if num_other > 0 {
+        warn!(
+            logger,
+            "{} spooled payload(s) are for servers that are not configured", num_other
+        );
+    }
+    if !settings.dry_run && total.rejected + total.remaining > 0 {
+        bail!(
+            "Could not send {} spooled payload(s), {} of them rejected by the server",
+            total.rejected + total.remaining,
+            total.rejected
+        );
+    }
+    Ok(())
+}
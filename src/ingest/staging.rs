@@ -0,0 +1,130 @@
+//! Staging of the base call files needed for sampling adapters to a local scratch directory.
+//!
+//! On slow network file systems, copying the files in one go and sampling from the local copy is
+//! much faster than the random access of sampling directly from the run folder.
+
+use glob::glob;
+use slog;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use super::super::errors::*;
+use ingest::bcl_data::{find_file_stacks, sampled_index_cycles};
+use ingest::bcl_meta::{FolderLayout, ReadDescription};
+use settings::Settings;
+
+/// Return the files below the run folder at `path` needed for sampling the index reads given
+/// as `(index_no, desc, start_cycle)`.
+///
+/// These are the base call files of the sampled index cycles, the `*.bci` tile indices, and the
+/// `*.filter` and `*.locs` files used for the cluster counts.
+pub fn files_to_stage(
+    logger: &slog::Logger,
+    path: &Path,
+    folder_layout: FolderLayout,
+    index_reads: &[(i32, &ReadDescription, i32)],
+    settings: &Settings,
+) -> Result<Vec<PathBuf>> {
+    let mut files = BTreeSet::new();
+    for (index_no, desc, start_cycle) in index_reads {
+        let desc = ReadDescription {
+            number: desc.number,
+            num_cycles: sampled_index_cycles(desc, *index_no, settings),
+            is_index: desc.is_index,
+            is_reverse_complement: desc.is_reverse_complement,
+        };
+        let stacks = find_file_stacks(logger, folder_layout, &desc, path, *start_cycle)
+            .chain_err(|| "Problem building paths to files")?;
+        for stack in stacks.iter().flatten() {
+            for file in &stack.paths {
+                // Strip the tile number from CBCL paths (`path/to/file.cbcl!${tile_no}`).
+                let file = file.split('!').next().unwrap();
+                files.insert(PathBuf::from(file));
+            }
+        }
+    }
+
+    let intensities = path.join("Data").join("Intensities");
+    let patterns = [
+        intensities.join("BaseCalls").join("L???").join("*.filter"),
+        intensities.join("BaseCalls").join("L???").join("*.bci"),
+        intensities.join("L???").join("*.locs"),
+        intensities.join("s.locs"),
+    ];
+    for pattern in &patterns {
+        files.extend(
+            glob(pattern.to_str().unwrap())
+                .expect("Failed to read glob pattern")
+                .filter_map(|path| path.ok()),
+        );
+    }
+
+    Ok(files.into_iter().filter(|file| file.is_file()).collect())
+}
+
+/// Copy of some files of a run folder in a scratch directory, removed again when dropped.
+pub struct StagedFolder {
+    logger: slog::Logger,
+    path: PathBuf,
+    /// The number of bytes copied.
+    pub bytes: u64,
+}
+
+impl StagedFolder {
+    /// Copy the `files` below the run folder at `path` into a new directory below `scratch_dir`,
+    /// keeping their paths relative to the run folder.
+    pub fn create(
+        logger: &slog::Logger,
+        scratch_dir: &Path,
+        path: &Path,
+        files: &[PathBuf],
+    ) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "run".to_string());
+        let staged_path = scratch_dir.join(format!("digestiflow-{}-{}", name, process::id()));
+        fs::create_dir_all(&staged_path)
+            .chain_err(|| format!("Problem creating staging directory {:?}", &staged_path))?;
+        // Clean up from here on, also if copying fails.
+        let mut result = Self {
+            logger: logger.clone(),
+            path: staged_path,
+            bytes: 0,
+        };
+
+        for file in files {
+            let relative = file
+                .strip_prefix(path)
+                .chain_err(|| format!("File {:?} is not below {:?}", file, path))?;
+            let target = result.path.join(relative);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .chain_err(|| format!("Problem creating directory {:?}", parent))?;
+            }
+            result.bytes += fs::copy(file, &target)
+                .chain_err(|| format!("Problem copying {:?} to {:?}", file, &target))?;
+        }
+
+        Ok(result)
+    }
+
+    /// Return the path of the staged copy of the run folder.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagedFolder {
+    fn drop(&mut self) {
+        debug!(self.logger, "Removing staging directory {:?}", &self.path);
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!(
+                self.logger,
+                "Problem removing staging directory {:?}: {}", &self.path, e
+            );
+        }
+    }
+}
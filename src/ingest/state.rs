@@ -0,0 +1,165 @@
+//! Persistent state of the run folders processed by `ingest` and `watch`, such that folders that
+//! look unchanged since they were last processed successfully are not processed again.
+//!
+//! A folder looks unchanged if the names and modification times of the files and directories at
+//! its top (`RunInfo.xml`, `RunParameters.xml`, `RTAComplete.txt`, ...) are the same, and the
+//! client version and the ingest settings are the same.  The lock file is ignored, as is the
+//! modification time of the folder itself that changes with creating and removing it.  Only the
+//! top of the folder is checked, which misses the base calls written below it, so folders whose
+//! sequencing was not finished are always processed again.  Changes on the server (e.g., removed
+//! index histograms) are not noticed; remove the state file for processing all folders again.
+
+use chaos;
+use chrono::Local;
+use serde_json;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use super::super::errors::*;
+use super::folder_cache::mtime_nanos;
+use super::lock::LOCK_FILE;
+use super::report::FolderReport;
+use settings::Settings;
+
+/// Cheap fingerprint of the top of a run folder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// The latest modification time of the entries at the top of the folder, in nanoseconds
+    /// since the epoch.
+    pub mtime: u64,
+    /// The number of entries at the top of the folder.
+    pub entries: usize,
+    /// Digest of the names of the entries at the top of the folder.
+    pub names_digest: String,
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of the folder at `path`.
+    pub fn compute(path: &Path) -> Result<Self> {
+        let mut names = Vec::new();
+        let mut mtime = 0;
+        for entry in fs::read_dir(path).chain_err(|| format!("Problem listing {:?}", path))? {
+            let entry = entry.chain_err(|| format!("Problem listing {:?}", path))?;
+            if entry.file_name() != LOCK_FILE {
+                mtime = mtime.max(mtime_nanos(&entry.path()));
+                names.push(entry.file_name());
+            }
+        }
+        names.sort();
+        let mut hasher = DefaultHasher::new();
+        names.hash(&mut hasher);
+        Ok(Self {
+            mtime,
+            entries: names.len(),
+            names_digest: format!("{:016x}", hasher.finish()),
+        })
+    }
+}
+
+/// The state of a run folder after it was last processed successfully with a server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderState {
+    /// The fingerprint of the folder before it was processed.
+    pub fingerprint: Fingerprint,
+    /// Digest of the ingest settings the folder was processed with.
+    pub settings_digest: String,
+    /// Version of the CLI client the folder was processed with.
+    pub version: String,
+    /// The sequencing status of the flow cell after processing.
+    pub status_sequencing: String,
+    /// Whether the adapters were analyzed (or found complete, or cannot be analyzed), i.e., not
+    /// postponed until the copy-complete marker file appears.
+    pub histograms_done: bool,
+    /// Time processing the folder finished.
+    pub processed: String,
+}
+
+/// The state of the processed run folders, by server URL and path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IngestState {
+    /// The processed folders by server URL and path.
+    pub servers: BTreeMap<String, BTreeMap<String, FolderState>>,
+}
+
+impl IngestState {
+    /// Load the state from the JSON file at `path`, empty if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path).chain_err(|| format!("Problem opening {:?}", path))?;
+        serde_json::from_reader(file).chain_err(|| format!("Problem reading JSON from {:?}", path))
+    }
+
+    /// Write the state as JSON to the file at `path`.
+    ///
+    /// The file is replaced atomically, such that an interrupted call leaves a usable one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let file =
+            chaos::create(&tmp_path).chain_err(|| format!("Problem creating {:?}", &tmp_path))?;
+        serde_json::to_writer_pretty(file, self)
+            .chain_err(|| format!("Problem writing JSON to {:?}", &tmp_path))?;
+        fs::rename(&tmp_path, path).chain_err(|| format!("Problem replacing {:?}", path))
+    }
+
+    /// Return the state of the folder at `path` with the server at `url`, if any.
+    pub fn get(&self, url: &str, path: &str) -> Option<&FolderState> {
+        self.servers.get(url).and_then(|folders| folders.get(path))
+    }
+
+    /// Record the outcome of processing the folder with `fingerprint` in `report`.
+    ///
+    /// Only folders with a flow cell on the server that were processed successfully are recorded,
+    /// the others are forgotten such that they are processed again.  Folders with spooled
+    /// payloads are not recorded either, as the server has not received them yet.
+    pub fn record(&mut self, report: &FolderReport, fingerprint: Fingerprint, settings: &Settings) {
+        let folders = self.servers.entry(report.server.clone()).or_default();
+        match (&report.flowcell_uuid, &report.status_sequencing) {
+            (Some(_), Some(status_sequencing)) if report.success && report.api_spooled == 0 => {
+                folders.insert(
+                    report.path.clone(),
+                    FolderState {
+                        fingerprint,
+                        settings_digest: settings_digest(settings),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        status_sequencing: status_sequencing.clone(),
+                        histograms_done: !report.adapters_postponed,
+                        processed: Local::now().to_rfc3339(),
+                    },
+                );
+            }
+            _ => {
+                folders.remove(&report.path);
+            }
+        }
+    }
+}
+
+/// Return the digest of the ingest settings in `settings` that affect the processing of a
+/// folder, i.e., without the paths of the folders.
+pub fn settings_digest(settings: &Settings) -> String {
+    let mut ingest = settings.ingest.clone();
+    ingest.path.clear();
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", &ingest).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// The sequencing statuses of flow cells whose folders are always processed again.
+const UNFINISHED_STATUSES: &[&str] = &["initial", "in_progress"];
+
+/// Return whether the folder with `fingerprint` looks unchanged since it was processed as
+/// recorded in `state`, such that processing it again with `settings` is not needed.
+pub fn is_unchanged(state: &FolderState, fingerprint: &Fingerprint, settings: &Settings) -> bool {
+    !UNFINISHED_STATUSES.contains(&state.status_sequencing.as_str())
+        && state.fingerprint == *fingerprint
+        && state.version == env!("CARGO_PKG_VERSION")
+        && state.settings_digest == settings_digest(settings)
+        && (state.histograms_done || !settings.ingest.analyze_adapters)
+        // The folder size in the description changes below the top of the folder.
+        && !settings.ingest.description_template.contains("{folder_size}")
+}
@@ -0,0 +1,182 @@
+//! Implementation of the `status` command that compares the sequencing state derived from run
+//! folders with the statuses recorded in Digestiflow Web, e.g., for finding out why a flow cell
+//! is still shown as in progress.
+
+use super::*;
+
+/// Sequencing statuses that `ingest` keeps updating from the run folder.
+const UPDATED_STATUSES: &[&str] = &["initial", "in_progress", "failed"];
+
+/// Return the completion markers of run folders with `folder_layout` and whether they exist in
+/// the run folder at `path`.  `RTAExited.txt` is only returned if it exists.
+fn markers(path: &Path, folder_layout: FolderLayout) -> Vec<(&'static str, bool)> {
+    let mut names = match folder_layout {
+        FolderLayout::Mgi | FolderLayout::Nanopore => Vec::new(),
+        FolderLayout::Aviti => vec![aviti::RUN_COMPLETE_FILE],
+        _ => vec!["RTAComplete.txt", copy_complete_file(folder_layout)],
+    };
+    names.dedup();
+    let mut result = names
+        .into_iter()
+        .map(|name| (name, meta_file_exists(path, name)))
+        .collect::<Vec<(&str, bool)>>();
+    if meta_file_exists(path, "RTAExited.txt") {
+        result.push(("RTAExited.txt", true));
+    }
+    result
+}
+
+/// Return whether the sequencing status `server` recorded in Digestiflow Web agrees with the
+/// status `local` derived from the run folder.
+fn is_consistent(server: &str, local: &str) -> bool {
+    server == local
+        || (local == "complete" && (server == "complete_warnings" || server == "closed"))
+}
+
+/// Return the lines describing the local and server state of the run folder at `path`, and
+/// whether they disagree.
+fn folder_status(
+    logger: &slog::Logger,
+    client: &mut api::Client,
+    path: &Path,
+    settings: &Settings,
+) -> Result<(Vec<String>, bool)> {
+    let folder_layout = match configured_folder_layout(path, settings)? {
+        Some(folder_layout) => folder_layout,
+        None => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules()))
+            .chain_err(|| format!("Could not guess folder layout from {:?}", path))?,
+    };
+    let (run_info, run_params) = parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut Vec::new(),
+    )?;
+    let local_status = get_status_sequencing(&run_info, &run_params, path, "initial");
+
+    let mut lines = vec![format!(
+        "flow cell  {} (run {} on {}, layout {:?})",
+        &run_info.flowcell, run_info.run_number, &run_info.instrument, folder_layout
+    )];
+    let mut local = vec![local_status.clone()];
+    let planned_reads = if run_params.planned_reads.is_empty() {
+        &run_info.reads
+    } else {
+        &run_params.planned_reads
+    };
+    let present = present_cycles(path, &run_info);
+    if !present.is_empty() {
+        let num_cycles: i32 = planned_reads.iter().map(|desc| desc.num_cycles).sum();
+        local.push(format!("{} of {} cycles", present.len(), num_cycles));
+    }
+    if run_info.reads != run_params.planned_reads && !run_params.planned_reads.is_empty() {
+        local.push(format!(
+            "reads {} but planned {}",
+            string_description(&run_info.reads),
+            string_description(&run_params.planned_reads)
+        ));
+    }
+    for (name, exists) in markers(path, folder_layout) {
+        local.push(format!(
+            "{} {}",
+            name,
+            if exists { "present" } else { "missing" }
+        ));
+    }
+    lines.push(format!("local      {}", local.join(", ")));
+
+    let result: result::Result<api::FlowCell, restson::Error> =
+        client.get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: run_info.instrument.clone(),
+            run_number: run_info.run_number,
+            flowcell: run_info.flowcell.clone(),
+        });
+    let flowcell = match result {
+        Ok(flowcell) => flowcell,
+        Err(restson::Error::HttpError(404, _)) => {
+            lines.push("server     not registered in project".to_string());
+            lines.push(
+                "MISMATCH   the flow cell is registered when `flowcells ingest` or `watch` \
+                 processes the folder"
+                    .to_string(),
+            );
+            return Ok((lines, true));
+        }
+        Err(e) => return Err(e).chain_err(|| "Could not query flow cell from server"),
+    };
+    lines.push(format!(
+        "server     sequencing {}, conversion {}, delivery {}",
+        &flowcell.status_sequencing, &flowcell.status_conversion, &flowcell.status_delivery
+    ));
+
+    if is_consistent(&flowcell.status_sequencing, &local_status) {
+        return Ok((lines, false));
+    }
+    if UPDATED_STATUSES.contains(&flowcell.status_sequencing.as_str()) {
+        lines.push(format!(
+            "MISMATCH   sequencing status is {} but the run folder is {}; it is updated the next \
+             time `flowcells ingest` or `watch` processes the folder",
+            &flowcell.status_sequencing, &local_status
+        ));
+    } else {
+        lines.push(format!(
+            "MISMATCH   sequencing status is {} but the run folder is {}; final statuses are not \
+             updated by `flowcells ingest`, use `flowcells set-status` to change it",
+            &flowcell.status_sequencing, &local_status
+        ));
+    }
+    Ok((lines, true))
+}
+
+/// Main entry point for the `status` command.
+///
+/// Prints the sequencing state derived from each run folder in `settings.status.path` next to
+/// the statuses of its flow cell in the project `settings.ingest.project_uuid`, highlighting
+/// mismatches.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client status");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let mut num_mismatches = 0;
+    let mut num_failed = 0;
+    for path in &settings.status.path {
+        match folder_status(logger, &mut client, Path::new(path), settings) {
+            Ok((lines, mismatch)) => {
+                println!("{}:", path);
+                for line in &lines {
+                    println!("  {}", line);
+                }
+                if mismatch {
+                    num_mismatches += 1;
+                }
+            }
+            Err(e) => {
+                println!("{}: check failed: {}", path, e);
+                num_failed += 1;
+            }
+        }
+    }
+
+    info!(
+        logger,
+        "{} of {} folder(s) with mismatches",
+        num_mismatches,
+        settings.status.path.len()
+    );
+    if num_failed > 0 {
+        bail!("Checking of {} folder(s) failed!", num_failed)
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! POST requests whose JSON body is serialized while it is sent, for payloads that should not be
+//! built in memory first, such as the index histograms of runs with UMIs in the index reads.
+//!
+//! The REST client builds each request body as a string, so these requests are made with `hyper`
+//! directly.  The payload is serialized twice: once for counting its bytes for the
+//! `Content-Length` header, as WSGI servers do not read chunked request bodies, and once in a
+//! separate thread into chunks that are sent while the following ones are written.  At most
+//! `MAX_PENDING_CHUNKS` chunks wait to be sent, which bounds the memory used for the body.
+
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT};
+use hyper::{self, Body, Chunk, Method, Request, Uri};
+use hyper_tls::HttpsConnector;
+use restson;
+use serde::Serialize;
+use serde_json;
+use std::io::{self, Write};
+use std::mem;
+use std::result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tokio_core::reactor::Core;
+
+/// Size of the chunks the body is sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maximal number of chunks waiting to be sent.
+const MAX_PENDING_CHUNKS: usize = 4;
+
+/// Time to wait before passing a chunk again while the channel is full.
+const SEND_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Writer passing what is written in chunks to the request body.
+struct ChunkWriter<'a> {
+    /// The channel to the request body.
+    sender: mpsc::Sender<Chunk>,
+    /// The data not passed on yet.
+    buffer: Vec<u8>,
+    /// Whether the request is over, such that nothing is taken from the channel any more.
+    done: &'a AtomicBool,
+}
+
+impl<'a> ChunkWriter<'a> {
+    /// Pass the buffered data to the request body, waiting while the channel is full.
+    fn send_buffer(&mut self) -> io::Result<()> {
+        let buffer = mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        let mut chunk = Chunk::from(buffer);
+        loop {
+            match self.sender.try_send(chunk) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if !e.is_full() || self.done.load(Ordering::SeqCst) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::BrokenPipe,
+                            "Request ended before the payload was sent",
+                        ));
+                    }
+                    chunk = e.into_inner();
+                    thread::sleep(SEND_RETRY_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Write for ChunkWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            self.send_buffer()
+        }
+    }
+}
+
+/// Client for POST requests with JSON bodies that are serialized while they are sent.
+pub struct StreamingClient {
+    /// The event loop running the requests.
+    core: Core,
+    /// The HTTP client.
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+    /// The scheme and authority of the server, e.g., `https://digestiflow.example.com`.
+    base: String,
+    /// The value of the `Authorization` header.
+    authorization: String,
+}
+
+impl StreamingClient {
+    /// Construct for the server at `url`, authenticating with `token`.
+    pub fn new(url: &str, token: &str) -> result::Result<Self, restson::Error> {
+        let uri = url.parse::<Uri>().map_err(|_| restson::Error::UrlError)?;
+        let base = match (uri.scheme_part(), uri.authority_part()) {
+            (Some(scheme), Some(authority)) => format!("{}://{}", scheme, authority),
+            _ => return Err(restson::Error::UrlError),
+        };
+        let core = Core::new().map_err(|_| restson::Error::HttpClientError)?;
+        let https = HttpsConnector::new(1).map_err(|_| restson::Error::HttpClientError)?;
+        Ok(Self {
+            core,
+            client: hyper::Client::builder().build(https),
+            base,
+            authorization: format!("Token {}", token),
+        })
+    }
+
+    /// Authenticate with `token` from now on.
+    pub fn set_token(&mut self, token: &str) {
+        self.authorization = format!("Token {}", token);
+    }
+
+    /// Build the POST request to `path` with the JSON `body` of `length` bytes.
+    fn request(
+        &self,
+        path: &str,
+        body: Body,
+        length: usize,
+    ) -> result::Result<Request<Body>, restson::Error> {
+        let mut request = Request::new(body);
+        *request.method_mut() = Method::POST;
+        // As the REST client, the path replaces the one of the server URL.
+        *request.uri_mut() = format!("{}/{}", &self.base, path.trim_start_matches('/'))
+            .parse()
+            .map_err(|_| restson::Error::UrlError)?;
+        let headers = request.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(length as u64));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&self.authorization).map_err(|_| restson::Error::RequestError)?,
+        );
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_static(concat!("digestiflow-cli/", env!("CARGO_PKG_VERSION"))),
+        );
+        Ok(request)
+    }
+
+    /// POST `data` of `length` bytes when serialized as JSON to `path`, serializing it while
+    /// it is sent.
+    ///
+    /// Fails with the errors of the REST client, i.e., `HttpError` with the status code and
+    /// response body for HTTP errors and `RequestError` if the server cannot be reached.
+    pub fn post<T>(
+        &mut self,
+        path: &str,
+        data: &T,
+        length: usize,
+    ) -> result::Result<(), restson::Error>
+    where
+        T: Serialize + Sync,
+    {
+        let (sender, receiver) = mpsc::channel(MAX_PENDING_CHUNKS);
+        let body = Body::wrap_stream(
+            receiver.map_err(|()| io::Error::other("Problem reading payload channel")),
+        );
+        let request = self.request(path, body, length)?;
+        let done = AtomicBool::new(false);
+        let client = &self.client;
+        let core = &mut self.core;
+        thread::scope(|scope| {
+            // Dropping the writer with its sender ends the body.
+            let done = &done;
+            scope.spawn(move || {
+                let mut writer = ChunkWriter {
+                    sender,
+                    buffer: Vec::with_capacity(CHUNK_SIZE),
+                    done,
+                };
+                serde_json::to_writer(&mut writer, data)
+                    .map_err(io::Error::from)
+                    .and_then(|_| writer.flush())
+            });
+            let response = client.request(request).and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map(move |body| (status, body))
+            });
+            let result = core.run(response);
+            // The writer gives up if the server answered before the payload was sent.
+            done.store(true, Ordering::SeqCst);
+            match result {
+                Ok((status, _)) if status.is_success() => Ok(()),
+                Ok((status, body)) => Err(restson::Error::HttpError(
+                    status.as_u16(),
+                    String::from_utf8_lossy(&body).to_string(),
+                )),
+                Err(_) => Err(restson::Error::RequestError),
+            }
+        })
+    }
+}
@@ -0,0 +1,70 @@
+//! Limiting the rate of reading base call files, see `max_read_rate` in the `[ingest]` section.
+//!
+//! The limit is shared by all threads, such that sampling on the instrument control computer
+//! leaves enough bandwidth for the instrument writing the run folder.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::errors::*;
+use super::memory::parse_size;
+use settings::Settings;
+
+/// The longest time the reading may be ahead of the rate limit, e.g., after an idle time.
+const MAX_BURST: Duration = Duration::from_secs(1);
+
+/// The state of the rate limit.
+struct Throttle {
+    /// The maximal number of bytes per second.
+    bytes_per_second: u64,
+    /// The time until which the bytes read so far use up the rate.
+    busy_until: Instant,
+}
+
+/// The rate limit, unlimited if `None`.
+static THROTTLE: Mutex<Option<Throttle>> = Mutex::new(None);
+
+/// Set the rate limit from `settings.ingest.max_read_rate` (e.g., `20M` for 20 MiB/s),
+/// unlimited if empty or 0.
+pub fn configure(settings: &Settings) -> Result<()> {
+    let rate = &settings.ingest.max_read_rate;
+    let bytes_per_second = if rate.trim().is_empty() {
+        0
+    } else {
+        parse_size(rate).chain_err(|| "Invalid ingest.max_read_rate")?
+    };
+    let mut guard = THROTTLE.lock().unwrap();
+    match guard.as_mut() {
+        Some(throttle) if bytes_per_second > 0 => throttle.bytes_per_second = bytes_per_second,
+        _ if bytes_per_second > 0 => {
+            *guard = Some(Throttle {
+                bytes_per_second,
+                busy_until: Instant::now(),
+            })
+        }
+        _ => *guard = None,
+    }
+    Ok(())
+}
+
+/// Account for reading `bytes`, sleeping as long as needed for keeping the rate limit.
+pub fn consume(bytes: usize) {
+    let wait = {
+        let mut guard = THROTTLE.lock().unwrap();
+        let throttle = match guard.as_mut() {
+            Some(throttle) => throttle,
+            None => return,
+        };
+        let now = Instant::now();
+        if let Some(earliest) = now.checked_sub(MAX_BURST) {
+            throttle.busy_until = throttle.busy_until.max(earliest);
+        }
+        throttle.busy_until +=
+            Duration::from_secs_f64(bytes as f64 / throttle.bytes_per_second as f64);
+        throttle.busy_until.saturating_duration_since(now)
+    };
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
@@ -0,0 +1,200 @@
+//! Implementation of the `verify` command that compares a run folder with its flow cell in
+//! Digestiflow Web without modifying anything, e.g., for audits before delivery.
+
+use serde_json;
+
+use super::*;
+
+/// One compared field of the flow cell.
+#[derive(Debug, Serialize)]
+struct FieldDiff {
+    /// Name of the field.
+    field: String,
+    /// The value derived from the run folder.
+    local: String,
+    /// The value recorded in Digestiflow Web.
+    server: String,
+    /// Whether or not the values agree.
+    agree: bool,
+}
+
+impl FieldDiff {
+    /// Construct for `field` with the values `local` and `server`, agreeing if they are equal.
+    fn new<T: ToString + PartialEq>(field: &str, local: T, server: T) -> Self {
+        Self {
+            field: field.to_string(),
+            agree: local == server,
+            local: local.to_string(),
+            server: server.to_string(),
+        }
+    }
+}
+
+/// Return `value` or `-` if it is not set.
+fn or_dash(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "-".to_string())
+}
+
+/// Return the diffs as a table with aligned columns.
+fn format_text(flowcell: &api::FlowCell, diffs: &[FieldDiff]) -> String {
+    let mut rows = vec![vec![
+        "field".to_string(),
+        "local".to_string(),
+        "server".to_string(),
+        String::new(),
+    ]];
+    for diff in diffs {
+        rows.push(vec![
+            diff.field.clone(),
+            diff.local.clone(),
+            diff.server.clone(),
+            if diff.agree { "ok" } else { "DIFF" }.to_string(),
+        ]);
+    }
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut result = format!(
+        "flow cell {} (run {} on {})\n",
+        &flowcell.vendor_id, flowcell.run_number, &flowcell.sequencing_machine
+    );
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = *width))
+            .collect::<Vec<String>>()
+            .join("  ");
+        result.push_str(line.trim_end());
+        result.push('\n');
+    }
+    result
+}
+
+/// Main entry point for the `verify` command.
+///
+/// Parses the run folder `settings.verify.path`, fetches its flow cell from the project
+/// `settings.ingest.project_uuid`, and prints the fields that `ingest` maintains side by side
+/// together with the number of index histograms present and expected.  Fails if any of them
+/// disagree.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client verify");
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    let path = Path::new(&settings.verify.path);
+    let folder_layout = match configured_folder_layout(path, settings)? {
+        Some(folder_layout) => folder_layout,
+        None => guess_folder_layout(path, &layout_rules(&settings.configured_layout_rules()))
+            .chain_err(|| format!("Could not guess folder layout from {:?}", path))?,
+    };
+    let (run_info, run_params) = parse_run_folder(
+        logger,
+        path,
+        folder_layout,
+        &settings.ingest.date_format,
+        &mut Vec::new(),
+    )?;
+
+    let mut client = build_client(
+        logger,
+        &settings.web,
+        settings.trace_api,
+        &settings.archive_dir,
+    )?;
+    let result: result::Result<api::FlowCell, restson::Error> =
+        client.get(&api::ResolveFlowCellArgs {
+            project_uuid: settings.ingest.project_uuid,
+            instrument: run_info.instrument.clone(),
+            run_number: run_info.run_number,
+            flowcell: run_info.flowcell.clone(),
+        });
+    let flowcell = match result {
+        Ok(flowcell) => flowcell,
+        Err(restson::Error::HttpError(404, _)) => bail!(
+            "Flow cell {} of run folder {:?} is not registered in the project",
+            &run_info.flowcell,
+            path
+        ),
+        Err(e) => return Err(e).chain_err(|| "Could not query flow cell from server"),
+    };
+    // Final statuses are kept by `ingest`, so the expected status depends on the current one.
+    let expected = build_flow_cell(
+        &run_info,
+        &run_params,
+        path,
+        Some(flowcell.status_sequencing.clone()),
+        settings,
+    );
+
+    let mut diffs = vec![
+        FieldDiff::new("run_date", &expected.run_date, &flowcell.run_date),
+        FieldDiff::new("num_lanes", expected.num_lanes, flowcell.num_lanes),
+        FieldDiff::new(
+            "status_sequencing",
+            &expected.status_sequencing,
+            &flowcell.status_sequencing,
+        ),
+        FieldDiff::new(
+            "planned_reads",
+            or_dash(&expected.planned_reads),
+            or_dash(&flowcell.planned_reads),
+        ),
+        FieldDiff::new(
+            "current_reads",
+            or_dash(&expected.current_reads),
+            or_dash(&flowcell.current_reads),
+        ),
+    ];
+    if settings.ingest.analyze_adapters && has_index_reads(&run_info) {
+        let api::LaneIndexHistogramArray::Array(histograms) = client
+            .get(&api::ProjectFlowcellArgs {
+                project_uuid: settings.ingest.project_uuid,
+                flowcell_uuid: flowcell
+                    .sodar_uuid
+                    .chain_err(|| "Flow cell from server has no UUID")?,
+            })
+            .chain_err(|| "Could not query index histograms from server")?;
+        let expected_hists = expected_histograms(&expected, settings);
+        diffs.push(FieldDiff {
+            field: "index_histograms".to_string(),
+            local: expected_hists.to_string(),
+            server: histograms.len().to_string(),
+            agree: histograms.len() >= expected_hists,
+        });
+    }
+
+    let num_diffs = diffs.iter().filter(|diff| !diff.agree).count();
+    if settings.verify.json {
+        let value = json!({
+            "flowcell": &flowcell.vendor_id,
+            "path": path,
+            "fields": &diffs,
+            "agree": num_diffs == 0,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&value).chain_err(|| "Problem serializing to JSON")?
+        );
+    } else {
+        print!("{}", format_text(&flowcell, &diffs));
+    }
+
+    if num_diffs > 0 {
+        bail!(
+            "Run folder {:?} and flow cell {} disagree in {} field(s)",
+            path,
+            &flowcell.vendor_id,
+            num_diffs
+        )
+    }
+    info!(
+        logger,
+        "Run folder agrees with flow cell {}", &flowcell.vendor_id
+    );
+    Ok(())
+}
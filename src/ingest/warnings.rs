@@ -0,0 +1,71 @@
+//! Structured warnings collected while processing a flow cell folder.
+
+use std::fmt;
+
+/// The kind of a `Warning`.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A quirk of the folder layout or meta data files had to be worked around.
+    LayoutQuirk,
+    /// No filter files could be found for a lane.
+    MissingFilterFile,
+    /// Fewer reads than expected could be sampled.
+    LowSampleSize,
+    /// Large fraction of N calls in an index read.
+    HighNContent,
+    /// The run date could not be parsed and was taken from a fallback.
+    DateFallback,
+    /// Cycles are missing from a run folder that has been truncated for archival.
+    MissingCycles,
+    /// The flow cell was found in another project than the configured one.
+    CrossProject,
+    /// The most frequent index does not match any expected barcode.
+    UnassignedBarcode,
+    /// The flow cell has been sequenced before in another run, e.g., after re-hybridization.
+    RerunFlowCell,
+    /// The flow cell slot from the run parameters disagrees with the run folder name.
+    SlotMismatch,
+    /// A single base dominates a first template cycle, e.g., as clustering failed.
+    SkewedBaseComposition,
+    /// The number of lanes of the flow cell on the server disagrees with `RunInfo.xml`.
+    LaneCountChange,
+    /// The InterOp metrics of a lane indicate that it failed, e.g., as it was clogged.
+    FailedLane,
+    /// A read has fewer or more cycles than configured as plausible, e.g., a truncated index read.
+    SuspiciousReadLength,
+}
+
+/// A warning that occured while processing a folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Warning {
+    /// The kind of the warning.
+    pub kind: WarningKind,
+    /// Human-readable description of the warning.
+    pub message: String,
+}
+
+impl Warning {
+    /// Construct a new warning of the given `kind`.
+    pub fn new(kind: WarningKind, message: String) -> Self {
+        Self { kind, message }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:?}] {}", self.kind, self.message)
+    }
+}
+
+/// Render `warnings` as the body of a single flow cell message.
+pub fn message_body(warnings: &[Warning]) -> String {
+    let lines = warnings
+        .iter()
+        .map(|w| format!("- {}", w))
+        .collect::<Vec<String>>();
+    format!(
+        "The following warnings occured when processing the flow cell folder:\n\n{}",
+        lines.join("\n")
+    )
+}
@@ -0,0 +1,359 @@
+//! Implementation of the `watch` command that monitors directories of run folders and ingests
+//! new or changed run folders as they appear, instead of calling `ingest` periodically.
+//!
+//! The directories are scanned periodically as with `reconcile`.  On Linux, inotify wakes up
+//! the scan early when a run folder is created or a file is written at the top of a run folder
+//! (e.g., `RTAComplete.txt`).  inotify does not see changes made by other hosts on network file
+//! systems, the periodic scan catches these.
+
+use libc;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::time::Instant;
+
+use super::folder_cache::*;
+use super::service::*;
+use super::*;
+
+/// The inotify events watched for, in the roots and at the top of the run folders.
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+/// Size of the buffer for reading inotify events.
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Watcher of directories with inotify.
+struct Inotify {
+    /// The inotify file descriptor.
+    fd: libc::c_int,
+    /// The watched directories by watch descriptor.
+    watches: HashMap<libc::c_int, PathBuf>,
+    /// The watched directories.
+    watched: HashSet<PathBuf>,
+}
+
+impl Inotify {
+    /// Construct without any watched directories.
+    fn new() -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).chain_err(|| "Problem initializing inotify");
+        }
+        Ok(Self {
+            fd,
+            watches: HashMap::new(),
+            watched: HashSet::new(),
+        })
+    }
+
+    /// Whether or not the directory at `path` is watched.
+    fn is_watched(&self, path: &Path) -> bool {
+        self.watched.contains(path)
+    }
+
+    /// Watch the directory at `path`.
+    fn add_watch(&mut self, path: &Path) -> Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .chain_err(|| format!("Invalid path {:?}", path))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error())
+                .chain_err(|| format!("Problem watching {:?}", path));
+        }
+        self.watches.insert(wd, path.to_path_buf());
+        self.watched.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for events and return the directories they occured in.
+    fn wait(&mut self, timeout: Duration) -> Result<BTreeSet<PathBuf>> {
+        let mut result = BTreeSet::new();
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(result);
+            }
+            return Err(err).chain_err(|| "Problem waiting for inotify events");
+        } else if ready == 0 {
+            return Ok(result);
+        }
+
+        let mut buffer = vec![0u8; EVENT_BUFFER_SIZE];
+        loop {
+            let len = unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            if len <= 0 {
+                // Nothing left to read, the file descriptor is non-blocking.
+                break;
+            }
+            let mut offset = 0;
+            let header_size = mem::size_of::<libc::inotify_event>();
+            while offset + header_size <= len as usize {
+                let event = unsafe {
+                    (buffer.as_ptr().add(offset) as *const libc::inotify_event).read_unaligned()
+                };
+                // The name is padded with NUL bytes.
+                let name_end = (offset + header_size + event.len as usize).min(len as usize);
+                let name = buffer[(offset + header_size)..name_end]
+                    .split(|byte| *byte == 0)
+                    .next()
+                    .unwrap_or(&[]);
+                if event.mask & libc::IN_IGNORED != 0 {
+                    // The directory was removed.
+                    if let Some(path) = self.watches.remove(&event.wd) {
+                        self.watched.remove(&path);
+                    }
+                } else if name == LOCK_FILE.as_bytes() {
+                    // Locking a run folder for processing it does not change it.
+                } else if let Some(path) = self.watches.get(&event.wd) {
+                    result.insert(path.clone());
+                }
+                offset += header_size + event.len as usize;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Return whether the directory `folder` at `path` is new or changed compared to the snapshot
+/// `previous`.
+fn is_changed(previous: &FolderCache, path: &str, folder: &CachedFolder) -> bool {
+    match previous.folders.get(path) {
+        Some(cached) => {
+            cached.mtime != folder.mtime
+                || cached.run_info_mtime != folder.run_info_mtime
+                || cached.run.is_none()
+        }
+        None => true,
+    }
+}
+
+/// Main entry point for the `watch` command.
+///
+/// Scans the directories `settings.watch.roots` every `settings.watch.interval` seconds (or
+/// earlier on inotify events) and processes the new or changed run folders as with `ingest`,
+/// each at most once per `settings.watch.cooldown` seconds.  Runs until `SIGTERM` or `SIGINT`,
+/// after finishing the folder in flight.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client watch");
+    info!(logger, "Options: {:?}", settings);
+    env::set_var("RAYON_NUM_THREADS", format!("{}", settings.threads));
+
+    if settings.ingest.project_uuid.is_nil() {
+        bail!("You have to specify the project UUID");
+    }
+    if settings.watch.roots.is_empty() {
+        bail!("You have to specify at least one directory to watch");
+    }
+    if settings.watch.interval == 0 {
+        bail!("The scan interval must be positive");
+    }
+    configured_folder_layout(Path::new(""), settings)?;
+
+    let _pid_file = if settings.watch.pid_file.is_empty() {
+        None
+    } else {
+        Some(PidFile::create(
+            logger,
+            Path::new(&settings.watch.pid_file),
+        )?)
+    };
+    install_shutdown_handler()?;
+    let notifier = Notifier::from_env(logger);
+
+    let servers = select_servers(logger, settings)?;
+    let mut clients = Vec::new();
+    for server_settings in &servers {
+        clients.push(build_server_client(logger, server_settings, settings)?);
+    }
+    // The spooled payloads are sent with clients that do not spool them again.
+    let mut replay_clients = Vec::new();
+    if !settings.spool_dir.is_empty() && !settings.ingest.no_net && !settings.dry_run {
+        for server_settings in &servers {
+            replay_clients.push(build_client(
+                logger,
+                &server_settings.web,
+                settings.trace_api,
+                &settings.archive_dir,
+            )?);
+        }
+    }
+
+    let mut inotify = match Inotify::new() {
+        Ok(inotify) => Some(inotify),
+        Err(e) => {
+            warn!(
+                logger,
+                "Could not set up inotify, relying on periodic scans: {:?}", &e
+            );
+            None
+        }
+    };
+    if let Some(inotify) = inotify.as_mut() {
+        for root in &settings.watch.roots {
+            if let Err(e) = inotify.add_watch(Path::new(root)) {
+                warn!(logger, "Could not watch {}: {:?}", root, &e);
+            }
+        }
+    }
+
+    let mut state = load_state(logger, settings)?;
+
+    let rules = layout_rules(&settings.configured_layout_rules());
+    let interval = Duration::from_secs(settings.watch.interval);
+    let cooldown = Duration::from_secs(settings.watch.cooldown);
+    let mut cache = FolderCache::default();
+    // The run folders waiting to be processed and the time each folder was last processed.
+    let mut pending: BTreeSet<String> = BTreeSet::new();
+    let mut last_processed: HashMap<String, Instant> = HashMap::new();
+    let mut changed_dirs: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut last_scan: Option<Instant> = None;
+
+    notifier.notify(logger, "READY=1");
+    while !shutdown_requested() {
+        notifier.ping(logger);
+
+        // Scan the roots when due or when something changed.
+        if last_scan.is_none_or(|time| time.elapsed() >= interval) || !changed_dirs.is_empty() {
+            debug!(logger, "Scanning {:?}", &settings.watch.roots);
+            let scanned = cache.scan(
+                logger,
+                &settings.watch.roots,
+                &rules,
+                &settings.ingest.date_format,
+            );
+            for (path, folder) in &scanned.folders {
+                // Directories that are no run folder (yet) are watched for their RunInfo.xml.
+                if folder.run.is_some()
+                    && (is_changed(&cache, path, folder) || changed_dirs.contains(Path::new(path)))
+                    && pending.insert(path.clone())
+                {
+                    info!(logger, "Run folder {} is new or changed", path);
+                }
+                if let Some(inotify) = inotify.as_mut() {
+                    if !inotify.is_watched(Path::new(path)) {
+                        if let Err(e) = inotify.add_watch(Path::new(path)) {
+                            warn!(logger, "Could not watch {}: {:?}", path, &e);
+                        }
+                    }
+                }
+            }
+            cache = scanned;
+            changed_dirs.clear();
+            last_scan = Some(Instant::now());
+        }
+
+        // Send the payloads spooled while a server could not be reached before any newer ones.
+        for (server_settings, client) in servers.iter().zip(replay_clients.iter_mut()) {
+            if let Err(e) = spool::replay(
+                logger,
+                client,
+                &server_settings.web.url,
+                Path::new(&settings.spool_dir),
+                settings,
+            ) {
+                warn!(logger, "Could not replay spooled payloads: {:?}", &e);
+            }
+        }
+
+        // Process the pending folders whose cooldown is over, oldest run first.
+        let due = pending
+            .iter()
+            .filter(|path| {
+                last_processed
+                    .get(*path)
+                    .is_none_or(|time| time.elapsed() >= cooldown)
+            })
+            .cloned()
+            .collect::<Vec<String>>();
+        for path_str in order_by_run_date(logger, &due, &settings.ingest.date_format) {
+            if shutdown_requested() {
+                break;
+            }
+            notifier.notify(logger, &format!("STATUS=Processing {}", &path_str));
+            pending.remove(&path_str);
+            last_processed.insert(path_str.clone(), Instant::now());
+            let reports = servers
+                .iter()
+                .zip(clients.iter_mut())
+                .map(|(server_settings, client)| match state.as_mut() {
+                    Some(state) => process_path_with_state(
+                        logger,
+                        &path_str,
+                        client,
+                        server_settings,
+                        settings,
+                        state,
+                    ),
+                    None => process_path(logger, &path_str, client, server_settings, settings),
+                })
+                .collect::<Vec<FolderReport>>();
+            if let Some(state) = state.as_ref() {
+                state.save(Path::new(&settings.ingest.state_file))?;
+            }
+            // Creating and removing the lock file changed the modification time of the folder.
+            if let Some(folder) = cache.folders.get_mut(&path_str) {
+                folder.mtime = mtime_nanos(Path::new(&path_str));
+            }
+            for report in &reports {
+                for warning in &report.warnings {
+                    warn!(logger, "  {}", warning);
+                }
+            }
+            notifier.ping(logger);
+        }
+        notifier.notify(
+            logger,
+            &format!("STATUS=Watching, {} folder(s) pending", pending.len()),
+        );
+
+        // Wait for the next scan, the end of the next cooldown, or a change.
+        let mut timeout = interval
+            .checked_sub(last_scan.map_or(interval, |time| time.elapsed()))
+            .unwrap_or_default();
+        for path in &pending {
+            if let Some(time) = last_processed.get(path) {
+                timeout = timeout.min(cooldown.checked_sub(time.elapsed()).unwrap_or_default());
+            }
+        }
+        if let Some(ping_interval) = notifier.ping_interval() {
+            timeout = timeout.min(ping_interval);
+        }
+        if shutdown_requested() {
+            break;
+        }
+        debug!(logger, "Waiting up to {:?}", &timeout);
+        // Writing a file at the top of a run folder leaves the modification time of the folder
+        // unchanged, so the directories with events are remembered for the next scan.  Signals
+        // interrupt waiting for inotify events.
+        match inotify.as_mut() {
+            Some(inotify) => changed_dirs = inotify.wait(timeout)?,
+            None => sleep_unless_shutdown(timeout),
+        }
+    }
+
+    info!(logger, "Shutting down");
+    notifier.notify(logger, "STOPPING=1");
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Implementation of the `inspect` command that prints the parsed run folder meta data.
+
+use serde_json::{self, Value};
+use std::path::Path;
+use sxd_document::{parser, Package};
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter};
+
+use super::errors::*;
+use anonymize::Anonymizer;
+use ingest::aviti;
+use ingest::bcl_meta::*;
+use ingest::mgi;
+use ingest::nanopore;
+use settings::Settings;
+
+/// Read and parse the XML file `filename` from the run folder at `path`.
+fn parse_xml(path: &Path, filename: &str) -> Result<Package> {
+    let contents = read_meta_file(path, filename)?;
+    parser::parse(contents.trim_start_matches('\u{feff}'))
+        .chain_err(|| format!("Problem parsing XML from {}", filename))
+}
+
+/// Convert a JSON value into the equivalent YAML value.
+fn to_yaml(value: &Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s.clone()),
+        Value::Array(values) => Yaml::Array(values.iter().map(to_yaml).collect()),
+        Value::Object(map) => {
+            let mut hash = Hash::new();
+            for (key, value) in map {
+                hash.insert(Yaml::String(key.clone()), to_yaml(value));
+            }
+            Yaml::Hash(hash)
+        }
+    }
+}
+
+/// Return the detected folder layout and the parsed `RunInfo.xml` and run parameters of the
+/// run folder at `path` as JSON, together with the warnings from parsing them.
+///
+/// For AVITI run folders, both are taken from `RunParameters.json`, for MGI output directories
+/// from `BioInfo.csv`, for MinKNOW run directories from the final summary and JSON report.
+pub fn inspect_folder(logger: &slog::Logger, path: &Path, settings: &Settings) -> Result<Value> {
+    let rules = layout_rules(&settings.configured_layout_rules());
+    let folder_layout = guess_folder_layout(path, &rules)
+        .chain_err(|| format!("Could not guess folder layout from {:?}", path))?;
+    let mut warnings = Vec::new();
+    let (run_info, mut run_params) = match folder_layout {
+        FolderLayout::Aviti => aviti::parse_run_folder(path, &mut warnings)?,
+        FolderLayout::Mgi => mgi::parse_run_folder(path, &mut warnings)?,
+        FolderLayout::Nanopore => nanopore::parse_run_folder(path, &mut warnings)?,
+        _ => {
+            let info_pkg = parse_xml(path, "RunInfo.xml")?;
+            let param_pkg = parse_xml(path, run_parameters_file(folder_layout))?;
+            process_xml(
+                logger,
+                folder_layout,
+                &info_pkg.as_document(),
+                &param_pkg.as_document(),
+                &settings.ingest.date_format,
+                &mut warnings,
+            )?
+        }
+    };
+    resolve_flowcell_slot(path, &run_info, &mut run_params, &mut warnings);
+
+    Ok(json!({
+        "path": path.to_string_lossy(),
+        "folder_layout": folder_layout,
+        "run_info": run_info,
+        "run_parameters": run_params,
+        "warnings": warnings,
+    }))
+}
+
+/// Main entry point for the `inspect` command.
+///
+/// Prints the result of `inspect_folder()` to stdout, anonymized with `--anonymize`.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client inspect");
+    let path = Path::new(&settings.inspect.path);
+    if !path.is_dir() {
+        bail!("Run folder {:?} does not exist", path);
+    }
+
+    let result = inspect_folder(logger, path, settings)?;
+    let mut anonymizer = Anonymizer::new(settings);
+    anonymizer.add_run_folder(logger, path, settings);
+    match settings.inspect.format.as_str() {
+        "json" => println!(
+            "{}",
+            anonymizer.apply(
+                &serde_json::to_string_pretty(&result)
+                    .chain_err(|| "Problem serializing to JSON")?
+            )
+        ),
+        "yaml" => {
+            let mut out = String::new();
+            YamlEmitter::new(&mut out)
+                .dump(&to_yaml(&result))
+                .chain_err(|| "Problem serializing to YAML")?;
+            println!("{}", anonymizer.apply(&out));
+        }
+        format => bail!("Invalid output format {:?}", format),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! The Digestiflow CLI library.
+//!
+//! The command implementations live here such that they can be used by the `digestiflow-cli`
+//! program as well as by tests, including the mock of the Digestiflow API in `testing`.
+
+// `error_chain!` can recurse deeply.
+#![recursion_limit = "1024"]
+
+extern crate byteorder;
+extern crate chrono;
+extern crate clap;
+extern crate config;
+#[macro_use]
+extern crate derivative;
+#[macro_use]
+extern crate error_chain;
+extern crate flate2;
+extern crate futures;
+extern crate glob;
+extern crate hostname;
+extern crate hyper;
+extern crate hyper_tls;
+extern crate libc;
+extern crate md5;
+extern crate rand;
+extern crate rand_xorshift;
+extern crate rayon;
+extern crate regex;
+extern crate restson;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate shellexpand;
+#[macro_use]
+extern crate slog;
+extern crate sxd_document;
+extern crate sxd_xpath;
+extern crate tokio_core;
+extern crate twox_hash;
+extern crate uuid;
+extern crate yaml_rust;
+
+pub mod anonymize;
+pub mod chaos;
+pub mod cli;
+pub mod config_init;
+pub mod config_validate;
+pub mod detect;
+pub mod ingest;
+pub mod inspect;
+pub mod settings;
+pub mod support_bundle;
+pub mod testing;
+
+/// Global module with error handlers.
+pub mod errors {
+    // Create the Error, ErrorKind, ResultExt, and Result types
+    error_chain! {}
+}
+
+pub use errors::*;
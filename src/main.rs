@@ -3,38 +3,15 @@
 //! The code in this module parses the command line, sets up logging, and then dispatches to the
 //! sub modules implementing the commands.
 
-// `error_chain!` can recurse deeply.
-#![recursion_limit = "1024"]
-
-extern crate byteorder;
 #[macro_use]
 extern crate clap;
-extern crate chrono;
-extern crate config;
-#[macro_use]
-extern crate derivative;
+extern crate digestiflow_cli;
 #[macro_use]
 extern crate error_chain;
-extern crate flate2;
-extern crate glob;
-extern crate rand;
-extern crate rand_xorshift;
-extern crate rayon;
-extern crate regex;
-extern crate restson;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate shellexpand;
 #[macro_use]
 extern crate slog;
 extern crate slog_async;
 extern crate slog_term;
-extern crate sxd_document;
-extern crate sxd_xpath;
-
-mod ingest;
-mod settings;
 
 use slog::Drain;
 
@@ -42,17 +19,14 @@ use std::result;
 use std::sync::atomic::Ordering;
 use std::sync::{atomic, Arc};
 
-/// Global module with error handlers.
-mod errors {
-    // Create the Error, ErrorKind, ResultExt, and Result types
-    error_chain! {}
-}
-
-pub use errors::*;
+use digestiflow_cli::errors::*;
+use digestiflow_cli::{
+    chaos, cli, config_init, config_validate, detect, ingest, inspect, support_bundle,
+};
 
 use clap::{App, ArgMatches};
 
-use settings::Settings;
+use digestiflow_cli::settings::Settings;
 
 /// Custom `slog` Drain logic
 struct RuntimeLevelFilter<D> {
@@ -86,6 +60,17 @@ where
     }
 }
 
+/// Return the settings for the commands that ingest run folders, with the ingest preferences of
+/// the project fetched from the server as defaults beneath the local configuration.
+fn ingest_settings(logger: &slog::Logger, matches: &ArgMatches) -> Result<Settings> {
+    let settings = Settings::new(matches).expect("Problem with obtaining configuration");
+    match ingest::fetch_project_settings(logger, &settings)? {
+        Some(project) => Ok(Settings::with_project_settings(matches, Some(&project))
+            .expect("Problem with obtaining configuration")),
+        None => Ok(settings),
+    }
+}
+
 /// Program entry point after using `clap` for parsing command line arguments, called by `main()`.
 fn run(matches: ArgMatches) -> Result<()> {
     // Logging setup ------------------------------------------------------------------------------
@@ -117,17 +102,158 @@ fn run(matches: ArgMatches) -> Result<()> {
 
     // Command Line Handling ----------------------------------------------------------------------
 
+    // Enable fault injection in developer mode.
+    let (command, m) = cli::command_path(&matches);
+    if let Some(rate) = m.value_of("chaos") {
+        let rate = rate
+            .parse::<f64>()
+            .ok()
+            .filter(|rate| *rate >= 0.0 && *rate <= 1.0)
+            .chain_err(|| format!("Invalid --chaos rate {:?}, expected 0 to 1", rate))?;
+        let seed = match m.value_of("chaos_seed") {
+            Some(seed) => seed
+                .parse::<u64>()
+                .chain_err(|| format!("Invalid --chaos-seed {:?}", seed))?,
+            None => 42,
+        };
+        chaos::enable(&logger, rate, seed);
+    }
+
     // Dispatch commands from command line.
-    match matches.subcommand() {
-        // cnvetti cmd <coverage|normalize|...>
-        ("ingest", Some(_m)) => ingest::run(
+    match command.as_str() {
+        // digestiflow-cli flowcells <ingest|reconcile|...>
+        "flowcells ingest" => ingest::run(&logger, &ingest_settings(&logger, &matches)?)
+            .chain_err(|| "Could not execute 'ingest' command")?,
+        "flowcells reconcile" => {
+            ingest::reconcile::run(&logger, &ingest_settings(&logger, &matches)?)
+                .chain_err(|| "Could not execute 'reconcile' command")?
+        }
+        "flowcells backfill" => {
+            ingest::backfill::run(&logger, &ingest_settings(&logger, &matches)?)
+                .chain_err(|| "Could not execute 'backfill' command")?
+        }
+        "flowcells import" => ingest::import::run(&logger, &ingest_settings(&logger, &matches)?)
+            .chain_err(|| "Could not execute 'import' command")?,
+        "flowcells watch" => ingest::watch::run(&logger, &ingest_settings(&logger, &matches)?)
+            .chain_err(|| "Could not execute 'watch' command")?,
+        "flowcells history" => ingest::history::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'history' command")?,
+        "flowcells list" => ingest::list::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'list' command")?,
+        "flowcells show" => ingest::show::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'show' command")?,
+        "flowcells set-status" => ingest::set_status::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'set-status' command")?,
+        "flowcells delete" => ingest::delete::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'delete' command")?,
+        "flowcells status" => ingest::status::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'status' command")?,
+        "flowcells samplesheet" => ingest::samplesheet_cmd::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'samplesheet' command")?,
+        "flowcells demux" => ingest::demux::run(
             &logger,
             &Settings::new(&matches).expect("Problem with obtaining configuration"),
         )
-        .chain_err(|| "Could not execute 'ingest' command")?,
-        _ => bail!("Invalid command: {}", matches.subcommand().0),
+        .chain_err(|| "Could not execute 'demux' command")?,
+        "flowcells report" => ingest::compare::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'report' command")?,
+        "flowcells verify" => ingest::verify::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'verify' command")?,
+        // digestiflow-cli folders <detect|inspect|support-bundle|...>
+        "folders detect" => detect::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'detect' command")?,
+        "folders inspect" => inspect::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'inspect' command")?,
+        "folders support-bundle" => support_bundle::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'support-bundle' command")?,
+        "folders export" => ingest::export::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'export' command")?,
+        "folders archive-check" => ingest::archive_check::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'archive-check' command")?,
+        "folders manifest" => ingest::manifest::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'manifest' command")?,
+        // digestiflow-cli api <check|healthcheck|replay>
+        "api check" => ingest::check::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'check' command")?,
+        "api healthcheck" => {
+            let code = ingest::healthcheck::run(
+                &logger,
+                &Settings::new(&matches).expect("Problem with obtaining configuration"),
+            )
+            .chain_err(|| "Could not execute 'healthcheck' command")?;
+            if code != ingest::healthcheck::EXIT_HEALTHY {
+                ::std::process::exit(code);
+            }
+        }
+        "api replay" => ingest::spool::run(
+            &logger,
+            &Settings::new(&matches).expect("Problem with obtaining configuration"),
+        )
+        .chain_err(|| "Could not execute 'replay' command")?,
+        // digestiflow-cli config <init|validate>
+        "config init" => {
+            config_init::run(&logger, &matches).chain_err(|| "Could not execute 'init' command")?
+        }
+        "config validate" => config_validate::run(&logger, &matches)
+            .chain_err(|| "Could not execute 'validate' command")?,
+        _ => bail!("Invalid command: {}", command),
     }
 
+    if chaos::is_enabled() {
+        info!(
+            logger,
+            "Injected {} fault(s) with --chaos",
+            chaos::injected()
+        );
+    }
     info!(logger, "All done. Have a nice day.");
 
     Ok(())
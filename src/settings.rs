@@ -2,12 +2,17 @@
 //! command line arguments.
 
 use clap::ArgMatches;
-use config::{Config, ConfigError, Environment, File};
+use cli::command_path;
+use config::{Config, ConfigError, Environment, File, Value};
+use ingest::bcl_meta::{FolderLayout, LayoutRule};
+use ingest::ProjectSettings;
 use shellexpand;
+use std::collections::HashMap;
 use std::path::Path;
+use uuid::Uuid;
 
 /// Configuration for the REST API in Digestiflow Web.
-#[derive(Derivative, Deserialize)]
+#[derive(Derivative, Clone, Deserialize)]
 #[derivative(Debug)]
 pub struct Web {
     /// The URL to Digestiflow Web. `$url/api` must be the URL to the API.
@@ -15,6 +20,15 @@ pub struct Web {
     /// The API authentication token.
     #[derivative(Debug = "ignore")]
     pub token: String,
+    /// Command printing the API token to stdout, e.g., fetching it from Vault, run at startup
+    /// and whenever the server rejects the token; `token` is used if empty.
+    pub token_command: String,
+    /// Maximal number of API requests per second, no limit if not positive.
+    pub requests_per_second: f64,
+    /// Version of the API payload schema expected by the server, 1 for servers that only
+    /// accept the index histogram fields from before the sampling statistics were added, 2 for
+    /// servers that do not know RTA version 4.
+    pub api_schema: u32,
 }
 
 impl Default for Web {
@@ -23,15 +37,27 @@ impl Default for Web {
         return Self {
             url: "".to_string(),
             token: "".to_string(),
+            token_command: "".to_string(),
+            requests_per_second: 0.0,
+            api_schema: 3,
         };
     }
 }
 
+/// A folder layout defined in the `[layouts]` section of the configuration.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayoutDefinition {
+    /// Glob patterns relative to the run folder that must all match an existing path.
+    pub markers: Vec<String>,
+    /// The built-in layout whose parser is used for the run parameters, e.g., `NextSeq2000`.
+    pub parser: FolderLayout,
+}
+
 /// Arguments/configuration for the `ingest` command.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct IngestArgs {
-    /// UUID of the project to import into.
-    pub project_uuid: String,
+    /// UUID of the project to import into, nil if not given.
+    pub project_uuid: Uuid,
     /// Vector of paths of flow cells to analyze.
     pub path: Vec<String>,
     /// Whether or not to register new flow cells via API.
@@ -42,6 +68,8 @@ pub struct IngestArgs {
     pub analyze_adapters: bool,
     /// Whether or not to to force adapter sequence histogram computation.
     pub force_analyze_adapters: bool,
+    /// Whether or not to postpone adapter analysis until the copy-complete marker file exists.
+    pub wait_for_copy_complete: bool,
     /// Whether or not to post adapter sequence histogram via API.
     pub post_adapters: bool,
     /// String to use for machine operator when creating flow cell via API.
@@ -50,34 +78,611 @@ pub struct IngestArgs {
     pub sample_tiles: i32,
     /// Number of reads to sample from each tile.
     pub sample_reads_per_tile: i32,
+    /// Fraction of each lane's clusters to sample, overrides `sample_reads_per_tile` if positive.
+    pub sample_fraction: f64,
+    /// Fraction of each lane's tiles to sample, at least one tile per surface and swath, a single
+    /// tile if not positive.
+    pub tile_fraction: f64,
     /// Skip if sequencing status is a final state.
     pub skip_if_status_final: bool,
     /// Minimum fraction of reads to show an index for index histogram to be computed.
     pub min_index_fraction: f64,
+    /// Maximal number of most frequent index sequences to post per histogram, all if 0.
+    pub max_histogram_entries: usize,
+    /// Maximal number of cycles to sample for each index read, all if missing or not positive.
+    pub max_index_cycles: Vec<i32>,
+    /// Whether or not to also post a histogram of all lanes merged, as lane 0.
+    pub merge_lanes: bool,
+    /// Whether or not to sample FASTQ files for runs without base calls.
+    pub sample_fastq: bool,
+    /// Maximal memory for sampling index reads, e.g., `6G`, unlimited if empty or 0.
+    pub max_memory: String,
+    /// Maximal bytes per second read from base call files, e.g., `20M`, unlimited if empty or 0.
+    pub max_read_rate: String,
+    /// Folder layouts to use instead of guessing them, each either `LAYOUT` for all paths or
+    /// `PATH=LAYOUT` for a single path.
+    pub folder_layout: Vec<String>,
+    /// Format of the run date in `RunInfo.xml` (e.g., `%d.%m.%Y`), tried before the built-in
+    /// formats, none if empty.
+    pub date_format: String,
+    /// Command for decompressing ORA files to stdout, the path is appended.
+    pub ora_command: String,
+    /// Local scratch directory to copy the files needed for sampling to, sampling from the run
+    /// folder directly if empty.
+    pub staging_dir: String,
+    /// Fraction of reads above which an unexpected most frequent index triggers a warning.
+    pub unassigned_barcode_threshold: f64,
+    /// Path to JSON file to write per-folder results to, disabled if empty.
+    pub json_output: String,
+    /// Whether or not to compute the size and file counts of the folder for the results.
+    pub folder_size: bool,
+    /// Whether or not to post collected warnings as a flow cell message via API.
+    pub post_warnings: bool,
+    /// Whether or not to check the per-lane quality from the InterOp metrics.
+    pub lane_quality: bool,
+    /// Fraction of clusters passing filter below which a lane is flagged as failed.
+    pub lane_min_pf_fraction: f64,
+    /// Error rate in percent above which a lane is flagged as failed.
+    pub lane_max_error_rate: f64,
+    /// Cluster density relative to the median of the lanes below which a lane is flagged as
+    /// failed.
+    pub lane_min_relative_density: f64,
+    /// Number of cycles below which an index read triggers a warning, not checked if not
+    /// positive.
+    pub index_read_min_cycles: i32,
+    /// Number of cycles above which an index read triggers a warning, not checked if not
+    /// positive.
+    pub index_read_max_cycles: i32,
+    /// Number of cycles below which a template read triggers a warning, not checked if not
+    /// positive.
+    pub template_read_min_cycles: i32,
+    /// Whether or not to update a flow cell found in another project than `project_uuid`.
+    pub allow_cross_project: bool,
+    /// Whether or not to update a flow cell whose number of lanes differs from `RunInfo.xml`.
+    pub allow_lane_count_change: bool,
+    /// Path to JSON file with the folders that failed permanently, disabled if empty.
+    pub skip_list: String,
+    /// Whether or not to process the folders on the skip list again.
+    pub retry_skipped: bool,
+    /// Path to JSON file with the state of the processed folders for skipping unchanged ones,
+    /// disabled if empty.
+    pub state_file: String,
+    /// Whether or not to remove stale lock files left in run folders by crashed calls.
+    pub break_stale_locks: bool,
+    /// Maximal number of folders to register, update, or fail per call, no limit if not
+    /// positive.
+    pub max_folders: i32,
+    /// Whether or not to process the folders without network access, capturing the payloads
+    /// instead of sending them.
+    pub no_net: bool,
+    /// Path to JSON file with the flow cell as fetched from the API, for `no_net`.
+    pub flowcell_json: String,
+    /// Template for the label of registered flow cells, see `RunHints::render()`.
+    pub label_template: String,
+    /// Template for the description of registered flow cells, disabled if empty.
+    pub description_template: String,
+    /// Template for the operator of registered flow cells, `operator` is used if empty.
+    pub operator_template: String,
+    /// Whether or not to fetch the ingest preferences of the project from the server and use them
+    /// as defaults beneath the local configuration.  Off by default, as the endpoint is not part
+    /// of every server.
+    pub project_defaults: bool,
 }
 
 impl Default for IngestArgs {
     /// Return defaults for `ingest` command arguments.
     fn default() -> Self {
         return IngestArgs {
-            project_uuid: "".to_string(),
+            project_uuid: Uuid::nil(),
             path: Vec::new(),
             register: true,
             update: true,
             analyze_adapters: true,
             force_analyze_adapters: false,
+            wait_for_copy_complete: false,
             post_adapters: true,
             operator: "".to_string(),
             sample_tiles: 1,
             sample_reads_per_tile: 1_000_000,
+            sample_fraction: 0.0,
+            tile_fraction: 0.0,
             skip_if_status_final: true,
             min_index_fraction: 0.001,
+            max_histogram_entries: 0,
+            max_index_cycles: Vec::new(),
+            merge_lanes: false,
+            sample_fastq: false,
+            max_memory: "".to_string(),
+            max_read_rate: "".to_string(),
+            folder_layout: Vec::new(),
+            date_format: "".to_string(),
+            ora_command: "orad -c".to_string(),
+            staging_dir: "".to_string(),
+            unassigned_barcode_threshold: 0.1,
+            json_output: "".to_string(),
+            folder_size: false,
+            post_warnings: false,
+            lane_quality: false,
+            lane_min_pf_fraction: 0.5,
+            lane_max_error_rate: 5.0,
+            lane_min_relative_density: 0.5,
+            index_read_min_cycles: 6,
+            index_read_max_cycles: 24,
+            template_read_min_cycles: 25,
+            allow_cross_project: false,
+            allow_lane_count_change: false,
+            skip_list: "".to_string(),
+            retry_skipped: false,
+            state_file: "".to_string(),
+            break_stale_locks: false,
+            max_folders: 0,
+            no_net: false,
+            flowcell_json: "".to_string(),
+            label_template: "{experiment_name}".to_string(),
+            description_template: "".to_string(),
+            operator_template: "".to_string(),
+            project_defaults: false,
         };
     }
 }
 
+/// Arguments/configuration for the `reconcile` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReconcileArgs {
+    /// Directories whose sub directories are the run folders to reconcile with.
+    pub roots: Vec<String>,
+    /// Whether or not to fix the discrepancies found.
+    pub fix: bool,
+    /// Path to JSON file with the snapshot of the directories below `roots`, disabled if empty.
+    pub folder_cache: String,
+}
+
+impl Default for ReconcileArgs {
+    /// Return defaults for `reconcile` command arguments.
+    fn default() -> Self {
+        ReconcileArgs {
+            roots: Vec::new(),
+            fix: false,
+            folder_cache: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `backfill` command, which also uses the `ingest` ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackfillArgs {
+    /// Path to JSON file recording the processed folders for resuming, disabled if empty.
+    pub journal: String,
+    /// Number of folders to process in parallel.
+    pub jobs: i32,
+}
+
+impl Default for BackfillArgs {
+    /// Return defaults for `backfill` command arguments.
+    fn default() -> Self {
+        BackfillArgs {
+            journal: "".to_string(),
+            jobs: 2,
+        }
+    }
+}
+
+/// Arguments/configuration for the `import` command, which also uses the `ingest` ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportArgs {
+    /// Paths to the JSON files written by the `export` command.
+    pub path: Vec<String>,
+}
+
+impl Default for ImportArgs {
+    /// Return defaults for `import` command arguments.
+    fn default() -> Self {
+        ImportArgs { path: Vec::new() }
+    }
+}
+
+/// Arguments/configuration for the `watch` command, which also uses the `ingest` ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchArgs {
+    /// Directories whose sub directories are the run folders to watch.
+    pub roots: Vec<String>,
+    /// Seconds between two scans of the roots.
+    pub interval: u64,
+    /// Minimal seconds between two ingests of the same run folder.
+    pub cooldown: u64,
+    /// File to write the process ID to while running, disabled if empty.
+    pub pid_file: String,
+}
+
+impl Default for WatchArgs {
+    /// Return defaults for `watch` command arguments.
+    fn default() -> Self {
+        WatchArgs {
+            roots: Vec::new(),
+            interval: 300,
+            cooldown: 600,
+            pid_file: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `list` command, which takes the project from the `ingest`
+/// ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListArgs {
+    /// Only list flow cells with this sequencing status, all if empty.
+    pub status_sequencing: String,
+    /// Only list flow cells of this instrument, all if empty.
+    pub instrument: String,
+    /// Only list flow cells with a run date on or after this date (`YYYY-MM-DD`), all if empty.
+    pub since: String,
+    /// Output format, either "table" or "json".
+    pub format: String,
+}
+
+impl Default for ListArgs {
+    /// Return defaults for `list` command arguments.
+    fn default() -> Self {
+        ListArgs {
+            status_sequencing: "".to_string(),
+            instrument: "".to_string(),
+            since: "".to_string(),
+            format: "table".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `show` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShowArgs {
+    /// Vendor ID or UUID of the flow cell to show.
+    pub flowcell: String,
+    /// Instrument of the flow cell for resolving its vendor ID, looked up if empty.
+    pub instrument: String,
+    /// Run number of the flow cell for resolving its vendor ID, looked up if not positive.
+    pub run_number: i32,
+    /// Whether or not to print JSON instead of human-readable text.
+    pub json: bool,
+}
+
+impl Default for ShowArgs {
+    /// Return defaults for `show` command arguments.
+    fn default() -> Self {
+        ShowArgs {
+            flowcell: "".to_string(),
+            instrument: "".to_string(),
+            run_number: 0,
+            json: false,
+        }
+    }
+}
+
+/// Arguments/configuration for the `set-status` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetStatusArgs {
+    /// Vendor ID or UUID of the flow cell to change.
+    pub flowcell: String,
+    /// Instrument of the flow cell for resolving its vendor ID, looked up if empty.
+    pub instrument: String,
+    /// Run number of the flow cell for resolving its vendor ID, looked up if not positive.
+    pub run_number: i32,
+    /// The new sequencing status, unchanged if empty.
+    pub status_sequencing: String,
+    /// The new conversion status, unchanged if empty.
+    pub status_conversion: String,
+    /// The new delivery status, unchanged if empty.
+    pub status_delivery: String,
+    /// Instead of `flowcell`, change all flow cells matching these `FIELD=VALUE` filters.
+    pub filter: Vec<String>,
+    /// Instead of `flowcell`, change all flow cells with a run date older than this age (e.g.,
+    /// `90d` or `12w`), all if empty.
+    pub older_than: String,
+}
+
+impl Default for SetStatusArgs {
+    /// Return defaults for `set-status` command arguments.
+    fn default() -> Self {
+        SetStatusArgs {
+            flowcell: "".to_string(),
+            instrument: "".to_string(),
+            run_number: 0,
+            status_sequencing: "".to_string(),
+            status_conversion: "".to_string(),
+            status_delivery: "".to_string(),
+            filter: Vec::new(),
+            older_than: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `delete` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeleteArgs {
+    /// Vendor ID or UUID of the flow cell to delete.
+    pub flowcell: String,
+    /// Instrument of the flow cell for resolving its vendor ID, looked up if empty.
+    pub instrument: String,
+    /// Run number of the flow cell for resolving its vendor ID, looked up if not positive.
+    pub run_number: i32,
+    /// Whether to delete without asking for confirmation.
+    pub yes: bool,
+}
+
+impl Default for DeleteArgs {
+    /// Return defaults for `delete` command arguments.
+    fn default() -> Self {
+        DeleteArgs {
+            flowcell: "".to_string(),
+            instrument: "".to_string(),
+            run_number: 0,
+            yes: false,
+        }
+    }
+}
+
+/// Arguments/configuration for the `status` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusArgs {
+    /// Paths of the run folders to check.
+    pub path: Vec<String>,
+}
+
+impl Default for StatusArgs {
+    /// Return defaults for `status` command arguments.
+    fn default() -> Self {
+        StatusArgs { path: Vec::new() }
+    }
+}
+
+/// Arguments/configuration for the `samplesheet` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SampleSheetArgs {
+    /// Path of the run folder to write the sample sheet for.
+    pub path: String,
+    /// Path of the sample sheet to write, `SampleSheet.csv` in the run folder if empty, `-` for
+    /// stdout.
+    pub output: String,
+    /// Whether or not to overwrite an existing sample sheet.
+    pub force: bool,
+    /// Format of the sample sheet, `v1` for bcl2fastq or `v2` for BCL Convert.
+    pub format: String,
+}
+
+impl Default for SampleSheetArgs {
+    /// Return defaults for `samplesheet` command arguments.
+    fn default() -> Self {
+        SampleSheetArgs {
+            path: "".to_string(),
+            output: "".to_string(),
+            force: false,
+            format: "v1".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `demux` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DemuxArgs {
+    /// Path of the run folder to demultiplex.
+    pub path: String,
+    /// Demultiplexer to run, `bcl-convert` or `bcl2fastq`.
+    pub tool: String,
+    /// Command line for running the demultiplexer, e.g., with the path to the binary and
+    /// additional arguments; the name of the tool if empty.
+    pub command: String,
+    /// Directory to write the FASTQ files to, `Unaligned` in the run folder if empty.
+    pub output_dir: String,
+    /// Whether or not to write into an existing non-empty output directory.
+    pub force: bool,
+}
+
+impl Default for DemuxArgs {
+    /// Return defaults for `demux` command arguments.
+    fn default() -> Self {
+        DemuxArgs {
+            path: "".to_string(),
+            tool: "bcl-convert".to_string(),
+            command: "".to_string(),
+            output_dir: "".to_string(),
+            force: false,
+        }
+    }
+}
+
+/// Arguments/configuration for the `report` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReportArgs {
+    /// Paths of the run folders to compare.
+    pub compare: Vec<String>,
+    /// Output format, `text` or `html`.
+    pub format: String,
+}
+
+impl Default for ReportArgs {
+    /// Return defaults for `report` command arguments.
+    fn default() -> Self {
+        ReportArgs {
+            compare: Vec::new(),
+            format: "text".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `verify` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VerifyArgs {
+    /// Path of the run folder to verify.
+    pub path: String,
+    /// Whether or not to print the comparison as JSON.
+    pub json: bool,
+}
+
+impl Default for VerifyArgs {
+    /// Return defaults for `verify` command arguments.
+    fn default() -> Self {
+        VerifyArgs {
+            path: "".to_string(),
+            json: false,
+        }
+    }
+}
+
+/// Arguments/configuration for the `history` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryArgs {
+    /// Vendor ID of the flow cell to show the history of, all flow cells if empty.
+    pub flowcell: String,
+    /// Path to the run folder to show the history of, all folders if empty.
+    pub folder: String,
+}
+
+impl Default for HistoryArgs {
+    /// Return defaults for `history` command arguments.
+    fn default() -> Self {
+        HistoryArgs {
+            flowcell: "".to_string(),
+            folder: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `detect` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DetectArgs {
+    /// Path to the run folder to inspect.
+    pub path: String,
+}
+
+impl Default for DetectArgs {
+    /// Return defaults for `detect` command arguments.
+    fn default() -> Self {
+        DetectArgs {
+            path: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `inspect` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InspectArgs {
+    /// Path to the run folder to inspect.
+    pub path: String,
+    /// Output format, either "json" or "yaml".
+    pub format: String,
+}
+
+impl Default for InspectArgs {
+    /// Return defaults for `inspect` command arguments.
+    fn default() -> Self {
+        InspectArgs {
+            path: "".to_string(),
+            format: "json".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `support-bundle` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SupportBundleArgs {
+    /// Path to the run folder to collect information on.
+    pub path: String,
+    /// Path to the `.tar.gz` file to write, derived from the run folder name if empty.
+    pub output: String,
+}
+
+impl Default for SupportBundleArgs {
+    /// Return defaults for `support-bundle` command arguments.
+    fn default() -> Self {
+        SupportBundleArgs {
+            path: "".to_string(),
+            output: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `export` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportArgs {
+    /// Path to the run folder to export.
+    pub path: String,
+    /// Path to the JSON file to write, stdout if empty or `-`.
+    pub output: String,
+}
+
+impl Default for ExportArgs {
+    /// Return defaults for `export` command arguments.
+    fn default() -> Self {
+        ExportArgs {
+            path: "".to_string(),
+            output: "".to_string(),
+        }
+    }
+}
+
+/// Arguments/configuration for the `healthcheck` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthcheckArgs {
+    /// Paths to check in addition to the configured ingest, watch, and reconcile paths.
+    pub path: Vec<String>,
+}
+
+impl Default for HealthcheckArgs {
+    /// Return defaults for `healthcheck` command arguments.
+    fn default() -> Self {
+        HealthcheckArgs { path: Vec::new() }
+    }
+}
+
+/// Arguments/configuration for the `archive-check` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveCheckArgs {
+    /// Paths of the run folders to check.
+    pub path: Vec<String>,
+    /// Whether or not to print the results as JSON.
+    pub json: bool,
+}
+
+impl Default for ArchiveCheckArgs {
+    /// Return defaults for `archive-check` command arguments.
+    fn default() -> Self {
+        ArchiveCheckArgs {
+            path: Vec::new(),
+            json: false,
+        }
+    }
+}
+
+/// Arguments/configuration for the `manifest` command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestArgs {
+    /// Path of the run folder to write the manifest for.
+    pub path: String,
+    /// Path of the manifest, `manifest.ALGORITHM` in the run folder if empty, `-` for stdout.
+    pub output: String,
+    /// Checksum algorithm, `xxh64` or `md5`.
+    pub algorithm: String,
+    /// Whether or not to verify the files against the manifest instead of writing it.
+    pub verify: bool,
+    /// Whether or not to overwrite an existing manifest.
+    pub force: bool,
+}
+
+impl Default for ManifestArgs {
+    /// Return defaults for `manifest` command arguments.
+    fn default() -> Self {
+        ManifestArgs {
+            path: "".to_string(),
+            output: "".to_string(),
+            algorithm: "xxh64".to_string(),
+            verify: false,
+            force: false,
+        }
+    }
+}
+
 /// Overall settings.
-#[derive(Debug, Deserialize)]
+#[derive(Derivative, Clone, Deserialize)]
+#[derivative(Debug)]
 pub struct Settings {
     /// Further increase log output verbosity,
     pub debug: bool,
@@ -85,18 +690,82 @@ pub struct Settings {
     pub verbose: bool,
     /// Decrease log output to a minimum.
     pub quiet: bool,
-    /// Number of threads to use for parallel processing.
+    /// Number of threads to use for parallel processing, 0 for one per CPU core.
     pub threads: i32,
     /// Seed value to use for random number generator.
     pub seed: u64,
     /// Whether or not to write out API token into log file.
     pub log_token: bool,
+    /// Whether or not to log each API call and the API time per folder.
+    pub trace_api: bool,
+    /// Directory to write each payload sent successfully to the API to, disabled if empty.
+    pub archive_dir: String,
+    /// Directory to write the payloads to that could not be sent as the server could not be
+    /// reached, for sending them later with `replay`, disabled if empty.
+    pub spool_dir: String,
+    /// Whether or not to replace instrument IDs, operator names, and experiment names by
+    /// pseudonyms in support bundles and exports.
+    pub anonymize: bool,
+    /// Salt for computing the pseudonyms with `anonymize`.
+    #[derivative(Debug = "ignore")]
+    pub anonymize_salt: String,
     /// Configuration regarding Digestiflow Web.
     pub web: Web,
+    /// Configuration of a secondary Digestiflow Web server, disabled if the URL is empty.
+    pub secondary_web: Web,
+    /// How to use the secondary server, either `"failover"` or `"dual"`.
+    pub server_mode: String,
     /// If activated, do not perform any modifying operations.
     pub dry_run: bool,
+    /// Additional folder layout detection rules, checked before the built-in ones.
+    pub layout_rules: Vec<LayoutRule>,
+    /// Additional folder layouts by name, checked after `layout_rules` in the order of their
+    /// names.
+    pub layouts: HashMap<String, LayoutDefinition>,
     /// Arguments to the `ingest` command.
     pub ingest: IngestArgs,
+    /// Arguments to the `reconcile` command.
+    pub reconcile: ReconcileArgs,
+    /// Arguments to the `backfill` command.
+    pub backfill: BackfillArgs,
+    /// Arguments to the `import` command.
+    pub import: ImportArgs,
+    /// Arguments to the `watch` command.
+    pub watch: WatchArgs,
+    /// Arguments to the `history` command.
+    pub history: HistoryArgs,
+    /// Arguments to the `list` command.
+    pub list: ListArgs,
+    /// Arguments to the `show` command.
+    pub show: ShowArgs,
+    /// Arguments to the `set-status` command.
+    pub set_status: SetStatusArgs,
+    /// Arguments to the `delete` command.
+    pub delete: DeleteArgs,
+    /// Arguments to the `status` command.
+    pub status: StatusArgs,
+    /// Arguments to the `samplesheet` command.
+    pub samplesheet: SampleSheetArgs,
+    /// Arguments to the `demux` command.
+    pub demux: DemuxArgs,
+    /// Arguments to the `report` command.
+    pub report: ReportArgs,
+    /// Arguments to the `verify` command.
+    pub verify: VerifyArgs,
+    /// Arguments to the `detect` command.
+    pub detect: DetectArgs,
+    /// Arguments to the `inspect` command.
+    pub inspect: InspectArgs,
+    /// Arguments to the `support-bundle` command.
+    pub support_bundle: SupportBundleArgs,
+    /// Arguments to the `export` command.
+    pub export: ExportArgs,
+    /// Arguments to the `healthcheck` command.
+    pub healthcheck: HealthcheckArgs,
+    /// Arguments to the `archive-check` command.
+    pub archive_check: ArchiveCheckArgs,
+    /// Arguments to the `manifest` command.
+    pub manifest: ManifestArgs,
 }
 
 impl Default for Settings {
@@ -106,23 +775,165 @@ impl Default for Settings {
             debug: false,
             verbose: false,
             quiet: false,
-            threads: 1,
+            threads: 0,
             web: Web::default(),
+            secondary_web: Web::default(),
+            server_mode: "failover".to_string(),
             ingest: IngestArgs::default(),
+            reconcile: ReconcileArgs::default(),
+            backfill: BackfillArgs::default(),
+            import: ImportArgs::default(),
+            watch: WatchArgs::default(),
+            history: HistoryArgs::default(),
+            list: ListArgs::default(),
+            show: ShowArgs::default(),
+            set_status: SetStatusArgs::default(),
+            delete: DeleteArgs::default(),
+            status: StatusArgs::default(),
+            samplesheet: SampleSheetArgs::default(),
+            demux: DemuxArgs::default(),
+            report: ReportArgs::default(),
+            verify: VerifyArgs::default(),
+            detect: DetectArgs::default(),
+            inspect: InspectArgs::default(),
+            support_bundle: SupportBundleArgs::default(),
+            export: ExportArgs::default(),
+            healthcheck: HealthcheckArgs::default(),
+            archive_check: ArchiveCheckArgs::default(),
+            manifest: ManifestArgs::default(),
             seed: 42,
             log_token: false,
+            trace_api: false,
+            archive_dir: "".to_string(),
+            spool_dir: "".to_string(),
+            anonymize: false,
+            anonymize_salt: "".to_string(),
             dry_run: false,
+            layout_rules: Vec::new(),
+            layouts: HashMap::new(),
         };
     }
 }
 
+/// Set the ingest preferences of the `project` that are given as defaults of `s`.
+fn set_project_defaults(s: &mut Config, project: &ProjectSettings) -> Result<(), ConfigError> {
+    let defaults: Vec<(&str, Option<Value>)> = vec![
+        (
+            "analyze_adapters",
+            project.analyze_adapters.map(Value::from),
+        ),
+        ("post_adapters", project.post_adapters.map(Value::from)),
+        (
+            "sample_tiles",
+            project.sample_tiles.map(|x| Value::from(i64::from(x))),
+        ),
+        (
+            "sample_reads_per_tile",
+            project
+                .sample_reads_per_tile
+                .map(|x| Value::from(i64::from(x))),
+        ),
+        ("sample_fraction", project.sample_fraction.map(Value::from)),
+        ("tile_fraction", project.tile_fraction.map(Value::from)),
+        (
+            "min_index_fraction",
+            project.min_index_fraction.map(Value::from),
+        ),
+        (
+            "max_histogram_entries",
+            project.max_histogram_entries.map(|x| Value::from(x as i64)),
+        ),
+        ("merge_lanes", project.merge_lanes.map(Value::from)),
+        ("post_warnings", project.post_warnings.map(Value::from)),
+        ("lane_quality", project.lane_quality.map(Value::from)),
+    ];
+    for (key, value) in defaults {
+        if let Some(value) = value {
+            s.set_default(&format!("ingest.{}", key), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Set whether to use the ingest preferences of the project from `--project-defaults` and
+/// `--no-project-defaults` in `m` into `s`, shared by the commands processing run folders.
+fn set_project_defaults_arg(s: &mut Config, m: &ArgMatches) -> Result<(), ConfigError> {
+    if m.is_present("project_defaults") {
+        s.set("ingest.project_defaults", true)?;
+    }
+    if m.is_present("no_project_defaults") {
+        s.set("ingest.project_defaults", false)?;
+    }
+    Ok(())
+}
+
+/// Set the values from the (possibly nested) `table` into `s` with the given key `prefix`.
+fn set_table(
+    s: &mut Config,
+    prefix: &str,
+    table: HashMap<String, Value>,
+) -> Result<(), ConfigError> {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value.clone().into_table() {
+            Ok(sub_table) => set_table(s, &key, sub_table)?,
+            Err(_) => {
+                s.set(&key, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return the settings of the built-in `instrument` profile for running on the control computer
+/// of a sequencer during a run: few threads, small samples, and limited memory, I/O, and API
+/// rates, instead of the full throughput of the defaults on cluster nodes.
+///
+/// The values can be changed in the `[profile.instrument]` section of the configuration file.
+fn instrument_profile() -> Vec<(&'static str, Value)> {
+    vec![
+        ("threads", Value::from(2)),
+        ("ingest.sample_reads_per_tile", Value::from(100_000)),
+        ("ingest.sample_fraction", Value::from(0.0)),
+        ("ingest.tile_fraction", Value::from(0.0)),
+        ("ingest.max_memory", Value::from("2G")),
+        ("ingest.max_read_rate", Value::from("20M")),
+        ("ingest.folder_size", Value::from(false)),
+        ("web.requests_per_second", Value::from(2.0)),
+        ("backfill.jobs", Value::from(1)),
+    ]
+}
+
 impl Settings {
     /// Construct from `ArgMatches`.
     ///
-    /// Will first load `~/.digestiflowrc.toml` and then consider the command line arguments
-    /// that were parsed into `ArgMatches`.  Command line arguments take precedence over values
-    /// from configuration file which take precedence over defaults.
+    /// Will first load `~/.digestiflowrc.toml` (or the file given with `--config`) and then
+    /// consider the command line arguments that were parsed into `ArgMatches`.  Command line
+    /// arguments take precedence over values from the profile selected with `--profile`, which
+    /// take precedence over the configuration file which take precedence over defaults.
     pub fn new(matches: &ArgMatches) -> Result<Self, ConfigError> {
+        Self::with_project_settings(matches, None)
+    }
+
+    /// Construct from `ArgMatches` as with `new()`, using the ingest preferences of the `project`
+    /// fetched from the server as defaults, if given.
+    ///
+    /// The preferences take precedence over the built-in defaults only, such that the
+    /// configuration file and the command line can still override them on each node.
+    pub fn with_project_settings(
+        matches: &ArgMatches,
+        project: Option<&ProjectSettings>,
+    ) -> Result<Self, ConfigError> {
+        // Deserialize and freeze configuration.
+        Self::layered_config(matches, project)?.try_into()
+    }
+
+    /// Return the configuration with the defaults of all settings.
+    pub fn default_config() -> Result<Config, ConfigError> {
         let mut s = Config::new();
 
         // Set defaults (currently explicit required, see for a future less-boilerplate option
@@ -136,9 +947,37 @@ impl Settings {
             .set_default("threads", default.threads as i64)?
             .set_default("seed", default.seed as i64)?
             .set_default("log_token", default.log_token)?
+            .set_default("trace_api", default.trace_api)?
+            .set_default("archive_dir", default.archive_dir.clone())?
+            .set_default("spool_dir", default.spool_dir.clone())?
+            .set_default("anonymize", default.anonymize)?
+            .set_default("anonymize_salt", default.anonymize_salt.clone())?
             .set_default("web.token", default.web.token.clone())?
+            .set_default("web.token_command", default.web.token_command.clone())?
             .set_default("web.url", default.web.url.clone())?
-            .set_default("ingest.project_uuid", default.ingest.project_uuid)?
+            .set_default("web.requests_per_second", default.web.requests_per_second)?
+            .set_default("web.api_schema", default.web.api_schema as i64)?
+            .set_default("secondary_web.token", default.secondary_web.token.clone())?
+            .set_default(
+                "secondary_web.token_command",
+                default.secondary_web.token_command.clone(),
+            )?
+            .set_default("secondary_web.url", default.secondary_web.url.clone())?
+            .set_default(
+                "secondary_web.requests_per_second",
+                default.secondary_web.requests_per_second,
+            )?
+            .set_default(
+                "secondary_web.api_schema",
+                default.secondary_web.api_schema as i64,
+            )?
+            .set_default("server_mode", default.server_mode.clone())?
+            .set_default("layout_rules", Vec::<Value>::new())?
+            .set_default("layouts", HashMap::<String, Value>::new())?
+            .set_default(
+                "ingest.project_uuid",
+                default.ingest.project_uuid.to_string(),
+            )?
             .set_default("ingest.path", default.ingest.path)?
             .set_default("ingest.register", default.ingest.register)?
             .set_default("ingest.update", default.ingest.update)?
@@ -147,6 +986,10 @@ impl Settings {
                 "ingest.force_analyze_adapters",
                 default.ingest.force_analyze_adapters,
             )?
+            .set_default(
+                "ingest.wait_for_copy_complete",
+                default.ingest.wait_for_copy_complete,
+            )?
             .set_default("ingest.post_adapters", default.ingest.post_adapters)?
             .set_default("ingest.operator", default.ingest.operator)?
             .set_default("ingest.sample_tiles", default.ingest.sample_tiles as i64)?
@@ -154,6 +997,14 @@ impl Settings {
                 "ingest.min_index_fraction",
                 default.ingest.min_index_fraction,
             )?
+            .set_default(
+                "ingest.max_histogram_entries",
+                default.ingest.max_histogram_entries as i64,
+            )?
+            .set_default(
+                "ingest.unassigned_barcode_threshold",
+                default.ingest.unassigned_barcode_threshold,
+            )?
             .set_default(
                 "ingest.skip_if_status_final",
                 default.ingest.skip_if_status_final,
@@ -161,41 +1012,236 @@ impl Settings {
             .set_default(
                 "ingest.sample_reads_per_tile",
                 default.ingest.sample_reads_per_tile as i64,
-            )?;
+            )?
+            .set_default("ingest.sample_fraction", default.ingest.sample_fraction)?
+            .set_default("ingest.tile_fraction", default.ingest.tile_fraction)?
+            .set_default("ingest.merge_lanes", default.ingest.merge_lanes)?
+            .set_default("ingest.max_index_cycles", Vec::<Value>::new())?
+            .set_default("ingest.sample_fastq", default.ingest.sample_fastq)?
+            .set_default("ingest.max_memory", default.ingest.max_memory)?
+            .set_default("ingest.max_read_rate", default.ingest.max_read_rate)?
+            .set_default("ingest.folder_layout", Vec::<Value>::new())?
+            .set_default("ingest.date_format", default.ingest.date_format)?
+            .set_default("ingest.ora_command", default.ingest.ora_command)?
+            .set_default("ingest.staging_dir", default.ingest.staging_dir)?
+            .set_default("ingest.json_output", default.ingest.json_output)?
+            .set_default("ingest.folder_size", default.ingest.folder_size)?
+            .set_default("ingest.post_warnings", default.ingest.post_warnings)?
+            .set_default("ingest.lane_quality", default.ingest.lane_quality)?
+            .set_default(
+                "ingest.lane_min_pf_fraction",
+                default.ingest.lane_min_pf_fraction,
+            )?
+            .set_default(
+                "ingest.lane_max_error_rate",
+                default.ingest.lane_max_error_rate,
+            )?
+            .set_default(
+                "ingest.lane_min_relative_density",
+                default.ingest.lane_min_relative_density,
+            )?
+            .set_default(
+                "ingest.index_read_min_cycles",
+                default.ingest.index_read_min_cycles as i64,
+            )?
+            .set_default(
+                "ingest.index_read_max_cycles",
+                default.ingest.index_read_max_cycles as i64,
+            )?
+            .set_default(
+                "ingest.template_read_min_cycles",
+                default.ingest.template_read_min_cycles as i64,
+            )?
+            .set_default(
+                "ingest.allow_cross_project",
+                default.ingest.allow_cross_project,
+            )?
+            .set_default(
+                "ingest.allow_lane_count_change",
+                default.ingest.allow_lane_count_change,
+            )?
+            .set_default("ingest.skip_list", default.ingest.skip_list)?
+            .set_default("ingest.retry_skipped", default.ingest.retry_skipped)?
+            .set_default("ingest.state_file", default.ingest.state_file)?
+            .set_default("ingest.break_stale_locks", default.ingest.break_stale_locks)?
+            .set_default("ingest.max_folders", default.ingest.max_folders as i64)?
+            .set_default("ingest.no_net", default.ingest.no_net)?
+            .set_default("ingest.flowcell_json", default.ingest.flowcell_json)?
+            .set_default("ingest.label_template", default.ingest.label_template)?
+            .set_default(
+                "ingest.description_template",
+                default.ingest.description_template,
+            )?
+            .set_default("ingest.operator_template", default.ingest.operator_template)?
+            .set_default("ingest.project_defaults", default.ingest.project_defaults)?
+            .set_default("reconcile.roots", Vec::<Value>::new())?
+            .set_default("reconcile.fix", default.reconcile.fix)?
+            .set_default("reconcile.folder_cache", default.reconcile.folder_cache)?
+            .set_default("backfill.journal", default.backfill.journal)?
+            .set_default("backfill.jobs", default.backfill.jobs as i64)?
+            .set_default("import.path", Vec::<Value>::new())?
+            .set_default("watch.roots", Vec::<Value>::new())?
+            .set_default("watch.interval", default.watch.interval as i64)?
+            .set_default("watch.cooldown", default.watch.cooldown as i64)?
+            .set_default("watch.pid_file", default.watch.pid_file.clone())?
+            .set_default("history.flowcell", default.history.flowcell)?
+            .set_default("history.folder", default.history.folder)?
+            .set_default("list.status_sequencing", default.list.status_sequencing)?
+            .set_default("list.instrument", default.list.instrument)?
+            .set_default("list.since", default.list.since)?
+            .set_default("list.format", default.list.format)?
+            .set_default("show.flowcell", default.show.flowcell)?
+            .set_default("show.instrument", default.show.instrument)?
+            .set_default("show.run_number", default.show.run_number as i64)?
+            .set_default("show.json", default.show.json)?
+            .set_default("set_status.flowcell", default.set_status.flowcell)?
+            .set_default("set_status.instrument", default.set_status.instrument)?
+            .set_default(
+                "set_status.run_number",
+                default.set_status.run_number as i64,
+            )?
+            .set_default(
+                "set_status.status_sequencing",
+                default.set_status.status_sequencing,
+            )?
+            .set_default(
+                "set_status.status_conversion",
+                default.set_status.status_conversion,
+            )?
+            .set_default(
+                "set_status.status_delivery",
+                default.set_status.status_delivery,
+            )?
+            .set_default("set_status.filter", Vec::<Value>::new())?
+            .set_default("set_status.older_than", default.set_status.older_than)?
+            .set_default("delete.flowcell", default.delete.flowcell)?
+            .set_default("delete.instrument", default.delete.instrument)?
+            .set_default("delete.run_number", default.delete.run_number as i64)?
+            .set_default("delete.yes", default.delete.yes)?
+            .set_default("status.path", default.status.path)?
+            .set_default("samplesheet.path", default.samplesheet.path)?
+            .set_default("samplesheet.output", default.samplesheet.output)?
+            .set_default("samplesheet.force", default.samplesheet.force)?
+            .set_default("samplesheet.format", default.samplesheet.format)?
+            .set_default("demux.path", default.demux.path)?
+            .set_default("demux.tool", default.demux.tool)?
+            .set_default("demux.command", default.demux.command)?
+            .set_default("demux.output_dir", default.demux.output_dir)?
+            .set_default("demux.force", default.demux.force)?
+            .set_default("report.compare", default.report.compare)?
+            .set_default("report.format", default.report.format)?
+            .set_default("verify.path", default.verify.path)?
+            .set_default("verify.json", default.verify.json)?
+            .set_default("detect.path", default.detect.path)?
+            .set_default("inspect.path", default.inspect.path)?
+            .set_default("inspect.format", default.inspect.format)?
+            .set_default("support_bundle.path", default.support_bundle.path)?
+            .set_default("support_bundle.output", default.support_bundle.output)?
+            .set_default("export.path", default.export.path)?
+            .set_default("export.output", default.export.output)?
+            .set_default("healthcheck.path", default.healthcheck.path)?
+            .set_default("archive_check.path", default.archive_check.path)?
+            .set_default("archive_check.json", default.archive_check.json)?
+            .set_default("manifest.path", default.manifest.path)?
+            .set_default("manifest.output", default.manifest.output)?
+            .set_default("manifest.algorithm", default.manifest.algorithm)?
+            .set_default("manifest.verify", default.manifest.verify)?
+            .set_default("manifest.force", default.manifest.force)?;
+        for (key, value) in instrument_profile() {
+            s.set_default(&format!("profile.instrument.{}", key), value)?;
+        }
+        Ok(s)
+    }
+
+    /// Return the path of the configuration file to load: the one given with `--config`, which
+    /// must exist, or `~/.digestiflowrc.toml` if it exists.
+    pub fn config_file(matches: &ArgMatches) -> Result<Option<String>, ConfigError> {
+        let (_, m) = command_path(matches);
+        if let Some(config_path) = m.value_of("config") {
+            if !Path::new(config_path).exists() {
+                return Err(ConfigError::Message(format!(
+                    "Configuration file {} does not exist",
+                    config_path
+                )));
+            }
+            Ok(Some(config_path.to_string()))
+        } else {
+            let expanded = shellexpand::tilde("~/.digestiflowrc.toml")
+                .into_owned()
+                .to_string();
+            if Path::new(&expanded).exists() {
+                Ok(Some(expanded))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+
+    /// Return the layered configuration from `ArgMatches` and the `project` preferences as used
+    /// by `with_project_settings()`, before deserializing it, e.g., for showing the values.
+    pub fn layered_config(
+        matches: &ArgMatches,
+        project: Option<&ProjectSettings>,
+    ) -> Result<Config, ConfigError> {
+        let (command, m) = command_path(matches);
+        let mut s = Self::default_config()?;
+        if let Some(project) = project {
+            set_project_defaults(&mut s, project)?;
+        }
 
         // Next, load configuration file.
-        let expanded = shellexpand::tilde("~/.digestiflowrc.toml")
-            .into_owned()
-            .to_string();
-        if Path::new(&expanded).exists() {
-            s.merge(File::with_name(&expanded))?;
+        if let Some(config_path) = Self::config_file(matches)? {
+            s.merge(File::with_name(&config_path))?;
         }
 
         // Add in settings from the environment (with a prefix of APP)
         // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
         s.merge(Environment::with_prefix("DIGESTIFLOW").separator("__"))?;
 
-        // Add settings from command line.
-        match matches.subcommand() {
-            ("ingest", Some(m)) => {
-                if m.is_present("quiet") {
-                    s.set("quiet", true)?;
-                }
-                if m.is_present("verbose") {
-                    s.set("verbose", true)?;
-                }
-                if m.is_present("dry_run") {
-                    s.set("dry_run", true)?;
-                }
-                if m.is_present("log_token") {
-                    s.set("log_token", true)?;
-                }
-                if m.is_present("threads") {
-                    s.set("threads", m.value_of("threads").unwrap())?;
-                }
-                if m.is_present("web_url") {
-                    s.set("web.url", m.value_of("web_url").unwrap())?;
-                }
+        // Apply the selected profile.
+        if let Some(profile) = m.value_of("profile") {
+            let table = s
+                .get_table(&format!("profile.{}", profile))
+                .map_err(|_| ConfigError::Message(format!("Unknown profile {:?}", profile)))?;
+            set_table(&mut s, "", table)?;
+        }
+
+        // Add global settings from command line.
+        if m.is_present("quiet") {
+            s.set("quiet", true)?;
+        }
+        if m.is_present("verbose") {
+            s.set("verbose", true)?;
+        }
+        if m.is_present("dry_run") {
+            s.set("dry_run", true)?;
+        }
+        if m.is_present("log_token") {
+            s.set("log_token", true)?;
+        }
+        if m.is_present("trace_api") {
+            s.set("trace_api", true)?;
+        }
+        if m.is_present("archive_dir") {
+            s.set("archive_dir", m.value_of("archive_dir"))?;
+        }
+        if m.is_present("spool_dir") {
+            s.set("spool_dir", m.value_of("spool_dir"))?;
+        }
+        if m.is_present("anonymize") {
+            s.set("anonymize", true)?;
+        }
+        // The argument has a default value, so only use it if actually given.
+        if m.occurrences_of("threads") > 0 {
+            s.set("threads", m.value_of("threads").unwrap())?;
+        }
+        if m.is_present("web_url") {
+            s.set("web.url", m.value_of("web_url").unwrap())?;
+        }
+
+        // Add command-specific settings from command line.
+        match command.as_str() {
+            "flowcells ingest" | "flowcells backfill" => {
                 if m.is_present("project_uuid") {
                     s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
                 }
@@ -218,6 +1264,9 @@ impl Settings {
                 if m.is_present("force_analyze_adapters") {
                     s.set("ingest.force_analyze_adapters", true)?;
                 }
+                if m.is_present("wait_for_copy_complete") {
+                    s.set("ingest.wait_for_copy_complete", true)?;
+                }
                 if m.is_present("post_adapters") {
                     s.set("ingest.post_adapters", true)?;
                 }
@@ -227,6 +1276,62 @@ impl Settings {
                         m.value_of("sample_reads_per_tile"),
                     )?;
                 }
+                if let Some(size) = m.value_of("sample_size") {
+                    let invalid = || ConfigError::Message(format!("Invalid sample size {}", size));
+                    if size.ends_with('%') || size.contains('.') {
+                        let fraction = if size.ends_with('%') {
+                            size.trim_end_matches('%')
+                                .parse::<f64>()
+                                .map_err(|_| invalid())?
+                                / 100.0
+                        } else {
+                            size.parse::<f64>().map_err(|_| invalid())?
+                        };
+                        if fraction <= 0.0 || fraction > 1.0 {
+                            return Err(invalid());
+                        }
+                        s.set("ingest.sample_fraction", fraction)?;
+                    } else {
+                        let count = size.parse::<i64>().map_err(|_| invalid())?;
+                        s.set("ingest.sample_reads_per_tile", count)?;
+                        s.set("ingest.sample_fraction", 0.0)?;
+                    }
+                }
+                if let Some(fraction) = m.value_of("tile_fraction") {
+                    let invalid =
+                        || ConfigError::Message(format!("Invalid tile fraction {}", fraction));
+                    let fraction = fraction.parse::<f64>().map_err(|_| invalid())?;
+                    if fraction <= 0.0 || fraction > 1.0 {
+                        return Err(invalid());
+                    }
+                    s.set("ingest.tile_fraction", fraction)?;
+                }
+                if m.is_present("max_histogram_entries") {
+                    s.set(
+                        "ingest.max_histogram_entries",
+                        m.value_of("max_histogram_entries"),
+                    )?;
+                }
+                if m.is_present("merge_lanes") {
+                    s.set("ingest.merge_lanes", true)?;
+                }
+                if m.is_present("sample_fastq") {
+                    s.set("ingest.sample_fastq", true)?;
+                }
+                if m.is_present("max_memory") {
+                    s.set("ingest.max_memory", m.value_of("max_memory"))?;
+                }
+                if let Some(layouts) = m.values_of("folder_layout") {
+                    s.set(
+                        "ingest.folder_layout",
+                        layouts
+                            .map(|layout| layout.to_string())
+                            .collect::<Vec<String>>(),
+                    )?;
+                }
+                if m.is_present("staging_dir") {
+                    s.set("ingest.staging_dir", m.value_of("staging_dir"))?;
+                }
                 if m.is_present("update_if_state_final") {
                     s.set("ingest.skip_if_status_final", false)?;
                 }
@@ -236,16 +1341,406 @@ impl Settings {
                         m.value_of("min_index_fraction"),
                     )?;
                 }
+                if let Some(cycles) = m.value_of("max_index_cycles") {
+                    let max_cycles = cycles
+                        .split(',')
+                        .map(|x| x.trim().parse::<i64>())
+                        .collect::<Result<Vec<i64>, _>>()
+                        .map_err(|_| {
+                            ConfigError::Message(format!("Invalid index read cycles {}", cycles))
+                        })?;
+                    s.set("ingest.max_index_cycles", max_cycles)?;
+                }
+                if m.is_present("unassigned_barcode_threshold") {
+                    s.set(
+                        "ingest.unassigned_barcode_threshold",
+                        m.value_of("unassigned_barcode_threshold"),
+                    )?;
+                }
+                if m.is_present("json_output") {
+                    s.set("ingest.json_output", m.value_of("json_output"))?;
+                }
+                if m.is_present("folder_size") {
+                    s.set("ingest.folder_size", true)?;
+                }
+                if m.is_present("post_warnings") {
+                    s.set("ingest.post_warnings", true)?;
+                }
+                if m.is_present("lane_quality") {
+                    s.set("ingest.lane_quality", true)?;
+                }
+                if m.is_present("allow_cross_project") {
+                    s.set("ingest.allow_cross_project", true)?;
+                }
+                if m.is_present("allow_lane_count_change") {
+                    s.set("ingest.allow_lane_count_change", true)?;
+                }
+                if m.is_present("skip_list") {
+                    s.set("ingest.skip_list", m.value_of("skip_list"))?;
+                }
+                if m.is_present("retry_skipped") {
+                    s.set("ingest.retry_skipped", true)?;
+                }
+                if m.is_present("state_file") {
+                    s.set("ingest.state_file", m.value_of("state_file"))?;
+                }
+                if m.is_present("break_stale_locks") {
+                    s.set("ingest.break_stale_locks", true)?;
+                }
+                if m.is_present("max_folders") {
+                    s.set("ingest.max_folders", m.value_of("max_folders"))?;
+                }
+                if m.is_present("no_net") {
+                    s.set("ingest.no_net", true)?;
+                }
+                set_project_defaults_arg(&mut s, m)?;
+                if m.is_present("flowcell_json") {
+                    s.set("ingest.flowcell_json", m.value_of("flowcell_json"))?;
+                }
+                // The `backfill` command shares the `ingest` settings and adds its own.
+                if m.is_present("journal") {
+                    s.set("backfill.journal", m.value_of("journal"))?;
+                }
+                if m.is_present("jobs") {
+                    s.set("backfill.jobs", m.value_of("jobs"))?;
+                }
+            }
+            "flowcells reconcile" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                if let Some(roots) = m.values_of("root") {
+                    s.set(
+                        "reconcile.roots",
+                        roots.map(|root| root.to_string()).collect::<Vec<String>>(),
+                    )?;
+                }
+                if m.is_present("fix") {
+                    s.set("reconcile.fix", true)?;
+                }
+                if m.is_present("folder_cache") {
+                    s.set("reconcile.folder_cache", m.value_of("folder_cache"))?;
+                }
+                set_project_defaults_arg(&mut s, m)?;
+            }
+            "flowcells import" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set(
+                    "import.path",
+                    m.values_of("path")
+                        .expect("Problem getting paths from command line")
+                        .map(|path| path.to_string())
+                        .collect::<Vec<String>>(),
+                )?;
+                set_project_defaults_arg(&mut s, m)?;
+            }
+            "flowcells watch" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set(
+                    "watch.roots",
+                    m.values_of("root")
+                        .expect("Problem getting roots from command line")
+                        .map(|root| root.to_string())
+                        .collect::<Vec<String>>(),
+                )?;
+                if m.is_present("interval") {
+                    s.set("watch.interval", m.value_of("interval"))?;
+                }
+                if m.is_present("cooldown") {
+                    s.set("watch.cooldown", m.value_of("cooldown"))?;
+                }
+                if m.is_present("state_file") {
+                    s.set("ingest.state_file", m.value_of("state_file"))?;
+                }
+                if m.is_present("pid_file") {
+                    s.set("watch.pid_file", m.value_of("pid_file"))?;
+                }
+                set_project_defaults_arg(&mut s, m)?;
+            }
+            "flowcells history" => {
+                if m.is_present("flowcell") {
+                    s.set("history.flowcell", m.value_of("flowcell"))?;
+                }
+                if m.is_present("folder") {
+                    s.set("history.folder", m.value_of("folder"))?;
+                }
+            }
+            "flowcells list" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                if m.is_present("status_sequencing") {
+                    s.set("list.status_sequencing", m.value_of("status_sequencing"))?;
+                }
+                if m.is_present("instrument") {
+                    s.set("list.instrument", m.value_of("instrument"))?;
+                }
+                if m.is_present("since") {
+                    s.set("list.since", m.value_of("since"))?;
+                }
+                if m.is_present("format") {
+                    s.set("list.format", m.value_of("format"))?;
+                }
+            }
+            "flowcells show" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set("show.flowcell", m.value_of("flowcell"))?;
+                if m.is_present("instrument") {
+                    s.set("show.instrument", m.value_of("instrument"))?;
+                }
+                if m.is_present("run_number") {
+                    s.set("show.run_number", m.value_of("run_number"))?;
+                }
+                if m.is_present("json") {
+                    s.set("show.json", true)?;
+                }
+            }
+            "flowcells set-status" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                if m.is_present("flowcell") {
+                    s.set("set_status.flowcell", m.value_of("flowcell"))?;
+                }
+                if m.is_present("instrument") {
+                    s.set("set_status.instrument", m.value_of("instrument"))?;
+                }
+                if m.is_present("run_number") {
+                    s.set("set_status.run_number", m.value_of("run_number"))?;
+                }
+                if m.is_present("sequencing") {
+                    s.set("set_status.status_sequencing", m.value_of("sequencing"))?;
+                }
+                if m.is_present("conversion") {
+                    s.set("set_status.status_conversion", m.value_of("conversion"))?;
+                }
+                if m.is_present("delivery") {
+                    s.set("set_status.status_delivery", m.value_of("delivery"))?;
+                }
+                if let Some(assignments) = m.values_of("set") {
+                    for assignment in assignments {
+                        let invalid = || {
+                            ConfigError::Message(format!(
+                                "Invalid --set {:?}, expected FIELD=STATUS",
+                                assignment
+                            ))
+                        };
+                        let pos = assignment.find('=').ok_or_else(invalid)?;
+                        let field = match &assignment[..pos] {
+                            "status_sequencing" | "status_conversion" | "status_delivery" => {
+                                &assignment[..pos]
+                            }
+                            _ => return Err(invalid()),
+                        };
+                        s.set(&format!("set_status.{}", field), &assignment[(pos + 1)..])?;
+                    }
+                }
+                if let Some(filters) = m.values_of("filter") {
+                    s.set(
+                        "set_status.filter",
+                        filters
+                            .map(|filter| filter.to_string())
+                            .collect::<Vec<String>>(),
+                    )?;
+                }
+                if m.is_present("older_than") {
+                    s.set("set_status.older_than", m.value_of("older_than"))?;
+                }
+            }
+            "flowcells delete" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set("delete.flowcell", m.value_of("flowcell"))?;
+                if m.is_present("instrument") {
+                    s.set("delete.instrument", m.value_of("instrument"))?;
+                }
+                if m.is_present("run_number") {
+                    s.set("delete.run_number", m.value_of("run_number"))?;
+                }
+                if m.is_present("yes") {
+                    s.set("delete.yes", true)?;
+                }
+            }
+            "flowcells status" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set(
+                    "status.path",
+                    m.values_of("path")
+                        .expect("Problem getting paths from command line")
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>(),
+                )?;
+            }
+            "flowcells samplesheet" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set("samplesheet.path", m.value_of("path"))?;
+                if m.is_present("output") {
+                    s.set("samplesheet.output", m.value_of("output"))?;
+                }
+                if m.is_present("force") {
+                    s.set("samplesheet.force", true)?;
+                }
+                if m.is_present("format") {
+                    s.set("samplesheet.format", m.value_of("format"))?;
+                }
+            }
+            "flowcells demux" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set("demux.path", m.value_of("path"))?;
+                if m.is_present("tool") {
+                    s.set("demux.tool", m.value_of("tool"))?;
+                }
+                if m.is_present("command") {
+                    s.set("demux.command", m.value_of("command"))?;
+                }
+                if m.is_present("output_dir") {
+                    s.set("demux.output_dir", m.value_of("output_dir"))?;
+                }
+                if m.is_present("force") {
+                    s.set("demux.force", true)?;
+                }
+            }
+            "flowcells report" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set(
+                    "report.compare",
+                    m.values_of("compare")
+                        .map(|values| values.map(|value| value.to_string()).collect())
+                        .unwrap_or_else(Vec::<String>::new),
+                )?;
+                if m.is_present("format") {
+                    s.set("report.format", m.value_of("format"))?;
+                }
+            }
+            "flowcells verify" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                s.set("verify.path", m.value_of("path"))?;
+                if m.is_present("json") {
+                    s.set("verify.json", true)?;
+                }
+            }
+            "api check" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+            }
+            "api replay" => {
+                // The spool directory is given with the global `--spool-dir`.
+            }
+            "config init" => {
+                // The settings are asked for on the terminal.
+            }
+            "config validate" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+            }
+            "api healthcheck" => {
+                if m.is_present("project_uuid") {
+                    s.set("ingest.project_uuid", m.value_of("project_uuid"))?;
+                }
+                if let Some(paths) = m.values_of("path") {
+                    s.set(
+                        "healthcheck.path",
+                        paths.map(|path| path.to_string()).collect::<Vec<String>>(),
+                    )?;
+                }
+            }
+            "folders detect" => {
+                s.set("detect.path", m.value_of("path"))?;
+            }
+            "folders inspect" => {
+                s.set("inspect.path", m.value_of("path"))?;
+                if m.is_present("format") {
+                    s.set("inspect.format", m.value_of("format"))?;
+                }
+            }
+            "folders support-bundle" => {
+                s.set("support_bundle.path", m.value_of("path"))?;
+                if m.is_present("output") {
+                    s.set("support_bundle.output", m.value_of("output"))?;
+                }
+            }
+            "folders export" => {
+                s.set("export.path", m.value_of("path"))?;
+                if m.is_present("output") {
+                    s.set("export.output", m.value_of("output"))?;
+                }
+            }
+            "folders archive-check" => {
+                s.set(
+                    "archive_check.path",
+                    m.values_of("path")
+                        .expect("Problem getting paths from command line")
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>(),
+                )?;
+                if m.is_present("json") {
+                    s.set("archive_check.json", true)?;
+                }
+            }
+            "folders manifest" => {
+                s.set("manifest.path", m.value_of("path"))?;
+                if m.is_present("output") {
+                    s.set("manifest.output", m.value_of("output"))?;
+                }
+                if m.is_present("algorithm") {
+                    s.set("manifest.algorithm", m.value_of("algorithm"))?;
+                }
+                if m.is_present("verify") {
+                    s.set("manifest.verify", true)?;
+                }
+                if m.is_present("force") {
+                    s.set("manifest.force", true)?;
+                }
             }
             _ => {
-                return Err(ConfigError::Message(format!(
-                    "Invalid command {}",
-                    matches.subcommand().0
-                )));
+                return Err(ConfigError::Message(format!("Invalid command {}", command)));
             }
         }
 
-        // Deserialize and freeze configuration.
-        s.try_into()
+        // Validate the project UUID, an empty one is the same as none given.
+        let project_uuid = s.get_str("ingest.project_uuid")?;
+        if project_uuid.is_empty() {
+            s.set("ingest.project_uuid", Uuid::nil().to_string())?;
+        } else if let Err(e) = Uuid::parse_str(&project_uuid) {
+            return Err(ConfigError::Message(format!(
+                "Invalid project UUID {:?}: {}",
+                project_uuid, e
+            )));
+        }
+
+        Ok(s)
+    }
+
+    /// Return the configured folder layout detection rules, those from `layout_rules` followed
+    /// by those from `layouts`.
+    pub fn configured_layout_rules(&self) -> Vec<LayoutRule> {
+        let mut names = self.layouts.keys().collect::<Vec<&String>>();
+        names.sort();
+        let mut result = self.layout_rules.clone();
+        result.extend(names.into_iter().map(|name| LayoutRule {
+            name: name.clone(),
+            layout: self.layouts[name].parser,
+            markers: self.layouts[name].markers.clone(),
+        }));
+        result
     }
 }
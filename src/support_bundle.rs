@@ -0,0 +1,247 @@
+//! Implementation of the `support-bundle` command that collects the information needed for
+//! reporting problems with a run folder into a `.tar.gz` file.
+
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::errors::*;
+use anonymize::Anonymizer;
+use detect;
+use ingest::bcl_meta::{first_cycle_dir_name, read_meta_bytes};
+use inspect;
+use settings::Settings;
+
+/// Meta data files copied into the bundle if present.
+const META_FILES: &[&str] = &[
+    "RunInfo.xml",
+    "RunParameters.xml",
+    "runParameters.xml",
+    "RunParameters.json",
+    "RunManifest.csv",
+    "L01/BioInfo.csv",
+];
+/// Number of cycle directories of the first lane to list.
+const LISTED_CYCLES: usize = 2;
+/// Size of blocks in tar files.
+const TAR_BLOCK: usize = 512;
+
+/// Minimal writer for uncompressed tar (ustar) archives with regular files only.
+struct TarWriter<W: Write> {
+    /// The output to write the archive to.
+    out: W,
+    /// Modification time to record for all files.
+    mtime: i64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Construct for writing to `out`.
+    fn new(out: W) -> Self {
+        Self {
+            out,
+            mtime: Local::now().timestamp(),
+        }
+    }
+
+    /// Append a file `name` (at most 99 bytes) with the given contents.
+    fn append(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        if name.len() >= 100 {
+            bail!("File name {:?} too long for tar archive", name);
+        }
+        let mut header = [0u8; TAR_BLOCK];
+        let mut put = |offset: usize, value: &str| {
+            header[offset..(offset + value.len())].copy_from_slice(value.as_bytes());
+        };
+        put(0, name);
+        put(100, "0000644\0");
+        put(108, "0000000\0");
+        put(116, "0000000\0");
+        put(124, &format!("{:011o}\0", data.len()));
+        put(136, &format!("{:011o}\0", self.mtime));
+        put(148, "        ");
+        put(156, "0");
+        put(257, "ustar\0");
+        put(263, "00");
+        let checksum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        let padding = (TAR_BLOCK - data.len() % TAR_BLOCK) % TAR_BLOCK;
+        self.out
+            .write_all(&header)
+            .and_then(|_| self.out.write_all(data))
+            .and_then(|_| self.out.write_all(&vec![0u8; padding]))
+            .chain_err(|| format!("Problem writing {} to archive", name))
+    }
+
+    /// Write the end-of-archive marker and return the output.
+    fn finish(mut self) -> Result<W> {
+        self.out
+            .write_all(&[0u8; 2 * TAR_BLOCK])
+            .chain_err(|| "Problem finishing archive")?;
+        Ok(self.out)
+    }
+}
+
+/// Return the messages of `error` and its causes, separated by colons.
+fn error_chain_text(error: &Error) -> String {
+    error
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<String>>()
+        .join(": ")
+}
+
+/// Write the entries of the directory at `path` to `out`, with sizes for files.
+fn list_dir(path: &Path, out: &mut String) {
+    writeln!(out, "{}:", path.display()).unwrap();
+    let mut entries = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                match entry.metadata() {
+                    Ok(ref meta) if meta.is_dir() => format!("{}/", name),
+                    Ok(meta) => format!("{}\t{}", name, meta.len()),
+                    Err(_) => name,
+                }
+            })
+            .collect::<Vec<String>>(),
+        Err(e) => {
+            writeln!(out, "  (could not list: {})\n", e).unwrap();
+            return;
+        }
+    };
+    entries.sort();
+    for entry in &entries {
+        writeln!(out, "  {}", entry).unwrap();
+    }
+    writeln!(out).unwrap();
+}
+
+/// Return listings of the run folder and the directories down to the first cycles of lane 1.
+fn listings(path: &Path) -> String {
+    let mut out = String::new();
+    let lane_dir = path
+        .join("Data")
+        .join("Intensities")
+        .join("BaseCalls")
+        .join("L001");
+    let mut dirs = vec![
+        path.to_path_buf(),
+        path.join("Data"),
+        path.join("Data").join("Intensities"),
+        path.join("Data").join("Intensities").join("BaseCalls"),
+        lane_dir.clone(),
+    ];
+    let first_cycle = first_cycle_dir_name(&lane_dir);
+    let first_cycle = first_cycle[1..first_cycle.len() - 2]
+        .parse::<usize>()
+        .unwrap_or(1);
+    dirs.extend(
+        (first_cycle..(first_cycle + LISTED_CYCLES))
+            .map(|cycle| lane_dir.join(format!("C{}.1", cycle))),
+    );
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        list_dir(dir, &mut out);
+    }
+    out
+}
+
+/// Main entry point for the `support-bundle` command.
+///
+/// Writes the meta data files, the layout detection trace, the parsing result, directory
+/// listings, and the client version and configuration (without tokens) to a `.tar.gz` file.
+/// With `--anonymize`, the sensitive values in all of them are replaced by pseudonyms.
+pub fn run(logger: &slog::Logger, settings: &Settings) -> Result<()> {
+    info!(logger, "Running: digestiflow-cli-client support-bundle");
+    let path = Path::new(&settings.support_bundle.path);
+    if !path.is_dir() {
+        bail!("Run folder {:?} does not exist", path);
+    }
+    let mut anonymizer = Anonymizer::new(settings);
+    anonymizer.add_run_folder(logger, path, settings);
+    let folder_name = path
+        .canonicalize()
+        .ok()
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .map(|name| anonymizer.apply(&name))
+        .unwrap_or_else(|| "run".to_string());
+    let output = if settings.support_bundle.output.is_empty() {
+        PathBuf::from(format!("support-bundle-{}.tar.gz", folder_name))
+    } else {
+        PathBuf::from(&settings.support_bundle.output)
+    };
+    let prefix = "support-bundle";
+
+    let file = File::create(&output).chain_err(|| format!("Could not create {:?}", &output))?;
+    let mut tar = TarWriter::new(GzEncoder::new(file, Compression::default()));
+    let mut errors = String::new();
+
+    for name in META_FILES {
+        if let Ok(contents) = read_meta_bytes(path, name) {
+            let contents = if anonymizer.is_enabled() {
+                anonymizer
+                    .apply(&String::from_utf8_lossy(&contents))
+                    .into_bytes()
+            } else {
+                contents
+            };
+            tar.append(&format!("{}/{}", prefix, name), &contents)?;
+        }
+    }
+
+    let mut detection = String::new();
+    if let Err(e) = detect::explain(path, settings, &mut detection) {
+        writeln!(errors, "detect: {}", error_chain_text(&e)).unwrap();
+    }
+    tar.append(
+        &format!("{}/detect.txt", prefix),
+        anonymizer.apply(&detection).as_bytes(),
+    )?;
+
+    match inspect::inspect_folder(logger, path, settings) {
+        Ok(result) => {
+            let json = serde_json::to_string_pretty(&result)
+                .chain_err(|| "Problem serializing to JSON")?;
+            tar.append(
+                &format!("{}/inspect.json", prefix),
+                anonymizer.apply(&json).as_bytes(),
+            )?;
+        }
+        Err(e) => writeln!(errors, "inspect: {}", error_chain_text(&e)).unwrap(),
+    }
+
+    tar.append(
+        &format!("{}/listing.txt", prefix),
+        anonymizer.apply(&listings(path)).as_bytes(),
+    )?;
+    tar.append(
+        &format!("{}/client.txt", prefix),
+        anonymizer
+            .apply(&format!(
+                "digestiflow-cli version: {}\n\nSettings:\n{:#?}\n",
+                env!("CARGO_PKG_VERSION"),
+                settings
+            ))
+            .as_bytes(),
+    )?;
+    tar.append(
+        &format!("{}/errors.txt", prefix),
+        anonymizer.apply(&errors).as_bytes(),
+    )?;
+
+    tar.finish()?
+        .finish()
+        .chain_err(|| format!("Problem writing {:?}", &output))?;
+    info!(logger, "Wrote support bundle to {:?}", &output);
+    println!("{}", output.display());
+
+    Ok(())
+}
@@ -0,0 +1,407 @@
+//! In-process mock of the Digestiflow REST API for tests.
+//!
+//! `MockServer` listens on a free port of the loopback interface and implements the endpoints
+//! used by `digestiflow-cli`: listing, resolving, registering, and updating flow cells, listing
+//! and posting index histograms, posting messages, and listing projects and their ingest
+//! preferences.  Its state can be seeded and inspected from the test, e.g.:
+//!
+//! ```no_run
+//! use digestiflow_cli::testing::MockServer;
+//!
+//! let server = MockServer::start().unwrap();
+//! let project_uuid = server.add_project("Test Project");
+//! // ... point `web.url` of the settings to `server.url()` and run `ingest::run` ...
+//! assert_eq!(server.flowcells(&project_uuid).len(), 1);
+//! ```
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::{self, Value};
+
+use errors::*;
+
+/// A request received by the mock server.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The HTTP method, e.g., `"GET"`.
+    pub method: String,
+    /// The request path, e.g., `"/api/projects/"`.
+    pub path: String,
+    /// The request body parsed as JSON, `Value::Null` if empty or not JSON.
+    pub body: Value,
+}
+
+/// The state of the mock server.
+#[derive(Debug, Default)]
+struct State {
+    /// Counter for generating UUIDs.
+    next_id: u64,
+    /// The projects as (UUID, title).
+    projects: Vec<(String, String)>,
+    /// Ingest preferences by project UUID.
+    project_settings: HashMap<String, Value>,
+    /// Flow cells by project UUID.
+    flowcells: HashMap<String, Vec<Value>>,
+    /// Index histograms by flow cell UUID.
+    histograms: HashMap<String, Vec<Value>>,
+    /// Messages by flow cell UUID.
+    messages: HashMap<String, Vec<Value>>,
+    /// All requests received so far.
+    requests: Vec<Request>,
+}
+
+impl State {
+    /// Generate a new, unique UUID.
+    fn uuid(&mut self) -> String {
+        self.next_id += 1;
+        format!("00000000-0000-4000-8000-{:012x}", self.next_id)
+    }
+
+    /// Return whether a flow cell with the given UUID exists in the project.
+    fn has_flowcell(&self, project_uuid: &str, flowcell_uuid: &str) -> bool {
+        self.flowcells
+            .get(project_uuid)
+            .map(|fcs| fcs.iter().any(|fc| fc["sodar_uuid"] == flowcell_uuid))
+            .unwrap_or(false)
+    }
+
+    /// Handle request, returning status code and response body.
+    fn handle(&mut self, method: &str, path: &str, body: Value) -> (u16, Value) {
+        let parts: Vec<&str> = path
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+        match (method, parts.as_slice()) {
+            ("GET", ["api", "projects"]) => {
+                let projects = self
+                    .projects
+                    .iter()
+                    .map(|(uuid, title)| json!({"sodar_uuid": uuid, "title": title}))
+                    .collect();
+                (200, Value::Array(projects))
+            }
+            ("GET", ["api", "projects", project, "settings"]) => {
+                match self.project_settings.get(*project) {
+                    Some(settings) => (200, settings.clone()),
+                    None => not_found(),
+                }
+            }
+            ("GET", ["api", "flowcells", "resolve", project, instrument, run_number, flowcell]) => {
+                let run_number: i64 = run_number.parse().unwrap_or(-1);
+                let found = self.flowcells.get(*project).and_then(|fcs| {
+                    fcs.iter().find(|fc| {
+                        fc["sequencing_machine"] == *instrument
+                            && fc["run_number"] == run_number
+                            && fc["vendor_id"] == *flowcell
+                    })
+                });
+                match found {
+                    Some(fc) => (200, fc.clone()),
+                    None => not_found(),
+                }
+            }
+            ("GET", ["api", "flowcells", project]) => {
+                let flowcells = self.flowcells.get(*project).cloned().unwrap_or_default();
+                (200, Value::Array(flowcells))
+            }
+            ("POST", ["api", "flowcells", project]) => {
+                if !self.projects.iter().any(|(uuid, _)| uuid == project) {
+                    return not_found();
+                }
+                if body.get("vendor_id").is_none() {
+                    return (400, json!({"vendor_id": ["This field is required."]}));
+                }
+                let mut flowcell = body;
+                flowcell["sodar_uuid"] = Value::String(self.uuid());
+                self.flowcells
+                    .entry(project.to_string())
+                    .or_default()
+                    .push(flowcell.clone());
+                (201, flowcell)
+            }
+            ("GET", ["api", "flowcells", project, flowcell]) => {
+                let found = self
+                    .flowcells
+                    .get(*project)
+                    .and_then(|fcs| fcs.iter().find(|fc| fc["sodar_uuid"] == *flowcell).cloned());
+                match found {
+                    Some(fc) => (200, fc),
+                    None => not_found(),
+                }
+            }
+            ("PUT", ["api", "flowcells", project, flowcell])
+            | ("PATCH", ["api", "flowcells", project, flowcell]) => {
+                let found = self
+                    .flowcells
+                    .get_mut(*project)
+                    .and_then(|fcs| fcs.iter_mut().find(|fc| fc["sodar_uuid"] == *flowcell));
+                match (found, body) {
+                    (Some(fc), Value::Object(fields)) => {
+                        for (key, value) in fields {
+                            if key != "sodar_uuid" && key != "libraries" {
+                                fc[key] = value;
+                            }
+                        }
+                        (200, fc.clone())
+                    }
+                    (Some(_), _) => (400, json!({"detail": "Expected JSON object."})),
+                    (None, _) => not_found(),
+                }
+            }
+            ("DELETE", ["api", "flowcells", project, flowcell]) => {
+                if !self.has_flowcell(project, flowcell) {
+                    return not_found();
+                }
+                if let Some(fcs) = self.flowcells.get_mut(*project) {
+                    fcs.retain(|fc| fc["sodar_uuid"] != *flowcell);
+                }
+                // The server deletes the histograms and messages with the flow cell.
+                self.histograms.remove(*flowcell);
+                self.messages.remove(*flowcell);
+                (204, Value::Null)
+            }
+            ("GET", ["api", "indexhistos", project, flowcell]) => {
+                if !self.has_flowcell(project, flowcell) {
+                    return not_found();
+                }
+                let histograms = self.histograms.get(*flowcell).cloned().unwrap_or_default();
+                (200, Value::Array(histograms))
+            }
+            ("POST", ["api", "indexhistos", project, flowcell])
+            | ("POST", ["api", "messages", project, flowcell]) => {
+                if !self.has_flowcell(project, flowcell) {
+                    return not_found();
+                }
+                let mut object = body;
+                object["sodar_uuid"] = Value::String(self.uuid());
+                let store = if parts[1] == "indexhistos" {
+                    &mut self.histograms
+                } else {
+                    &mut self.messages
+                };
+                store
+                    .entry(flowcell.to_string())
+                    .or_default()
+                    .push(object.clone());
+                (201, object)
+            }
+            _ => not_found(),
+        }
+    }
+}
+
+/// The response for unknown objects and endpoints.
+fn not_found() -> (u16, Value) {
+    (404, json!({"detail": "Not found."}))
+}
+
+/// Mock of the Digestiflow REST API, running in a background thread until dropped.
+pub struct MockServer {
+    /// The address the server listens on.
+    addr: SocketAddr,
+    /// The state shared with the server thread.
+    state: Arc<Mutex<State>>,
+    /// Flag for stopping the server thread.
+    stop: Arc<AtomicBool>,
+    /// Handle of the server thread.
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Start a new mock server on a free port of the loopback interface.
+    pub fn start() -> Result<Self> {
+        let listener =
+            TcpListener::bind("127.0.0.1:0").chain_err(|| "Could not bind mock server")?;
+        let addr = listener
+            .local_addr()
+            .chain_err(|| "Could not get mock server address")?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        // Errors only affect the single connection and show up in the client.
+                        let _ = serve(stream, &state);
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            addr,
+            state,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The URL to use as `web.url`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Add a project with the given title, returning its UUID.
+    pub fn add_project(&self, title: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let uuid = state.uuid();
+        state.projects.push((uuid.clone(), title.to_string()));
+        uuid
+    }
+
+    /// Set the ingest preferences of a project, e.g., `json!({"analyze_adapters": true})`.
+    pub fn set_project_settings(&self, project_uuid: &str, settings: Value) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .project_settings
+            .insert(project_uuid.to_string(), settings);
+    }
+
+    /// Add a flow cell to a project, returning its UUID.
+    ///
+    /// The flow cell is given as JSON as sent by the client, e.g., with `"libraries"` for
+    /// checking barcodes.
+    pub fn add_flowcell(&self, project_uuid: &str, flowcell: Value) -> String {
+        let mut state = self.state.lock().unwrap();
+        let uuid = state.uuid();
+        let mut flowcell = flowcell;
+        flowcell["sodar_uuid"] = Value::String(uuid.clone());
+        state
+            .flowcells
+            .entry(project_uuid.to_string())
+            .or_default()
+            .push(flowcell);
+        uuid
+    }
+
+    /// The flow cells of a project.
+    pub fn flowcells(&self, project_uuid: &str) -> Vec<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .flowcells
+            .get(project_uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The index histograms posted for a flow cell.
+    pub fn histograms(&self, flowcell_uuid: &str) -> Vec<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .histograms
+            .get(flowcell_uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The messages posted for a flow cell.
+    pub fn messages(&self, flowcell_uuid: &str) -> Vec<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .messages
+            .get(flowcell_uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Unblock the `accept()` call of the server thread.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Serve a single request on the given connection.
+fn serve(stream: TcpStream, state: &Mutex<State>) -> Result<()> {
+    let mut reader = BufReader::new(
+        stream
+            .try_clone()
+            .chain_err(|| "Could not clone connection")?,
+    );
+
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .chain_err(|| "Could not read request line")?;
+    let mut fields = request_line.split_whitespace();
+    let method = fields.next().unwrap_or("").to_string();
+    let path = fields.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .chain_err(|| "Could not read header")?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .chain_err(|| "Could not read body")?;
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (status, response) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(Request {
+            method: method.clone(),
+            path: path.clone(),
+            body: body.clone(),
+        });
+        state.handle(&method, &path, body)
+    };
+
+    let response = match status {
+        204 => String::new(),
+        _ => response.to_string(),
+    };
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        _ => "Not Found",
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        response.len(),
+        response
+    )
+    .chain_err(|| "Could not write response")?;
+    stream.flush().chain_err(|| "Could not flush response")
+}
@@ -0,0 +1,315 @@
+//! Tests running the `ingest` command against the mock of the Digestiflow API.
+
+extern crate digestiflow_cli;
+extern crate flate2;
+#[macro_use]
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+
+use digestiflow_cli::testing::MockServer;
+
+/// The bases of each read of the run folder written by `write_run_folder()`.
+const READ_BASES: &str = "AAAACGTACGTAAA";
+
+/// The number of clusters per tile of the run folder written by `write_run_folder()`.
+const NUM_CLUSTERS: u32 = 1000;
+
+/// Create an empty directory named `name` for the test in the temporary directory.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("digestiflow-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write a HiSeq X run folder with two lanes of two tiles to `path`, with a run of 3 + 8 + 3
+/// cycles where all clusters have the index `ACGTACGT`.
+fn write_run_folder(path: &Path) {
+    fs::create_dir_all(path.join("Data").join("Intensities")).unwrap();
+    fs::write(
+        path.join("RunInfo.xml"),
+        r#"<?xml version="1.0"?>
+<RunInfo Version="2">
+  <Run Id="170101_E00123_0042_AHXXXXCCXX" Number="42">
+    <Flowcell>HXXXXCCXX</Flowcell>
+    <Instrument>E00123</Instrument>
+    <Date>170101</Date>
+    <Reads>
+      <Read Number="1" NumCycles="3" IsIndexedRead="N"/>
+      <Read Number="2" NumCycles="8" IsIndexedRead="Y"/>
+      <Read Number="3" NumCycles="3" IsIndexedRead="N"/>
+    </Reads>
+    <FlowcellLayout LaneCount="2" SurfaceCount="2" SwathCount="2" TileCount="24"/>
+  </Run>
+</RunInfo>
+"#,
+    )
+    .unwrap();
+    fs::write(
+        path.join("RunParameters.xml"),
+        r#"<?xml version="1.0"?>
+<RunParameters xmlns:xsd="http://www.w3.org/2001/XMLSchema">
+  <Setup>
+    <ApplicationName>HiSeq Control Software</ApplicationName>
+    <ApplicationVersion>HD 3.4.0.38</ApplicationVersion>
+    <RunID>170101_E00123_0042_AHXXXXCCXX</RunID>
+    <ScanNumber>42</ScanNumber>
+    <FCPosition>A</FCPosition>
+    <ExperimentName>Integration test</ExperimentName>
+    <RTAVersion>2.7.7</RTAVersion>
+  </Setup>
+  <Reads>
+    <RunInfoRead Number="1" NumCycles="3" IsIndexedRead="N" />
+    <RunInfoRead Number="2" NumCycles="8" IsIndexedRead="Y" />
+    <RunInfoRead Number="3" NumCycles="3" IsIndexedRead="N" />
+  </Reads>
+</RunParameters>
+"#,
+    )
+    .unwrap();
+
+    // One cluster at the origin, all clusters pass the filter.
+    let mut locs = Vec::new();
+    for value in &[1u32, 1.0f32.to_bits(), 0u32] {
+        locs.extend_from_slice(&value.to_le_bytes());
+    }
+    fs::write(path.join("Data").join("Intensities").join("s.locs"), locs).unwrap();
+    for lane in 1..3 {
+        let lane_dir = path
+            .join("Data")
+            .join("Intensities")
+            .join("BaseCalls")
+            .join(format!("L{:03}", lane));
+        for tile in &[1101, 1102] {
+            fs::create_dir_all(&lane_dir).unwrap();
+            let mut filter = Vec::new();
+            for value in &[0u32, 3, NUM_CLUSTERS] {
+                filter.extend_from_slice(&value.to_le_bytes());
+            }
+            filter.extend(vec![1u8; NUM_CLUSTERS as usize]);
+            fs::write(lane_dir.join(format!("s_{}_{}.filter", lane, tile)), filter).unwrap();
+
+            for (i, base) in READ_BASES.chars().enumerate() {
+                let cycle_dir = lane_dir.join(format!("C{}.1", i + 1));
+                fs::create_dir_all(&cycle_dir).unwrap();
+                let code = "ACGT".find(base).unwrap() as u8;
+                let mut bcl = NUM_CLUSTERS.to_le_bytes().to_vec();
+                bcl.extend(vec![(30 << 2) | code; NUM_CLUSTERS as usize]);
+                let file = fs::File::create(cycle_dir.join(format!("s_{}_{}.bcl.gz", lane, tile)))
+                    .unwrap();
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                encoder.write_all(&bcl).unwrap();
+                encoder.finish().unwrap();
+            }
+        }
+    }
+}
+
+/// Run `digestiflow-cli` with `args` against `server` and return whether it succeeded.
+///
+/// The home directory is set to `home`, such that no configuration file is read.
+fn run_cli(server: &MockServer, home: &Path, args: &[&str]) -> bool {
+    let output = Command::new(env!("CARGO_BIN_EXE_digestiflow-cli"))
+        .args(args)
+        .env("HOME", home)
+        .env("DIGESTIFLOW_WEB__URL", server.url())
+        .env("DIGESTIFLOW_WEB__TOKEN", "secret")
+        .output()
+        .unwrap();
+    if !output.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+    output.status.success()
+}
+
+/// Return the requests to `server` as (method, path).
+fn requests(server: &MockServer) -> Vec<(String, String)> {
+    server
+        .requests()
+        .into_iter()
+        .map(|request| (request.method, request.path))
+        .collect()
+}
+
+#[test]
+fn ingest_registers_flowcell_and_posts_histograms() {
+    let dir = test_dir("register");
+    let run_dir = dir.join("170101_E00123_0042_AHXXXXCCXX");
+    write_run_folder(&run_dir);
+    let server = MockServer::start().unwrap();
+    let project_uuid = server.add_project("Test Project");
+
+    assert!(run_cli(
+        &server,
+        &dir,
+        &[
+            "ingest",
+            "--project-uuid",
+            &project_uuid,
+            run_dir.to_str().unwrap(),
+        ],
+    ));
+
+    let flowcells = server.flowcells(&project_uuid);
+    assert_eq!(flowcells.len(), 1);
+    let flowcell = &flowcells[0];
+    assert_eq!(flowcell["vendor_id"], "HXXXXCCXX");
+    assert_eq!(flowcell["run_number"], 42);
+    assert_eq!(flowcell["sequencing_machine"], "E00123");
+    assert_eq!(flowcell["num_lanes"], 2);
+
+    let mut histograms = server.histograms(flowcell["sodar_uuid"].as_str().unwrap());
+    histograms.sort_by_key(|histogram| histogram["lane"].as_i64());
+    assert_eq!(histograms.len(), 2);
+    for (lane, histogram) in histograms.iter().enumerate() {
+        assert_eq!(histogram["lane"], lane as i64 + 1);
+        assert_eq!(histogram["index_read_no"], 1);
+        let entries = histogram["histogram"].as_object().unwrap();
+        assert_eq!(entries.keys().collect::<Vec<&String>>(), vec!["ACGTACGT"]);
+    }
+
+    let requests = requests(&server);
+    let resolve = format!(
+        "/api/flowcells/resolve/{}/E00123/42/HXXXXCCXX/",
+        &project_uuid
+    );
+    assert!(requests.contains(&("GET".to_string(), resolve)));
+    assert!(requests.contains(&(
+        "POST".to_string(),
+        format!("/api/flowcells/{}/", &project_uuid)
+    )));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ingest_resolves_and_updates_registered_flowcell() {
+    let dir = test_dir("update");
+    let run_dir = dir.join("170101_E00123_0042_AHXXXXCCXX");
+    write_run_folder(&run_dir);
+    let server = MockServer::start().unwrap();
+    let project_uuid = server.add_project("Test Project");
+    let flowcell_uuid = server.add_flowcell(
+        &project_uuid,
+        json!({
+            "run_date": "2017-01-01",
+            "run_number": 42,
+            "slot": "A",
+            "vendor_id": "HXXXXCCXX",
+            "label": null,
+            "manual_label": null,
+            "description": null,
+            "sequencing_machine": "E00123",
+            "num_lanes": 2,
+            "operator": null,
+            "rta_version": 2,
+            "status_sequencing": "in_progress",
+            "status_conversion": "initial",
+            "status_delivery": "initial",
+            "delivery_type": "seq",
+            "planned_reads": null,
+            "current_reads": null,
+            "libraries": [],
+        }),
+    );
+
+    assert!(run_cli(
+        &server,
+        &dir,
+        &[
+            "ingest",
+            "--project-uuid",
+            &project_uuid,
+            run_dir.to_str().unwrap()
+        ],
+    ));
+
+    let flowcells = server.flowcells(&project_uuid);
+    assert_eq!(flowcells.len(), 1);
+    assert_eq!(
+        flowcells[0]["sodar_uuid"],
+        Value::from(flowcell_uuid.clone())
+    );
+    assert_eq!(flowcells[0]["planned_reads"], "3T8B3T");
+    assert_eq!(server.histograms(&flowcell_uuid).len(), 2);
+
+    let requests = requests(&server);
+    assert!(requests.contains(&(
+        "PUT".to_string(),
+        format!("/api/flowcells/{}/{}/", &project_uuid, &flowcell_uuid)
+    )));
+    assert!(!requests.contains(&(
+        "POST".to_string(),
+        format!("/api/flowcells/{}/", &project_uuid)
+    )));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ingest_fetches_project_defaults_only_if_enabled() {
+    let dir = test_dir("defaults");
+    let run_dir = dir.join("170101_E00123_0042_AHXXXXCCXX");
+    write_run_folder(&run_dir);
+    let server = MockServer::start().unwrap();
+    let project_uuid = server.add_project("Test Project");
+    server.set_project_settings(&project_uuid, json!({"analyze_adapters": false}));
+    let settings = (
+        "GET".to_string(),
+        format!("/api/projects/{}/settings/", &project_uuid),
+    );
+
+    assert!(run_cli(
+        &server,
+        &dir,
+        &[
+            "ingest",
+            "--project-uuid",
+            &project_uuid,
+            run_dir.to_str().unwrap()
+        ],
+    ));
+    assert!(!requests(&server).contains(&settings));
+    let flowcell_uuid = server.flowcells(&project_uuid)[0]["sodar_uuid"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(server.histograms(&flowcell_uuid).len(), 2);
+
+    // The project's preferences turn off the analysis of the adapters.
+    let server = MockServer::start().unwrap();
+    let project_uuid = server.add_project("Test Project");
+    server.set_project_settings(&project_uuid, json!({"analyze_adapters": false}));
+    let settings = (
+        "GET".to_string(),
+        format!("/api/projects/{}/settings/", &project_uuid),
+    );
+    assert!(run_cli(
+        &server,
+        &dir,
+        &[
+            "ingest",
+            "--project-uuid",
+            &project_uuid,
+            "--project-defaults",
+            run_dir.to_str().unwrap(),
+        ],
+    ));
+    assert!(requests(&server).contains(&settings));
+    let flowcell_uuid = server.flowcells(&project_uuid)[0]["sodar_uuid"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(server.histograms(&flowcell_uuid).is_empty());
+
+    fs::remove_dir_all(&dir).unwrap();
+}